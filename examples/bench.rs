@@ -1,4 +1,15 @@
+use std::time::Duration;
+
+use uring_fast_read::{UringConfig, UringReader};
+
 fn main() {
     let n = uring_fast_read::read_one_file("Cargo.toml").unwrap();
     println!("Read {} bytes", n);
+
+    let config = UringConfig::default().spin_before_wait(Duration::from_micros(50));
+    let reader = UringReader::new(config).unwrap();
+    for _ in 0..1000 {
+        reader.read_one_file("Cargo.toml").unwrap();
+    }
+    println!("{:?}", reader.stats());
 }
@@ -0,0 +1,13 @@
+use uring_fast_read::bench::{self, BenchConfig};
+
+fn main() {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    let paths = if paths.is_empty() {
+        vec!["src".to_string()]
+    } else {
+        paths
+    };
+
+    let report = bench::compare(&paths, &BenchConfig::default()).unwrap();
+    println!("{report}");
+}
@@ -0,0 +1,49 @@
+use std::future::poll_fn;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake};
+use std::thread::{self, Thread};
+
+use futures_core::Stream;
+use uring_fast_read::{UringConfig, UringReader};
+
+/// Just enough of an executor to drive one future on this thread
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Arc::new(Unpark(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+fn main() {
+    let reader = UringReader::new(UringConfig::default().max_in_flight(4)).unwrap();
+    let paths = [
+        "Cargo.toml",
+        "src/lib.rs",
+        "/proc/self/status",
+        "does/not/exist",
+    ];
+
+    block_on(async {
+        let mut stream = pin!(reader.read_many_stream(paths));
+        while let Some((path, result)) = poll_fn(|cx| stream.as_mut().poll_next(cx)).await {
+            match result {
+                Ok(data) => println!("{}: {} bytes", path.display(), data.len()),
+                Err(e) => println!("{}: {e}", path.display()),
+            }
+        }
+    });
+}
@@ -0,0 +1,114 @@
+/* Generated by cbindgen from src/ffi.rs, do not edit by hand */
+
+#ifndef URING_FAST_READ_H
+#define URING_FAST_READ_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#if (defined(__linux__) && defined(__linux__))
+/*
+ Returned when the crate panicked, the panic was caught at the boundary
+ */
+#define URING_PANICKED -1
+#endif
+
+#if defined(__linux__)
+/*
+ A long lived reader that owns one ring and can be shared between threads (`&self` everywhere)
+
+ `read_one_file` creates a ring, uses it for one request and throws it away. That is fine for learning
+ but `io_uring_setup` + mmap is not free, so anything that reads more than a handful of files should
+ create one `UringReader` and keep it around.
+
+ How requests are told apart:
+ - Every call gets its own `Session` with a unique 32 bit id
+ - user_data = (session id << 32) | slot, the slot is chosen by the caller (e.g. the index of the file)
+ - Whoever reaps the completion queue parks each CQE under its session id, so a thread never
+   "loses" a completion that belongs to somebody else
+ */
+typedef struct UringReader UringReader;
+#endif
+
+#if !defined(__linux__)
+/*
+ `std::fs` stand-in for the io_uring reader, see the module docs
+ */
+typedef struct UringReader UringReader;
+#endif
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+#if (defined(__linux__) && defined(__linux__))
+/*
+ Read a whole file with a ring of its own, see `UringReader::read_file_to_vec`
+
+ On success `*out_buf` / `*out_len` are the contents, free them with `uring_buf_free`. On failure
+ they are set to NULL / 0.
+
+ # Safety
+
+ `path` is a NUL terminated string, `out_buf` and `out_len` are valid for writes.
+ */
+int uring_fast_read(const char *path, uint8_t **out_buf, size_t *out_len);
+#endif
+
+#if (defined(__linux__) && defined(__linux__))
+/*
+ Create a persistent reader, `queue_depth` 0 keeps the default
+
+ On success `*out` is the reader, free it with `uring_reader_free`. It may be used from several
+ threads at once.
+
+ # Safety
+
+ `out` is valid for writes.
+ */
+int uring_reader_new(uint32_t queue_depth, struct UringReader **out);
+#endif
+
+#if (defined(__linux__) && defined(__linux__))
+/*
+ `uring_fast_read` on a reader of `uring_reader_new`
+
+ # Safety
+
+ `reader` came from `uring_reader_new` and was not freed, the rest as for `uring_fast_read`.
+ */
+int uring_reader_read(const struct UringReader *reader,
+                      const char *path,
+                      uint8_t **out_buf,
+                      size_t *out_len);
+#endif
+
+#if (defined(__linux__) && defined(__linux__))
+/*
+ Free a reader of `uring_reader_new`, NULL is ignored
+
+ Waits (up to a second) for requests still in flight, like dropping a `UringReader`.
+
+ # Safety
+
+ `reader` came from `uring_reader_new`, is not used by any other thread anymore and is not freed twice.
+ */
+void uring_reader_free(struct UringReader *reader);
+#endif
+
+#if (defined(__linux__) && defined(__linux__))
+/*
+ Free a buffer handed out by `uring_fast_read` / `uring_reader_read`, NULL is ignored
+
+ # Safety
+
+ `buf` and `len` are exactly what the read returned, and it is not freed twice.
+ */
+void uring_buf_free(uint8_t *buf, size_t len);
+#endif
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* URING_FAST_READ_H */
@@ -0,0 +1,335 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::Path;
+
+use crate::config::{ShrinkPolicy, UringConfig};
+use crate::error::ReadError;
+
+/// The few operations the whole-file reads are built from, so they can run on something else than a
+/// kernel ring (see `MockBackend`, feature `test-util`)
+///
+/// Requests carry a `tag` chosen by the caller, completions come back with it in any order:
+/// 1. `submit_read` / `submit_statx` queue requests
+/// 2. `wait` blocks until at least one of them completed (and hands the queued ones to the kernel)
+/// 3. `reap` returns every completion there is, never blocks
+///
+/// `UringBackend` is the real one, `UringReader::backend` creates it.
+pub trait ReadBackend {
+    /// Read up to `len` bytes of `path` at `offset`, fewer is a short read and 0 is EOF
+    fn submit_read(&mut self, tag: u64, path: &Path, offset: u64, len: usize) -> io::Result<()>;
+    /// The size of `path`, 0 for files without one (`/proc`, pipes)
+    fn submit_statx(&mut self, tag: u64, path: &Path) -> io::Result<()>;
+    /// Block until at least one request completed, Err only if the backend itself failed
+    fn wait(&mut self) -> io::Result<()>;
+    /// Every completion since the last call
+    fn reap(&mut self) -> Vec<(u64, Reaped)>;
+}
+
+/// The result of one backend request
+/// - Read -> the bytes read (shorter than asked for a short read, empty at EOF)
+/// - Statx -> the file size
+#[derive(Debug)]
+pub enum Reaped {
+    Read(io::Result<Vec<u8>>),
+    Statx(io::Result<u64>),
+}
+
+/// The whole-file reads of `UringReader`, on top of any `ReadBackend`
+///
+/// Same behavior as the ring's own implementation: sizes are asked first, then up to `queue_depth`
+/// chunk reads of `chunk_size` are in flight, short reads are resubmitted for the rest, EINTR/EAGAIN
+/// are retried, `max_bytes` and `on_shrink` apply and files without a size are read until EOF. Write the code under
+/// test against `BackendReader<B>` and hand it a `MockBackend` in tests, a `UringBackend` otherwise.
+///
+/// ```no_run
+/// use uring_fast_read::{BackendReader, UringConfig, UringReader};
+///
+/// let ring = UringReader::new(UringConfig::default()).unwrap();
+/// let mut reader = BackendReader::new(ring.backend(), UringConfig::default());
+/// let data = reader.read_file_to_vec("/etc/hostname").unwrap();
+/// ```
+pub struct BackendReader<B> {
+    backend: B,
+    config: UringConfig,
+    next_tag: u64,
+}
+
+/// Where one file of `read_files` stands
+/// - known -> the size came from statx (the buffer has that length), otherwise it grows until EOF
+/// - filled -> bytes that are valid (shrinks when EOF comes early)
+/// - ended -> a chunk hit EOF early (the file was truncated), the rest is not read
+struct FileRead {
+    buf: Vec<u8>,
+    known: bool,
+    filled: usize,
+    ended: bool,
+    error: Option<io::Error>,
+}
+
+/// One chunk read to issue
+#[derive(Clone, Copy)]
+struct Work {
+    file: usize,
+    offset: usize,
+    len: usize,
+}
+
+impl<B: ReadBackend> BackendReader<B> {
+    /// `config` supplies `queue_depth`, `chunk_size`, `max_bytes` and `on_shrink`, nothing else is used
+    pub fn new(backend: B, config: UringConfig) -> Self {
+        BackendReader {
+            backend,
+            config,
+            next_tag: 0,
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    pub fn into_backend(self) -> B {
+        self.backend
+    }
+
+    /// The size of `path`, one statx
+    pub fn file_size(&mut self, path: impl AsRef<Path>) -> io::Result<u64> {
+        self.sizes(&[path.as_ref()])
+            .pop()
+            .expect("one size per path")
+    }
+
+    /// Read a whole file into memory, see `UringReader::read_file_to_vec`
+    pub fn read_file_to_vec(&mut self, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        self.read_many_files(&[path.as_ref()])
+            .pop()
+            .expect("one result per path")
+    }
+
+    /// Read many whole files at once, results are in the same order as `paths`, a file that fails
+    /// only fails its own entry
+    #[allow(unused_doc_comments)]
+    pub fn read_many_files<P: AsRef<Path>>(&mut self, paths: &[P]) -> Vec<io::Result<Vec<u8>>> {
+        let paths: Vec<&Path> = paths.iter().map(AsRef::as_ref).collect();
+        let sizes = self.sizes(&paths);
+        let mut files: Vec<FileRead> = sizes
+            .into_iter()
+            .zip(&paths)
+            .map(|(size, path)| {
+                let size = size.and_then(|size| {
+                    self.check_size(path, size)?;
+                    Ok(size)
+                });
+                match size {
+                    Ok(size) => FileRead {
+                        buf: vec![0u8; size as usize],
+                        known: size > 0,
+                        filled: size as usize,
+                        ended: false,
+                        error: None,
+                    },
+                    Err(e) => FileRead {
+                        buf: Vec::new(),
+                        known: true,
+                        filled: 0,
+                        ended: false,
+                        error: Some(e),
+                    },
+                }
+            })
+            .collect();
+
+        if let Err(e) = self.read_files(&paths, &mut files) {
+            /// The backend itself failed, every file that was not finished fails with it
+            for file in &mut files {
+                file.error.get_or_insert_with(|| copy_error(&e));
+            }
+        }
+
+        files
+            .into_iter()
+            .zip(&paths)
+            .map(|(file, path)| match file.error {
+                Some(e) => Err(e),
+                None if file.ended && self.config.on_shrink == ShrinkPolicy::Fail => {
+                    Err(ReadError::FileChangedDuringRead {
+                        path: path.to_path_buf(),
+                        expected: file.buf.len() as u64,
+                        actual: file.filled as u64,
+                    }
+                    .into())
+                }
+                None => {
+                    let mut buf = file.buf;
+                    buf.truncate(file.filled);
+                    Ok(buf)
+                }
+            })
+            .collect()
+    }
+
+    /// statx every path, all in flight at once
+    fn sizes(&mut self, paths: &[&Path]) -> Vec<io::Result<u64>> {
+        let mut sizes: Vec<Option<io::Result<u64>>> = paths.iter().map(|_| None).collect();
+        let mut tags = HashMap::new();
+        for (i, path) in paths.iter().enumerate() {
+            let tag = self.tag();
+            match self.backend.submit_statx(tag, path) {
+                Ok(()) => {
+                    tags.insert(tag, i);
+                }
+                Err(e) => sizes[i] = Some(Err(e)),
+            }
+        }
+        while !tags.is_empty() {
+            if let Err(e) = self.backend.wait() {
+                for i in tags.into_values() {
+                    sizes[i] = Some(Err(copy_error(&e)));
+                }
+                break;
+            }
+            for (tag, reaped) in self.backend.reap() {
+                if let (Some(i), Reaped::Statx(size)) = (tags.remove(&tag), reaped) {
+                    sizes[i] = Some(size);
+                }
+            }
+        }
+        sizes
+            .into_iter()
+            .map(|size| size.expect("every path has a size"))
+            .collect()
+    }
+
+    /// The submit/reap loop, Err only if the backend itself fails
+    #[allow(unused_doc_comments)]
+    fn read_files(&mut self, paths: &[&Path], files: &mut [FileRead]) -> io::Result<()> {
+        let chunk_size = self.config.chunk_size;
+        let depth = self.config.queue_depth.max(1) as usize;
+        let mut queue: VecDeque<Work> = VecDeque::new();
+        for (i, file) in files.iter().enumerate() {
+            if file.error.is_some() {
+                continue;
+            }
+            if file.known {
+                for offset in (0..file.buf.len()).step_by(chunk_size) {
+                    queue.push_back(Work {
+                        file: i,
+                        offset,
+                        len: chunk_size.min(file.buf.len() - offset),
+                    });
+                }
+            } else {
+                /// No size: one chunk at a time at the end, until a read returns 0
+                queue.push_back(Work {
+                    file: i,
+                    offset: 0,
+                    len: chunk_size,
+                });
+            }
+        }
+
+        let mut in_flight: HashMap<u64, Work> = HashMap::new();
+        loop {
+            while in_flight.len() < depth {
+                let Some(work) = queue.pop_front() else {
+                    break;
+                };
+                if files[work.file].error.is_some() || files[work.file].ended {
+                    continue;
+                }
+                let tag = self.tag();
+                self.backend
+                    .submit_read(tag, paths[work.file], work.offset as u64, work.len)?;
+                in_flight.insert(tag, work);
+            }
+            if in_flight.is_empty() {
+                return Ok(());
+            }
+
+            self.backend.wait()?;
+            for (tag, reaped) in self.backend.reap() {
+                let (Some(work), Reaped::Read(result)) = (in_flight.remove(&tag), reaped) else {
+                    continue;
+                };
+                let file = &mut files[work.file];
+                let data = match result {
+                    Ok(data) => data,
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+                        ) =>
+                    {
+                        queue.push_front(work);
+                        continue;
+                    }
+                    Err(e) => {
+                        file.error.get_or_insert(e);
+                        continue;
+                    }
+                };
+                let n = data.len().min(work.len);
+
+                if !file.known {
+                    file.buf.extend_from_slice(&data[..n]);
+                    file.filled = file.buf.len();
+                    if let Err(e) = self.check_size(paths[work.file], file.buf.len() as u64) {
+                        file.error.get_or_insert(e);
+                    } else if n > 0 {
+                        queue.push_back(Work {
+                            offset: file.buf.len(),
+                            ..work
+                        });
+                    }
+                } else if n == 0 {
+                    // EOF before the end of this chunk, the file is shorter than statx said (it was
+                    // truncated): nothing after it is issued anymore
+                    file.filled = file.filled.min(work.offset);
+                    file.ended = true;
+                } else {
+                    file.buf[work.offset..work.offset + n].copy_from_slice(&data[..n]);
+                    if n < work.len && !file.ended {
+                        queue.push_front(Work {
+                            file: work.file,
+                            offset: work.offset + n,
+                            len: work.len - n,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    fn tag(&mut self) -> u64 {
+        self.next_tag += 1;
+        self.next_tag
+    }
+
+    fn check_size(&self, path: &Path, size: u64) -> io::Result<()> {
+        match self.config.max_bytes {
+            Some(limit) if size > limit => Err(ReadError::FileTooLarge {
+                path: path.to_path_buf(),
+                size,
+                limit,
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// `io::Error` is not `Clone`, this keeps the kind and the OS error code (or the message), and
+/// `ReadError::Cancelled` with its reason
+pub(crate) fn copy_error(e: &io::Error) -> io::Error {
+    if let Some(&ReadError::Cancelled { reason }) = ReadError::from_io(e) {
+        return ReadError::Cancelled { reason }.into();
+    }
+    match e.raw_os_error() {
+        Some(code) => io::Error::from_raw_os_error(code),
+        None => io::Error::new(e.kind(), e.to_string()),
+    }
+}
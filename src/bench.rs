@@ -0,0 +1,250 @@
+//! Does io_uring help on this machine, for this workload?
+//!
+//! `compare` reads the same set of files twice, once with `UringReader::read_many_files` and once
+//! with `std::fs::read` on a pool of threads, and reports both runs side by side.
+//!
+//! ```no_run
+//! use uring_fast_read::bench::{self, BenchConfig};
+//!
+//! let report = bench::compare(&["/usr/share/doc"], &BenchConfig::default().drop_caches(true)).unwrap();
+//! println!("{report}");
+//! ```
+
+use std::fmt;
+use std::fs;
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::UringReader;
+use crate::config::UringConfig;
+#[cfg(not(target_os = "linux"))]
+use crate::fallback::lock;
+#[cfg(target_os = "linux")]
+use crate::reader::lock;
+use crate::walk::walk_files;
+
+/// How `compare` runs
+/// - reader -> config of the `UringReader` used for the io_uring run
+/// - threads -> size of the thread pool calling `std::fs::read` (defaults to the number of CPUs)
+/// - drop_caches -> evict the files from the page cache (fadvise DONTNEED) before each run, to measure
+///   the disk instead of memcpy. Only works for pages that are not dirty and not mapped by anyone,
+///   and only on Linux.
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    pub(crate) reader: UringConfig,
+    pub(crate) threads: usize,
+    pub(crate) drop_caches: bool,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        BenchConfig {
+            reader: UringConfig::default(),
+            threads: thread::available_parallelism().map_or(4, |n| n.get()),
+            drop_caches: false,
+        }
+    }
+}
+
+impl BenchConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reader_config(mut self, reader: UringConfig) -> Self {
+        self.reader = reader;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    pub fn drop_caches(mut self, drop_caches: bool) -> Self {
+        self.drop_caches = drop_caches;
+        self
+    }
+}
+
+/// One run over the file set
+/// - wall -> time from the first open to the last byte
+/// - bytes -> bytes read in total
+/// - failed -> files that could not be read
+/// - syscalls -> read type syscalls (`syscr` of `/proc/self/io`) plus `io_uring_enter` calls. Opens and
+///   stats are the same for both runs and not counted. Always 0 off Linux.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct BenchRun {
+    #[cfg_attr(feature = "serde", serde(with = "crate::stats::nanos"))]
+    pub wall: Duration,
+    pub bytes: u64,
+    pub failed: usize,
+    pub syscalls: u64,
+}
+
+impl BenchRun {
+    /// Bytes per second
+    pub fn throughput(&self) -> f64 {
+        self.bytes as f64 / self.wall.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+/// Result of `compare`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct BenchReport {
+    /// Number of files in the set
+    pub files: usize,
+    /// Size of the std thread pool
+    pub threads: usize,
+    pub uring: BenchRun,
+    pub std: BenchRun,
+}
+
+impl BenchReport {
+    /// Wall time of std divided by wall time of io_uring, above 1.0 means io_uring was faster
+    pub fn speedup(&self) -> f64 {
+        self.std.wall.as_secs_f64() / self.uring.wall.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} files", self.files)?;
+        let runs = [
+            ("io_uring", &self.uring),
+            (&*format!("std x{}", self.threads), &self.std),
+        ];
+        for (name, run) in runs {
+            writeln!(
+                f,
+                "{name:>10}: {:>10.2?} {:>10.1} MiB/s {:>8} syscalls {:>12} bytes {} failed",
+                run.wall,
+                run.throughput() / (1024.0 * 1024.0),
+                run.syscalls,
+                run.bytes,
+                run.failed
+            )?;
+        }
+        write!(f, "   speedup: {:.2}x", self.speedup())
+    }
+}
+
+/// Read `paths` with both backends and compare, directories are walked recursively
+///
+/// The io_uring run goes first. Without `drop_caches` the second run may find the files in the page
+/// cache where the first one had to go to the disk, for a fair result drop them or run it twice.
+pub fn compare<P: AsRef<Path>>(paths: &[P], config: &BenchConfig) -> io::Result<BenchReport> {
+    let mut files = Vec::new();
+    for path in paths {
+        let path = path.as_ref();
+        if fs::metadata(path)?.is_dir() {
+            files.extend(walk_files(path)?.files);
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+
+    let reader = UringReader::new(config.reader.clone())?;
+
+    let uring = measure(&files, config, |files| {
+        let enters = reader.stats().enters;
+        let results = reader.read_many_files(files);
+        let sizes = results
+            .iter()
+            .map(|r| r.as_ref().ok().map(|data| data.len() as u64))
+            .collect();
+        (sizes, reader.stats().enters - enters)
+    })?;
+
+    let std = measure(&files, config, |files| {
+        let next = AtomicUsize::new(0);
+        let sizes = Mutex::new(vec![None; files.len()]);
+        thread::scope(|scope| {
+            for _ in 0..config.threads {
+                scope.spawn(|| {
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = files.get(i) else { break };
+                        let size = fs::read(path).ok().map(|data| data.len() as u64);
+                        lock(&sizes)[i] = size;
+                    }
+                });
+            }
+        });
+        (sizes.into_inner().unwrap_or_else(|e| e.into_inner()), 0)
+    })?;
+
+    Ok(BenchReport {
+        files: files.len(),
+        threads: config.threads,
+        uring,
+        std,
+    })
+}
+
+/// Time one run, `run` returns the size of every file (None if it failed) and its own extra syscalls
+fn measure(
+    files: &[PathBuf],
+    config: &BenchConfig,
+    run: impl FnOnce(&[PathBuf]) -> (Vec<Option<u64>>, u64),
+) -> io::Result<BenchRun> {
+    if config.drop_caches {
+        for path in files {
+            drop_cache(path);
+        }
+    }
+
+    let reads = read_syscalls()?;
+    let start = Instant::now();
+    let (sizes, extra) = run(files);
+    let wall = start.elapsed();
+    let reads = read_syscalls()? - reads;
+
+    Ok(BenchRun {
+        wall,
+        bytes: sizes.iter().flatten().sum(),
+        failed: sizes.iter().filter(|size| size.is_none()).count(),
+        syscalls: reads + extra,
+    })
+}
+
+/// Ask the kernel to forget the cached pages of `path`, best effort
+#[cfg(target_os = "linux")]
+fn drop_cache(path: &Path) {
+    if let Ok(file) = fs::File::open(path) {
+        // SAFETY: plain syscall on an fd we own
+        unsafe {
+            libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+        }
+    }
+}
+
+/// Not available off Linux
+#[cfg(not(target_os = "linux"))]
+fn drop_cache(_path: &Path) {}
+
+/// `syscr` of `/proc/self/io`, the number of read syscalls the process made so far
+#[cfg(target_os = "linux")]
+fn read_syscalls() -> io::Result<u64> {
+    let io = fs::read_to_string("/proc/self/io")?;
+    io.lines()
+        .find_map(|line| line.strip_prefix("syscr: "))
+        .and_then(|count| count.trim().parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no syscr in /proc/self/io"))
+}
+
+/// No `/proc/self/io` off Linux, syscalls are reported as 0
+#[cfg(not(target_os = "linux"))]
+fn read_syscalls() -> io::Result<u64> {
+    Ok(0)
+}
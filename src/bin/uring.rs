@@ -0,0 +1,172 @@
+//! `uring` -> small command line front end, built only on the public API of the crate
+//!
+//! uring [OPTIONS] cat FILE...   -> write the files to stdout
+//! uring [OPTIONS] cp SRC DST    -> copy a file
+//! uring [OPTIONS] hash DIR      -> hash every file below DIR, one `hash  path` line per file
+//!
+//! Options map straight onto `UringConfig`:
+//! -q, --queue-depth N   -> queue_depth
+//! -b, --buffer-size N   -> chunk_size (K/M/G suffixes allowed)
+//! --direct              -> direct_io (O_DIRECT)
+//! --algo NAME           -> hash algorithm: xxh3 (default) or sha256 (needs the `sha256` feature)
+//!
+//! Exit codes: 0 -> everything worked, 1 -> at least one file failed, 2 -> bad usage or no ring
+
+use std::io::{self, Write};
+use std::process::ExitCode;
+
+use uring_fast_read::{HashAlgo, UringConfig, UringReader};
+
+const USAGE: &str =
+    "usage: uring [-q N] [-b SIZE] [--direct] [--algo NAME] (cat FILE... | cp SRC DST | hash DIR)";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+    let (config, algo, command) = match parse_args(&args) {
+        Ok(parsed) => parsed,
+        Err(msg) => {
+            eprintln!("uring: {msg}\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let reader = match UringReader::new(config) {
+        Ok(reader) => reader,
+        Err(e) => {
+            eprintln!("uring: can't set up io_uring: {e}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let ok = match &command[..] {
+        ["cat", files @ ..] if !files.is_empty() => cat(&reader, files),
+        ["cp", src, dst] => cp(&reader, src, dst),
+        ["hash", dir] => hash(&reader, dir, algo),
+        _ => {
+            eprintln!("uring: unknown or incomplete command\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    if ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Split the options off, the rest is the command and its arguments
+fn parse_args(args: &[String]) -> Result<(UringConfig, HashAlgo, Vec<&str>), String> {
+    let mut config = UringConfig::default();
+    let mut algo = HashAlgo::Xxh3;
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-q" | "--queue-depth" => {
+                let value = args.next().ok_or("missing value for --queue-depth")?;
+                let depth = value
+                    .parse()
+                    .map_err(|_| format!("bad queue depth {value:?}"))?;
+                config = config.queue_depth(depth);
+            }
+            "-b" | "--buffer-size" => {
+                let value = args.next().ok_or("missing value for --buffer-size")?;
+                let size = parse_size(value).ok_or(format!("bad buffer size {value:?}"))?;
+                config = config.chunk_size(size);
+            }
+            "--direct" => config = config.direct_io(true),
+            "--algo" => {
+                algo = match args.next().map(String::as_str) {
+                    Some("xxh3") => HashAlgo::Xxh3,
+                    #[cfg(feature = "sha256")]
+                    Some("sha256") => HashAlgo::Sha256,
+                    Some(other) => return Err(format!("unknown hash algorithm {other:?}")),
+                    None => return Err("missing value for --algo".to_string()),
+                };
+            }
+            _ => rest.push(arg.as_str()),
+        }
+    }
+    Ok((config, algo, rest))
+}
+
+/// `4096`, `64K`, `1M`, `1G`
+fn parse_size(value: &str) -> Option<usize> {
+    let (number, shift) = match value.as_bytes().last()? {
+        b'k' | b'K' => (&value[..value.len() - 1], 10),
+        b'm' | b'M' => (&value[..value.len() - 1], 20),
+        b'g' | b'G' => (&value[..value.len() - 1], 30),
+        _ => (value, 0),
+    };
+    number.parse::<usize>().ok()?.checked_mul(1 << shift)
+}
+
+fn cat(reader: &UringReader, files: &[&str]) -> bool {
+    let mut stdout = io::stdout().lock();
+    let mut ok = true;
+
+    for (path, result) in files.iter().zip(reader.read_many_files(files)) {
+        let written = match result {
+            Ok(data) => stdout.write_all(&data),
+            Err(e) => {
+                eprintln!("uring: cat: {path}: {e}");
+                ok = false;
+                continue;
+            }
+        };
+        if let Err(e) = written {
+            return stdout_failed(e);
+        }
+    }
+    ok && stdout.flush().map_or_else(stdout_failed, |_| true)
+}
+
+fn cp(reader: &UringReader, src: &str, dst: &str) -> bool {
+    match reader.copy_file(src, dst) {
+        Ok(_) => true,
+        Err(e) => {
+            eprintln!("uring: cp: {src} -> {dst}: {e}");
+            false
+        }
+    }
+}
+
+fn hash(reader: &UringReader, dir: &str, algo: HashAlgo) -> bool {
+    let entries = match reader.checksum_tree(dir, algo) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("uring: hash: {dir}: {e}");
+            return false;
+        }
+    };
+
+    let mut stdout = io::stdout().lock();
+    let mut ok = true;
+    for (path, result) in entries {
+        match result {
+            Ok(digest) => {
+                if let Err(e) = writeln!(stdout, "{digest}  {}", path.display()) {
+                    return stdout_failed(e);
+                }
+            }
+            Err(e) => {
+                eprintln!("uring: hash: {}: {e}", path.display());
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+/// A closed pipe (`uring cat big | head`) is not worth a message
+fn stdout_failed(e: io::Error) -> bool {
+    if e.kind() != io::ErrorKind::BrokenPipe {
+        eprintln!("uring: stdout: {e}");
+    }
+    false
+}
@@ -0,0 +1,162 @@
+use std::alloc::{self, Layout};
+use std::io;
+use std::sync::Mutex;
+
+use crate::config::MemlockPolicy;
+
+use crate::error::ReadError;
+use crate::reader::lock;
+
+/// A zeroed heap buffer with a chosen alignment, `Vec<u8>` can't do that
+pub(crate) struct AlignedBuf {
+    pub(crate) ptr: *mut u8,
+    layout: Layout,
+}
+
+// SAFETY: a plain heap allocation, nothing in it is tied to a thread
+unsafe impl Send for AlignedBuf {}
+// SAFETY: `&AlignedBuf` only hands out the pointer, who writes through it is up to the caller
+unsafe impl Sync for AlignedBuf {}
+
+impl AlignedBuf {
+    pub(crate) fn new(len: usize, align: usize) -> Self {
+        let layout = Layout::from_size_align(len.max(1), align).expect("valid buffer layout");
+        // SAFETY: the layout has a non-zero size
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        AlignedBuf { ptr, layout }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.layout.size()
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        // SAFETY: allocated in `new` with the same layout
+        unsafe { alloc::dealloc(self.ptr, self.layout) }
+    }
+}
+
+/// Page aligned, so the buffers also work for `O_DIRECT`
+const BUFFER_ALIGN: usize = 4096;
+
+/// The buffers registered with the ring (`UringConfig::fixed_buffers`) and which of them are free
+///
+/// Indices are handed out as `Lease`s, a lease gives its buffer back on drop.
+pub(crate) struct FixedBuffers {
+    bufs: Vec<AlignedBuf>,
+    free: Mutex<Vec<u16>>,
+}
+
+impl FixedBuffers {
+    /// Allocate `count` buffers of `size` bytes and register them through `register`
+    ///
+    /// Ok(None) -> registration hit the memlock limit and `policy` says to go on without
+    pub(crate) fn register(
+        count: usize,
+        size: usize,
+        policy: MemlockPolicy,
+        register: impl Fn(&[libc::iovec]) -> io::Result<()>,
+    ) -> io::Result<Option<Self>> {
+        let mut count = count.clamp(1, u16::MAX as usize);
+        loop {
+            let bufs: Vec<AlignedBuf> = (0..count)
+                .map(|_| AlignedBuf::new(size, BUFFER_ALIGN))
+                .collect();
+            let iovecs: Vec<libc::iovec> = bufs
+                .iter()
+                .map(|buf| libc::iovec {
+                    iov_base: buf.ptr.cast(),
+                    iov_len: buf.len(),
+                })
+                .collect();
+            match register(&iovecs) {
+                Ok(()) => {
+                    return Ok(Some(FixedBuffers {
+                        free: Mutex::new((0..count as u16).rev().collect()),
+                        bufs,
+                    }));
+                }
+                Err(e) if is_memlock(&e) => match policy {
+                    MemlockPolicy::Strict => {
+                        return Err(memlock_error(
+                            "registering buffers",
+                            (count * size) as u64,
+                            e,
+                        ));
+                    }
+                    MemlockPolicy::Shrink if count > 1 => count /= 2,
+                    _ => return Ok(None),
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.bufs.len()
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        self.bufs.first().map_or(0, AlignedBuf::len)
+    }
+
+    /// Up to `wanted` free buffers, never waits (an empty Vec if all of them are in use)
+    pub(crate) fn try_lease(&self, wanted: usize) -> Vec<Lease<'_>> {
+        let mut free = lock(&self.free);
+        let keep = free.len() - wanted.min(free.len());
+        free.split_off(keep)
+            .into_iter()
+            .map(|index| Lease { pool: self, index })
+            .collect()
+    }
+}
+
+/// One registered buffer, borrowed from `FixedBuffers`
+pub(crate) struct Lease<'p> {
+    pool: &'p FixedBuffers,
+    pub(crate) index: u16,
+}
+
+impl Lease<'_> {
+    pub(crate) fn ptr(&self) -> *mut u8 {
+        self.pool.bufs[self.index as usize].ptr
+    }
+}
+
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        lock(&self.pool.free).push(self.index);
+    }
+}
+
+/// ENOMEM / EPERM from pinning memory, what RLIMIT_MEMLOCK runs out as
+pub(crate) fn is_memlock(e: &io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(libc::ENOMEM) | Some(libc::EPERM))
+}
+
+/// `ReadError::MemlockLimit` for `what` failing to pin `wanted` bytes
+pub(crate) fn memlock_error(what: &'static str, wanted: u64, source: io::Error) -> io::Error {
+    ReadError::MemlockLimit {
+        what,
+        wanted,
+        limit: memlock_limit(),
+        source,
+    }
+    .into()
+}
+
+/// The soft RLIMIT_MEMLOCK, None for unlimited (or if it can't be read)
+fn memlock_limit() -> Option<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is a valid rlimit to write into
+    let res = unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) };
+    (res == 0 && limit.rlim_cur != libc::RLIM_INFINITY).then_some(limit.rlim_cur)
+}
@@ -0,0 +1,86 @@
+#[cfg(target_os = "linux")]
+use io_uring::Parameters;
+
+/// What the ring of a `UringReader` can do, read once from the `io_uring_setup` parameters
+///
+/// Everything is false on the std fallback, and on kernels that are too old for the feature.
+/// - nodrop -> IORING_FEAT_NODROP (5.5): CQEs that don't fit are held back instead of dropped.
+///   Without it the reader reaps right after every submit so the CQ never fills up.
+/// - submit_stable -> IORING_FEAT_SUBMIT_STABLE (5.7): everything an SQE points to was consumed at
+///   submit. Without it the data of the reader's own wait timers is kept until their CQE.
+/// - rw_cur_pos -> IORING_FEAT_RW_CUR_POS (5.6): offset -1 reads at the file position. Without it
+///   `read_when_ready` reads at offset 0, which pipes and FIFOs ignore anyway.
+/// - fast_poll -> IORING_FEAT_FAST_POLL (5.7): a read of a pipe/socket without data waits for a poll
+///   instead of tying up an io-wq worker
+/// - ext_arg -> IORING_FEAT_EXT_ARG (5.11): bounded waits (`UringConfig::timeout`) inside
+///   `io_uring_enter`. Without it a timeout SQE is pushed to bound the wait.
+/// - native_workers -> IORING_FEAT_NATIVE_WORKERS (5.12): io-wq workers are threads of the submitting
+///   task, not of the ring
+/// - cqe_skip -> IORING_FEAT_CQE_SKIP (5.17): IOSQE_CQE_SKIP_SUCCESS works
+/// - linked_file -> IORING_FEAT_LINKED_FILE (5.17): a linked request resolves its fixed file when it
+///   runs, not at submit. `read_linked` / `write_file_atomic` refuse to run without it.
+/// - sqpoll -> a kernel thread polls the submission queue (`UringConfig::sqpoll`), false if it
+///   wasn't asked for or the kernel refused it
+/// - attached_wq -> this ring shares the io-wq of another ring (`PoolConfig::shared_workqueue`)
+/// - fixed_buffers / fixed_buffer_size -> the registered buffers the reader got
+///   (`UringConfig::fixed_buffers`), 0 if none
+/// - memlock_degraded -> RLIMIT_MEMLOCK made the reader settle for less than configured: fewer or no
+///   registered buffers, or a smaller ring (`UringConfig::memlock_policy`)
+/// - sq_entries / cq_entries -> the queue sizes the kernel actually gave us
+/// - max_workers -> the io-wq worker limits (bounded, unbounded) in effect after
+///   `UringConfig::max_bounded_workers` / `max_unbounded_workers` were applied, the kernel may have
+///   granted less (RLIMIT_NPROC). None if neither was asked for, or they couldn't be applied.
+/// - max_workers_unsupported -> limits were asked for but the kernel refused the registration
+///   (older than 5.15), the reader runs without them
+/// - hipri_direct -> an `O_DIRECT` read with RWF_HIPRI (`PreparedRead::hipri`) succeeded on this ring.
+///   Found out with a 4 KiB test read of the running executable the first time it's asked for, so
+///   it speaks for the filesystem that lives on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Capabilities {
+    pub nodrop: bool,
+    pub submit_stable: bool,
+    pub rw_cur_pos: bool,
+    pub fast_poll: bool,
+    pub ext_arg: bool,
+    pub native_workers: bool,
+    pub cqe_skip: bool,
+    pub linked_file: bool,
+    pub sqpoll: bool,
+    pub attached_wq: bool,
+    pub fixed_buffers: usize,
+    pub fixed_buffer_size: usize,
+    pub memlock_degraded: bool,
+    pub sq_entries: u32,
+    pub cq_entries: u32,
+    pub hipri_direct: bool,
+    pub max_workers: Option<(u32, u32)>,
+    pub max_workers_unsupported: bool,
+}
+
+#[cfg(target_os = "linux")]
+impl Capabilities {
+    pub(crate) fn from_params(params: &Parameters) -> Self {
+        Capabilities {
+            nodrop: params.is_feature_nodrop(),
+            submit_stable: params.is_feature_submit_stable(),
+            rw_cur_pos: params.is_feature_rw_cur_pos(),
+            fast_poll: params.is_feature_fast_poll(),
+            ext_arg: params.is_feature_ext_arg(),
+            native_workers: params.is_feature_native_workers(),
+            cqe_skip: params.is_feature_skip_cqe_on_success(),
+            linked_file: params.is_feature_linked_file(),
+            sqpoll: false,
+            attached_wq: false,
+            fixed_buffers: 0,
+            fixed_buffer_size: 0,
+            memlock_degraded: false,
+            sq_entries: params.sq_entries(),
+            cq_entries: params.cq_entries(),
+            hipri_direct: false,
+            max_workers: None,
+            max_workers_unsupported: false,
+        }
+    }
+}
@@ -0,0 +1,758 @@
+use io_uring::{opcode, squeue, types};
+
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+
+use crate::completion::Completion;
+use crate::error::{Cancelled, ReadError, Stage, is_cancelled};
+use crate::journal::SqeFields;
+use crate::reader::{Session, UringReader, lock};
+
+/// A chain of your own in flight, returned by `UringReader::submit_chain`
+///
+/// Dropping it without `wait_chain` waits for the CQEs of the chain (the memory its entries point to
+/// must stay valid until then).
+pub struct ChainToken<'r> {
+    session: Session<'r>,
+    len: usize,
+    /// entry i -> soft linked (IO_LINK) to entry i + 1
+    linked: Vec<bool>,
+}
+
+impl ChainToken<'_> {
+    /// Number of entries in the chain
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// One CQE of a chain of `submit_chain`
+/// - index -> position of its entry in the `Vec` that was submitted
+/// - completion -> result and flags (its user_data is the crate's, not the one you set)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainResult {
+    pub index: usize,
+    pub completion: Completion,
+}
+
+/// Slots of the reader's registered (sparse) file table, handed out one per chain
+///
+/// A linked chain can't pass a normal fd from the open to the read, the read is built before the open
+/// ran. With a direct descriptor the open installs the file into a slot we picked up front and the
+/// later stages use `types::Fixed(slot)`.
+pub(crate) struct FixedFiles {
+    free: Mutex<Vec<u32>>,
+    freed: Condvar,
+}
+
+impl FixedFiles {
+    pub(crate) fn new(slots: u32) -> Self {
+        FixedFiles {
+            free: Mutex::new((0..slots).rev().collect()),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Take a free slot, waits while every slot is used by another chain
+    fn acquire(&self) -> Slot<'_> {
+        let mut free = lock(&self.free);
+        loop {
+            if let Some(index) = free.pop() {
+                return Slot { files: self, index };
+            }
+            free = self
+                .freed
+                .wait(free)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    /// `acquire` without waiting, `None` while every slot is taken
+    pub(crate) fn try_acquire(&self) -> Option<Slot<'_>> {
+        let index = lock(&self.free).pop()?;
+        Some(Slot { files: self, index })
+    }
+}
+
+/// A slot in use, given back on drop (declare it before the session, like a buffer)
+pub(crate) struct Slot<'f> {
+    files: &'f FixedFiles,
+    pub(crate) index: u32,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        lock(&self.files.free).push(self.index);
+        self.files.freed.notify_one();
+    }
+}
+
+/// One stage of a chain
+/// - expect -> the result that counts as success for reads/writes, a short write breaks the chain
+struct Link {
+    stage: Stage,
+    entry: squeue::Entry,
+    expect: Option<u32>,
+}
+
+/// Per stage result of a chain, in chain order
+struct Outcome {
+    stages: Vec<Stage>,
+    results: Vec<io::Result<u32>>,
+}
+
+impl Outcome {
+    fn ok(&self, stage: Stage) -> bool {
+        self.find(stage).is_some_and(|result| result.is_ok())
+    }
+
+    fn find(&self, stage: Stage) -> Option<&io::Result<u32>> {
+        let at = self.stages.iter().position(|&s| s == stage)?;
+        Some(&self.results[at])
+    }
+
+    /// The stage that actually failed and the ones canceled because of it, None if none failed
+    ///
+    /// A failed stage makes the kernel cancel the rest of the chain, those come back as
+    /// `Cancelled::ChainAborted`. The first error that isn't a cancellation is the real one (if
+    /// everything was canceled, e.g. by a timeout, the first stage is blamed).
+    fn failure(self, path: &Path) -> Option<ReadError> {
+        let failed = self
+            .results
+            .iter()
+            .position(|result| matches!(result, Err(e) if !is_cancelled(e)))
+            .or_else(|| self.results.iter().position(|result| result.is_err()))?;
+        let canceled = self.results[failed + 1..]
+            .iter()
+            .zip(&self.stages[failed + 1..])
+            .filter(|(result, _)| matches!(result, Err(e) if is_cancelled(e)))
+            .map(|(_, &stage)| stage)
+            .collect();
+        let stage = self.stages[failed];
+        let source = self
+            .results
+            .into_iter()
+            .nth(failed)
+            .and_then(Result::err)
+            .expect("the failed stage has an error");
+
+        Some(ReadError::ChainFailed {
+            path: path.to_path_buf(),
+            stage,
+            source,
+            canceled,
+        })
+    }
+}
+
+/// Whether the entry after `entry` only runs if this one succeeds (IOSQE_IO_LINK, a hard link runs
+/// anyway)
+fn soft_linked(entry: &squeue::Entry) -> bool {
+    SqeFields::of(entry).flags & squeue::Flags::IO_LINK.bits() != 0
+}
+
+/// Give the -ECANCELED CQEs the kernel posted for breaking a chain their `Cancelled::ChainAborted`
+///
+/// `cqes` are in chain order, `index` says which entry each belongs to. An entry soft linked behind
+/// another is canceled when that one failed (errno `cause`), came up short (`cause` 0: a short
+/// read/write breaks the chain too) or was canceled itself (its own cause, passed on). CQEs that
+/// already know why they were canceled (`cancel_all`) keep their reason.
+fn chain_aborted(cqes: &mut [Completion], index: impl Fn(&Completion) -> usize, linked: &[bool]) {
+    let mut last: Option<(usize, i32)> = None;
+    for cqe in cqes {
+        let at = index(cqe);
+        let behind = match last {
+            Some((before, cause)) if before + 1 == at && linked[before] => Some(cause),
+            _ => None,
+        };
+        if let Some(cause) = behind
+            && cqe.result() == -libc::ECANCELED
+            && cqe.cancelled().is_none()
+        {
+            *cqe = cqe.with_cancelled(Cancelled::ChainAborted { cause });
+        }
+        let passed_on = match cqe.cancelled() {
+            Some(Cancelled::ChainAborted { cause }) => cause,
+            _ => -cqe.result().min(0),
+        };
+        last = Some((at, passed_on));
+    }
+}
+
+pub(crate) fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))
+}
+
+impl UringReader {
+    /// Read up to `len` bytes from the start of `path` with one linked open -> read -> close chain
+    ///
+    /// One submit for the whole file, made for small files (config files, sysfs/procfs values) where
+    /// three round trips would cost more than the read. The read is hard linked, so the close runs
+    /// even when it fails.
+    ///
+    /// Ok(data) -> the bytes read, also when only the close failed (counted in `ReadStats::close_failures`)
+    /// Err(e) -> `ReadError::ChainFailed`, which stage failed and which ones were canceled with it
+    #[allow(unused_doc_comments)]
+    pub fn read_linked(&self, path: impl AsRef<Path>, len: usize) -> io::Result<Vec<u8>> {
+        let path = path.as_ref();
+        let c_path = c_path(path)?;
+        let len = len.min(u32::MAX as usize);
+        let mut buf = vec![0u8; len];
+        /// The path, the buffer and the slot are declared before the session, see `Session`
+        let slot = self.fixed_files()?.acquire();
+        let mut session = self.session();
+
+        let links = read_links(&c_path, slot.index, &mut buf)?;
+        let n = self.run_read_links(&mut session, &slot, links, path)?;
+        drop(session);
+        buf.truncate(n);
+        Ok(buf)
+    }
+
+    /// The chain of `read_linked` once its links are built: run it, close what it left open
+    ///
+    /// Ok(n) -> bytes read, also when only the close failed (counted in `ReadStats::close_failures`)
+    fn run_read_links(
+        &self,
+        session: &mut Session<'_>,
+        slot: &Slot<'_>,
+        links: Vec<Link>,
+        path: &Path,
+    ) -> io::Result<usize> {
+        let outcome = self.run_chain(session, links)?;
+        self.close_leftover(session, slot, &outcome);
+
+        let n = match outcome.find(Stage::Read) {
+            Some(Ok(n)) if outcome.ok(Stage::Open) => *n as usize,
+            _ => return Err(outcome.failure(path).expect("a stage failed").into()),
+        };
+        if !outcome.ok(Stage::Close) {
+            lock(&self.stats).close_failures += 1;
+        }
+        Ok(n)
+    }
+
+    /// Replace `path` with `data` with one linked open -> write -> fsync -> close chain and a rename
+    ///
+    /// The data goes to a temporary file next to `path` that is only renamed over it once it is
+    /// written, synced and closed, so readers see either the old or the new contents. A short write
+    /// counts as a failed write. If any stage fails the temporary file is removed again.
+    ///
+    /// The rename is a submit of its own: a failed fsync doesn't break a chain (the kernel posts its
+    /// error and goes on with the next link), a linked rename would put unsynced data in place.
+    ///
+    /// Err(e) -> `ReadError::ChainFailed`, which stage failed and which ones were canceled with it
+    #[allow(unused_doc_comments)]
+    pub fn write_file_atomic(&self, path: impl AsRef<Path>, data: &[u8]) -> io::Result<()> {
+        let path = path.as_ref();
+        let len = u32::try_from(data.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "write_file_atomic writes at most 4 GiB in one request",
+            )
+        })?;
+        let tmp = temp_path(path)?;
+        let (c_tmp, c_dst) = (c_path(&tmp)?, c_path(path)?);
+        /// Declared before the session, see `Session`
+        let slot = self.fixed_files()?.acquire();
+        let mut session = self.session();
+
+        let links = write_links(&c_tmp, slot.index, &data[..len as usize])?;
+        let outcome = self.run_write_links(&mut session, &slot, links, &c_tmp, &c_dst)?;
+        drop(session);
+
+        match outcome.failure(path) {
+            None => Ok(()),
+            Some(e) => {
+                let _ = fs::remove_file(&tmp);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// The chain of `write_file_atomic` once its links are built: run it, close what it left open and
+    /// rename `tmp` to `dst` if every stage succeeded
+    ///
+    /// The outcome has the rename as its last stage, `ChainAborted` if it was not issued.
+    fn run_write_links(
+        &self,
+        session: &mut Session<'_>,
+        slot: &Slot<'_>,
+        links: Vec<Link>,
+        tmp: &CString,
+        dst: &CString,
+    ) -> io::Result<Outcome> {
+        let mut outcome = self.run_chain(session, links)?;
+        self.close_leftover(session, slot, &outcome);
+
+        let renamed = match outcome
+            .results
+            .iter()
+            .find_map(|result| result.as_ref().err())
+        {
+            None => {
+                let cwd = types::Fd(libc::AT_FDCWD);
+                let rename = Link {
+                    stage: Stage::Rename,
+                    entry: opcode::RenameAt::new(cwd, tmp.as_ptr(), cwd, dst.as_ptr()).build(),
+                    expect: None,
+                };
+                let mut renamed = self.run_chain(session, vec![rename])?;
+                renamed.results.pop().expect("one result per stage")
+            }
+            Some(e) => Err(ReadError::Cancelled {
+                reason: Cancelled::ChainAborted {
+                    cause: e.raw_os_error().unwrap_or(0),
+                },
+            }
+            .into()),
+        };
+        outcome.stages.push(Stage::Rename);
+        outcome.results.push(renamed);
+        Ok(outcome)
+    }
+
+    /// Push a group of (linked) entries built by hand and submit it, one `io_uring_enter`
+    ///
+    /// The entries are pushed back to back in the order given, no request of another thread ends up in
+    /// between, so IO_LINK / IO_HARDLINK flags work as set. Their user_data is replaced by the crate's,
+    /// `wait_chain` hands the CQEs back by index. A chain longer than the submission queue fails with
+    /// `InvalidInput`.
+    ///
+    /// # Safety
+    ///
+    /// The entries go to the kernel as they are: every buffer, path, fd or struct they point to must
+    /// stay valid until `wait_chain` returned (or the token was dropped).
+    pub unsafe fn submit_chain(&self, entries: Vec<squeue::Entry>) -> io::Result<ChainToken<'_>> {
+        let len = entries.len();
+        let linked = entries.iter().map(soft_linked).collect();
+        let mut session = self.session();
+        session.push_group(
+            entries
+                .into_iter()
+                .enumerate()
+                .map(|(index, entry)| (index as u32, entry))
+                .collect(),
+        )?;
+        session.submit()?;
+        Ok(ChainToken {
+            session,
+            len,
+            linked,
+        })
+    }
+
+    /// Wait for every CQE of a chain of `submit_chain`, sorted by index
+    ///
+    /// One result per entry, canceled links included: `Completion::cancelled` says why, e.g.
+    /// `Cancelled::ChainAborted` behind a link that failed. Multishot entries bring all of their CQEs,
+    /// in the order they were posted.
+    pub fn wait_chain(&self, token: ChainToken<'_>) -> io::Result<Vec<ChainResult>> {
+        let ChainToken {
+            mut session,
+            len,
+            linked,
+        } = token;
+        if !std::ptr::eq(session.reader(), self) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the chain was submitted on another reader",
+            ));
+        }
+        let mut results = Vec::with_capacity(len);
+        while session.in_flight() > 0 {
+            let completion = session.next()?;
+            results.push(ChainResult {
+                index: (completion.user_data() & u64::from(u32::MAX)) as usize,
+                completion,
+            });
+        }
+        results.sort_by_key(|result| result.index);
+        let mut cqes: Vec<Completion> = results.iter().map(|result| result.completion).collect();
+        chain_aborted(
+            &mut cqes,
+            |cqe| (cqe.user_data() & u64::from(u32::MAX)) as usize,
+            &linked,
+        );
+        for (result, cqe) in results.iter_mut().zip(cqes) {
+            result.completion = cqe;
+        }
+        Ok(results)
+    }
+
+    /// Push a chain, wait for the CQE of every stage (canceled ones post -ECANCELED too)
+    fn run_chain(&self, session: &mut Session<'_>, links: Vec<Link>) -> io::Result<Outcome> {
+        let mut expect = Vec::with_capacity(links.len());
+        let mut stages = Vec::with_capacity(links.len());
+        let mut linked = Vec::with_capacity(links.len());
+        let mut entries = Vec::with_capacity(links.len());
+        for (slot, link) in links.into_iter().enumerate() {
+            linked.push(soft_linked(&link.entry));
+            entries.push((slot as u32, link.entry));
+            stages.push(link.stage);
+            expect.push(link.expect);
+        }
+        session.push_group(entries)?;
+        session.submit()?;
+
+        let slot_of = |cqe: &Completion| (cqe.user_data() & u64::from(u32::MAX)) as usize;
+        let mut cqes: Vec<Option<Completion>> = stages.iter().map(|_| None).collect();
+        while session.in_flight() > 0 {
+            let cqe = session.next()?;
+            cqes[slot_of(&cqe)] = Some(cqe);
+        }
+        let mut cqes: Vec<Completion> = cqes
+            .into_iter()
+            .map(|cqe| cqe.expect("every stage posted a CQE"))
+            .collect();
+        chain_aborted(&mut cqes, slot_of, &linked);
+
+        let results = cqes
+            .into_iter()
+            .enumerate()
+            .map(|(slot, cqe)| match (cqe.into_result(), expect[slot]) {
+                (Ok(n), Some(want)) if n != want => Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    format!("short {}: {n} of {want} bytes", stages[slot]),
+                )),
+                (result, _) => result,
+            })
+            .collect();
+        Ok(Outcome { stages, results })
+    }
+
+    /// A chain that opened the file but never got to its close leaves it in the slot, close it now
+    fn close_leftover(&self, session: &mut Session<'_>, slot: &Slot<'_>, outcome: &Outcome) {
+        if !outcome.ok(Stage::Open) || outcome.ok(Stage::Close) {
+            return;
+        }
+        let close_e = opcode::Close::new(types::Fixed(slot.index)).build();
+        if session.push(0, close_e).is_ok() && session.submit().is_ok() {
+            let _ = session.next();
+        }
+    }
+}
+
+/// The open -> read -> close chain of `read_linked`: `path` into the fixed file `slot`, all of
+/// `buf` read from its start
+fn read_links(path: &CString, slot: u32, buf: &mut [u8]) -> io::Result<Vec<Link>> {
+    let fixed = types::Fixed(slot);
+    Ok(vec![
+        Link {
+            stage: Stage::Open,
+            entry: opcode::OpenAt::new(types::Fd(libc::AT_FDCWD), path.as_ptr())
+                .flags(libc::O_RDONLY)
+                .file_index(Some(destination(slot)?))
+                .build()
+                .flags(squeue::Flags::IO_LINK),
+            expect: None,
+        },
+        Link {
+            stage: Stage::Read,
+            entry: opcode::Read::new(fixed, buf.as_mut_ptr(), buf.len() as u32)
+                .offset(0)
+                .build()
+                .flags(squeue::Flags::IO_HARDLINK),
+            expect: None,
+        },
+        Link {
+            stage: Stage::Close,
+            entry: opcode::Close::new(fixed).build(),
+            expect: None,
+        },
+    ])
+}
+
+/// The open -> write -> fsync -> close chain of `write_file_atomic`: `data` into `tmp` through the
+/// fixed file `slot`
+fn write_links(tmp: &CString, slot: u32, data: &[u8]) -> io::Result<Vec<Link>> {
+    let fixed = types::Fixed(slot);
+    let cwd = types::Fd(libc::AT_FDCWD);
+    let len = data.len() as u32;
+    Ok(vec![
+        Link {
+            stage: Stage::Open,
+            entry: opcode::OpenAt::new(cwd, tmp.as_ptr())
+                .flags(libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC)
+                .mode(0o666)
+                .file_index(Some(destination(slot)?))
+                .build()
+                .flags(squeue::Flags::IO_LINK),
+            expect: None,
+        },
+        Link {
+            stage: Stage::Write,
+            entry: opcode::Write::new(fixed, data.as_ptr(), len)
+                .offset(0)
+                .build()
+                .flags(squeue::Flags::IO_LINK),
+            expect: Some(len),
+        },
+        Link {
+            stage: Stage::Fsync,
+            entry: opcode::Fsync::new(fixed)
+                .build()
+                .flags(squeue::Flags::IO_LINK),
+            expect: None,
+        },
+        Link {
+            stage: Stage::Close,
+            entry: opcode::Close::new(fixed).build(),
+            expect: None,
+        },
+    ])
+}
+
+fn destination(slot: u32) -> io::Result<types::DestinationSlot> {
+    types::DestinationSlot::try_from_slot_target(slot)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "fixed file slot out of range"))
+}
+
+/// `dir/.name.<pid>.<n>.tmp`, unique per process and call
+fn temp_path(path: &Path) -> io::Result<PathBuf> {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut tmp = std::ffi::OsString::from(".");
+    tmp.push(name);
+    tmp.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    Ok(path.with_file_name(tmp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UringConfig;
+    use crate::files::partial;
+    use crate::files::tests::scratch_dir;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn failed_open_aborts_the_read_linked_behind_it() {
+        let reader = UringReader::new(UringConfig::default()).unwrap();
+        let missing = scratch_dir("chain-aborted").join("missing");
+
+        let e = reader.read_linked(&missing, 64).unwrap_err();
+        match ReadError::from_io(&e) {
+            Some(ReadError::ChainFailed {
+                stage,
+                source,
+                canceled,
+                ..
+            }) => {
+                assert_eq!(*stage, Stage::Open);
+                assert_eq!(source.raw_os_error(), Some(libc::ENOENT));
+                assert_eq!(canceled, &[Stage::Read, Stage::Close]);
+            }
+            other => panic!("expected ChainFailed, got {other:?} ({e})"),
+        }
+
+        // The same chain by hand, to see what each stage got
+        let c_path = c_path(&missing).unwrap();
+        let mut buf = vec![0u8; 64];
+        let slot = reader.fixed_files().unwrap().acquire();
+        let mut session = reader.session();
+        let links = read_links(&c_path, slot.index, &mut buf).unwrap();
+        let outcome = reader.run_chain(&mut session, links).unwrap();
+        drop(session);
+
+        let read = outcome.find(Stage::Read).unwrap().as_ref().unwrap_err();
+        assert!(matches!(
+            ReadError::from_io(read),
+            Some(ReadError::Cancelled {
+                reason: Cancelled::ChainAborted {
+                    cause: libc::ENOENT
+                }
+            })
+        ));
+        let results = outcome
+            .results
+            .into_iter()
+            .map(|result| result.map(|_| Vec::new()))
+            .collect();
+        let stages = [&missing; 3];
+        let counted = partial(
+            results,
+            &stages,
+            Instant::now() + Duration::from_secs(60),
+            |r| r,
+        );
+        // The kernel cancels the close too: it is hard linked to the read, which never ran
+        assert_eq!(
+            (counted.finished, counted.cancelled, counted.failed),
+            (0, 2, 1)
+        );
+    }
+
+    /// The stage, errno and canceled stages of the `ChainFailed` in `e`
+    fn attribution(e: &io::Error) -> (Stage, Option<i32>, Vec<Stage>) {
+        match ReadError::from_io(e) {
+            Some(ReadError::ChainFailed {
+                stage,
+                source,
+                canceled,
+                ..
+            }) => (*stage, source.raw_os_error(), canceled.clone()),
+            other => panic!("expected ChainFailed, got {other:?} ({e})"),
+        }
+    }
+
+    #[test]
+    fn read_linked_of_a_directory_fails_its_read() {
+        let reader = UringReader::new(UringConfig::default()).unwrap();
+        let dir = scratch_dir("chain-read");
+
+        let e = reader.read_linked(&dir, 64).unwrap_err();
+        // The close is hard linked, it still ran (and closed what the open installed)
+        assert_eq!(attribution(&e), (Stage::Read, Some(libc::EISDIR), vec![]));
+        assert_eq!(reader.stats().close_failures, 0);
+    }
+
+    #[test]
+    fn write_file_atomic_into_a_missing_directory_fails_its_open() {
+        let reader = UringReader::new(UringConfig::default()).unwrap();
+        let path = scratch_dir("chain-open").join("missing").join("file");
+
+        let e = reader.write_file_atomic(&path, b"data").unwrap_err();
+        assert_eq!(
+            attribution(&e),
+            (
+                Stage::Open,
+                Some(libc::ENOENT),
+                vec![Stage::Write, Stage::Fsync, Stage::Close, Stage::Rename]
+            )
+        );
+    }
+
+    #[test]
+    fn write_file_atomic_over_a_directory_fails_its_rename() {
+        let reader = UringReader::new(UringConfig::default()).unwrap();
+        let dir = scratch_dir("chain-rename");
+        let path = dir.join("taken");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("inside"), b"keep").unwrap();
+
+        let e = reader.write_file_atomic(&path, b"data").unwrap_err();
+        let (stage, errno, canceled) = attribution(&e);
+        assert_eq!((stage, canceled), (Stage::Rename, vec![]));
+        assert!(matches!(errno, Some(libc::EISDIR | libc::ENOTEMPTY)), "{e}");
+
+        // Nothing of the attempt is left: the directory as it was, no temporary file next to it
+        assert_eq!(fs::read(path.join("inside")).unwrap(), b"keep");
+        let names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["taken"]);
+    }
+
+    /// The chain of `write_file_atomic` of `dir/file`, changed by `tweak` first
+    ///
+    /// `tweak` gets the slot of the chain and one that stays empty.
+    fn write_tweaked(
+        reader: &UringReader,
+        dir: &Path,
+        tweak: impl FnOnce(&mut [Link], u32, u32),
+    ) -> (Outcome, PathBuf) {
+        let (tmp, path) = (dir.join(".file.tmp"), dir.join("file"));
+        let (c_tmp, c_dst) = (c_path(&tmp).unwrap(), c_path(&path).unwrap());
+        let data = b"data";
+        let slot = reader.fixed_files().unwrap().acquire();
+        let empty = reader.fixed_files().unwrap().acquire();
+        let mut session = reader.session();
+        let mut links = write_links(&c_tmp, slot.index, data).unwrap();
+        tweak(&mut links, slot.index, empty.index);
+        let outcome = reader
+            .run_write_links(&mut session, &slot, links, &c_tmp, &c_dst)
+            .unwrap();
+        drop(session);
+        (outcome, path)
+    }
+
+    #[test]
+    fn write_links_of_a_file_without_fsync_fail_their_fsync() {
+        let reader = UringReader::new(UringConfig::default()).unwrap();
+        let dir = scratch_dir("chain-fsync");
+
+        // /dev/null takes the write but has no fsync (EINVAL). The tmp file does exist, renaming it
+        // over `file` would work
+        fs::write(dir.join(".file.tmp"), b"old").unwrap();
+        let c_null = c_path(Path::new("/dev/null")).unwrap();
+        let (outcome, path) = write_tweaked(&reader, &dir, |links, slot, _| {
+            links[0].entry = opcode::OpenAt::new(types::Fd(libc::AT_FDCWD), c_null.as_ptr())
+                .flags(libc::O_WRONLY)
+                .file_index(Some(destination(slot).unwrap()))
+                .build()
+                .flags(squeue::Flags::IO_LINK);
+        });
+        // The kernel doesn't break the chain at a failed fsync, the close still ran
+        assert!(outcome.ok(Stage::Close));
+        let e: io::Error = outcome.failure(&path).unwrap().into();
+        assert_eq!(
+            attribution(&e),
+            (Stage::Fsync, Some(libc::EINVAL), vec![Stage::Rename])
+        );
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn write_links_with_a_failed_close_are_not_renamed() {
+        let reader = UringReader::new(UringConfig::default()).unwrap();
+        let dir = scratch_dir("chain-close");
+
+        // The close goes to a slot nothing was opened into (EBADF), the file stays in its own slot
+        // until `close_leftover`
+        let (outcome, path) = write_tweaked(&reader, &dir, |links, _, empty| {
+            links[3].entry = opcode::Close::new(types::Fixed(empty)).build();
+        });
+        assert!(outcome.ok(Stage::Fsync));
+        let e: io::Error = outcome.failure(&path).unwrap().into();
+        assert_eq!(
+            attribution(&e),
+            (Stage::Close, Some(libc::EBADF), vec![Stage::Rename])
+        );
+        assert!(!path.exists());
+        assert_eq!(fs::read(dir.join(".file.tmp")).unwrap(), b"data");
+    }
+
+    #[test]
+    fn read_linked_with_a_failed_close_returns_the_data() {
+        let reader = UringReader::new(UringConfig::default()).unwrap();
+        let path = scratch_dir("chain-close-read").join("file");
+        fs::write(&path, b"contents").unwrap();
+
+        let c_path = c_path(&path).unwrap();
+        let mut buf = vec![0u8; 64];
+        let slot = reader.fixed_files().unwrap().acquire();
+        let empty = reader.fixed_files().unwrap().acquire();
+        let mut session = reader.session();
+        let mut links = read_links(&c_path, slot.index, &mut buf).unwrap();
+        links[2].entry = opcode::Close::new(types::Fixed(empty.index)).build();
+        let n = reader
+            .run_read_links(&mut session, &slot, links, &path)
+            .unwrap();
+        drop(session);
+
+        assert_eq!(&buf[..n], b"contents");
+        assert_eq!(reader.stats().close_failures, 1);
+        // `close_leftover` emptied the slot of the file: the next chain can open into it
+        drop((slot, empty));
+        assert_eq!(reader.read_linked(&path, 64).unwrap(), b"contents");
+        assert_eq!(reader.stats().close_failures, 1);
+    }
+}
@@ -0,0 +1,617 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, Read};
+use std::path::{Path, PathBuf};
+#[cfg(target_os = "linux")]
+use std::sync::{Mutex, mpsc};
+
+use crate::walk::walk_files;
+use crate::{UringFile, UringReader};
+
+/// Hash algorithms `checksum_tree` can use, each one behind its own feature
+/// - Xxh3 -> 128 bit XXH3 (feature `xxh3`, on by default), fast, not cryptographic
+/// - Sha256 -> SHA-256 (feature `sha256`), when the manifest has to stand up against tampering
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum HashAlgo {
+    #[cfg(feature = "xxh3")]
+    Xxh3,
+    #[cfg(feature = "sha256")]
+    Sha256,
+}
+
+/// The digest of one file, `Display` prints it as lowercase hex
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Digest {
+    algo: HashAlgo,
+    bytes: Vec<u8>,
+}
+
+impl Digest {
+    /// A digest you already know (e.g. from a content-addressed store), `bytes` as the hash emits them
+    pub fn new(algo: HashAlgo, bytes: impl Into<Vec<u8>>) -> Self {
+        Digest {
+            algo,
+            bytes: bytes.into(),
+        }
+    }
+
+    /// `Digest::new` from the lowercase or uppercase hex `Display` prints, None if it isn't hex
+    pub fn from_hex(algo: HashAlgo, hex: &str) -> Option<Self> {
+        if !hex.len().is_multiple_of(2) {
+            return None;
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        Some(Digest::new(algo, bytes))
+    }
+
+    pub fn algo(&self) -> HashAlgo {
+        self.algo
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.bytes.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+/// The digest of one file hashed chunk by chunk (`UringReader::hash_file_parallel`)
+/// - digest -> the result, only comparable with digests of the same `algo` and `chunk_size`
+/// - chunk_size -> Some(size) for the tree mode, None if the algorithm was hashed sequentially
+///   (`digest` is then the plain hash of the file)
+/// - chunks -> leaves of the tree, 0 for the sequential mode
+/// - len -> bytes hashed
+///
+/// The tree mode hashes every `chunk_size` piece on its own (the leaves, the last one may be short),
+/// then hashes `chunk_size` and `len` (8 bytes little endian each) followed by every leaf digest in
+/// file order. That is what makes it parallel, and also why it is not the digest `sha256sum` prints.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TreeDigest {
+    pub digest: Digest,
+    pub chunk_size: Option<usize>,
+    pub chunks: u64,
+    pub len: u64,
+}
+
+impl HashAlgo {
+    /// Whether the algorithm has the chunked tree mode of `hash_file_parallel`, all of them so far
+    pub fn is_tree_hashable(self) -> bool {
+        match self {
+            #[cfg(feature = "xxh3")]
+            HashAlgo::Xxh3 => true,
+            #[cfg(feature = "sha256")]
+            HashAlgo::Sha256 => true,
+        }
+    }
+}
+
+/// Running state of one hash
+enum Hasher {
+    #[cfg(feature = "xxh3")]
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+    #[cfg(feature = "sha256")]
+    Sha256(sha2::Sha256),
+}
+
+impl Hasher {
+    fn new(algo: HashAlgo) -> Self {
+        match algo {
+            #[cfg(feature = "xxh3")]
+            HashAlgo::Xxh3 => Hasher::Xxh3(Box::default()),
+            #[cfg(feature = "sha256")]
+            HashAlgo::Sha256 => Hasher::Sha256(sha2::Digest::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            #[cfg(feature = "xxh3")]
+            Hasher::Xxh3(h) => h.update(data),
+            #[cfg(feature = "sha256")]
+            Hasher::Sha256(h) => sha2::Digest::update(h, data),
+        }
+    }
+
+    fn finish(self) -> Digest {
+        match self {
+            #[cfg(feature = "xxh3")]
+            Hasher::Xxh3(h) => Digest {
+                algo: HashAlgo::Xxh3,
+                bytes: h.digest128().to_be_bytes().to_vec(),
+            },
+            #[cfg(feature = "sha256")]
+            Hasher::Sha256(h) => Digest {
+                algo: HashAlgo::Sha256,
+                bytes: sha2::Digest::finalize(h).to_vec(),
+            },
+        }
+    }
+}
+
+/// Why `read_verified` returned no data
+/// - Io -> opening or reading failed, nothing was compared
+/// - ChecksumMismatch -> everything was read but hashes to `actual`, not `expected`. `data` is what was
+///   read, for quarantine or a closer look, never hand it out as the blob.
+#[derive(Debug)]
+pub enum VerifyError {
+    Io(io::Error),
+    ChecksumMismatch {
+        expected: Digest,
+        actual: Digest,
+        data: Vec<u8>,
+    },
+}
+
+impl VerifyError {
+    /// The data of a `ChecksumMismatch`, None for `Io`
+    pub fn into_data(self) -> Option<Vec<u8>> {
+        match self {
+            VerifyError::Io(_) => None,
+            VerifyError::ChecksumMismatch { data, .. } => Some(data),
+        }
+    }
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::Io(e) => e.fmt(f),
+            VerifyError::ChecksumMismatch {
+                expected,
+                actual,
+                data,
+            } => write!(
+                f,
+                "checksum mismatch: expected {expected}, the {} bytes read hash to {actual}",
+                data.len()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VerifyError::Io(e) => Some(e),
+            VerifyError::ChecksumMismatch { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for VerifyError {
+    fn from(e: io::Error) -> Self {
+        VerifyError::Io(e)
+    }
+}
+
+/// A mismatch becomes `io::ErrorKind::InvalidData` (with the `VerifyError` inside), for `?` in code
+/// that only deals in `io::Result`
+impl From<VerifyError> for io::Error {
+    fn from(e: VerifyError) -> Self {
+        match e {
+            VerifyError::Io(e) => e,
+            mismatch => io::Error::new(io::ErrorKind::InvalidData, mismatch),
+        }
+    }
+}
+
+/// The leaf hashes of a tree digest, chunk by chunk in file order
+struct Tree {
+    algo: HashAlgo,
+    chunk_size: usize,
+    leaves: Vec<Option<Vec<u8>>>,
+    len: u64,
+}
+
+impl Tree {
+    fn new(algo: HashAlgo, chunk_size: usize) -> Self {
+        Tree {
+            algo,
+            chunk_size,
+            leaves: Vec::new(),
+            len: 0,
+        }
+    }
+
+    fn leaf(algo: HashAlgo, chunk: &[u8]) -> Vec<u8> {
+        let mut hasher = Hasher::new(algo);
+        hasher.update(chunk);
+        hasher.finish().bytes
+    }
+
+    /// Put the leaf of chunk `index`, in any order
+    fn insert(&mut self, index: usize, leaf: Vec<u8>) {
+        if self.leaves.len() <= index {
+            self.leaves.resize(index + 1, None);
+        }
+        self.leaves[index] = Some(leaf);
+    }
+
+    fn finish(self) -> TreeDigest {
+        let mut root = Hasher::new(self.algo);
+        root.update(&(self.chunk_size as u64).to_le_bytes());
+        root.update(&self.len.to_le_bytes());
+        for leaf in &self.leaves {
+            root.update(leaf.as_deref().expect("every chunk has a leaf"));
+        }
+        TreeDigest {
+            digest: root.finish(),
+            chunk_size: Some(self.chunk_size),
+            chunks: self.leaves.len() as u64,
+            len: self.len,
+        }
+    }
+}
+
+/// Fill `buf` from `file` as far as it goes, less only at EOF
+fn read_full(file: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// A file that is being hashed
+struct Hashing<'r> {
+    index: usize,
+    file: UringFile<'r>,
+    hasher: Hasher,
+}
+
+impl UringReader {
+    /// Read a whole file and check it against the digest it is supposed to have
+    ///
+    /// The file is streamed through a `UringFile` (one chunk in flight while the previous one is
+    /// hashed), each chunk goes through the hasher as it arrives and is appended to the result. Only
+    /// after the last one the digest is compared, the data is never handed out unverified.
+    ///
+    /// Err(VerifyError::Io(e)) -> open/read failed (stops at the first error), `ReadError::FileTooLarge`,
+    /// or `InvalidInput` if `expected` is not an `algo` digest
+    /// Err(VerifyError::ChecksumMismatch { .. }) -> the content is not what `expected` says
+    pub fn read_verified(
+        &self,
+        path: impl AsRef<Path>,
+        expected: &Digest,
+        algo: HashAlgo,
+    ) -> Result<Vec<u8>, VerifyError> {
+        if expected.algo != algo {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("expected digest is {:?}, not {algo:?}", expected.algo),
+            )
+            .into());
+        }
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        self.check_size(path, size)?;
+        let mut file = self.uring_file(file)?;
+
+        let mut data = Vec::with_capacity(size as usize);
+        let mut hasher = Hasher::new(algo);
+        loop {
+            let chunk = file.fill_buf()?;
+            if chunk.is_empty() {
+                break;
+            }
+            hasher.update(chunk);
+            data.extend_from_slice(chunk);
+            let len = chunk.len();
+            file.consume(len);
+            self.check_size(path, data.len() as u64)?;
+        }
+
+        let actual = hasher.finish();
+        if actual != *expected {
+            return Err(VerifyError::ChecksumMismatch {
+                expected: expected.clone(),
+                actual,
+                data,
+            });
+        }
+        Ok(data)
+    }
+
+    /// Hash every regular file below `root`, sorted by path
+    ///
+    /// Ok(entries) -> (path, digest or error) per file, plus an error entry for every directory that
+    /// could not be listed
+    /// Err(e) -> `root` itself could not be listed
+    ///
+    /// Files are streamed through `UringFile`s, one chunk at a time, nothing is held in memory as a
+    /// whole (`max_bytes` does not apply). Up to `UringConfig::max_in_flight` files are open at once,
+    /// each with a read in flight, and are hashed round robin, so the disk always has work while the
+    /// CPU hashes.
+    pub fn checksum_tree(
+        &self,
+        root: impl AsRef<Path>,
+        algo: HashAlgo,
+    ) -> io::Result<Vec<(PathBuf, io::Result<Digest>)>> {
+        let walk = walk_files(root.as_ref())?;
+        let mut results: Vec<Option<io::Result<Digest>>> =
+            walk.files.iter().map(|_| None).collect();
+
+        let mut paths = walk.files.iter().enumerate();
+        let mut active: VecDeque<Hashing<'_>> = VecDeque::new();
+        loop {
+            while active.len() < self.config.max_in_flight {
+                let Some((index, path)) = paths.next() else {
+                    break;
+                };
+                match self.open_file(path) {
+                    Ok(file) => active.push_back(Hashing {
+                        index,
+                        file,
+                        hasher: Hasher::new(algo),
+                    }),
+                    Err(e) => results[index] = Some(Err(e)),
+                }
+            }
+            let Some(mut hashing) = active.pop_front() else {
+                break;
+            };
+
+            match hashing.file.fill_buf() {
+                Ok([]) => results[hashing.index] = Some(Ok(hashing.hasher.finish())),
+                Ok(chunk) => {
+                    let len = chunk.len();
+                    hashing.hasher.update(chunk);
+                    hashing.file.consume(len);
+                    active.push_back(hashing);
+                }
+                Err(e) => results[hashing.index] = Some(Err(e)),
+            }
+        }
+
+        let mut entries: Vec<_> = walk
+            .files
+            .into_iter()
+            .zip(results)
+            .map(|(path, result)| (path, result.expect("every file has a result")))
+            .collect();
+        entries.extend(walk.errors.into_iter().map(|(path, e)| (path, Err(e))));
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(entries)
+    }
+
+    /// Hash one big file with the chunks hashed in parallel, see `TreeDigest` for the digest
+    ///
+    /// Up to `queue_depth` chunk reads are in flight. Each chunk is hashed by one of `workers` threads
+    /// (0 -> one per CPU) as soon as its read completes, whatever its place in the file, while the
+    /// next reads are already issued: the disk and the CPUs stay busy together. The leaves are
+    /// combined at the end, the digest only depends on the content and `chunk_size`, not on the
+    /// order the reads completed in. At most `queue_depth` + `workers` chunk buffers exist at once,
+    /// `max_bytes` doesn't apply.
+    ///
+    /// The file is hashed up to the size it had when it was opened. Files without a size (`/proc`,
+    /// pipes), algorithms without a tree mode (`HashAlgo::is_tree_hashable`) and the `std::fs`
+    /// backend are hashed on the calling thread instead, files with the same tree digest.
+    pub fn hash_file_parallel(
+        &self,
+        path: impl AsRef<Path>,
+        algo: HashAlgo,
+        chunk_size: usize,
+        workers: usize,
+    ) -> io::Result<TreeDigest> {
+        let chunk_size = chunk_size.clamp(1, u32::MAX as usize);
+        let file = File::open(path)?;
+        if !algo.is_tree_hashable() {
+            let mut file = self.uring_file(file)?;
+            let mut hasher = Hasher::new(algo);
+            let mut len = 0;
+            loop {
+                let chunk = file.fill_buf()?;
+                if chunk.is_empty() {
+                    break;
+                }
+                hasher.update(chunk);
+                let n = chunk.len();
+                len += n as u64;
+                file.consume(n);
+            }
+            return Ok(TreeDigest {
+                digest: hasher.finish(),
+                chunk_size: None,
+                chunks: 0,
+                len,
+            });
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            let size = file.metadata()?.len();
+            if size > 0 {
+                let workers = match workers {
+                    0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+                    n => n,
+                };
+                return self.hash_tree_parallel(&file, size, algo, chunk_size, workers);
+            }
+        }
+        let _ = workers;
+        let mut file = self.uring_file(file)?;
+        let mut tree = Tree::new(algo, chunk_size);
+        let mut buf = vec![0u8; chunk_size];
+        for index in 0.. {
+            let n = read_full(&mut file, &mut buf)?;
+            if n == 0 {
+                break;
+            }
+            tree.insert(index, Tree::leaf(algo, &buf[..n]));
+            tree.len += n as u64;
+        }
+        Ok(tree.finish())
+    }
+
+    /// The ring and worker side of `hash_file_parallel` for `size` bytes of `file`
+    #[cfg(target_os = "linux")]
+    fn hash_tree_parallel(
+        &self,
+        file: &File,
+        size: u64,
+        algo: HashAlgo,
+        chunk_size: usize,
+        workers: usize,
+    ) -> io::Result<TreeDigest> {
+        let (jobs, queue) = mpsc::channel::<(usize, Vec<u8>)>();
+        let queue = Mutex::new(queue);
+        let (hashed, leaves) = mpsc::channel::<(usize, Vec<u8>, Vec<u8>)>();
+        let mut tree = Tree::new(algo, chunk_size);
+        tree.len = size;
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                let (queue, hashed) = (&queue, hashed.clone());
+                scope.spawn(move || {
+                    loop {
+                        let job = queue
+                            .lock()
+                            .unwrap_or_else(|poisoned| poisoned.into_inner())
+                            .recv();
+                        let Ok((index, buf)) = job else {
+                            break;
+                        };
+                        let leaf = Tree::leaf(algo, &buf);
+                        if hashed.send((index, leaf, buf)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(hashed);
+            // Dropping `jobs` when the reads are done (or failed) lets the workers run dry and exit
+            let fed = self.feed_tree(file, size, chunk_size, workers, jobs, &leaves, &mut tree);
+            fed.map(|()| tree.finish())
+        })
+    }
+
+    /// Read every chunk of `file` into a free buffer and hand it to the workers, until all leaves
+    /// came back
+    #[cfg(target_os = "linux")]
+    #[allow(clippy::too_many_arguments, unused_doc_comments)]
+    fn feed_tree(
+        &self,
+        file: &File,
+        size: u64,
+        chunk_size: usize,
+        workers: usize,
+        jobs: mpsc::Sender<(usize, Vec<u8>)>,
+        leaves: &mpsc::Receiver<(usize, Vec<u8>, Vec<u8>)>,
+        tree: &mut Tree,
+    ) -> io::Result<()> {
+        use io_uring::{opcode, types};
+        use std::os::unix::io::AsRawFd;
+
+        use crate::reader::is_retryable;
+
+        /// One chunk read in flight: its index, its buffer, bytes read so far
+        struct Pending {
+            index: usize,
+            buf: Vec<u8>,
+            done: usize,
+        }
+        let fd = types::Fd(file.as_raw_fd());
+        let read = |chunk_size: u64, pending: &mut Pending| {
+            let start = pending.index as u64 * chunk_size;
+            let rest = &mut pending.buf[pending.done..];
+            opcode::Read::new(fd, rest.as_mut_ptr(), rest.len() as u32)
+                .offset(start + pending.done as u64)
+                .build()
+        };
+        let gone = || io::Error::other("a hash worker exited");
+
+        let chunks = size.div_ceil(chunk_size as u64) as usize;
+        let depth = self.depth().max(1);
+        let mut free: Vec<Vec<u8>> = Vec::new();
+        let mut allocated = 0;
+        /// Declared before the session: the kernel writes into the buffers in here until it's dropped
+        let mut slots: Vec<Option<Pending>> = (0..depth).map(|_| None).collect();
+        let mut session = self.session();
+        let mut next = 0;
+        let mut collected = 0;
+        let collect = |tree: &mut Tree, free: &mut Vec<Vec<u8>>, (index, leaf, buf)| {
+            tree.insert(index, leaf);
+            free.push(buf);
+        };
+
+        while collected < chunks {
+            let mut pushed = false;
+            while next < chunks {
+                let Some(slot) = slots.iter().position(Option::is_none) else {
+                    break;
+                };
+                let len = (chunk_size as u64).min(size - next as u64 * chunk_size as u64) as usize;
+                let mut buf = match free.pop() {
+                    Some(buf) => buf,
+                    None if allocated < depth + workers => {
+                        allocated += 1;
+                        Vec::with_capacity(chunk_size)
+                    }
+                    None => break,
+                };
+                buf.resize(len, 0);
+                let pending = slots[slot].insert(Pending {
+                    index: next,
+                    buf,
+                    done: 0,
+                });
+                session.push(slot as u32, read(chunk_size as u64, pending))?;
+                next += 1;
+                pushed = true;
+            }
+            while let Ok(leaf) = leaves.try_recv() {
+                collect(tree, &mut free, leaf);
+                collected += 1;
+            }
+            if session.in_flight() == 0 {
+                /// Every buffer is with the workers, wait for one of them
+                if collected < chunks {
+                    let leaf = leaves.recv().map_err(|_| gone())?;
+                    collect(tree, &mut free, leaf);
+                    collected += 1;
+                }
+                continue;
+            }
+            if pushed {
+                session.submit()?;
+            }
+
+            let cqe = session.next()?;
+            let slot = cqe.user_data() as u32 as usize;
+            let pending = slots[slot].as_mut().expect("a CQE for a pending chunk");
+            match cqe.into_result() {
+                Err(e) if is_retryable(&e) => {}
+                Err(e) => return Err(e),
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "the file got shorter while it was hashed",
+                    ));
+                }
+                Ok(n) => pending.done += n as usize,
+            }
+            if pending.done < pending.buf.len() {
+                session.push(slot as u32, read(chunk_size as u64, pending))?;
+                session.submit()?;
+                continue;
+            }
+            let Pending { index, buf, .. } = slots[slot].take().expect("just looked at it");
+            jobs.send((index, buf)).map_err(|_| gone())?;
+        }
+        Ok(())
+    }
+}
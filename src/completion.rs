@@ -0,0 +1,166 @@
+/// cqueue -> the completion side of the ring, `cqueue::Entry` is the raw CQE the kernel hands back
+#[cfg(target_os = "linux")]
+use io_uring::cqueue;
+
+use crate::error::{Cancelled, ReadError};
+use crate::timing::RequestTiming;
+
+/// A decoded CQE (Completion Queue Entry)
+///
+/// The raw `cqueue::Entry` only lives as long as we are iterating the completion queue, this is the owned copy
+/// of everything the kernel told us about one request:
+/// - user_data -> the tag we attached to the SQE, so we know which request finished
+/// - result -> bytes transferred (>= 0) or -errno (< 0)
+/// - flags -> extra information from the kernel (multishot still armed, provided buffer id, ...)
+///
+/// This is the single place where the CQE flags word is decoded. Everything that needs to know about
+/// `F_MORE` or buffer ids should ask the `Completion` instead of looking at the ring again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Completion {
+    user_data: u64,
+    result: i32,
+    flags: u32,
+    pub(crate) timing: Option<RequestTiming>,
+    /// Set on an -ECANCELED result the crate knows the reason of
+    cancelled: Option<Cancelled>,
+}
+
+impl Completion {
+    /// Build a completion from its raw parts
+    ///
+    /// Mostly useful for callers that need to fabricate completions (e.g. tests of their own code)
+    pub fn new(user_data: u64, result: i32, flags: u32) -> Self {
+        Completion {
+            user_data,
+            result,
+            flags,
+            timing: None,
+            cancelled: None,
+        }
+    }
+
+    /// The same completion with another result, what an injected fault turns it into
+    #[cfg(all(target_os = "linux", feature = "failpoints"))]
+    pub(crate) fn with_result(self, result: i32) -> Self {
+        Completion { result, ..self }
+    }
+
+    /// The same completion, canceled for `reason`
+    #[cfg(target_os = "linux")]
+    pub(crate) fn with_cancelled(self, reason: Cancelled) -> Self {
+        Completion {
+            cancelled: Some(reason),
+            ..self
+        }
+    }
+
+    /// The tag that was set with `.user_data(...)` on the SQE
+    pub fn user_data(&self) -> u64 {
+        self.user_data
+    }
+
+    /// The raw result, exactly as the kernel reported it
+    /// - res >= 0 -> operation specific value (bytes read for a read)
+    /// - res < 0 -> -errno
+    pub fn result(&self) -> i32 {
+        self.result
+    }
+
+    /// The raw flags word
+    ///
+    /// NOTE: Every bit is passed through untouched, including the ones this crate does not know about yet.
+    /// Newer kernels can add flags and callers can still look at them here.
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// `IORING_CQE_F_MORE` -> the request is still armed and more completions will follow for the same SQE
+    /// (multishot operations)
+    pub fn is_more(&self) -> bool {
+        cqueue::more(self.flags)
+    }
+
+    /// `IORING_CQE_F_BUFFER` -> the kernel picked a provided buffer for this request, this is its id
+    pub fn buffer_id(&self) -> Option<u16> {
+        cqueue::buffer_select(self.flags)
+    }
+
+    /// `IORING_CQE_F_BUF_MORE` -> the provided buffer is only partially consumed and will be used again
+    pub fn is_buffer_more(&self) -> bool {
+        cqueue::buffer_more(self.flags)
+    }
+
+    /// `IORING_CQE_F_SOCK_NONEMPTY` -> the socket still has data that can be read right away
+    pub fn is_sock_nonempty(&self) -> bool {
+        cqueue::sock_nonempty(self.flags)
+    }
+
+    /// `IORING_CQE_F_NOTIF` -> this is a zero-copy send notification, not the result of the send itself
+    pub fn is_notif(&self) -> bool {
+        cqueue::notif(self.flags)
+    }
+
+    /// Queue time vs. kernel time of this request, `None` unless `UringConfig::record_timings` is on
+    pub fn timing(&self) -> Option<RequestTiming> {
+        self.timing
+    }
+
+    /// Why the request was canceled, for an -ECANCELED result of a cancel the crate did itself
+    /// (None for everything else, `result` is still -ECANCELED)
+    pub fn cancelled(&self) -> Option<Cancelled> {
+        self.cancelled
+    }
+
+    /// Turn the raw result into a Rust result
+    /// Ok(n) -> the non-negative result
+    /// Err(e) -> `ReadError::Cancelled` for a cancel with a known reason, the OS error for -res
+    ///   otherwise
+    pub fn into_result(self) -> std::io::Result<u32> {
+        if let Some(reason) = self.cancelled {
+            return Err(ReadError::Cancelled { reason }.into());
+        }
+        if self.result < 0 {
+            return Err(std::io::Error::from_raw_os_error(-self.result));
+        }
+        Ok(self.result as u32)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<cqueue::Entry> for Completion {
+    fn from(cqe: cqueue::Entry) -> Self {
+        Completion::new(cqe.user_data(), cqe.result(), cqe.flags())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<&cqueue::Entry> for Completion {
+    fn from(cqe: &cqueue::Entry) -> Self {
+        Completion::new(cqe.user_data(), cqe.result(), cqe.flags())
+    }
+}
+
+/// The flag bits of the kernel ABI, for targets without the io_uring crate. Nothing produces CQEs
+/// there, but `Completion` keeps working for callers that build them by hand.
+#[cfg(not(target_os = "linux"))]
+mod cqueue {
+    pub(super) fn buffer_select(flags: u32) -> Option<u16> {
+        (flags & 1 != 0).then_some((flags >> 16) as u16)
+    }
+
+    pub(super) fn more(flags: u32) -> bool {
+        flags & (1 << 1) != 0
+    }
+
+    pub(super) fn sock_nonempty(flags: u32) -> bool {
+        flags & (1 << 2) != 0
+    }
+
+    pub(super) fn notif(flags: u32) -> bool {
+        flags & (1 << 3) != 0
+    }
+
+    pub(super) fn buffer_more(flags: u32) -> bool {
+        flags & (1 << 4) != 0
+    }
+}
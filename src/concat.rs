@@ -0,0 +1,132 @@
+use io_uring::types;
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use crate::error::ReadError;
+use crate::files::Region;
+use crate::reader::UringReader;
+
+/// One opened part and the size `statx` reported for it
+struct Part {
+    file: File,
+    size: usize,
+}
+
+impl UringReader {
+    /// Read `paths` back to back into one buffer, in the order given (shard reassembly)
+    ///
+    /// Every part is opened and `statx`ed first, that gives the total size and where each part starts.
+    /// The buffer is allocated once, then the chunk reads of all parts share the ring and land right at
+    /// their final position, no copies. Parts are taken at the size `statx` reports, so a file without
+    /// a size (`/proc`, a pipe) counts as empty and a part that grows meanwhile is cut at its old size.
+    ///
+    /// Err(e) -> `ReadError::PartFailed` naming the first part that failed: missing, unreadable, or
+    /// shorter than `statx` said when it was read (a misaligned concatenation is never returned).
+    /// `ReadError::FileTooLarge` if the total is over `max_bytes`.
+    pub fn read_concat<P: AsRef<Path>>(&self, paths: &[P]) -> io::Result<Vec<u8>> {
+        let parts = self.open_parts(paths, 0)?;
+        let total: usize = parts.iter().map(|part| part.size).sum();
+        let mut data = vec![0u8; total];
+        self.read_parts(paths, 0, &parts, &mut data)?;
+        Ok(data)
+    }
+
+    /// `read_concat` into a writer instead of one big buffer, returns the bytes written
+    ///
+    /// The parts go in windows of `UringConfig::max_in_flight` parts: one window is read (in parallel,
+    /// like `read_concat`) while nothing is written, then written out in order, so memory stays at
+    /// about the size of the biggest window. A failing part fails the call, what came before it may
+    /// already be written.
+    pub fn read_concat_to<P: AsRef<Path>>(
+        &self,
+        paths: &[P],
+        out: &mut impl Write,
+    ) -> io::Result<u64> {
+        let mut buf = Vec::new();
+        let mut written = 0u64;
+        for (w, window) in paths.chunks(self.config.max_in_flight).enumerate() {
+            let first = w * self.config.max_in_flight;
+            let parts = self.open_parts(window, first)?;
+            buf.clear();
+            buf.resize(parts.iter().map(|part| part.size).sum(), 0);
+            self.read_parts(window, first, &parts, &mut buf)?;
+            out.write_all(&buf)?;
+            written += buf.len() as u64;
+        }
+        Ok(written)
+    }
+
+    /// Open and size every part, `first` is the index of `paths[0]` in the whole call (for errors)
+    fn open_parts<P: AsRef<Path>>(&self, paths: &[P], first: usize) -> io::Result<Vec<Part>> {
+        let mut total = 0u64;
+        let mut parts = Vec::with_capacity(paths.len());
+        for (i, path) in paths.iter().enumerate() {
+            let path = path.as_ref();
+            let part = File::open(path)
+                .and_then(|file| {
+                    let size = self.statx_fd(types::Fd(file.as_raw_fd()))?.stx_size;
+                    Ok((file, size))
+                })
+                .map_err(|e| part_failed(first + i, path, e))?;
+            total += part.1;
+            self.check_size(path, total)?;
+            parts.push(Part {
+                file: part.0,
+                size: part.1 as usize,
+            });
+        }
+        Ok(parts)
+    }
+
+    /// Read every part into its slice of `data`, which is exactly as long as all parts together
+    fn read_parts<P: AsRef<Path>>(
+        &self,
+        paths: &[P],
+        first: usize,
+        parts: &[Part],
+        data: &mut [u8],
+    ) -> io::Result<()> {
+        let mut rest = data;
+        let mut regions = Vec::with_capacity(parts.len());
+        for part in parts {
+            let (buf, tail) = rest.split_at_mut(part.size);
+            rest = tail;
+            regions.push(Region {
+                fd: types::Fd(part.file.as_raw_fd()),
+                buf,
+                offset: 0,
+            });
+        }
+
+        for (i, result) in self.read_regions(&mut regions).into_iter().enumerate() {
+            let path = paths[i].as_ref();
+            match result {
+                Ok(n) if n < parts[i].size => {
+                    let e = io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!(
+                            "{n} of {} bytes, the part shrank while it was read",
+                            parts[i].size
+                        ),
+                    );
+                    return Err(part_failed(first + i, path, e));
+                }
+                Ok(_) => {}
+                Err(e) => return Err(part_failed(first + i, path, e)),
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn part_failed(index: usize, path: &Path, source: io::Error) -> io::Error {
+    ReadError::PartFailed {
+        index,
+        path: path.to_path_buf(),
+        source,
+    }
+    .into()
+}
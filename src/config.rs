@@ -0,0 +1,486 @@
+/// Duration -> how long to busy-poll before sleeping
+use std::time::Duration;
+
+#[cfg(feature = "failpoints")]
+use crate::failpoints::FailPoints;
+use crate::retry::RetryPolicy;
+use crate::throttle::RateLimit;
+use crate::tune::AutoTune;
+
+/// What to do when pinning memory runs into RLIMIT_MEMLOCK (`UringConfig::memlock_policy`)
+/// - Strict -> `UringReader::new` fails with `ReadError::MemlockLimit`
+/// - Degrade -> carry on without registered buffers, `Capabilities::fixed_buffers` is 0 and
+///   `memlock_degraded` is set. The default.
+/// - Shrink -> halve the number of buffers (and, for the ring itself, the queue depth) until it fits,
+///   degrade if even one doesn't. For callers that asked for "as many as you can get".
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MemlockPolicy {
+    Strict,
+    #[default]
+    Degrade,
+    Shrink,
+}
+
+/// What the whole-file reads do when a file gets shorter while it is read (`UringConfig::on_shrink`)
+/// - Truncate -> return what was there up to the new EOF, counted in `ReadStats::shrunk_files`. The
+///   default.
+/// - Fail -> fail with `ReadError::FileChangedDuringRead`
+///
+/// Growing files are never an error: the read returns the first `size` bytes, the size the file had
+/// when it was sized.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ShrinkPolicy {
+    #[default]
+    Truncate,
+    Fail,
+}
+
+/// What `read_file_to_vec` / `read_file_stamped` do about a file modified while it was read
+/// (`UringConfig::on_change`)
+/// - Ignore -> statx once, before the read, and trust it. The default.
+/// - Fail -> statx again after the read, fail with `ReadError::ModifiedDuringRead` if the size, the
+///   mtime or the inode changed
+/// - Retry(n) -> same check, but read the file again (opened again, it may have been replaced) up to
+///   `n` more times before failing like `Fail`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChangePolicy {
+    #[default]
+    Ignore,
+    Fail,
+    Retry(u32),
+}
+
+/// What `UringConfig::verify_against_std` does with each whole-file read
+/// - Off -> nothing, the default
+/// - Count -> the file is read again with std in the background, `ReadStats::verify_mismatches` counts
+///   the reads that differ. The call returns as soon as the ring's read is done.
+/// - Fail -> the call waits for the std read and fails with `ReadError::VerificationFailed` when the
+///   two differ
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum VerifyPolicy {
+    #[default]
+    Off,
+    Count,
+    Fail,
+}
+
+/// What the reads of a piece of a file return when the file ends inside it (`UringConfig::pad`)
+/// - Exact -> only the bytes that were there, the buffer is shorter than asked for. The default.
+/// - ZeroFill -> always as long as asked for, zeroes after EOF. For parsers of fixed size records.
+///
+/// Either way the number of real bytes is returned next to the data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PadPolicy {
+    #[default]
+    Exact,
+    ZeroFill,
+}
+
+/// How a batch finds paths that name the same file, to read it only once (`UringConfig::dedup`)
+/// - Off -> every path is read on its own. The default of `read_many_files`.
+/// - Lexical -> same path once it's absolute and `.`/`//` are gone, no syscall. The default of
+///   `read_many_to_map`.
+/// - Resolve -> `fs::canonicalize` every path (a syscall each), also catches `..` and symlinks
+///
+/// `ReadStats::deduplicated_reads` counts the reads saved.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DedupPolicy {
+    #[default]
+    Off,
+    Lexical,
+    Resolve,
+}
+
+/// Configuration for a `UringReader`
+///
+/// Every knob has a default that matches the plain behavior of `read_one_file`, so
+/// `UringConfig::default()` is always a safe starting point. The setters consume and return `self`
+/// so they can be chained:
+/// ```no_run
+/// use std::time::Duration;
+/// use uring_fast_read::UringConfig;
+///
+/// let config = UringConfig::default()
+///     .queue_depth(128)
+///     .spin_before_wait(Duration::from_micros(20));
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct UringConfig {
+    pub(crate) queue_depth: u32,
+    pub(crate) chunk_size: usize,
+    pub(crate) max_bytes: Option<u64>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::stats::nanos"))]
+    pub(crate) spin_before_wait: Duration,
+    pub(crate) record_timings: bool,
+    pub(crate) journal: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::stats::nanos::option"))]
+    pub(crate) timeout: Option<Duration>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::stats::nanos::option"))]
+    pub(crate) sqpoll: Option<Duration>,
+    pub(crate) direct_io: bool,
+    pub(crate) preserve_sparse: bool,
+    pub(crate) force_async: bool,
+    pub(crate) max_bounded_workers: Option<u32>,
+    pub(crate) max_unbounded_workers: Option<u32>,
+    pub(crate) max_in_flight: usize,
+    pub(crate) auto_tune: Option<AutoTune>,
+    pub(crate) retry: Option<RetryPolicy>,
+    pub(crate) rate_limit: Option<RateLimit>,
+    pub(crate) fixed_buffers: Option<(usize, usize)>,
+    pub(crate) memlock_policy: MemlockPolicy,
+    pub(crate) on_shrink: ShrinkPolicy,
+    pub(crate) on_change: ChangePolicy,
+    pub(crate) verify_against_std: VerifyPolicy,
+    pub(crate) pad: PadPolicy,
+    pub(crate) coalesce_gap: Option<u64>,
+    pub(crate) dedup: DedupPolicy,
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics_label: String,
+    #[cfg(feature = "failpoints")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) failpoints: Option<FailPoints>,
+}
+
+impl Default for UringConfig {
+    fn default() -> Self {
+        UringConfig {
+            queue_depth: 64,
+            chunk_size: 256 * 1024,
+            max_bytes: Some(1 << 30),
+            spin_before_wait: Duration::ZERO,
+            record_timings: false,
+            journal: None,
+            timeout: None,
+            sqpoll: None,
+            direct_io: false,
+            preserve_sparse: false,
+            force_async: false,
+            max_bounded_workers: None,
+            max_unbounded_workers: None,
+            max_in_flight: 32,
+            auto_tune: None,
+            retry: None,
+            rate_limit: None,
+            fixed_buffers: None,
+            memlock_policy: MemlockPolicy::Degrade,
+            on_shrink: ShrinkPolicy::Truncate,
+            on_change: ChangePolicy::Ignore,
+            verify_against_std: VerifyPolicy::Off,
+            pad: PadPolicy::Exact,
+            coalesce_gap: None,
+            dedup: DedupPolicy::Off,
+            #[cfg(feature = "metrics")]
+            metrics_label: "default".to_string(),
+            #[cfg(feature = "failpoints")]
+            failpoints: None,
+        }
+    }
+}
+
+impl UringConfig {
+    /// Same as `UringConfig::default()`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of SQ entries the ring is created with (the kernel rounds it up to a power of two)
+    ///
+    /// This is also the upper bound on how many requests the reader keeps in flight at once.
+    pub fn queue_depth(mut self, entries: u32) -> Self {
+        self.queue_depth = entries.max(1);
+        self
+    }
+
+    /// Size of a single read request when a file is read in pieces (default 256 KiB)
+    ///
+    /// Big files are split into chunks of this size and up to `queue_depth` chunks are in flight at once.
+    pub fn chunk_size(mut self, bytes: usize) -> Self {
+        self.chunk_size = bytes.clamp(1, u32::MAX as usize);
+        self
+    }
+
+    /// Largest file the whole-file APIs (`read_file_to_vec`, `read_many_files`, `read_tree`) will read
+    ///
+    /// Default 1 GiB. Bigger files fail with `ReadError::FileTooLarge` instead of allocating their full
+    /// size, files without a size (`/dev/zero`, pipes, ...) fail once they produced more than the limit.
+    /// `None` removes the limit.
+    pub fn max_bytes(mut self, limit: Option<u64>) -> Self {
+        self.max_bytes = limit;
+        self
+    }
+
+    /// Keep a journal of the requests in memory (default `None`, off)
+    ///
+    /// Every push records user_data, opcode, fd, offset and length, every reaped CQE completes its
+    /// record with the result. `UringReader::dump_pending` lists what never completed ("the batch
+    /// hangs, on what?"), `dump_recent` the last completions, up to `capacity` of them are kept.
+    /// Costs a timestamp and a short lock hold per request, cheap enough to leave on in production
+    /// while waiting for a rare hang.
+    pub fn journal(mut self, capacity: Option<usize>) -> Self {
+        self.journal = capacity;
+        self
+    }
+
+    /// How long to spin on the completion queue before falling back to a blocking wait
+    ///
+    /// On fast NVMe a read can complete in a few microseconds, which is less than the cost of the
+    /// sleep/wake cycle of `io_uring_enter(GETEVENTS)`. With a non-zero value the reader submits, then
+    /// polls the CQ in a tight loop (`std::hint::spin_loop`) for up to this long before blocking.
+    ///
+    /// `Duration::ZERO` (the default) never spins. `ReadStats::spin_hits` / `spin_misses` tell you how
+    /// often the spin was long enough.
+    pub fn spin_before_wait(mut self, spin: Duration) -> Self {
+        self.spin_before_wait = spin;
+        self
+    }
+
+    /// Let a kernel thread poll the submission queue (IORING_SETUP_SQPOLL, default `None`)
+    ///
+    /// Submitting then costs no syscall at all while the thread is awake, it goes to sleep after
+    /// `idle` without new SQEs (and is woken by the next submit). It burns a CPU while awake, so it's
+    /// for small rings with latency critical reads (`Lane::Fast`), not for bulk work. Unprivileged
+    /// processes only get it from Linux 5.11 on: when the kernel refuses, the ring is created without
+    /// it and `Capabilities::sqpoll` is false.
+    pub fn sqpoll(mut self, idle: Option<Duration>) -> Self {
+        self.sqpoll = idle;
+        self
+    }
+
+    /// Upper bound on how long one call may wait for its completions (default `None`, wait forever)
+    ///
+    /// When it runs out, the requests of that call still in flight are canceled, reaped, and the call
+    /// fails with `io::ErrorKind::TimedOut`. Needs IORING_FEAT_EXT_ARG (Linux 5.11) to bound the wait
+    /// inside the kernel, older kernels only notice the deadline after the next completion.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Open files with `O_DIRECT` where the reader owns the buffers (default off)
+    ///
+    /// The page cache is bypassed, data goes straight between the device and memory. Used by
+    /// `copy_file`, which then works with 4 KiB aligned buffers and rounds `chunk_size` up to a multiple
+    /// of 4 KiB. The whole-file reads return plain `Vec`s and ignore this.
+    ///
+    /// Not every filesystem supports it (tmpfs doesn't), opening then fails with EINVAL.
+    pub fn direct_io(mut self, on: bool) -> Self {
+        self.direct_io = on;
+        self
+    }
+
+    /// Let `copy_file` keep the holes of sparse files (default off, holes come out as written zeroes)
+    ///
+    /// Only the data extents are copied, a 10 GB VM image with 1 GB of data moves 1 GB.
+    /// `copy_file_report` tells how much was skipped, and whether a filesystem without holes made the
+    /// copy dense anyway.
+    pub fn preserve_sparse(mut self, on: bool) -> Self {
+        self.preserve_sparse = on;
+        self
+    }
+
+    /// Mark every read and write with IOSQE_ASYNC (default off)
+    ///
+    /// A buffered read is first tried inline, inside the `io_uring_enter` of the submitting thread. With
+    /// this on the kernel skips that attempt and hands the request to an io-wq worker right away, so the
+    /// submitter never does the copy or the miss handling itself. That costs a thread hop on every page
+    /// cache hit, so it only pays off for cold data. `RequestTiming::forced_async`
+    /// shows which requests had it, `PreparedRead::force_async` sets it for a single template.
+    pub fn force_async(mut self, on: bool) -> Self {
+        self.force_async = on;
+        self
+    }
+
+    /// Let the reader pick how many chunk reads it keeps in flight (default `None`, always `queue_depth`)
+    ///
+    /// Used by the chunked and batch reads (`read_file_to_vec`, `read_many_files`, `read_tree`,
+    /// `read_many_stream`). `queue_depth` still sizes the ring and is the ceiling, see `AutoTune` for
+    /// how the depth moves.
+    pub fn auto_tune(mut self, tune: Option<AutoTune>) -> Self {
+        self.auto_tune = tune;
+        self
+    }
+
+    /// Retry reads that fail with a transient error (default `None`, fail right away)
+    ///
+    /// Applies to every chunk of the chunked and batch reads (`read_file_to_vec`, `read_many_files`,
+    /// `read_tree`, `read_to_shared`), see `RetryPolicy`. `ReadStats::retries` counts the retries.
+    pub fn retry(mut self, policy: Option<RetryPolicy>) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Cap the io-wq kernel workers for bounded I/O, regular files and block devices (default `None`,
+    /// the kernel's own limit)
+    ///
+    /// Buffered reads that miss the page cache are handed to io-wq workers, a big batch can spawn
+    /// dozens of them (`iou-wrk-<tid>` threads in `/proc/<pid>/task`). The cap is registered right after the ring is created
+    /// (IORING_REGISTER_IOWQ_MAX_WORKERS, Linux 5.15), `Capabilities::max_workers` is what the kernel
+    /// granted, `max_workers_unsupported` says it couldn't be applied (the reader is created anyway).
+    /// Since Linux 5.12 io-wq belongs to the submitting thread: the cap is per NUMA node, and for the
+    /// thread that creates the reader. Threads that submit on their own get their own io-wq, see
+    /// `UringReader::set_iowq_max_workers`.
+    pub fn max_bounded_workers(mut self, workers: u32) -> Self {
+        self.max_bounded_workers = Some(workers);
+        self
+    }
+
+    /// Cap the io-wq kernel workers for unbounded I/O, sockets, pipes, `read_when_ready` (default
+    /// `None`), see `max_bounded_workers`
+    pub fn max_unbounded_workers(mut self, workers: u32) -> Self {
+        self.max_unbounded_workers = Some(workers);
+        self
+    }
+
+    /// Inject faults into the reads of the reader (default `None`), see `FailPoints`
+    ///
+    /// For testing what sits on top of the reader, never for production. Not serialized with the
+    /// config, a deserialized one has none.
+    #[cfg(feature = "failpoints")]
+    pub fn failpoints(mut self, points: Option<FailPoints>) -> Self {
+        self.failpoints = points;
+        self
+    }
+
+    /// Cap the bandwidth of the reader (default `None`, as fast as the disk goes)
+    ///
+    /// For background scans that share the disk with something latency sensitive, see `RateLimit`.
+    /// Applies to the chunked and batch reads (`read_file_to_vec`, `read_many_files`, `read_tree`,
+    /// `read_regions` based calls) and to `UringFile`, so to `checksum_tree` too. The budget is shared by
+    /// every thread using the reader. `ReadStats::throttled_rate` is the rate it achieved.
+    pub fn rate_limit(mut self, limit: Option<RateLimit>) -> Self {
+        self.rate_limit = limit;
+        self
+    }
+
+    /// Register `count` buffers of `size` bytes with the ring (IORING_REGISTER_BUFFERS, default none)
+    ///
+    /// Registered buffers are pinned once, the kernel skips mapping the pages of every single request.
+    /// `copy_file` moves its chunks through them when they are at least `chunk_size` big. Pinned memory
+    /// counts against RLIMIT_MEMLOCK (unless the process has CAP_IPC_LOCK), which is tiny by default in
+    /// many containers, see `memlock_policy` for what happens when it runs out.
+    pub fn fixed_buffers(mut self, count: usize, size: usize) -> Self {
+        self.fixed_buffers = (count > 0 && size > 0).then_some((count, size));
+        self
+    }
+
+    /// What to do when the ring or the `fixed_buffers` can't be pinned (default `MemlockPolicy::Degrade`)
+    ///
+    /// `Capabilities::memlock_degraded` says whether the reader got less than it asked for.
+    pub fn memlock_policy(mut self, policy: MemlockPolicy) -> Self {
+        self.memlock_policy = policy;
+        self
+    }
+
+    /// What to do when a file is truncated while it is read (default `ShrinkPolicy::Truncate`)
+    ///
+    /// A chunk that hits EOF before the size the file had when it was opened means somebody truncated
+    /// it. No more chunks of that file are issued, what was read up to the new EOF is kept or thrown
+    /// away depending on the policy. Applies to `read_file_to_vec`, `read_many_files`, `read_tree` and
+    /// `read_to_shared`.
+    pub fn on_shrink(mut self, policy: ShrinkPolicy) -> Self {
+        self.on_shrink = policy;
+        self
+    }
+
+    /// Whether `read_file_to_vec` and `read_file_stamped` make sure the file didn't change while it
+    /// was read (default `ChangePolicy::Ignore`)
+    ///
+    /// For caches keyed by what a stat said: anything but `Ignore` costs a second statx per file,
+    /// after the read, and only hands out data the two of them agree on. The mtime has the
+    /// granularity of the filesystem clock, a write right after the first statx within the same tick
+    /// only shows when it also changes the size.
+    pub fn on_change(mut self, policy: ChangePolicy) -> Self {
+        self.on_change = policy;
+        self
+    }
+
+    /// Check every whole-file read against a plain `std::fs::read` of the same file (default
+    /// `VerifyPolicy::Off`)
+    ///
+    /// For the burn-in when this crate replaces std reads on a path where wrong data is worse than
+    /// slow data, not for production: every file is read twice, expect half the throughput. Turn it
+    /// off once the counters have stayed at zero long enough.
+    ///
+    /// Two background threads do the std reads, streamed and hashed in 256 KiB pieces, and compare
+    /// length and hash (XXH3 with the feature `xxh3`) with what the ring returned. At most 1024 checks
+    /// wait for them: with `Count` a read that finds the queue full goes unchecked
+    /// (`ReadStats::verify_skipped`), with `Fail` it waits for room. A file whose stamp (size, mtime,
+    /// inode) changed between the ring's read and the std one counts as `ReadStats::verify_raced`,
+    /// neither a match nor a mismatch. Applies to `read_file_to_vec`, `read_file_stamped`,
+    /// `read_to_shared` and `read_many_files`, only on io_uring: the std fallback has nothing to
+    /// compare with.
+    pub fn verify_against_std(mut self, policy: VerifyPolicy) -> Self {
+        self.verify_against_std = policy;
+        self
+    }
+
+    /// What `read_at`, `read_ranges` and `read_chunks_with` hand out past EOF (default `PadPolicy::Exact`)
+    ///
+    /// With `ZeroFill` a range or the last chunk that runs past the end of the file is padded with
+    /// zeroes up to its full length. Only the short ones are touched, a read that was satisfied in full
+    /// never pays for the padding.
+    pub fn pad(mut self, policy: PadPolicy) -> Self {
+        self.pad = policy;
+        self
+    }
+
+    /// Merge `read_ranges` ranges that are at most `gap` bytes apart into one read (default `None`,
+    /// every range is its own read)
+    ///
+    /// 200 lookups of 4 KiB inside the same 2 MiB become a handful of reads, each range is then cut
+    /// out of the one that covers it. Overlapping ranges always merge. The bytes in between are read
+    /// for nothing, a merged read counts them against `max_bytes` and isn't grown past it.
+    /// `ReadStats::coalesced_ranges` and `ReadStats::gap_bytes` tell what it did, to tune `gap`.
+    /// On an `O_DIRECT` file ranges are only merged if the merged read is still 4 KiB aligned.
+    pub fn coalesce_gap(mut self, gap: Option<u64>) -> Self {
+        self.coalesce_gap = gap;
+        self
+    }
+
+    /// Read a path that shows up several times in one batch only once (default `DedupPolicy::Off`)
+    ///
+    /// Applies to `read_many_files`, `read_many_files_until` and `read_many_to_map` (which dedups
+    /// lexically even with `Off`). Every copy gets the same result, errors included, the buffers of
+    /// `read_many_files` are cloned.
+    pub fn dedup(mut self, policy: DedupPolicy) -> Self {
+        self.dedup = policy;
+        self
+    }
+
+    /// How many files the streaming reads keep open and in flight at once (default 32)
+    ///
+    /// This is the backpressure knob: the next file is only opened once an earlier one was handed to
+    /// the consumer, a slow consumer never has more than this many files buffered (up to this many
+    /// times `max_bytes`, the limit is in files).
+    pub fn max_in_flight(mut self, files: usize) -> Self {
+        self.max_in_flight = files.max(1);
+        self
+    }
+
+    /// Record push/submit/reap timestamps of every request (default off)
+    ///
+    /// Each `Completion` then carries a `RequestTiming` (queue time vs. in-kernel time) and `ReadStats`
+    /// sums them up separately. Off means no timestamps are taken at all.
+    pub fn record_timings(mut self, on: bool) -> Self {
+        self.record_timings = on;
+        self
+    }
+
+    /// Value of the `reader` label on everything the reader exports through `metrics` (default "default")
+    ///
+    /// Give every long lived reader its own name so the dashboards can tell them apart. The request
+    /// latency histogram is only filled with `record_timings(true)`, the counters always are.
+    #[cfg(feature = "metrics")]
+    pub fn metrics_label(mut self, name: impl Into<String>) -> Self {
+        self.metrics_label = name.into();
+        self
+    }
+}
@@ -0,0 +1,524 @@
+use io_uring::{opcode, types};
+
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::fs::{DirBuilderExt, symlink};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+
+use crate::buffers::{AlignedBuf, Lease};
+use crate::config::UringConfig;
+use crate::reader::{Session, UringReader, is_retryable, lock};
+use crate::stats::{CopyReport, TreeCopyReport, dense};
+use crate::tree::{self, CopyTreeOptions, TreeOps};
+use crate::xattr::c_string;
+
+/// `O_DIRECT` wants buffers, offsets and lengths aligned to the logical block size, 4 KiB covers every
+/// device in practice
+pub(crate) const DIRECT_ALIGN: usize = 4096;
+
+/// Where the chunks of one copy slot live
+/// - Own -> allocated for this copy
+/// - Fixed -> one of the reader's registered buffers (`UringConfig::fixed_buffers`), moved with
+///   ReadFixed/WriteFixed
+enum SlotBuf<'p> {
+    Own(AlignedBuf),
+    Fixed(Lease<'p>),
+}
+
+impl SlotBuf<'_> {
+    fn ptr(&self) -> *mut u8 {
+        match self {
+            SlotBuf::Own(buf) => buf.ptr,
+            SlotBuf::Fixed(lease) => lease.ptr(),
+        }
+    }
+}
+
+/// The buffer set of one copy, one buffer per slot
+///
+/// A slot takes the next chunk only once the write of its last one was reaped, so there are never more
+/// chunks in flight than buffers and a copy allocates nothing after it started. The own buffers go
+/// back to the reader on drop (after the session, which waits for every request), for the next copy
+/// to take: copies one after the other allocate once, up to `queue_depth` buffers.
+struct Recycle<'r> {
+    reader: &'r UringReader,
+    bufs: Vec<SlotBuf<'r>>,
+}
+
+impl Drop for Recycle<'_> {
+    fn drop(&mut self) {
+        let depth = self.reader.config.queue_depth as usize;
+        let mut recycled = lock(&self.reader.copy_bufs);
+        for buf in self.bufs.drain(..) {
+            if let SlotBuf::Own(buf) = buf
+                && recycled.len() < depth
+            {
+                recycled.push(buf);
+            }
+        }
+    }
+}
+
+/// Chunk size and alignment of a copy, `O_DIRECT` wants whole blocks
+///
+/// The same for every copy of a reader, so all the buffers it recycles fit every copy.
+fn copy_chunk(config: &UringConfig) -> (usize, usize) {
+    if config.direct_io {
+        (
+            config.chunk_size.next_multiple_of(DIRECT_ALIGN),
+            DIRECT_ALIGN,
+        )
+    } else {
+        (config.chunk_size, 1)
+    }
+}
+
+/// What a copy slot is doing, every slot has at most one request in flight
+/// - offset -> where the chunk starts in both files
+/// - len -> bytes the current step wants to move
+/// - done -> bytes of it moved so far (short reads/writes are resubmitted for the rest)
+#[derive(Clone, Copy)]
+enum Step {
+    Idle,
+    Reading {
+        offset: u64,
+        len: usize,
+        done: usize,
+    },
+    Writing {
+        offset: u64,
+        len: usize,
+        done: usize,
+    },
+}
+
+/// Where the holes of a file are, from SEEK_DATA/SEEK_HOLE
+///
+/// Ok(Some(extents)) -> (offset, len) of every data extent, in order
+/// Ok(None) -> the filesystem doesn't report holes (EINVAL)
+#[allow(unused_doc_comments)]
+fn data_extents(fd: RawFd, size: u64) -> io::Result<Option<Vec<(u64, u64)>>> {
+    let mut extents = Vec::new();
+    let mut pos = 0u64;
+    while pos < size {
+        /// SAFETY: plain lseek on an fd we own
+        let data = unsafe { libc::lseek(fd, pos as libc::off_t, libc::SEEK_DATA) };
+        if data < 0 {
+            match io::Error::last_os_error().raw_os_error() {
+                /// No data after `pos`, the rest is one hole
+                Some(libc::ENXIO) => break,
+                Some(libc::EINVAL) => return Ok(None),
+                _ => return Err(io::Error::last_os_error()),
+            }
+        }
+        /// SAFETY: as above
+        let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+        if hole < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (data, hole) = (data as u64, (hole as u64).min(size));
+        if data >= hole {
+            break;
+        }
+        extents.push((data, hole - data));
+        pos = hole;
+    }
+    Ok(Some(extents))
+}
+
+/// Whether `fd` (just truncated to a non-zero size, nothing written) keeps holes: its start must be
+/// one
+#[allow(unused_doc_comments)]
+fn keeps_holes(fd: RawFd) -> bool {
+    /// SAFETY: plain lseek on an fd we own
+    unsafe {
+        libc::lseek(fd, 0, libc::SEEK_HOLE) == 0
+    }
+}
+
+impl UringReader {
+    /// Copy `src` to `dst` through the ring, returns the number of bytes copied
+    ///
+    /// Same as `copy_file_report(src, dst)?.logical_size`, see there for sparse files.
+    pub fn copy_file(&self, src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<u64> {
+        self.copy_file_report(src, dst)
+            .map(|report| report.logical_size)
+    }
+
+    /// `copy_file` that says what it did, see `CopyReport`
+    ///
+    /// Up to `queue_depth` chunks are in flight, each one is read and then written back at the same
+    /// offset, so reads of later chunks overlap with writes of earlier ones. `dst` is created or
+    /// truncated and gets the permissions of `src`.
+    ///
+    /// Files without a size (`/proc`, ...) are copied until a read returns 0. `max_bytes` does not apply,
+    /// the file is never held in memory as a whole. With `UringConfig::direct_io` both files are opened
+    /// with `O_DIRECT`.
+    ///
+    /// Every slot has one buffer, a chunk's buffer is only free for the next chunk once its write was
+    /// reaped, and reads wait for a free one. Registered buffers (`UringConfig::fixed_buffers`) that are
+    /// free and at least one chunk big are used first, registered once with the reader and moved with
+    /// ReadFixed/WriteFixed. The other slots take buffers the copies before left on the reader and
+    /// only allocate when there are none, `CopyReport::allocated` and
+    /// `ReadStats::copy_buffers_allocated` say how many: 0 from the second copy on.
+    ///
+    /// With `UringConfig::preserve_sparse` only the data extents of `src` (SEEK_DATA/SEEK_HOLE) are
+    /// copied: `dst` is set to the full size first and the holes are never written, so they stay
+    /// holes. When either filesystem can't do holes the copy is dense and `CopyReport::dense_reason`
+    /// says why.
+    pub fn copy_file_report(
+        &self,
+        src: impl AsRef<Path>,
+        dst: impl AsRef<Path>,
+    ) -> io::Result<CopyReport> {
+        let sparse = self.config.preserve_sparse;
+        self.copy_with(src.as_ref(), dst.as_ref(), sparse, false)
+    }
+
+    /// Copy every directory, regular file and (per `CopyTreeOptions::symlinks`) symlink below
+    /// `src_root` to the same place below `dst_root`, see `TreeCopyReport`
+    ///
+    /// ```no_run
+    /// use uring_fast_read::{CopyTreeOptions, ExistingPolicy, UringConfig, UringReader};
+    ///
+    /// let reader = UringReader::new(UringConfig::default()).unwrap();
+    /// let options = CopyTreeOptions::default().existing(ExistingPolicy::Skip).fsync(true);
+    /// let report = reader.copy_tree("/srv/data", "/backup/data", options).unwrap();
+    /// println!("{} files, {} bytes, {} failed", report.files, report.total.copied, report.failed);
+    /// ```
+    ///
+    /// Ok(report) -> every entry and what happened to it, a failed one doesn't stop the rest
+    /// Err(e) -> `src_root` can't be listed or `dst_root` can't be created, or
+    /// `ReadError::TreeCopyAborted` with `CopyTreeOptions::fail_fast`
+    ///
+    /// `dst_root` and the directories below it are created (IORING_OP_MKDIRAT) or merged into when
+    /// they are there already, and get the permissions of their source once they are filled.
+    /// Files go through `copy_file_report` one after the other, so the whole tree never has more than
+    /// `queue_depth` chunks of `chunk_size` bytes in flight, through the same recycled buffers. With
+    /// `fsync` each file is fsynced through the ring before it is closed. Symlinks are never followed,
+    /// links recreated with `SymlinkPolicy::Recreate` use IORING_OP_SYMLINKAT, links replaced under
+    /// `ExistingPolicy::Overwrite` are removed with IORING_OP_UNLINKAT. On kernels without one of
+    /// them (older than 5.15) that step is the plain syscall. FIFOs, sockets and device nodes are
+    /// left out.
+    pub fn copy_tree(
+        &self,
+        src_root: impl AsRef<Path>,
+        dst_root: impl AsRef<Path>,
+        options: CopyTreeOptions,
+    ) -> io::Result<TreeCopyReport> {
+        tree::copy_tree(self, src_root.as_ref(), dst_root.as_ref(), &options)
+    }
+
+    /// `copy_file_report` with the sparse setting of the call, fsync of `dst` on top
+    #[allow(unused_doc_comments)]
+    fn copy_with(
+        &self,
+        src: &Path,
+        dst: &Path,
+        sparse: bool,
+        fsync: bool,
+    ) -> io::Result<CopyReport> {
+        let direct = self.config.direct_io;
+        let flags = if direct { libc::O_DIRECT } else { 0 };
+        let (chunk_size, align) = copy_chunk(&self.config);
+
+        let src_file = OpenOptions::new()
+            .read(true)
+            .custom_flags(flags)
+            .open(src)?;
+        let meta = src_file.metadata()?;
+        let dst_file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .custom_flags(flags)
+            .open(dst)?;
+        fs::set_permissions(dst, meta.permissions())?;
+
+        /// 0 -> unknown size, keep going until a read returns 0
+        let size = meta.len();
+        let mut dense_reason = None;
+        let mut extents = None;
+        if sparse && size > 0 {
+            match data_extents(src_file.as_raw_fd(), size)? {
+                None => dense_reason = Some(dense::SOURCE_WITHOUT_HOLES),
+                /// Nothing to skip, a plain copy
+                Some(found) if found == [(0, size)] => {}
+                Some(found) => {
+                    dst_file.set_len(size)?;
+                    if keeps_holes(dst_file.as_raw_fd()) {
+                        extents = Some(found);
+                    } else {
+                        dense_reason = Some(dense::DESTINATION_WITHOUT_HOLES);
+                    }
+                }
+            }
+        }
+        /// The chunks of the data extents, back to front so they can be popped in order
+        let mut sparse_chunks: Option<Vec<(u64, usize)>> = extents.as_ref().map(|extents| {
+            let mut chunks: Vec<(u64, usize)> = extents
+                .iter()
+                .flat_map(|&(offset, len)| {
+                    (0..len)
+                        .step_by(chunk_size)
+                        .map(move |at| (offset + at, (len - at).min(chunk_size as u64) as usize))
+                })
+                .collect();
+            chunks.reverse();
+            chunks
+        });
+        let holes = extents.as_ref().map_or(0, |extents| {
+            size - extents.iter().map(|&(_, len)| len).sum::<u64>()
+        });
+        let wanted = match &sparse_chunks {
+            Some(chunks) => chunks.len(),
+            None if size == 0 => usize::MAX,
+            None => size.div_ceil(chunk_size as u64) as usize,
+        };
+        let slots = (self.config.queue_depth as usize).min(wanted).max(1);
+
+        /// The buffers are declared before the session, the session waits for every request on drop
+        let mut leases = match &self.buffers {
+            Some(buffers) if buffers.size() >= chunk_size => buffers.try_lease(slots),
+            _ => Vec::new(),
+        };
+        let mut allocated = 0;
+        let bufs = {
+            let mut recycled = lock(&self.copy_bufs);
+            let bufs: Vec<SlotBuf<'_>> = (0..slots)
+                .map(|_| match leases.pop() {
+                    Some(lease) => SlotBuf::Fixed(lease),
+                    None => SlotBuf::Own(recycled.pop().unwrap_or_else(|| {
+                        allocated += 1;
+                        AlignedBuf::new(chunk_size, DIRECT_ALIGN)
+                    })),
+                })
+                .collect();
+            Recycle { reader: self, bufs }
+        };
+        if allocated > 0 {
+            lock(&self.stats).copy_buffers_allocated += allocated as u64;
+        }
+        let mut steps = vec![Step::Idle; slots];
+        let mut session = self.session();
+
+        let src_fd = types::Fd(src_file.as_raw_fd());
+        let dst_fd = types::Fd(dst_file.as_raw_fd());
+        let mut next_offset = 0u64;
+        /// the first offset a read came back empty at, nothing at or after it is read anymore
+        let mut end: Option<u64> = (size > 0).then_some(size);
+        /// bytes written to `dst`, without the O_DIRECT padding
+        let mut copied = 0u64;
+        let mut chunks = 0u64;
+
+        /// Issue the request of `step` into slot `slot`
+        let push = |session: &mut Session<'_>, slot: usize, step: Step| {
+            let buf = bufs.bufs[slot].ptr();
+            let fixed = match &bufs.bufs[slot] {
+                SlotBuf::Fixed(lease) => Some(lease.index),
+                SlotBuf::Own(_) => None,
+            };
+            let entry = match (step, fixed) {
+                (Step::Idle, _) => return Ok(()),
+                (Step::Reading { offset, len, done }, Some(index)) => opcode::ReadFixed::new(
+                    src_fd,
+                    // SAFETY: done < len <= chunk_size <= the registered buffer's size
+                    unsafe { buf.add(done) },
+                    (len - done) as u32,
+                    index,
+                )
+                .offset(offset + done as u64)
+                .build(),
+                (Step::Reading { offset, len, done }, None) => opcode::Read::new(
+                    src_fd,
+                    // SAFETY: done < len <= chunk_size, inside the slot's buffer
+                    unsafe { buf.add(done) },
+                    (len - done) as u32,
+                )
+                .offset(offset + done as u64)
+                .build(),
+                (Step::Writing { offset, len, done }, fixed) => {
+                    /// O_DIRECT writes must be whole blocks, the tail is padded and cut off again
+                    /// with `set_len` at the end
+                    let padded = len.next_multiple_of(align);
+                    if done == 0 && padded > len {
+                        /// A recycled buffer still holds the last chunk of another copy, the padding
+                        /// is zeroed so none of that reaches `dst`
+                        /// SAFETY: len < padded <= chunk_size, inside the slot's buffer
+                        unsafe {
+                            buf.add(len).write_bytes(0, padded - len)
+                        };
+                    }
+                    let len = padded;
+                    /// SAFETY: done < len <= chunk_size, inside the slot's buffer
+                    let ptr = unsafe { buf.add(done) };
+                    match fixed {
+                        Some(index) => {
+                            opcode::WriteFixed::new(dst_fd, ptr, (len - done) as u32, index)
+                                .offset(offset + done as u64)
+                                .build()
+                        }
+                        None => opcode::Write::new(dst_fd, ptr, (len - done) as u32)
+                            .offset(offset + done as u64)
+                            .build(),
+                    }
+                }
+            };
+            session.push(slot as u32, entry)
+        };
+
+        loop {
+            for (slot, step) in steps.iter_mut().enumerate() {
+                if !matches!(step, Step::Idle) {
+                    continue;
+                }
+                let (offset, len) = match &mut sparse_chunks {
+                    Some(chunks) => match chunks.pop() {
+                        Some(chunk) if end.is_none_or(|end| chunk.0 < end) => chunk,
+                        _ => continue,
+                    },
+                    None if end.is_some_and(|end| next_offset >= end) => continue,
+                    None => {
+                        next_offset += chunk_size as u64;
+                        (next_offset - chunk_size as u64, chunk_size)
+                    }
+                };
+                *step = Step::Reading {
+                    offset,
+                    len,
+                    done: 0,
+                };
+                push(&mut session, slot, *step)?;
+            }
+            if session.in_flight() == 0 {
+                break;
+            }
+            session.submit()?;
+
+            let cqe = session.next()?;
+            let slot = (cqe.user_data() & u64::from(u32::MAX)) as usize;
+            let n = match cqe.into_result() {
+                Ok(n) => n as usize,
+                Err(e) if is_retryable(&e) => {
+                    push(&mut session, slot, steps[slot])?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            steps[slot] = match steps[slot] {
+                Step::Idle => Step::Idle,
+                Step::Reading { offset, len, done } => {
+                    let done = done + n;
+                    let at = offset + done as u64;
+                    if n == 0 || Some(at) == end {
+                        /// EOF (or the known size) inside this chunk
+                        if n == 0 {
+                            end = Some(end.map_or(at, |end| end.min(at)));
+                        }
+                        if done == 0 {
+                            Step::Idle
+                        } else {
+                            Step::Writing {
+                                offset,
+                                len: done,
+                                done: 0,
+                            }
+                        }
+                    } else if done < len {
+                        Step::Reading { offset, len, done }
+                    } else {
+                        Step::Writing {
+                            offset,
+                            len,
+                            done: 0,
+                        }
+                    }
+                }
+                Step::Writing { offset, len, done } => {
+                    let done = done + n;
+                    if done < len {
+                        Step::Writing { offset, len, done }
+                    } else {
+                        // Written, the slot's buffer is free for the next chunk
+                        copied += len as u64;
+                        chunks += 1;
+                        Step::Idle
+                    }
+                }
+            };
+            push(&mut session, slot, steps[slot])?;
+        }
+        drop(session);
+
+        /// Cuts off the padding of an O_DIRECT tail (and keeps a trailing hole)
+        let logical_size = end.expect("the loop only stops once the end is known");
+        dst_file.set_len(logical_size)?;
+        if fsync {
+            self.run_one(opcode::Fsync::new(dst_fd).build())?;
+        }
+        Ok(CopyReport {
+            logical_size,
+            copied: copied.min(logical_size),
+            holes: holes.min(logical_size),
+            dense_reason,
+            chunks,
+            buffers: slots,
+            allocated,
+        })
+    }
+}
+
+impl TreeOps for UringReader {
+    fn make_dir(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let c_path = c_string(path.as_os_str().as_bytes())?;
+        if !self.is_supported(opcode::MkDirAt::CODE) {
+            self.metrics.fell_back();
+            return fs::DirBuilder::new().mode(mode).create(path);
+        }
+        let mkdir_e = opcode::MkDirAt::new(types::Fd(libc::AT_FDCWD), c_path.as_ptr())
+            .mode(mode)
+            .build();
+        self.run_one(mkdir_e).map(|_| ())
+    }
+
+    fn copy(
+        &self,
+        src: &Path,
+        dst: &Path,
+        sparse: Option<bool>,
+        fsync: bool,
+    ) -> io::Result<CopyReport> {
+        let sparse = sparse.unwrap_or(self.config.preserve_sparse);
+        self.copy_with(src, dst, sparse, fsync)
+    }
+
+    fn make_link(&self, target: &Path, link: &Path) -> io::Result<()> {
+        let c_target = c_string(target.as_os_str().as_bytes())?;
+        let c_link = c_string(link.as_os_str().as_bytes())?;
+        if !self.is_supported(opcode::SymlinkAt::CODE) {
+            self.metrics.fell_back();
+            return symlink(target, link);
+        }
+        let symlink_e = opcode::SymlinkAt::new(
+            types::Fd(libc::AT_FDCWD),
+            c_target.as_ptr(),
+            c_link.as_ptr(),
+        )
+        .build();
+        self.run_one(symlink_e).map(|_| ())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let c_path = c_string(path.as_os_str().as_bytes())?;
+        if !self.is_supported(opcode::UnlinkAt::CODE) {
+            self.metrics.fell_back();
+            return fs::remove_file(path);
+        }
+        let unlink_e = opcode::UnlinkAt::new(types::Fd(libc::AT_FDCWD), c_path.as_ptr()).build();
+        self.run_one(unlink_e).map(|_| ())
+    }
+}
@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io::{self, BufRead, Read, Seek};
+use std::path::Path;
+
+use crate::UringReader;
+use crate::error::ReadError;
+
+/// Formats `read_decompressed` understands, each one behind its own feature
+/// - None -> plain data, read as is
+/// - Gzip -> gzip, also several members one after the other (feature `flate2`)
+/// - Zstd -> zstd, also several frames one after the other (feature `zstd`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Compression {
+    None,
+    #[cfg(feature = "flate2")]
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    /// Guess the format from the first bytes of a file (4 are enough)
+    ///
+    /// Anything that is not a known (and enabled) magic number is `Compression::None`.
+    pub fn detect(magic: &[u8]) -> Compression {
+        match magic {
+            #[cfg(feature = "flate2")]
+            [0x1f, 0x8b, ..] => Compression::Gzip,
+            #[cfg(feature = "zstd")]
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// The compressed input as the decoder sees it: a `UringFile` that counts what was taken from it
+/// and remembers whether an error came from the file itself (and not from the decoder)
+struct Source<R> {
+    inner: R,
+    consumed: u64,
+    failed: bool,
+}
+
+impl<R: BufRead> Read for Source<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(out).inspect_err(|_| self.failed = true)?;
+        self.consumed += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Source<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self.inner.fill_buf() {
+            Ok(buf) => Ok(buf),
+            Err(e) => {
+                self.failed = true;
+                Err(e)
+            }
+        }
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.consumed += amount as u64;
+        self.inner.consume(amount);
+    }
+}
+
+impl UringReader {
+    /// Read a whole file and decompress it, the format is detected from its magic bytes
+    ///
+    /// See `read_decompressed_as` for the details.
+    pub fn read_decompressed(&self, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        self.decompress(path.as_ref(), None)
+    }
+
+    /// Read a whole file and decompress it as `compression`
+    ///
+    /// - `Compression::None` -> exactly `read_file_to_vec`, no decoder in the way
+    /// - otherwise the file is streamed through the ring (`UringFile`) into the decoder chunk by
+    ///   chunk, the compressed file is never in memory as a whole
+    ///
+    /// `max_bytes` limits the decompressed size (a small file can inflate to gigabytes). Truncated or
+    /// corrupt input fails with `ReadError::CorruptInput` (`InvalidData`), which tells where in the
+    /// compressed file decoding stopped.
+    pub fn read_decompressed_as(
+        &self,
+        path: impl AsRef<Path>,
+        compression: Compression,
+    ) -> io::Result<Vec<u8>> {
+        self.decompress(path.as_ref(), Some(compression))
+    }
+
+    fn decompress(&self, path: &Path, compression: Option<Compression>) -> io::Result<Vec<u8>> {
+        let mut file = File::open(path)?;
+        let compression = match compression {
+            Some(compression) => compression,
+            None => {
+                let mut magic = Vec::with_capacity(4);
+                (&file).take(4).read_to_end(&mut magic)?;
+                file.rewind()?;
+                Compression::detect(&magic)
+            }
+        };
+        if compression == Compression::None {
+            return self.read_open_file(file, path);
+        }
+
+        let mut source = Source {
+            inner: self.uring_file(file)?,
+            consumed: 0,
+            failed: false,
+        };
+        let mut out = Vec::new();
+        let decoded = decode(compression, &mut source, &mut out, self.config.max_bytes);
+        match decoded {
+            Err(e) if !source.failed => Err(ReadError::CorruptInput {
+                path: path.to_path_buf(),
+                offset: source.consumed,
+                reason: e.to_string(),
+            }
+            .into()),
+            Err(e) => Err(e),
+            Ok(()) => {
+                self.check_size(path, out.len() as u64)?;
+                Ok(out)
+            }
+        }
+    }
+}
+
+/// Decode everything in `source` into `out`, stopping one byte past `limit`
+#[allow(unused_mut, unused_doc_comments)]
+fn decode<R: BufRead>(
+    compression: Compression,
+    source: &mut Source<R>,
+    out: &mut Vec<u8>,
+    limit: Option<u64>,
+) -> io::Result<()> {
+    let mut room = limit.map_or(u64::MAX, |limit| limit + 1);
+    match compression {
+        Compression::None => {
+            source.take(room).read_to_end(out)?;
+        }
+        #[cfg(feature = "flate2")]
+        Compression::Gzip => {
+            flate2::bufread::MultiGzDecoder::new(source)
+                .take(room)
+                .read_to_end(out)?;
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => {
+            /// One decoder per frame, as long as there is input left
+            while !source.fill_buf()?.is_empty() && room > 0 {
+                let frame = ruzstd::decoding::StreamingDecoder::new(&mut *source)
+                    .map_err(io::Error::other)?;
+                room -= frame.take(room).read_to_end(out)? as u64;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::backend::copy_error;
+use crate::config::DedupPolicy;
+
+/// The distinct paths of a batch
+/// - unique -> one path per file, in order of first appearance
+/// - slots -> for every path of the batch, its index in `unique`
+pub(crate) struct Deduped<'p> {
+    pub(crate) unique: Vec<&'p Path>,
+    pub(crate) slots: Vec<usize>,
+}
+
+#[cfg(target_os = "linux")]
+impl Deduped<'_> {
+    /// Reads saved by reading `unique` instead of every path
+    pub(crate) fn saved(&self) -> usize {
+        self.slots.len() - self.unique.len()
+    }
+}
+
+/// Group `paths` that name the same file under `policy` (`Off` keeps every path on its own)
+#[allow(unused_doc_comments)]
+pub(crate) fn dedup<'p>(paths: &[&'p Path], policy: DedupPolicy) -> Deduped<'p> {
+    let cwd = match policy {
+        DedupPolicy::Off => None,
+        _ => std::env::current_dir().ok(),
+    };
+    let mut seen: HashMap<PathBuf, usize> = HashMap::new();
+    let mut unique = Vec::new();
+    let mut slots = Vec::with_capacity(paths.len());
+    for &path in paths {
+        let key = match policy {
+            DedupPolicy::Off => None,
+            DedupPolicy::Lexical => Some(lexical(path, cwd.as_deref())),
+            /// A path that doesn't resolve fails in the read, the lexical key still groups its copies
+            DedupPolicy::Resolve => {
+                Some(std::fs::canonicalize(path).unwrap_or_else(|_| lexical(path, cwd.as_deref())))
+            }
+        };
+        let slot = match key {
+            Some(key) => *seen.entry(key).or_insert_with(|| {
+                unique.push(path);
+                unique.len() - 1
+            }),
+            None => {
+                unique.push(path);
+                unique.len() - 1
+            }
+        };
+        slots.push(slot);
+    }
+    Deduped { unique, slots }
+}
+
+/// `path` made absolute against `cwd`, with `.` and repeated separators gone
+///
+/// No syscall: `..` stays (it may go through a symlink) and links are not looked at, `a` and a
+/// symlink to it are two files here.
+fn lexical(path: &Path, cwd: Option<&Path>) -> PathBuf {
+    let absolute = match cwd {
+        Some(cwd) if path.is_relative() => cwd.join(path),
+        _ => path.to_path_buf(),
+    };
+    absolute
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+/// One result per path of the batch from the results of `unique`, the last copy moves, the others
+/// are cloned with `clone` (errors with `copy_error`)
+pub(crate) fn fan_out<T>(
+    results: Vec<io::Result<T>>,
+    slots: &[usize],
+    clone: impl Fn(&T) -> T,
+) -> Vec<io::Result<T>> {
+    let mut remaining = vec![0usize; results.len()];
+    for &slot in slots {
+        remaining[slot] += 1;
+    }
+    let mut results: Vec<Option<io::Result<T>>> = results.into_iter().map(Some).collect();
+    slots
+        .iter()
+        .map(|&slot| {
+            remaining[slot] -= 1;
+            if remaining[slot] == 0 {
+                return results[slot].take().expect("every copy is handed out once");
+            }
+            match results[slot].as_ref().expect("the last copy is taken last") {
+                Ok(value) => Ok(clone(value)),
+                Err(e) => Err(copy_error(e)),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,212 @@
+use io_uring::{opcode, types};
+
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Component, Path, PathBuf};
+
+use crate::chain::c_path;
+use crate::error::ReadError;
+use crate::files::copy_error;
+use crate::reader::{UringReader, is_retryable};
+
+/// One directory held open, its files are opened by name relative to its fd
+///
+/// Every open is an `openat` against the directory fd (`IORING_OP_OPENAT`, the plain syscall on
+/// kernels before 5.6), so the path up to the directory is resolved once, in `open`, and not again
+/// for every file. The fd also pins the directory: renaming it (or one of its parents) in the middle
+/// of a batch doesn't change which files are read.
+///
+/// Names are relative to the directory, sub-paths (`"sub/file"`) are fine. With `sandbox(true)` a
+/// name must be a single plain file name instead: no `/`, no `..`, and a symlink isn't followed
+/// (`O_NOFOLLOW`). Breaking that fails with `ReadError::PathEscapesSandbox`. For sub-paths that must
+/// stay below a root, see `SandboxedReader`.
+///
+/// The handle owns the directory fd and every read runs to completion before the call returns, so
+/// no request that references the fd is ever in flight without it.
+pub struct DirHandle<'r> {
+    reader: &'r UringReader,
+    path: PathBuf,
+    dir: File,
+    sandbox: bool,
+}
+
+impl<'r> DirHandle<'r> {
+    /// Open the directory `path` for reads on `reader`
+    pub fn open(reader: &'r UringReader, path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let dir = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY | libc::O_CLOEXEC)
+            .open(path)?;
+        Ok(DirHandle {
+            reader,
+            path: path.to_path_buf(),
+            dir,
+            sandbox: false,
+        })
+    }
+
+    /// Only accept plain file names (default off), see the type docs
+    pub fn sandbox(mut self, on: bool) -> Self {
+        self.sandbox = on;
+        self
+    }
+
+    /// The directory (as it was named when it was opened)
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read the whole file `name` of the directory
+    ///
+    /// Ok(data) -> the file contents
+    /// Err(e) -> what `UringReader::read_file_to_vec` would report, or
+    /// `ReadError::PathEscapesSandbox` for a name `sandbox` refuses
+    pub fn read_file(&self, name: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        let name = name.as_ref();
+        let file = self.open_many(&[name]).pop().expect("one open per name")?;
+        self.reader.read_open_file(file, &self.path.join(name))
+    }
+
+    /// Read many files of the directory at once, one result per name in the same order
+    ///
+    /// The opens go through the ring together, then the files are read like `read_many_files` reads
+    /// them (linked statx + first read, `chunk_size` pieces for the big ones).
+    pub fn read_many<P: AsRef<Path>>(&self, names: &[P]) -> Vec<io::Result<Vec<u8>>> {
+        let names: Vec<&Path> = names.iter().map(AsRef::as_ref).collect();
+        let files = self.open_many(&names);
+        let paths: Vec<PathBuf> = names.iter().map(|name| self.path.join(name)).collect();
+        let paths: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+        self.reader.read_many_open(&paths, files, None)
+    }
+
+    /// The flags of every open, `sandbox` adds `O_NOFOLLOW`
+    fn flags(&self) -> i32 {
+        let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+        if self.sandbox {
+            flags | libc::O_NOFOLLOW
+        } else {
+            flags
+        }
+    }
+
+    /// `name` as the kernel gets it, refused if `sandbox` doesn't allow it
+    fn c_name(&self, name: &Path) -> io::Result<CString> {
+        if self.sandbox {
+            let mut components = name.components();
+            if !matches!(
+                (components.next(), components.next()),
+                (Some(Component::Normal(_)), None)
+            ) {
+                return Err(self.escapes(name));
+            }
+        }
+        c_path(name)
+    }
+
+    fn escapes(&self, name: &Path) -> io::Error {
+        ReadError::PathEscapesSandbox {
+            root: self.path.clone(),
+            path: name.to_path_buf(),
+        }
+        .into()
+    }
+
+    /// Open every name relative to the directory, up to `queue_depth` opens in flight
+    #[allow(unused_doc_comments)]
+    fn open_many(&self, names: &[&Path]) -> Vec<io::Result<File>> {
+        let mut files: Vec<Option<io::Result<File>>> = names.iter().map(|_| None).collect();
+        /// The names are read by the kernel, they must outlive the session
+        let mut c_names = Vec::with_capacity(names.len());
+        for (i, name) in names.iter().enumerate() {
+            match self.c_name(name) {
+                Ok(c_name) => c_names.push((i, c_name)),
+                Err(e) => files[i] = Some(Err(e)),
+            }
+        }
+
+        if !self.reader.is_supported(opcode::OpenAt::CODE) {
+            for (i, c_name) in &c_names {
+                /// SAFETY: a valid directory fd and a NUL terminated path
+                let fd =
+                    unsafe { libc::openat(self.dir.as_raw_fd(), c_name.as_ptr(), self.flags()) };
+                let fd = if fd < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(fd)
+                };
+                files[*i] = Some(self.opened(names[*i], fd));
+            }
+        } else if let Err(e) = self.run_opens(names, &c_names, &mut files) {
+            // The ring itself failed, every name without a result fails with it
+            for file in &mut files {
+                file.get_or_insert_with(|| Err(copy_error(&e)));
+            }
+        }
+        files
+            .into_iter()
+            .map(|file| file.expect("every name has an open result"))
+            .collect()
+    }
+
+    /// The submit/reap loop behind `open_many`, Err only if the ring itself fails
+    ///
+    /// Slot i is the open of `c_names[i]`.
+    #[allow(unused_doc_comments)]
+    fn run_opens(
+        &self,
+        names: &[&Path],
+        c_names: &[(usize, CString)],
+        files: &mut [Option<io::Result<File>>],
+    ) -> io::Result<()> {
+        let dir = types::Fd(self.dir.as_raw_fd());
+        let open = |c_name: &CString| {
+            opcode::OpenAt::new(dir, c_name.as_ptr())
+                .flags(self.flags())
+                .build()
+        };
+        /// Declared after `c_names`: the kernel reads them until it is dropped
+        let mut session = self.reader.session();
+        let depth = self.reader.depth();
+        let mut next = 0;
+        loop {
+            let mut pushed = false;
+            while next < c_names.len() && session.in_flight() < depth {
+                session.push(next as u32, open(&c_names[next].1))?;
+                next += 1;
+                pushed = true;
+            }
+            if session.in_flight() == 0 {
+                return Ok(());
+            }
+            if pushed {
+                session.submit()?;
+            }
+            let cqe = session.next()?;
+            let slot = cqe.user_data() as u32;
+            let (i, c_name) = &c_names[slot as usize];
+            match cqe.into_result() {
+                Err(e) if is_retryable(&e) => {
+                    session.push(slot, open(c_name))?;
+                    session.submit()?;
+                }
+                fd => files[*i] = Some(self.opened(names[*i], fd.map(|fd| fd as i32))),
+            }
+        }
+    }
+
+    /// The `File` of a finished open, ELOOP of a `sandbox` open is the symlink it refused
+    fn opened(&self, name: &Path, fd: io::Result<i32>) -> io::Result<File> {
+        match fd {
+            // SAFETY: the kernel just handed us this fd, nobody else owns it
+            Ok(fd) => Ok(unsafe { File::from_raw_fd(fd) }),
+            Err(e) if self.sandbox && e.raw_os_error() == Some(libc::ELOOP) => {
+                Err(self.escapes(name))
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use async_io::Async;
+use futures_core::Stream;
+
+use std::fs::File;
+use std::future::Future;
+use std::io;
+use std::os::fd::OwnedFd;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use crate::config::UringConfig;
+use crate::reader::UringReader;
+
+/// A `UringReader` for async-io based executors (smol, async-std, ...), feature `async-io`
+///
+/// Instead of the notifier thread of the plain async reads, the ring's eventfd sits in the executor's
+/// reactor: `new` hands out a `Driver` future that has to be spawned next to the tasks using the
+/// reader. Cheap to clone, every clone is the same ring and any number of tasks can read at once.
+///
+/// ```no_run
+/// use uring_fast_read::{AsyncUring, UringConfig};
+///
+/// let (uring, driver) = AsyncUring::new(UringConfig::default()).unwrap();
+/// # let spawn = |_driver| {};
+/// spawn(driver); // smol::spawn(driver).detach(), ...
+/// let data = async_io::block_on(uring.read_to_vec("/etc/hostname")).unwrap();
+/// ```
+///
+/// `current_thread` skips the `Driver`: the reads drive the ring themselves, for a simple CLI on
+/// `async_io::block_on` or a single threaded executor.
+#[derive(Clone)]
+pub struct AsyncUring {
+    reader: Arc<UringReader>,
+    /// The eventfd in the reactor, for `current_thread` (None: a `Driver` pumps it)
+    eventfd: Option<Arc<Async<OwnedFd>>>,
+}
+
+/// Reaps the completion queue whenever the eventfd fires and wakes the tasks it belongs to
+///
+/// Never finishes on its own, it lives as long as the executor keeps it. Once it is dropped the reads
+/// still work, but every pending one is polled again right away instead of waiting for its wake up.
+pub struct Driver {
+    reader: Arc<UringReader>,
+    eventfd: Async<OwnedFd>,
+}
+
+impl AsyncUring {
+    /// Create the ring and register its eventfd, see `UringReader::new`
+    pub fn new(config: UringConfig) -> io::Result<(AsyncUring, Driver)> {
+        let reader = Arc::new(UringReader::new(config)?);
+        let eventfd = Async::new(reader.external_notifier()?.eventfd().try_clone_to_owned()?)?;
+        let driver = Driver {
+            reader: Arc::clone(&reader),
+            eventfd,
+        };
+        Ok((
+            AsyncUring {
+                reader,
+                eventfd: None,
+            },
+            driver,
+        ))
+    }
+
+    /// Create the ring without a `Driver`: every read pumps the eventfd itself while it is pending
+    ///
+    /// A pending read first reaps what is there without blocking. With nothing of its own in it, it
+    /// waits for the eventfd in the executor's reactor, no task or thread runs next to it. Whichever
+    /// read wakes up reaps for everybody: completions of the other reads are parked for them and
+    /// their wakers fired, the same bookkeeping the `Driver` uses. Any number of reads can be pending
+    /// on one thread.
+    ///
+    /// Only futures of this `AsyncUring` pump, wrap the other async reads of `reader()` in `drive`.
+    pub fn current_thread(config: UringConfig) -> io::Result<AsyncUring> {
+        let reader = Arc::new(UringReader::new(config)?);
+        let eventfd = Async::new(reader.external_notifier()?.eventfd().try_clone_to_owned()?)?;
+        Ok(AsyncUring {
+            reader,
+            eventfd: Some(Arc::new(eventfd)),
+        })
+    }
+
+    /// `future` (or a stream) with the ring pumped while it is pending (`current_thread`)
+    ///
+    /// For the futures of `reader()` (`read_owned`, the `read_many_stream` streams), which only register
+    /// their wakers and leave the reaping to whoever drives the eventfd. With a `Driver` it is the
+    /// future as it is.
+    pub fn drive<F>(&self, future: F) -> Driven<F> {
+        Driven {
+            future,
+            pumping: Pumping {
+                reader: Arc::clone(&self.reader),
+                eventfd: self.eventfd.clone(),
+            },
+        }
+    }
+
+    /// The reader underneath, for everything that is not async
+    pub fn reader(&self) -> &UringReader {
+        &self.reader
+    }
+
+    /// Read a whole file into memory, see `UringReader::read_file_to_vec`
+    ///
+    /// The buffer is allocated once with the file size and filled by reads that own it while in flight
+    /// (`read_owned`), as few as possible and one after the other. `max_bytes` applies, files without a
+    /// size are read `chunk_size` at a time until EOF. Opening the file is a blocking `open`.
+    pub async fn read_to_vec(&self, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        self.reader.check_size(path, size)?;
+
+        let mut data = Vec::with_capacity(size as usize);
+        loop {
+            if size == 0 {
+                data.reserve(self.reader.config.chunk_size);
+            } else if data.len() as u64 >= size {
+                return Ok(data);
+            }
+            let offset = data.len() as u64;
+            let completed = self
+                .drive(self.reader.read_owned(&file, offset, data))
+                .await?;
+            data = completed.buf;
+            if completed.bytes == 0 {
+                return Ok(data);
+            }
+            if size == 0 {
+                self.reader.check_size(path, data.len() as u64)?;
+            }
+        }
+    }
+}
+
+/// A future of `AsyncUring::drive`
+///
+/// Polls the future, and while it is pending the eventfd: each time the eventfd fires the ring is
+/// reaped. If the eventfd fails the reader stops waiting for it, wakers are then woken right away
+/// like after a `Driver` was dropped.
+pub struct Driven<F> {
+    future: F,
+    pumping: Pumping,
+}
+
+/// What a `Driven` pumps, `eventfd` is None when there is nothing to (a `Driver` does it, or it failed)
+struct Pumping {
+    reader: Arc<UringReader>,
+    eventfd: Option<Arc<Async<OwnedFd>>>,
+}
+
+/// Reap whenever `eventfd` fires and wake every registered task, Pending once it has nothing more
+///
+/// The one loop behind `Driver` and `Driven`. Only the last waker polling the eventfd is woken by the
+/// reactor, that's enough: the reap wakes everybody else.
+fn pump(reader: &UringReader, eventfd: &Async<OwnedFd>, cx: &mut Context<'_>) -> Poll<io::Error> {
+    loop {
+        if let Err(e) = std::task::ready!(eventfd.poll_readable(cx)) {
+            return Poll::Ready(e);
+        }
+        let mut count = 0u64;
+        // SAFETY: reads 8 bytes into `count`, the fd is non-blocking (`Async::new`)
+        let n = unsafe { libc::read(eventfd.as_raw_fd(), (&raw mut count).cast(), 8) };
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            match e.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => {}
+                _ => return Poll::Ready(e),
+            }
+        }
+        reader.reap_ready();
+        if let Some(notifier) = reader.notifier() {
+            notifier.wake_all();
+        }
+    }
+}
+
+impl<F> Driven<F> {
+    /// The inner future, still pinned, and the pumping next to it
+    fn project(self: Pin<&mut Self>) -> (Pin<&mut F>, &mut Pumping) {
+        // SAFETY: `future` is never moved out of the pinned `Driven`, `pumping` isn't pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        // SAFETY: see above
+        (
+            unsafe { Pin::new_unchecked(&mut this.future) },
+            &mut this.pumping,
+        )
+    }
+}
+
+impl Pumping {
+    /// The inner future is pending: pump the eventfd until it is (or stop using it if it failed)
+    fn pending(&mut self, cx: &mut Context<'_>) {
+        if let Some(eventfd) = &self.eventfd
+            && let Poll::Ready(_) = pump(&self.reader, eventfd, cx)
+        {
+            self.eventfd = None;
+            if let Some(notifier) = self.reader.notifier() {
+                notifier.detach();
+            }
+        }
+    }
+}
+
+impl<F: Future> Future for Driven<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let (future, pumping) = self.project();
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(output);
+        }
+        pumping.pending(cx);
+        Poll::Pending
+    }
+}
+
+impl<S: Stream> Stream for Driven<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let (stream, pumping) = self.project();
+        if let Poll::Ready(item) = stream.poll_next(cx) {
+            return Poll::Ready(item);
+        }
+        pumping.pending(cx);
+        Poll::Pending
+    }
+}
+
+impl Future for Driver {
+    type Output = io::Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        pump(&self.reader, &self.eventfd, cx).map(Err)
+    }
+}
+
+impl Drop for Driver {
+    fn drop(&mut self) {
+        if let Some(notifier) = self.reader.notifier() {
+            notifier.detach();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::tests::scratch_dir;
+    use std::io::Write;
+    use std::time::Duration;
+
+    /// Several reads pending at once on `block_on`, the only thread that touches the ring
+    #[test]
+    fn current_thread_reads_side_by_side() {
+        let dir = scratch_dir("current-thread");
+        let mut paths = Vec::new();
+        let mut expected = Vec::new();
+        for (i, size) in [3 * 4096 + 7, 1, 64 * 1024].into_iter().enumerate() {
+            let path = dir.join(format!("file-{i}"));
+            let data: Vec<u8> = (0..size).map(|b| (b * 7 + i) as u8).collect();
+            std::fs::write(&path, &data).unwrap();
+            paths.push(path);
+            expected.push(data);
+        }
+        // A fifo can't be done before its writer is, the other reads finish while it waits
+        let fifo = dir.join("fifo");
+        let c_fifo = crate::chain::c_path(&fifo).unwrap();
+        // SAFETY: `c_fifo` is a NUL terminated path
+        assert_eq!(unsafe { libc::mkfifo(c_fifo.as_ptr(), 0o600) }, 0);
+        let writer = std::thread::spawn({
+            let fifo = fifo.clone();
+            move || {
+                let mut tx = File::options().write(true).open(fifo).unwrap();
+                for part in [b"late ", b"data "] {
+                    std::thread::sleep(Duration::from_millis(30));
+                    tx.write_all(part).unwrap();
+                }
+            }
+        });
+        paths.push(fifo);
+        expected.push(b"late data ".to_vec());
+
+        let uring = AsyncUring::current_thread(UringConfig::default().chunk_size(4096)).unwrap();
+        let mut reads: Vec<_> = paths
+            .iter()
+            .map(|path| Some(Box::pin(uring.read_to_vec(path))))
+            .collect();
+        let mut results: Vec<Option<io::Result<Vec<u8>>>> = paths.iter().map(|_| None).collect();
+        async_io::block_on(std::future::poll_fn(|cx| {
+            for (read, result) in reads.iter_mut().zip(&mut results) {
+                if let Some(future) = read
+                    && let Poll::Ready(done) = future.as_mut().poll(cx)
+                {
+                    *result = Some(done);
+                    *read = None;
+                }
+            }
+            match results.iter().all(Option::is_some) {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            }
+        }));
+        writer.join().unwrap();
+
+        for (result, expected) in results.into_iter().zip(expected) {
+            assert_eq!(result.unwrap().unwrap(), expected);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use crate::caps::Capabilities;
+use crate::config::UringConfig;
+use crate::{Backend, UringReader};
+
+/// What a reader asked for next to what it got, for support bundles (`UringConfig::to_effective`)
+///
+/// - backend -> io_uring or the `std::fs` fallback
+/// - requested -> the config as it was written, `UringConfig::from_effective` gives it back
+/// - granted -> what the reader actually runs with, see `Granted`
+/// - capabilities -> the kernel features the ring was created with, registered buffers and memlock
+///   degradation included
+///
+/// With the feature `serde` the whole thing serializes (field names are a stable format, fields are
+/// only added), two dumps of different machines can be diffed as JSON. A `RetryPolicy::retryable`
+/// classifier is code and doesn't travel, a restored policy uses `is_transient`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EffectiveConfig {
+    pub backend: Backend,
+    pub requested: UringConfig,
+    pub granted: Granted,
+    pub capabilities: Capabilities,
+}
+
+/// The values a reader settled on, where they can differ from the `UringConfig` it was made from
+/// - queue_depth -> ring size, halved until it fit with `MemlockPolicy::Shrink`
+/// - chunk_size -> size of one chunk read
+/// - fixed_buffers -> (count, size) of the registered buffers, None if none were registered
+/// - file_table -> the registered file table of the linked chains: Some(true) registered,
+///   Some(false) refused by the kernel, None not needed so far (it's set up on first use)
+/// - tuned_depth -> where `UringConfig::auto_tune` is right now, None without it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Granted {
+    pub queue_depth: u32,
+    pub chunk_size: usize,
+    pub fixed_buffers: Option<(usize, usize)>,
+    pub file_table: Option<bool>,
+    pub tuned_depth: Option<u32>,
+}
+
+impl UringConfig {
+    /// This config (what was requested) next to what `reader`, created from it, was granted
+    pub fn to_effective(&self, reader: &UringReader) -> EffectiveConfig {
+        let capabilities = reader.capabilities();
+        let granted = reader.config();
+        EffectiveConfig {
+            backend: crate::backend(),
+            requested: self.clone(),
+            granted: Granted {
+                queue_depth: granted.queue_depth,
+                chunk_size: granted.chunk_size,
+                fixed_buffers: (capabilities.fixed_buffers > 0)
+                    .then_some((capabilities.fixed_buffers, capabilities.fixed_buffer_size)),
+                file_table: reader.file_table(),
+                tuned_depth: reader.stats().tuned_depth,
+            },
+            capabilities,
+        }
+    }
+
+    /// The requested config of a dump, to build an equivalent reader (on this machine)
+    ///
+    /// Only what was asked for is applied. What was granted there is not copied, the new reader
+    /// probes this kernel and its limits again: compare its `to_effective` with the dump to see
+    /// where the machines differ.
+    pub fn from_effective(effective: &EffectiveConfig) -> Self {
+        effective.requested.clone()
+    }
+}
@@ -0,0 +1,400 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::stats::{CloseReport, FileStamp, TreeCopyReport};
+
+/// Errors this crate reports on top of plain OS errors
+///
+/// The public APIs keep returning `std::io::Result`, a `ReadError` travels inside the `io::Error`
+/// (with a matching `io::ErrorKind`). Get it back with `ReadError::from_io`:
+/// ```no_run
+/// use uring_fast_read::{ReadError, UringConfig, UringReader};
+///
+/// let reader = UringReader::new(UringConfig::default()).unwrap();
+/// if let Err(e) = reader.read_file_to_vec("/dev/zero") {
+///     if let Some(ReadError::FileTooLarge { limit, .. }) = ReadError::from_io(&e) {
+///         eprintln!("refusing to read more than {limit} bytes");
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ReadError {
+    /// The file is bigger than `UringConfig::max_bytes`
+    /// - size -> the size we saw (for files without a size, how much was read before giving up)
+    /// - limit -> the configured limit
+    FileTooLarge {
+        path: PathBuf,
+        size: u64,
+        limit: u64,
+    },
+    /// A `SandboxedReader` path tried to leave its root (`..`, an absolute path, a symlink pointing
+    /// outside, a `/proc` magic link); the kernel refused with EXDEV or ELOOP
+    PathEscapesSandbox { root: PathBuf, path: PathBuf },
+    /// A `LineReader` line is longer than its `max_line_len`
+    LineTooLong { path: PathBuf, limit: usize },
+    /// The file ends inside a record (`RecordReader` with `TrailingRecord::Error`)
+    /// - record -> index of the cut off record
+    /// - len -> the bytes of it that are there
+    PartialRecord {
+        path: PathBuf,
+        record: u64,
+        len: usize,
+        record_size: usize,
+    },
+    /// `read_decompressed` input that is truncated or corrupt
+    /// - offset -> position in the compressed file the decoder had reached when it gave up
+    /// - reason -> what the decoder said
+    CorruptInput {
+        path: PathBuf,
+        offset: u64,
+        reason: String,
+    },
+    /// The fd of a `PreparedRead` went bad (ESTALE, EBADF, ENODEV, ENXIO), rebuild the template
+    PreparedReadInvalid { path: PathBuf, source: io::Error },
+    /// A stage of a linked chain (`read_linked`, `write_file_atomic`) failed
+    /// - stage -> the one that actually failed, `source` is its error
+    /// - canceled -> the stages after it the kernel canceled because of it (they never ran)
+    ChainFailed {
+        path: PathBuf,
+        stage: Stage,
+        source: io::Error,
+        canceled: Vec<Stage>,
+    },
+    /// A request still failed after `attempts` tries (`UringConfig::retry`), `source` is the last error
+    RetriesExhausted { attempts: u32, source: io::Error },
+    /// One part of `read_concat` failed, so the whole concatenation did
+    /// - index -> position of the part in `paths`
+    /// - source -> why: the open/statx/read error, or `UnexpectedEof` for a part that came back shorter
+    ///   than its size
+    PartFailed {
+        index: usize,
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// Pinning memory failed on the RLIMIT_MEMLOCK limit (`UringConfig::memlock_policy` is `Strict`,
+    /// or even the smallest ring didn't fit)
+    /// - what -> "creating the ring", "registering buffers"
+    /// - wanted -> bytes we tried to pin (for the ring an estimate of its queues)
+    /// - limit -> the soft RLIMIT_MEMLOCK, None if unlimited
+    MemlockLimit {
+        what: &'static str,
+        wanted: u64,
+        limit: Option<u64>,
+        source: io::Error,
+    },
+    /// `close` went through every step but some of them failed, `report` says which
+    CloseFailed { report: CloseReport },
+    /// The file got shorter while it was read: `expected` bytes when it was sized, EOF at `actual`
+    /// (`UringConfig::on_shrink` is `ShrinkPolicy::Fail`)
+    FileChangedDuringRead {
+        path: PathBuf,
+        expected: u64,
+        actual: u64,
+    },
+    /// The stat after the read doesn't match the one before it (`UringConfig::on_change`), the data
+    /// may mix two versions of the file
+    /// - attempts -> reads done, the retries of `ChangePolicy::Retry` included
+    ModifiedDuringRead {
+        path: PathBuf,
+        before: FileStamp,
+        after: FileStamp,
+        attempts: u32,
+    },
+    /// The ring's read and a `std::fs::read` of the unchanged file disagree
+    /// (`VerifyPolicy::Fail`)
+    /// - len / std_len -> bytes the ring read, bytes std read (the same when only the contents differ)
+    VerificationFailed {
+        path: PathBuf,
+        len: u64,
+        std_len: u64,
+    },
+    /// The file wasn't done when the deadline of the batch passed (`read_many_files_until`), its
+    /// reads were canceled
+    Deadline { path: PathBuf },
+    /// A request completed with ECANCELED, `reason` says who canceled it
+    Cancelled { reason: Cancelled },
+    /// `copy_tree` with `CopyTreeOptions::fail_fast` stopped at the first entry that failed
+    /// - path -> that entry (in the source tree), `source` is its error
+    /// - report -> what was done before it, the entries after it were never tried
+    TreeCopyAborted {
+        path: PathBuf,
+        source: io::Error,
+        report: TreeCopyReport,
+    },
+}
+
+/// Why a request was canceled, what its -ECANCELED completion turns into (`ReadError::Cancelled`,
+/// `Completion::cancelled`)
+/// - ByTimeout -> its deadline passed (`UringConfig::timeout`, or the budget of the call) and the
+///   crate canceled it. `io::ErrorKind::TimedOut`.
+/// - ByUser -> canceled on request: `ReadOwned::cancel`, `ReadPoller::cancel_all`, a scan that
+///   returned `Break`, a dropped future
+/// - ChainAborted -> never ran, an earlier link of its chain failed with the errno `cause` (0: it
+///   came up short, a short read or write breaks a chain too) and the kernel canceled the rest
+///
+/// A cancel the crate didn't ask for (by another process' `AsyncCancel`, a kernel without the
+/// bookkeeping) stays a plain ECANCELED OS error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Cancelled {
+    ByTimeout,
+    ByUser,
+    ChainAborted { cause: i32 },
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cancelled::ByTimeout => f.write_str("canceled, its deadline passed"),
+            Cancelled::ByUser => f.write_str("canceled on request"),
+            Cancelled::ChainAborted { cause: 0 } => {
+                f.write_str("canceled, an earlier link of its chain came up short")
+            }
+            Cancelled::ChainAborted { cause } => write!(
+                f,
+                "canceled, an earlier link of its chain failed: {}",
+                io::Error::from_raw_os_error(*cause)
+            ),
+        }
+    }
+}
+
+/// Whether `e` is a canceled request, typed (`ReadError::Cancelled`) or a plain ECANCELED
+#[cfg(target_os = "linux")]
+pub(crate) fn is_cancelled(e: &io::Error) -> bool {
+    if e.raw_os_error() == Some(libc::ECANCELED) {
+        return true;
+    }
+    matches!(ReadError::from_io(e), Some(ReadError::Cancelled { .. }))
+}
+
+impl ReadError {
+    /// The `ReadError` inside an `io::Error`, if there is one
+    pub fn from_io(e: &io::Error) -> Option<&ReadError> {
+        e.get_ref()
+            .and_then(|inner| inner.downcast_ref::<ReadError>())
+    }
+
+    /// The `io::ErrorKind` this error is reported with
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            ReadError::FileTooLarge { .. } => io::ErrorKind::FileTooLarge,
+            ReadError::PathEscapesSandbox { .. } => io::ErrorKind::PermissionDenied,
+            ReadError::LineTooLong { .. } => io::ErrorKind::InvalidData,
+            ReadError::PartialRecord { .. } => io::ErrorKind::UnexpectedEof,
+            ReadError::CorruptInput { .. } => io::ErrorKind::InvalidData,
+            ReadError::PreparedReadInvalid { source, .. } => source.kind(),
+            ReadError::ChainFailed { source, .. } => source.kind(),
+            ReadError::RetriesExhausted { source, .. } => source.kind(),
+            ReadError::PartFailed { source, .. } => source.kind(),
+            ReadError::MemlockLimit { source, .. } => source.kind(),
+            ReadError::CloseFailed { .. } => io::ErrorKind::Other,
+            ReadError::FileChangedDuringRead { .. } => io::ErrorKind::UnexpectedEof,
+            ReadError::ModifiedDuringRead { .. } => io::ErrorKind::InvalidData,
+            ReadError::VerificationFailed { .. } => io::ErrorKind::InvalidData,
+            ReadError::Deadline { .. } => io::ErrorKind::TimedOut,
+            ReadError::Cancelled {
+                reason: Cancelled::ByTimeout,
+            } => io::ErrorKind::TimedOut,
+            ReadError::Cancelled { .. } => io::ErrorKind::Other,
+            ReadError::TreeCopyAborted { source, .. } => source.kind(),
+        }
+    }
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::FileTooLarge { path, size, limit } => write!(
+                f,
+                "{} is {size} bytes, more than the {limit} byte limit (UringConfig::max_bytes); \
+                 read it in chunks instead of all at once, or raise the limit",
+                path.display()
+            ),
+            ReadError::PathEscapesSandbox { root, path } => write!(
+                f,
+                "{} resolves outside of the sandbox root {}",
+                path.display(),
+                root.display()
+            ),
+            ReadError::LineTooLong { path, limit } => write!(
+                f,
+                "{} has a line longer than {limit} bytes (LineReader::max_line_len)",
+                path.display()
+            ),
+            ReadError::PartialRecord {
+                path,
+                record,
+                len,
+                record_size,
+            } => write!(
+                f,
+                "{} ends inside record {record}: {len} of {record_size} bytes",
+                path.display()
+            ),
+            ReadError::CorruptInput {
+                path,
+                offset,
+                reason,
+            } => write!(
+                f,
+                "{} can't be decompressed, decoding failed at byte {offset}: {reason}",
+                path.display()
+            ),
+            ReadError::PreparedReadInvalid { path, source } => write!(
+                f,
+                "prepared read of {} is no longer valid, prepare it again: {source}",
+                path.display()
+            ),
+            ReadError::ChainFailed {
+                path,
+                stage,
+                source,
+                canceled,
+            } => {
+                write!(f, "{}: {stage} failed: {source}", path.display())?;
+                if !canceled.is_empty() {
+                    let names: Vec<String> = canceled.iter().map(Stage::to_string).collect();
+                    write!(f, " (canceled: {})", names.join(", "))?;
+                }
+                Ok(())
+            }
+            ReadError::RetriesExhausted { attempts, source } => {
+                write!(f, "{source} (gave up after {attempts} attempts)")
+            }
+            ReadError::PartFailed {
+                index,
+                path,
+                source,
+            } => write!(f, "part {index} ({}) failed: {source}", path.display()),
+            ReadError::MemlockLimit {
+                what,
+                wanted,
+                limit,
+                source,
+            } => {
+                write!(
+                    f,
+                    "{what} failed pinning {wanted} bytes: {source}; RLIMIT_MEMLOCK is "
+                )?;
+                match limit {
+                    Some(limit) => write!(f, "{limit} bytes")?,
+                    None => f.write_str("unlimited")?,
+                }
+                f.write_str(
+                    " (raise it: ulimit -l, LimitMEMLOCK= in systemd, --ulimit memlock= in docker)",
+                )
+            }
+            ReadError::CloseFailed { report } => {
+                write!(
+                    f,
+                    "closing the reader failed: {}",
+                    report.failures.join("; ")
+                )
+            }
+            ReadError::FileChangedDuringRead {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} shrank while it was read: {expected} bytes when it was sized, EOF at {actual}",
+                path.display()
+            ),
+            ReadError::ModifiedDuringRead {
+                path,
+                before,
+                after,
+                attempts,
+            } => write!(
+                f,
+                "{} changed while it was read ({attempts} attempts): {} bytes, inode {} when it \
+                 was opened, {} bytes, inode {} after the read{}",
+                path.display(),
+                before.size,
+                before.ino,
+                after.size,
+                after.ino,
+                if before.mtime == after.mtime {
+                    ""
+                } else {
+                    ", the mtime moved"
+                }
+            ),
+            ReadError::VerificationFailed { path, len, std_len } if len == std_len => write!(
+                f,
+                "{}: the io_uring read and std::fs::read of the unchanged file differ, {len} bytes \
+                 each (UringConfig::verify_against_std)",
+                path.display()
+            ),
+            ReadError::VerificationFailed { path, len, std_len } => write!(
+                f,
+                "{}: the io_uring read returned {len} bytes of the unchanged file, std::fs::read \
+                 {std_len} (UringConfig::verify_against_std)",
+                path.display()
+            ),
+            ReadError::Deadline { path } => write!(
+                f,
+                "{} was abandoned, the batch ran past its deadline",
+                path.display()
+            ),
+            ReadError::Cancelled { reason } => reason.fmt(f),
+            ReadError::TreeCopyAborted {
+                path,
+                source,
+                report,
+            } => write!(
+                f,
+                "copying {} failed, the tree copy stopped there (fail_fast) after {} entries: \
+                 {source}",
+                path.display(),
+                report.entries.len()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReadError::RetriesExhausted { source, .. } => Some(source),
+            ReadError::PreparedReadInvalid { source, .. } => Some(source),
+            ReadError::ChainFailed { source, .. } => Some(source),
+            ReadError::PartFailed { source, .. } => Some(source),
+            ReadError::MemlockLimit { source, .. } => Some(source),
+            ReadError::TreeCopyAborted { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// One step of a linked chain, see `ReadError::ChainFailed`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    Open,
+    Read,
+    Write,
+    Fsync,
+    Close,
+    Rename,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Stage::Open => "open",
+            Stage::Read => "read",
+            Stage::Write => "write",
+            Stage::Fsync => "fsync",
+            Stage::Close => "close",
+            Stage::Rename => "rename",
+        })
+    }
+}
+
+impl From<ReadError> for io::Error {
+    fn from(e: ReadError) -> Self {
+        io::Error::new(e.kind(), e)
+    }
+}
@@ -0,0 +1,200 @@
+#[cfg(target_os = "linux")]
+use io_uring::{opcode, squeue};
+
+#[cfg(target_os = "linux")]
+use std::path::PathBuf;
+#[cfg(target_os = "linux")]
+use std::sync::Mutex;
+
+#[cfg(target_os = "linux")]
+use crate::completion::Completion;
+#[cfg(target_os = "linux")]
+use crate::journal::SqeFields;
+#[cfg(target_os = "linux")]
+use crate::reader::{lock, reads_data};
+
+/// What an injected fault turns a read into (`FailRule::new`)
+/// - Errno -> the read fails with this errno (EIO 5, EAGAIN 11, ENOSPC 28, ...)
+/// - ShortRead -> the read returns half of what it read, reads of less than 2 bytes are left alone
+/// - Timeout -> the read fails with ETIMEDOUT, an `io::ErrorKind::TimedOut` like the one a passed
+///   `UringConfig::timeout` reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InjectedFault {
+    Errno(i32),
+    ShortRead,
+    Timeout,
+}
+
+/// One fault and the reads it hits, see `FailPoints`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailRule {
+    pub(crate) fault: InjectedFault,
+    pub(crate) probability: f64,
+    pub(crate) path: Option<String>,
+    pub(crate) instead: bool,
+}
+
+impl FailRule {
+    /// Every read gets `fault`, narrow it down with `probability` and `path_contains`
+    pub fn new(fault: InjectedFault) -> Self {
+        FailRule {
+            fault,
+            probability: 1.0,
+            path: None,
+            instead: false,
+        }
+    }
+
+    /// Share of the reads that get the fault, 0.0 to 1.0 (default 1.0, all of them)
+    pub fn probability(mut self, probability: f64) -> Self {
+        self.probability = probability.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Only reads of files whose path contains `pattern` (default: any file)
+    ///
+    /// The path is what `/proc/self/fd` says about the fd of the read, the absolute path with symlinks
+    /// resolved. Reads of registered files (`SequentialReader`, the linked chains) have no fd to look
+    /// at and never match a rule with a path.
+    pub fn path_contains(mut self, pattern: impl Into<String>) -> Self {
+        self.path = Some(pattern.into());
+        self
+    }
+
+    /// Don't issue the read at all, a NOP takes its place and completes with the fault (default off:
+    /// the read is done, only its result is replaced)
+    ///
+    /// Either way ring and buffers stay consistent, the kernel never writes into a buffer after the
+    /// call saw the fault. Ignored for `InjectedFault::ShortRead`, which needs the real read.
+    pub fn instead(mut self, on: bool) -> Self {
+        self.instead = on;
+        self
+    }
+}
+
+/// Faults injected into the reads of a reader, to test what sits on top of it (feature `failpoints`)
+///
+/// ```no_run
+/// use uring_fast_read::{FailPoints, FailRule, InjectedFault, UringConfig, UringReader};
+///
+/// let points = FailPoints::new(42)
+///     .rule(FailRule::new(InjectedFault::Errno(5)).path_contains("/shard-3/")) // EIO
+///     .rule(FailRule::new(InjectedFault::ShortRead).probability(0.2));
+/// let reader = UringReader::new(UringConfig::default().failpoints(Some(points))).unwrap();
+/// ```
+///
+/// Every read (`IORING_OP_READ`, `READ_FIXED`, `READV`) is checked against the rules in order when it
+/// is pushed, the first one that matches and wins its roll decides the fault. The rolls come from a
+/// generator seeded with `seed`: the same calls in the same order get the same faults. Reads on
+/// several threads interleave, only the share of faults is reproducible then.
+///
+/// The fault is applied where the CQE becomes a `Completion` for the call that waits on it, so the
+/// retry, short read and error paths of the crate handle it like a real one.
+/// `ReadStats::injected_faults` counts what was applied (`uring_injected_faults` with `metrics`),
+/// anything beyond that was real. Only the io_uring reader injects, the std fallback ignores it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailPoints {
+    pub(crate) seed: u64,
+    pub(crate) rules: Vec<FailRule>,
+}
+
+impl FailPoints {
+    /// No rules yet, `seed` makes the rolls reproducible
+    pub fn new(seed: u64) -> Self {
+        FailPoints {
+            seed,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Add a rule, rules are checked in the order they were added
+    pub fn rule(mut self, rule: FailRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+}
+
+/// The running `FailPoints` of one reader
+/// - rng -> splitmix64 state, shared by every session of the reader
+#[cfg(target_os = "linux")]
+pub(crate) struct Injector {
+    rules: Vec<FailRule>,
+    rng: Mutex<u64>,
+}
+
+#[cfg(target_os = "linux")]
+impl Injector {
+    pub(crate) fn new(points: &FailPoints) -> Self {
+        Injector {
+            rules: points.rules.clone(),
+            rng: Mutex::new(points.seed),
+        }
+    }
+
+    /// The fault for `entry` if it gets one, and the entry that goes into the ring in its place
+    pub(crate) fn plan(&self, entry: squeue::Entry) -> (squeue::Entry, Option<InjectedFault>) {
+        if !reads_data(entry.get_opcode()) {
+            return (entry, None);
+        }
+        let mut path = None;
+        let rule = self.rules.iter().find(|rule| {
+            if let Some(pattern) = &rule.path {
+                let path = path.get_or_insert_with(|| target_path(&entry));
+                if !path
+                    .as_ref()
+                    .is_some_and(|path| path.to_string_lossy().contains(pattern.as_str()))
+                {
+                    return false;
+                }
+            }
+            self.roll() < rule.probability
+        });
+        let Some(rule) = rule else {
+            return (entry, None);
+        };
+        if rule.instead && rule.fault != InjectedFault::ShortRead {
+            return (stand_in(&entry), Some(rule.fault));
+        }
+        (entry, Some(rule.fault))
+    }
+
+    /// Uniform in [0, 1)
+    fn roll(&self) -> f64 {
+        let mut state = lock(&self.rng);
+        *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)) as f64 / (u64::MAX as f64 + 1.0)
+    }
+}
+
+/// `cqe` with `fault` applied, None if it doesn't apply (a short read of a read that got nothing)
+#[cfg(target_os = "linux")]
+pub(crate) fn apply(fault: InjectedFault, cqe: Completion) -> Option<Completion> {
+    match fault {
+        InjectedFault::Errno(errno) => Some(cqe.with_result(-errno)),
+        InjectedFault::Timeout => Some(cqe.with_result(-libc::ETIMEDOUT)),
+        InjectedFault::ShortRead if cqe.result() > 1 => Some(cqe.with_result(cqe.result() / 2)),
+        InjectedFault::ShortRead => None,
+    }
+}
+
+/// Path of the file `entry` reads, None for registered files and fds without one
+#[cfg(target_os = "linux")]
+fn target_path(entry: &squeue::Entry) -> Option<PathBuf> {
+    let fd = SqeFields::of(entry).fd?;
+    std::fs::read_link(format!("/proc/self/fd/{fd}")).ok()
+}
+
+/// A NOP with the user_data and flags (links included) of `entry`
+#[cfg(target_os = "linux")]
+fn stand_in(entry: &squeue::Entry) -> squeue::Entry {
+    let flags = squeue::Flags::from_bits_truncate(SqeFields::of(entry).flags)
+        - squeue::Flags::FIXED_FILE
+        - squeue::Flags::BUFFER_SELECT;
+    opcode::Nop::new()
+        .build()
+        .flags(flags)
+        .user_data(entry.get_user_data())
+}
@@ -0,0 +1,788 @@
+//! The slow path: `std::fs` behind the same API, for targets without io_uring
+//!
+//! Everything here is plain blocking I/O, one syscall after the other, no ring, no batching. It exists
+//! so code that only uses io_uring on Linux still builds and runs everywhere else (macOS dev machines,
+//! Windows CI). Check `crate::backend()` if you need to know which one you got.
+//!
+//! - `UringConfig` is accepted as is, only `chunk_size` (buffer size of `UringFile`), `max_bytes` and
+//!   `on_change` mean something here
+//! - `ReadStats` stays all zero
+//! - `RingPool`, `SandboxedReader`, `PreparedRead`, `read_owned`, `ReadPoller`, personalities, xattrs,
+//!   io-wq limits and the linked chains (`read_linked`, `write_file_atomic`) only exist on Linux
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::marker::PhantomData;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::Instant;
+
+use crate::backend::{ReadBackend, Reaped, copy_error};
+use crate::caps::Capabilities;
+use crate::config::{ChangePolicy, DedupPolicy, PadPolicy, UringConfig};
+use crate::dedup::{dedup, fan_out};
+use crate::error::ReadError;
+use crate::stats::{
+    CloseReport, CopyReport, DrainReport, FileMeta, FileStamp, Partial, ReadOutcome, ReadStats,
+    RingSnapshot, TreeCopyReport, dense,
+};
+use crate::tree::{self, CopyTreeOptions, TreeOps};
+use crate::walk::walk_files;
+
+/// `std::fs` stand-in for the io_uring reader, see the module docs
+pub struct UringReader {
+    pub(crate) config: UringConfig,
+    stats: Mutex<ReadStats>,
+}
+
+/// Lock a mutex, ignoring poisoning
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl UringReader {
+    /// Never fails here, there is no ring to set up
+    pub fn new(config: UringConfig) -> io::Result<Self> {
+        Ok(UringReader {
+            config,
+            stats: Mutex::new(ReadStats::default()),
+        })
+    }
+
+    /// The configuration this reader was created with
+    pub fn config(&self) -> &UringConfig {
+        &self.config
+    }
+
+    /// Always all zero on this backend
+    pub fn stats(&self) -> ReadStats {
+        lock(&self.stats).clone()
+    }
+
+    /// No ring, no features: everything is false
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    /// There is no file table to register
+    pub(crate) fn file_table(&self) -> Option<bool> {
+        None
+    }
+
+    /// There is no ring, every field is 0
+    pub fn ring_snapshot(&self) -> RingSnapshot {
+        RingSnapshot::default()
+    }
+
+    /// Nothing is ever in flight on this backend, the report is always empty
+    pub fn drain(&self, _timeout: Option<std::time::Duration>) -> DrainReport {
+        DrainReport::default()
+    }
+
+    /// Nothing is registered on this backend, closing just drops the reader
+    pub fn close(self) -> io::Result<CloseReport> {
+        Ok(CloseReport::default())
+    }
+
+    /// The process wide reader behind convenience constructors like `LineReader::open`
+    pub(crate) fn shared_default() -> io::Result<&'static UringReader> {
+        static DEFAULT: OnceLock<UringReader> = OnceLock::new();
+        Ok(DEFAULT.get_or_init(|| UringReader {
+            config: UringConfig::default(),
+            stats: Mutex::new(ReadStats::default()),
+        }))
+    }
+
+    /// Read the first block (up to 4096 bytes) of `path`
+    pub fn read_one_file(&self, path: impl AsRef<Path>) -> io::Result<usize> {
+        read_block(path.as_ref())
+    }
+
+    /// Read a whole file into memory
+    pub fn read_file_to_vec(&self, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        let path = path.as_ref();
+        if self.config.on_change != ChangePolicy::Ignore {
+            return self.read_file_stamped(path).map(|(data, _)| data);
+        }
+        self.read_open_file(File::open(path)?, path)
+    }
+
+    /// Read a whole file, with its metadata from before the read, checked against `path` after it
+    pub fn read_file_stamped(&self, path: impl AsRef<Path>) -> io::Result<(Vec<u8>, FileStamp)> {
+        let path = path.as_ref();
+        let retries = match self.config.on_change {
+            ChangePolicy::Retry(retries) => retries,
+            ChangePolicy::Ignore | ChangePolicy::Fail => 0,
+        };
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let file = File::open(path)?;
+            let before = FileStamp::from_metadata(&file.metadata()?)?;
+            let data = self.read_open_file(file, path)?;
+            let after = FileStamp::from_metadata(&fs::metadata(path)?)?;
+            if after == before {
+                return Ok((data, after));
+            }
+            if attempts > retries {
+                return Err(ReadError::ModifiedDuringRead {
+                    path: path.to_path_buf(),
+                    before,
+                    after,
+                    attempts,
+                }
+                .into());
+            }
+        }
+    }
+
+    /// `read_file_to_vec` for a file that is already open, from its current position
+    #[allow(unused_doc_comments)]
+    pub(crate) fn read_open_file(&self, file: File, path: &Path) -> io::Result<Vec<u8>> {
+        let size = file.metadata()?.len();
+        self.check_size(path, size)?;
+
+        /// Files without a size are read one byte past the limit, to notice one that is too big
+        let limit = self.config.max_bytes.map_or(u64::MAX, |limit| limit + 1);
+        let mut data = Vec::with_capacity(size as usize);
+        file.take(limit).read_to_end(&mut data)?;
+        self.check_size(path, data.len() as u64)?;
+        Ok(data)
+    }
+
+    /// Same as `read_file_to_vec`, moved into an `Arc`
+    pub fn read_to_shared(&self, path: impl AsRef<Path>) -> io::Result<Arc<[u8]>> {
+        self.read_file_to_vec(path).map(Arc::from)
+    }
+
+    /// `read_to_shared` as `bytes::Bytes`
+    #[cfg(feature = "bytes")]
+    pub fn read_to_bytes(&self, path: impl AsRef<Path>) -> io::Result<bytes::Bytes> {
+        self.read_file_to_vec(path).map(bytes::Bytes::from)
+    }
+
+    /// Read the files one after the other, results are in the same order as `paths`
+    pub fn read_many_files<P: AsRef<Path>>(&self, paths: &[P]) -> Vec<io::Result<Vec<u8>>> {
+        let paths: Vec<&Path> = paths.iter().map(AsRef::as_ref).collect();
+        let deduped = dedup(&paths, self.config.dedup);
+        let results = deduped
+            .unique
+            .iter()
+            .map(|path| self.read_file_to_vec(path))
+            .collect();
+        fan_out(results, &deduped.slots, Vec::clone)
+    }
+
+    /// Same as the io_uring `read_many_to_map`
+    pub fn read_many_to_map<P: AsRef<Path>>(
+        &self,
+        paths: &[P],
+    ) -> HashMap<PathBuf, io::Result<Arc<[u8]>>> {
+        let paths: Vec<&Path> = paths.iter().map(AsRef::as_ref).collect();
+        let policy = match self.config.dedup {
+            DedupPolicy::Off => DedupPolicy::Lexical,
+            policy => policy,
+        };
+        let deduped = dedup(&paths, policy);
+        let results = deduped
+            .unique
+            .iter()
+            .map(|path| self.read_to_shared(path))
+            .collect();
+        let results = fan_out(results, &deduped.slots, Arc::clone);
+        paths
+            .into_iter()
+            .map(Path::to_path_buf)
+            .zip(results)
+            .collect()
+    }
+
+    /// `std::fs::metadata` of every path, one after the other
+    pub fn stat_many<P: AsRef<Path>>(&self, paths: &[P]) -> Vec<(PathBuf, io::Result<FileMeta>)> {
+        paths
+            .iter()
+            .map(|path| {
+                let path = path.as_ref();
+                let meta = fs::metadata(path).and_then(|m| FileMeta::from_metadata(&m));
+                (path.to_path_buf(), meta)
+            })
+            .collect()
+    }
+
+    /// Same as the io_uring `read_many_files_until`, a file that was started is always finished
+    pub fn read_many_files_until<P: AsRef<Path>>(
+        &self,
+        paths: &[P],
+        deadline: Instant,
+    ) -> Partial<io::Result<Vec<u8>>> {
+        let mut partial = Partial {
+            results: Vec::with_capacity(paths.len()),
+            finished: 0,
+            abandoned: 0,
+            cancelled: 0,
+            failed: 0,
+        };
+        for path in paths {
+            let path = path.as_ref();
+            let result = if Instant::now() >= deadline {
+                partial.abandoned += 1;
+                Err(ReadError::Deadline {
+                    path: path.to_path_buf(),
+                }
+                .into())
+            } else {
+                let result = self.read_file_to_vec(path);
+                match result {
+                    Ok(_) => partial.finished += 1,
+                    Err(_) => partial.failed += 1,
+                }
+                result
+            };
+            partial.results.push(result);
+        }
+        partial
+    }
+
+    /// Read `paths` back to back into one buffer, one after the other
+    ///
+    /// Same checks as on io_uring: sizes are taken first, a part that is missing or shorter than that
+    /// fails the call with `ReadError::PartFailed`.
+    pub fn read_concat<P: AsRef<Path>>(&self, paths: &[P]) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.read_concat_to(paths, &mut data)?;
+        Ok(data)
+    }
+
+    /// `read_concat` into a writer, part by part
+    pub fn read_concat_to<P: AsRef<Path>>(
+        &self,
+        paths: &[P],
+        out: &mut impl io::Write,
+    ) -> io::Result<u64> {
+        let mut parts = Vec::with_capacity(paths.len());
+        let mut total = 0u64;
+        for (i, path) in paths.iter().enumerate() {
+            let path = path.as_ref();
+            let part = File::open(path)
+                .and_then(|file| Ok((file.metadata()?.len(), file)))
+                .map_err(|e| part_failed(i, path, e))?;
+            total += part.0;
+            self.check_size(path, total)?;
+            parts.push(part);
+        }
+
+        let mut buf = Vec::new();
+        for (i, (size, mut file)) in parts.into_iter().enumerate() {
+            buf.resize(size as usize, 0);
+            file.read_exact(&mut buf)
+                .map_err(|e| part_failed(i, paths[i].as_ref(), e))?;
+            out.write_all(&buf)?;
+        }
+        Ok(total)
+    }
+
+    /// Read every regular file below `root`, sorted by path
+    pub fn read_tree(
+        &self,
+        root: impl AsRef<Path>,
+    ) -> io::Result<Vec<(PathBuf, io::Result<Vec<u8>>)>> {
+        let walk = walk_files(root.as_ref())?;
+        let mut entries: Vec<_> = walk
+            .files
+            .into_iter()
+            .map(|path| {
+                let data = self.read_file_to_vec(&path);
+                (path, data)
+            })
+            .collect();
+        entries.extend(walk.errors.into_iter().map(|(path, e)| (path, Err(e))));
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(entries)
+    }
+
+    /// Same as the io_uring `read_tree_until`
+    pub fn read_tree_until(
+        &self,
+        root: impl AsRef<Path>,
+        deadline: Instant,
+    ) -> io::Result<Partial<(PathBuf, io::Result<Vec<u8>>)>> {
+        let walk = walk_files(root.as_ref())?;
+        let read = self.read_many_files_until(&walk.files, deadline);
+        let mut entries: Vec<_> = walk.files.into_iter().zip(read.results).collect();
+        let unwalked = walk.errors.len();
+        entries.extend(walk.errors.into_iter().map(|(path, e)| (path, Err(e))));
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(Partial {
+            results: entries,
+            finished: read.finished,
+            abandoned: read.abandoned,
+            cancelled: read.cancelled,
+            failed: read.failed + unwalked,
+        })
+    }
+
+    /// `std::fs::copy`
+    pub fn copy_file(&self, src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<u64> {
+        fs::copy(src, dst)
+    }
+
+    /// `copy_file` as a report, this one is always dense
+    pub fn copy_file_report(
+        &self,
+        src: impl AsRef<Path>,
+        dst: impl AsRef<Path>,
+    ) -> io::Result<CopyReport> {
+        TreeOps::copy(self, src.as_ref(), dst.as_ref(), None, false)
+    }
+
+    /// Same as the io_uring `copy_tree`, with `std::fs` calls (symlinks only on unix)
+    pub fn copy_tree(
+        &self,
+        src_root: impl AsRef<Path>,
+        dst_root: impl AsRef<Path>,
+        options: CopyTreeOptions,
+    ) -> io::Result<TreeCopyReport> {
+        tree::copy_tree(self, src_root.as_ref(), dst_root.as_ref(), &options)
+    }
+
+    /// Open `path` for sequential reading, a `BufReader` with `chunk_size` bytes of buffer
+    pub fn open_file(&self, path: impl AsRef<Path>) -> io::Result<UringFile<'_>> {
+        self.uring_file(File::open(path)?)
+    }
+
+    /// `open_file` for a file that is already open, from its current position
+    pub(crate) fn uring_file(&self, file: File) -> io::Result<UringFile<'_>> {
+        Ok(UringFile {
+            inner: BufReader::with_capacity(self.config.chunk_size, file),
+            reader: PhantomData,
+        })
+    }
+
+    pub(crate) fn check_size(&self, path: &Path, size: u64) -> io::Result<()> {
+        match self.config.max_bytes {
+            Some(limit) if size > limit => Err(ReadError::FileTooLarge {
+                path: path.to_path_buf(),
+                size,
+                limit,
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Same as the io_uring `read_chunks_with`, one blocking read per chunk
+    pub fn read_chunks_with(
+        &self,
+        path: impl AsRef<Path>,
+        chunk_size: usize,
+        mut f: impl FnMut(u64, &[u8]) -> ControlFlow<()>,
+    ) -> io::Result<ReadOutcome> {
+        let mut file = File::open(path)?;
+        let mut buf = Vec::with_capacity(chunk_size.max(1));
+        let mut consumed = 0u64;
+        loop {
+            buf.clear();
+            (&mut file)
+                .take(buf.capacity() as u64)
+                .read_to_end(&mut buf)?;
+            let n = buf.len();
+            if n == 0 {
+                return Ok(ReadOutcome {
+                    consumed,
+                    stopped: false,
+                });
+            }
+            let offset = consumed;
+            consumed += n as u64;
+            if self.config.pad == PadPolicy::ZeroFill {
+                buf.resize(buf.capacity(), 0);
+            }
+            if f(offset, &buf).is_break() {
+                return Ok(ReadOutcome {
+                    consumed,
+                    stopped: true,
+                });
+            }
+        }
+    }
+
+    /// Same as the io_uring `read_at`, a seek and a read
+    pub fn read_at(
+        &self,
+        path: impl AsRef<Path>,
+        offset: u64,
+        len: usize,
+    ) -> io::Result<(Vec<u8>, usize)> {
+        self.read_ranges(path, std::slice::from_ref(&(offset..offset + len as u64)))
+            .pop()
+            .expect("one result per range")
+    }
+
+    /// Same as the io_uring `read_ranges`, one range after the other
+    pub fn read_ranges(
+        &self,
+        path: impl AsRef<Path>,
+        ranges: &[std::ops::Range<u64>],
+    ) -> Vec<io::Result<(Vec<u8>, usize)>> {
+        let path = path.as_ref();
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => return ranges.iter().map(|_| Err(copy_error(&e))).collect(),
+        };
+        ranges
+            .iter()
+            .map(|range| {
+                let len = range.end.saturating_sub(range.start);
+                self.check_size(path, len)?;
+                file.seek(SeekFrom::Start(range.start))?;
+                let mut data = Vec::with_capacity(len as usize);
+                (&mut file).take(len).read_to_end(&mut data)?;
+                let n = data.len();
+                if self.config.pad == PadPolicy::ZeroFill {
+                    data.resize(len as usize, 0);
+                }
+                Ok((data, n))
+            })
+            .collect()
+    }
+}
+
+/// Same as the io_uring `SequentialReader`, a seek and a read per block, never registered
+pub struct SequentialReader<'r> {
+    file: File,
+    offset: u64,
+    reader: PhantomData<&'r UringReader>,
+}
+
+impl<'r> SequentialReader<'r> {
+    pub fn open(_reader: &'r UringReader, path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(SequentialReader {
+            file: File::open(path)?,
+            offset: 0,
+            reader: PhantomData,
+        })
+    }
+
+    /// Accepted and ignored, there is nothing to advise here
+    pub fn read_ahead(self, _on: bool) -> Self {
+        self
+    }
+
+    pub fn read_next(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        self.file.seek(SeekFrom::Start(self.offset))?;
+        let mut buf = Vec::with_capacity(len);
+        (&mut self.file).take(len as u64).read_to_end(&mut buf)?;
+        self.offset += buf.len() as u64;
+        Ok(buf)
+    }
+
+    pub fn skip(&mut self, n: u64) {
+        self.offset = self.offset.saturating_add(n);
+    }
+
+    pub fn rewind(&mut self) {
+        self.offset = 0;
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn is_registered(&self) -> bool {
+        false
+    }
+}
+
+/// Same as the io_uring `DirHandle`, but every open resolves the whole joined path again (no dirfd
+/// here), so the directory isn't pinned
+pub struct DirHandle<'r> {
+    reader: &'r UringReader,
+    path: PathBuf,
+    sandbox: bool,
+}
+
+impl<'r> DirHandle<'r> {
+    pub fn open(reader: &'r UringReader, path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        if !fs::metadata(path)?.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotADirectory,
+                format!("{} is not a directory", path.display()),
+            ));
+        }
+        Ok(DirHandle {
+            reader,
+            path: path.to_path_buf(),
+            sandbox: false,
+        })
+    }
+
+    pub fn sandbox(mut self, on: bool) -> Self {
+        self.sandbox = on;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn read_file(&self, name: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        self.reader.read_file_to_vec(self.resolve(name.as_ref())?)
+    }
+
+    pub fn read_many<P: AsRef<Path>>(&self, names: &[P]) -> Vec<io::Result<Vec<u8>>> {
+        names.iter().map(|name| self.read_file(name)).collect()
+    }
+
+    /// The joined path, with the checks of `sandbox` (a single plain name that isn't a symlink)
+    fn resolve(&self, name: &Path) -> io::Result<PathBuf> {
+        let path = self.path.join(name);
+        if self.sandbox {
+            let mut components = name.components();
+            let plain = matches!(
+                (components.next(), components.next()),
+                (Some(std::path::Component::Normal(_)), None)
+            );
+            if !plain || fs::symlink_metadata(&path).is_ok_and(|meta| meta.is_symlink()) {
+                return Err(ReadError::PathEscapesSandbox {
+                    root: self.path.clone(),
+                    path: name.to_path_buf(),
+                }
+                .into());
+            }
+        }
+        Ok(path)
+    }
+}
+
+/// A file read front to back, a plain `BufReader<File>` on this backend
+pub struct UringFile<'r> {
+    inner: BufReader<File>,
+    reader: PhantomData<&'r UringReader>,
+}
+
+impl UringFile<'_> {
+    /// Continue reading at `offset`, the buffer is thrown away
+    pub(crate) fn seek_to(&mut self, offset: u64) -> io::Result<()> {
+        self.inner.seek(SeekFrom::Start(offset)).map(drop)
+    }
+}
+
+impl Read for UringFile<'_> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(out)
+    }
+}
+
+impl BufRead for UringFile<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.inner.consume(amount)
+    }
+}
+
+/// `ReadBackend` on top of `std::fs`, every request is done (blocking) when it is submitted
+pub struct UringBackend<'r> {
+    files: HashMap<PathBuf, File>,
+    done: Vec<(u64, Reaped)>,
+    reader: PhantomData<&'r UringReader>,
+}
+
+impl UringReader {
+    /// A `ReadBackend` on this reader, for `BackendReader`
+    pub fn backend(&self) -> UringBackend<'_> {
+        UringBackend {
+            files: HashMap::new(),
+            done: Vec::new(),
+            reader: PhantomData,
+        }
+    }
+}
+
+impl UringBackend<'_> {
+    fn file(&mut self, path: &Path) -> io::Result<&mut File> {
+        if !self.files.contains_key(path) {
+            self.files.insert(path.to_path_buf(), File::open(path)?);
+        }
+        Ok(self.files.get_mut(path).expect("just inserted"))
+    }
+
+    fn read_at(&mut self, path: &Path, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let file = self.file(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut buf = Vec::with_capacity(len);
+        file.take(len as u64).read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+impl ReadBackend for UringBackend<'_> {
+    fn submit_read(&mut self, tag: u64, path: &Path, offset: u64, len: usize) -> io::Result<()> {
+        let data = self.read_at(path, offset, len);
+        self.done.push((tag, Reaped::Read(data)));
+        Ok(())
+    }
+
+    fn submit_statx(&mut self, tag: u64, path: &Path) -> io::Result<()> {
+        let size = self
+            .file(path)
+            .and_then(|file| file.metadata())
+            .map(|meta| meta.len());
+        self.done.push((tag, Reaped::Statx(size)));
+        Ok(())
+    }
+
+    fn wait(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn reap(&mut self) -> Vec<(u64, Reaped)> {
+        std::mem::take(&mut self.done)
+    }
+}
+
+/// Same as the io_uring `read_one_file`: read the first block (up to 4096 bytes) of `path`
+pub fn read_one_file(path: &str) -> io::Result<usize> {
+    read_block(Path::new(path))
+}
+
+fn read_block(path: &Path) -> io::Result<usize> {
+    let mut buffer = vec![0u8; 4096];
+    let mut file = File::open(path)?;
+    loop {
+        match file.read(&mut buffer) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            other => return other,
+        }
+    }
+}
+
+impl TreeOps for UringReader {
+    fn make_dir(&self, path: &Path, mode: u32) -> io::Result<()> {
+        #[cfg(unix)]
+        return std::os::unix::fs::DirBuilderExt::mode(&mut fs::DirBuilder::new(), mode)
+            .create(path);
+        #[cfg(not(unix))]
+        return {
+            let _ = mode;
+            fs::create_dir(path)
+        };
+    }
+
+    fn copy(
+        &self,
+        src: &Path,
+        dst: &Path,
+        sparse: Option<bool>,
+        fsync: bool,
+    ) -> io::Result<CopyReport> {
+        let copied = fs::copy(src, dst)?;
+        if fsync {
+            File::options().write(true).open(dst)?.sync_all()?;
+        }
+        Ok(CopyReport {
+            logical_size: copied,
+            copied,
+            holes: 0,
+            dense_reason: sparse
+                .unwrap_or(self.config.preserve_sparse)
+                .then_some(dense::FALLBACK),
+            ..CopyReport::default()
+        })
+    }
+
+    fn make_link(&self, target: &Path, link: &Path) -> io::Result<()> {
+        #[cfg(unix)]
+        return std::os::unix::fs::symlink(target, link);
+        #[cfg(not(unix))]
+        return {
+            let _ = (target, link);
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the std fallback recreates symlinks only on unix",
+            ))
+        };
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+}
+
+fn part_failed(index: usize, path: &Path, source: io::Error) -> io::Error {
+    ReadError::PartFailed {
+        index,
+        path: path.to_path_buf(),
+        source,
+    }
+    .into()
+}
+
+#[cfg(feature = "async")]
+pub use stream::ReadManyStream;
+
+#[cfg(feature = "async")]
+mod stream {
+    use futures_core::Stream;
+
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use super::UringReader;
+
+    /// Files as a `futures_core::Stream`, every poll reads the next file synchronously
+    pub struct ReadManyStream<'r> {
+        reader: &'r UringReader,
+        paths: std::vec::IntoIter<PathBuf>,
+    }
+
+    impl UringReader {
+        /// Read many whole files as a stream, in the order of `paths` on this backend
+        pub fn read_many_stream<P: AsRef<Path>>(
+            &self,
+            paths: impl IntoIterator<Item = P>,
+        ) -> ReadManyStream<'_> {
+            let paths: Vec<PathBuf> = paths
+                .into_iter()
+                .map(|p| p.as_ref().to_path_buf())
+                .collect();
+            ReadManyStream {
+                reader: self,
+                paths: paths.into_iter(),
+            }
+        }
+
+        /// Same as `read_many_stream`, this backend is always in order
+        pub fn read_many_stream_ordered<P: AsRef<Path>>(
+            &self,
+            paths: impl IntoIterator<Item = P>,
+        ) -> ReadManyStream<'_> {
+            self.read_many_stream(paths)
+        }
+    }
+
+    impl Stream for ReadManyStream<'_> {
+        type Item = (PathBuf, io::Result<Vec<u8>>);
+
+        fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            let this = self.get_mut();
+            Poll::Ready(this.paths.next().map(|path| {
+                let data = this.reader.read_file_to_vec(&path);
+                (path, data)
+            }))
+        }
+
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            (self.paths.len(), Some(self.paths.len()))
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! A C ABI for callers that are not Rust (feature `ffi`, Linux only)
+//!
+//! `cargo build --release --features ffi` builds the shared library (`liburing_fast_read.so`), the
+//! header is `include/uring_fast_read.h` (regenerate it with cbindgen, the command is in
+//! `cbindgen.toml`). `tests/ffi.c` is a small C program against both, run by `cargo test --features
+//! ffi`.
+//!
+//! ```c
+//! uint8_t *buf;
+//! size_t len;
+//! int err = uring_fast_read("/etc/hostname", &buf, &len);
+//! if (err == 0) {
+//!     fwrite(buf, 1, len, stdout);
+//!     uring_buf_free(buf, len);
+//! }
+//! ```
+//!
+//! Every function returns 0 on success, a positive errno (`ENOENT`, `EFBIG` for `max_bytes`, ...) on
+//! failure, or `URING_PANICKED` if the crate panicked, no panic ever crosses into C. Buffers handed out
+//! are allocated by the crate and must go back through `uring_buf_free` with their length, never
+//! `free()`.
+
+use std::ffi::{CStr, OsStr, c_char, c_int};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+
+use crate::config::UringConfig;
+use crate::reader::UringReader;
+
+/// Returned when the crate panicked, the panic was caught at the boundary
+pub const URING_PANICKED: c_int = -1;
+
+/// Read a whole file with a ring of its own, see `UringReader::read_file_to_vec`
+///
+/// On success `*out_buf` / `*out_len` are the contents, free them with `uring_buf_free`. On failure
+/// they are set to NULL / 0.
+///
+/// # Safety
+///
+/// `path` is a NUL terminated string, `out_buf` and `out_len` are valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn uring_fast_read(
+    path: *const c_char,
+    out_buf: *mut *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    guard(|| {
+        let reader = UringReader::new(UringConfig::default())?;
+        // SAFETY: forwarded from the caller
+        unsafe { read_into(&reader, path, out_buf, out_len) }
+    })
+}
+
+/// Create a persistent reader, `queue_depth` 0 keeps the default
+///
+/// On success `*out` is the reader, free it with `uring_reader_free`. It may be used from several
+/// threads at once.
+///
+/// # Safety
+///
+/// `out` is valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn uring_reader_new(queue_depth: u32, out: *mut *mut UringReader) -> c_int {
+    guard(|| {
+        let mut config = UringConfig::default();
+        if queue_depth > 0 {
+            config = config.queue_depth(queue_depth);
+        }
+        let reader = UringReader::new(config)?;
+        // SAFETY: the caller guarantees `out` is valid for writes
+        unsafe { out.write(Box::into_raw(Box::new(reader))) };
+        Ok(())
+    })
+}
+
+/// `uring_fast_read` on a reader of `uring_reader_new`
+///
+/// # Safety
+///
+/// `reader` came from `uring_reader_new` and was not freed, the rest as for `uring_fast_read`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn uring_reader_read(
+    reader: *const UringReader,
+    path: *const c_char,
+    out_buf: *mut *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    guard(|| {
+        // SAFETY: the caller guarantees `reader` is alive
+        let reader = unsafe { reader.as_ref() }.ok_or(io::ErrorKind::InvalidInput)?;
+        // SAFETY: forwarded from the caller
+        unsafe { read_into(reader, path, out_buf, out_len) }
+    })
+}
+
+/// Free a reader of `uring_reader_new`, NULL is ignored
+///
+/// Waits (up to a second) for requests still in flight, like dropping a `UringReader`.
+///
+/// # Safety
+///
+/// `reader` came from `uring_reader_new`, is not used by any other thread anymore and is not freed twice.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn uring_reader_free(reader: *mut UringReader) {
+    if reader.is_null() {
+        return;
+    }
+    // SAFETY: the caller hands the reader back, it was leaked from a Box by `uring_reader_new`
+    let reader = unsafe { Box::from_raw(reader) };
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(reader)));
+}
+
+/// Free a buffer handed out by `uring_fast_read` / `uring_reader_read`, NULL is ignored
+///
+/// # Safety
+///
+/// `buf` and `len` are exactly what the read returned, and it is not freed twice.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn uring_buf_free(buf: *mut u8, len: usize) {
+    if buf.is_null() {
+        return;
+    }
+    // SAFETY: `buf` / `len` are a boxed slice leaked by `read_into`
+    drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(buf, len)) });
+}
+
+/// Read `path` and hand the buffer out through `out_buf` / `out_len`
+///
+/// SAFETY: `path` is a NUL terminated string, `out_buf` / `out_len` are valid for writes
+unsafe fn read_into(
+    reader: &UringReader,
+    path: *const c_char,
+    out_buf: *mut *mut u8,
+    out_len: *mut usize,
+) -> io::Result<()> {
+    if path.is_null() || out_buf.is_null() || out_len.is_null() {
+        return Err(io::ErrorKind::InvalidInput.into());
+    }
+    // SAFETY: the caller guarantees these are valid for writes
+    unsafe {
+        out_buf.write(ptr::null_mut());
+        out_len.write(0);
+    }
+    // SAFETY: the caller guarantees `path` is NUL terminated
+    let path = Path::new(OsStr::from_bytes(
+        unsafe { CStr::from_ptr(path) }.to_bytes(),
+    ));
+    let data = reader.read_file_to_vec(path)?.into_boxed_slice();
+    let len = data.len();
+    // SAFETY: as above
+    unsafe {
+        out_len.write(len);
+        out_buf.write(Box::into_raw(data).cast());
+    }
+    Ok(())
+}
+
+/// Run `f`, turning its error into an errno and a panic into `URING_PANICKED`
+fn guard(f: impl FnOnce() -> io::Result<()>) -> c_int {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => 0,
+        Ok(Err(e)) => errno(&e),
+        Err(_) => URING_PANICKED,
+    }
+}
+
+/// The errno of an OS error, a close one for the crate's own errors (`ReadError`)
+fn errno(e: &io::Error) -> c_int {
+    if let Some(code) = e.raw_os_error() {
+        return code;
+    }
+    match e.kind() {
+        io::ErrorKind::NotFound => libc::ENOENT,
+        io::ErrorKind::PermissionDenied => libc::EACCES,
+        io::ErrorKind::FileTooLarge => libc::EFBIG,
+        io::ErrorKind::TimedOut => libc::ETIMEDOUT,
+        io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => libc::EINVAL,
+        io::ErrorKind::Unsupported => libc::EOPNOTSUPP,
+        io::ErrorKind::OutOfMemory => libc::ENOMEM,
+        io::ErrorKind::Interrupted => libc::EINTR,
+        _ => libc::EIO,
+    }
+}
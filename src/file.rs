@@ -0,0 +1,145 @@
+use io_uring::{opcode, types};
+
+use std::fs::File;
+use std::io::{self, BufRead, Read};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use crate::reader::{Session, UringReader, is_retryable};
+
+/// A seek reads from the block boundary before its offset, the chunks stay block aligned
+const SEEK_ALIGN: u64 = 4096;
+
+/// A file read front to back through the ring, with one chunk always read ahead
+///
+/// While the caller works on the current chunk the kernel is already filling the next one, so a
+/// consumer that is about as fast as the disk never waits. Implements `Read` and `BufRead`, anything
+/// that takes a `BufRead` (`lines()`, `read_until`, parsers, ...) works on top of it.
+///
+/// Chunks are `UringConfig::chunk_size` bytes. `UringConfig::timeout` applies to every single wait
+/// for a chunk, not to the lifetime of the file.
+pub struct UringFile<'r> {
+    /// Declared first so it is dropped first, dropping it waits for the read ahead, which writes
+    /// into `ahead`
+    session: Session<'r>,
+    file: File,
+    buf: Vec<u8>,
+    pos: usize,
+    filled: usize,
+    ahead: Vec<u8>,
+    /// offset of the read ahead (the one in flight, or the next one to issue)
+    ahead_offset: u64,
+    /// bytes at the start of the next chunk that are before the offset of a seek
+    skip: usize,
+    eof: bool,
+}
+
+impl UringReader {
+    /// Open `path` for sequential reading, see `UringFile`
+    pub fn open_file(&self, path: impl AsRef<Path>) -> io::Result<UringFile<'_>> {
+        self.uring_file(File::open(path)?)
+    }
+
+    /// `open_file` for a file that is already open, reading starts at offset 0
+    pub(crate) fn uring_file(&self, file: File) -> io::Result<UringFile<'_>> {
+        let chunk_size = self.config.chunk_size;
+
+        let mut uring_file = UringFile {
+            session: self.session(),
+            file,
+            buf: vec![0; chunk_size],
+            pos: 0,
+            filled: 0,
+            ahead: vec![0; chunk_size],
+            ahead_offset: 0,
+            skip: 0,
+            eof: false,
+        };
+        uring_file.read_ahead()?;
+        Ok(uring_file)
+    }
+}
+
+impl UringFile<'_> {
+    /// Issue the read of the next chunk into `ahead`
+    fn read_ahead(&mut self) -> io::Result<()> {
+        let fd = types::Fd(self.file.as_raw_fd());
+        let read_e = opcode::Read::new(fd, self.ahead.as_mut_ptr(), self.ahead.len() as u32)
+            .offset(self.ahead_offset)
+            .build();
+        self.session.push(0, read_e)?;
+        self.session.submit()
+    }
+
+    /// Wait for the read ahead, swap it in as the current chunk and issue the one after it
+    fn advance(&mut self) -> io::Result<()> {
+        let n = loop {
+            self.session.restart_deadline();
+            match self.session.next()?.into_result() {
+                Ok(n) => break n as usize,
+                Err(e) if is_retryable(&e) => self.read_ahead()?,
+                Err(e) => return Err(e),
+            }
+        };
+
+        std::mem::swap(&mut self.buf, &mut self.ahead);
+        self.pos = self.skip.min(n);
+        self.skip -= self.pos;
+        self.filled = n;
+        if n == 0 {
+            self.eof = true;
+            return Ok(());
+        }
+        self.ahead_offset += n as u64;
+        let reader = self.session.reader();
+        reader.throttled(n as u64);
+        reader.admit(0);
+        self.read_ahead()
+    }
+
+    /// Continue reading at `offset`
+    ///
+    /// Inside the current chunk this only moves the position. Anywhere else the read ahead is waited
+    /// for (it writes into `ahead`) and thrown away, the next chunk is read from the block boundary
+    /// at or before `offset`.
+    pub(crate) fn seek_to(&mut self, offset: u64) -> io::Result<()> {
+        let start = self.ahead_offset - self.filled as u64;
+        if (start..=self.ahead_offset).contains(&offset) && self.skip == 0 && !self.eof {
+            self.pos = (offset - start) as usize;
+            return Ok(());
+        }
+        while self.session.in_flight() > 0 {
+            self.session.next()?;
+        }
+        let aligned = offset - offset % SEEK_ALIGN;
+        self.pos = 0;
+        self.filled = 0;
+        self.ahead_offset = aligned;
+        self.skip = (offset - aligned) as usize;
+        self.eof = false;
+        self.read_ahead()
+    }
+}
+
+impl Read for UringFile<'_> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for UringFile<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.filled && !self.eof {
+            self.advance()?;
+        }
+        Ok(&self.buf[self.pos..self.filled])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.filled);
+    }
+}
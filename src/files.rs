@@ -0,0 +1,1222 @@
+use io_uring::{opcode, squeue, types};
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::config::{ChangePolicy, DedupPolicy, ShrinkPolicy};
+use crate::dedup::{dedup, fan_out};
+use crate::error::{ReadError, is_cancelled};
+use crate::owned::read_spare;
+use crate::reader::{Session, UringReader, is_retryable, lock};
+use crate::retry::exhausted;
+use crate::stat::stamp;
+use crate::stats::{FileStamp, Partial};
+use crate::walk::walk_files;
+
+/// One buffer to fill from one fd, starting at `offset` in the file
+pub(crate) struct Region<'b> {
+    pub(crate) fd: types::Fd,
+    pub(crate) buf: &'b mut [u8],
+    pub(crate) offset: u64,
+}
+
+/// One `chunk_size` piece of a region
+/// - start/end -> position inside the region's buffer
+/// - done -> bytes already read (short reads are resubmitted from here)
+struct Chunk {
+    region: usize,
+    start: usize,
+    end: usize,
+    done: usize,
+    /// failed attempts so far (`UringConfig::retry`)
+    attempts: u32,
+}
+
+/// Slot bit of the timeout that delays a retry, the rest of the slot is the chunk (or probe read) to retry
+const RETRY_TIMER: u32 = 1 << 31;
+
+/// Size of the read linked behind the statx of `read_many_files`, files up to this are done with it
+const PROBE_BYTES: usize = 64 * 1024;
+
+/// One file of `read_many_files` during the linked statx + first read
+/// - stx/buf -> written by the kernel, boxed/heap so they don't move with the `Probe`
+/// - size/read -> the CQE results, None while in flight
+/// - attempts -> failed reads so far (`UringConfig::retry`)
+struct Probe {
+    index: usize,
+    file: File,
+    stx: Box<MaybeUninit<libc::statx>>,
+    buf: Vec<u8>,
+    size: Option<io::Result<u64>>,
+    read: Option<io::Result<u32>>,
+    attempts: u32,
+}
+
+/// A file that was opened and sized, waiting for its reads
+enum Opened {
+    /// The size is known, the buffer is already allocated
+    Sized(File, Vec<u8>),
+    /// `fstat` says 0 bytes, which for `/proc`, pipes, ... means "unknown"
+    Unsized(File),
+}
+
+impl UringReader {
+    /// Read a whole file into memory
+    ///
+    /// Ok(data) -> the file contents
+    /// Err(e) -> an OS error (open, stat or one of the reads), or `ReadError::FileTooLarge`
+    ///
+    /// The size comes from `fstat`, the buffer is allocated once with that size and then filled by up to
+    /// `queue_depth` chunk reads in parallel. Files that report a size of 0 (`/proc`, pipes, ...) are read
+    /// chunk by chunk until EOF instead.
+    ///
+    /// With `UringConfig::on_change` set this is `read_file_stamped` without the stamp.
+    pub fn read_file_to_vec(&self, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        let path = path.as_ref();
+        if self.config.on_change != ChangePolicy::Ignore {
+            return self.read_file_stamped(path).map(|(data, _)| data);
+        }
+        let verifying = self.verifying(path);
+        let result = self.read_open_file(File::open(path)?, path);
+        self.verified(path, verifying, result)
+    }
+
+    /// `read_file_to_vec` for a file that is already open, `path` is only used in errors
+    pub(crate) fn read_open_file(&self, file: File, path: &Path) -> io::Result<Vec<u8>> {
+        let size = file.metadata()?.len();
+        self.read_sized(types::Fd(file.as_raw_fd()), path, size)
+    }
+
+    /// Read a whole file, together with what statx said about it, checked after the read
+    ///
+    /// Ok((data, stamp)) -> the contents, and the size/mtime/inode that held both before and after
+    ///   they were read: key a cache with the stamp, no third stat needed
+    /// Err(e) -> like `read_file_to_vec`, or `ReadError::ModifiedDuringRead` with both stamps
+    ///
+    /// The first statx is of the open file, the one after the read is of `path` again, so a file that
+    /// was replaced (renamed over) in between counts as changed too. `UringConfig::on_change` decides
+    /// what a change does: `Retry(n)` opens and reads the file again up to `n` times, `Fail` and
+    /// `Ignore` fail right away (this call always checks, whatever the policy).
+    pub fn read_file_stamped(&self, path: impl AsRef<Path>) -> io::Result<(Vec<u8>, FileStamp)> {
+        let path = path.as_ref();
+        let verifying = self.verifying(path);
+        let (data, stamp) = self.read_stamped(path)?;
+        let data = self.verified(path, verifying, Ok(data))?;
+        Ok((data, stamp))
+    }
+
+    /// `read_file_stamped` without `UringConfig::verify_against_std`
+    fn read_stamped(&self, path: &Path) -> io::Result<(Vec<u8>, FileStamp)> {
+        let retries = match self.config.on_change {
+            ChangePolicy::Retry(retries) => retries,
+            ChangePolicy::Ignore | ChangePolicy::Fail => 0,
+        };
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let file = File::open(path)?;
+            let fd = types::Fd(file.as_raw_fd());
+            let before = stamp(&self.statx_fd(fd)?);
+            let data = self.read_sized(fd, path, before.size)?;
+            let after = stamp(&self.statx_path(path)?);
+            if after == before {
+                return Ok((data, after));
+            }
+            if attempts > retries {
+                return Err(ReadError::ModifiedDuringRead {
+                    path: path.to_path_buf(),
+                    before,
+                    after,
+                    attempts,
+                }
+                .into());
+            }
+            lock(&self.stats).modified_rereads += 1;
+        }
+    }
+
+    /// Read a file of `size` bytes (0 -> unknown, read until EOF) into a new buffer
+    fn read_sized(&self, fd: types::Fd, path: &Path, size: u64) -> io::Result<Vec<u8>> {
+        if size == 0 {
+            return self.read_fd_to_end(fd, path);
+        }
+        self.check_size(path, size)?;
+        let mut buffer = vec![0u8; size as usize];
+        let n = self.read_into(fd, &mut buffer, 0)?;
+        self.check_shrunk(path, buffer.len(), n)?;
+        buffer.truncate(n);
+        Ok(buffer)
+    }
+
+    /// Read a whole file into a buffer that can be shared without copying
+    ///
+    /// The `Arc<[u8]>` is allocated once with the size `statx` reports and the chunk reads land directly
+    /// in it, cloning it afterwards only bumps a reference count. Hand the same bytes to a cache, a hash
+    /// task and a network send without a `Vec` clone for each of them.
+    ///
+    /// Only unusual files pay for a copy: files that report no size (`/proc`, pipes) and files that
+    /// turned out shorter than `statx` said.
+    pub fn read_to_shared(&self, path: impl AsRef<Path>) -> io::Result<Arc<[u8]>> {
+        let path = path.as_ref();
+        let verifying = self.verifying(path);
+        let result = self.read_shared(path);
+        self.verified(path, verifying, result)
+    }
+
+    /// `read_to_shared` without `UringConfig::verify_against_std`
+    fn read_shared(&self, path: &Path) -> io::Result<Arc<[u8]>> {
+        let file = File::open(path)?;
+        let fd = types::Fd(file.as_raw_fd());
+
+        let size = self.statx_fd(fd)?.stx_size;
+        if size == 0 {
+            return self.read_fd_to_end(fd, path).map(Arc::from);
+        }
+        self.check_size(path, size)?;
+
+        // SAFETY: all zeroes is a valid `[u8]`
+        let mut shared = unsafe { Arc::<[u8]>::new_zeroed_slice(size as usize).assume_init() };
+        let buf = Arc::get_mut(&mut shared).expect("a new Arc is not shared yet");
+        let n = self.read_into(fd, buf, 0)?;
+        self.check_shrunk(path, shared.len(), n)?;
+
+        if n < shared.len() {
+            return Ok(Arc::from(&shared[..n]));
+        }
+        Ok(shared)
+    }
+
+    /// `read_to_shared` as `bytes::Bytes`, the `Arc` becomes the owner of the `Bytes`, nothing is copied
+    #[cfg(feature = "bytes")]
+    pub fn read_to_bytes(&self, path: impl AsRef<Path>) -> io::Result<bytes::Bytes> {
+        self.read_to_shared(path).map(bytes::Bytes::from_owner)
+    }
+
+    /// Read many whole files at once, results are in the same order as `paths`
+    ///
+    /// Every file is opened and sized first, then the reads of all of them share the ring: up to
+    /// `queue_depth` chunks are in flight at any time, no matter which file they belong to. A file that
+    /// fails (missing, too large, EIO, ...) only fails its own entry.
+    ///
+    /// Where the kernel has `IORING_OP_STATX` (5.6) the size doesn't cost a syscall of its own: the
+    /// statx of each file is hard linked to a first read of up to 64 KiB, both go in with the same
+    /// submit. Files that fit are done right there (`ReadStats::linked_statx_files`), only the rest of
+    /// bigger ones is read afterwards. Older kernels `fstat` every file first.
+    pub fn read_many_files<P: AsRef<Path>>(&self, paths: &[P]) -> Vec<io::Result<Vec<u8>>> {
+        let paths: Vec<&Path> = paths.iter().map(AsRef::as_ref).collect();
+        self.read_many_by(&paths, None)
+    }
+
+    /// `read_many_files` with a budget for the whole batch: whatever is done at `deadline` is kept
+    ///
+    /// Once the deadline passes no more reads are issued, the ones in flight are canceled and reaped
+    /// (no buffer is left with the kernel when this returns). Files that weren't done fail with
+    /// `ReadError::Deadline`, `Partial` counts them, the files that finished keep their data. This
+    /// is on top of `UringConfig::timeout`, which still bounds every single wait.
+    pub fn read_many_files_until<P: AsRef<Path>>(
+        &self,
+        paths: &[P],
+        deadline: Instant,
+    ) -> Partial<io::Result<Vec<u8>>> {
+        let paths: Vec<&Path> = paths.iter().map(AsRef::as_ref).collect();
+        let results = self.read_many_by(&paths, Some(deadline));
+        partial(results, &paths, deadline, |result| result)
+    }
+
+    /// Read many whole files into a map from path (as passed in) to shared contents
+    ///
+    /// The same file under several paths is read once and every one of them shares its buffer:
+    /// `UringConfig::dedup` decides what counts as the same, `DedupPolicy::Off` means `Lexical` here.
+    /// Otherwise the same as `read_many_files`.
+    pub fn read_many_to_map<P: AsRef<Path>>(
+        &self,
+        paths: &[P],
+    ) -> HashMap<PathBuf, io::Result<Arc<[u8]>>> {
+        let paths: Vec<&Path> = paths.iter().map(AsRef::as_ref).collect();
+        let policy = match self.config.dedup {
+            DedupPolicy::Off => DedupPolicy::Lexical,
+            policy => policy,
+        };
+        let deduped = dedup(&paths, policy);
+        lock(&self.stats).deduplicated_reads += deduped.saved() as u64;
+        let shared = self
+            .read_many_unique(&deduped.unique, None)
+            .into_iter()
+            .map(|result| result.map(Arc::from))
+            .collect();
+        let results = fan_out(shared, &deduped.slots, Arc::clone);
+        paths
+            .into_iter()
+            .map(Path::to_path_buf)
+            .zip(results)
+            .collect()
+    }
+
+    /// `read_many_files`, or `read_many_files_until` with a deadline, deduplicated with
+    /// `UringConfig::dedup`
+    fn read_many_by(&self, paths: &[&Path], deadline: Option<Instant>) -> Vec<io::Result<Vec<u8>>> {
+        if self.config.dedup == DedupPolicy::Off {
+            return self.read_many_unique(paths, deadline);
+        }
+        let deduped = dedup(paths, self.config.dedup);
+        if deduped.saved() == 0 {
+            return self.read_many_unique(paths, deadline);
+        }
+        lock(&self.stats).deduplicated_reads += deduped.saved() as u64;
+        let results = self.read_many_unique(&deduped.unique, deadline);
+        fan_out(results, &deduped.slots, Vec::clone)
+    }
+
+    /// `read_many_by` for paths that are read as they are
+    fn read_many_unique(
+        &self,
+        paths: &[&Path],
+        deadline: Option<Instant>,
+    ) -> Vec<io::Result<Vec<u8>>> {
+        let verifying = paths.iter().map(|path| self.verifying(path)).collect();
+        let files = paths
+            .iter()
+            .map(|path| {
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    return Err(timed_out());
+                }
+                File::open(path)
+            })
+            .collect();
+        let results = self.read_many_open(paths, files, deadline);
+        self.verified_many(paths, verifying, results)
+    }
+
+    /// The reads of `read_many_files` for files that are open already (or failed to open), `paths`
+    /// are only used in errors
+    #[allow(unused_doc_comments)]
+    pub(crate) fn read_many_open(
+        &self,
+        paths: &[&Path],
+        files: Vec<io::Result<File>>,
+        deadline: Option<Instant>,
+    ) -> Vec<io::Result<Vec<u8>>> {
+        let mut results: Vec<Option<io::Result<Vec<u8>>>> = paths.iter().map(|_| None).collect();
+        let mut opened = Vec::new();
+        for (i, file) in files.into_iter().enumerate() {
+            match file {
+                Ok(file) => opened.push((i, file)),
+                Err(e) => results[i] = Some(Err(e)),
+            }
+        }
+        /// Keep the files open (and the buffers alive) until every read has been reaped
+        /// (index, file, buffer, bytes of the buffer that were read already)
+        let mut sized: Vec<(usize, File, Vec<u8>, usize)> = Vec::new();
+        if self.is_supported(opcode::Statx::CODE) {
+            self.probe_many(paths, opened, &mut sized, &mut results, deadline);
+        } else {
+            for (i, file) in opened {
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    results[i] = Some(Err(timed_out()));
+                    continue;
+                }
+                match self.size_file(file, paths[i]) {
+                    Ok(Opened::Sized(file, buffer)) => sized.push((i, file, buffer, 0)),
+                    Ok(Opened::Unsized(file)) => {
+                        results[i] =
+                            Some(self.read_fd_to_end(types::Fd(file.as_raw_fd()), paths[i]))
+                    }
+                    Err(e) => results[i] = Some(Err(e)),
+                }
+            }
+        }
+
+        let mut regions: Vec<Region<'_>> = sized
+            .iter_mut()
+            .map(|(_, file, buffer, done)| Region {
+                fd: types::Fd(file.as_raw_fd()),
+                buf: &mut buffer[*done..],
+                offset: *done as u64,
+            })
+            .collect();
+        let lengths = self.read_regions_by(&mut regions, deadline);
+        drop(regions);
+
+        for ((i, _, mut buffer, done), n) in sized.into_iter().zip(lengths) {
+            results[i] = Some(n.and_then(|n| {
+                self.check_shrunk(paths[i], buffer.len(), done + n)?;
+                buffer.truncate(done + n);
+                Ok(buffer)
+            }));
+        }
+
+        results
+            .into_iter()
+            .map(|r| r.expect("every file has a result"))
+            .collect()
+    }
+
+    /// Statx and first read of every open file in one linked pair, see `read_many_files`
+    ///
+    /// Files that are done (or failed) go to `results`, the ones with more to read to `sized`.
+    #[allow(unused_doc_comments)]
+    fn probe_many(
+        &self,
+        paths: &[&Path],
+        opened: Vec<(usize, File)>,
+        sized: &mut Vec<(usize, File, Vec<u8>, usize)>,
+        results: &mut [Option<io::Result<Vec<u8>>>],
+        deadline: Option<Instant>,
+    ) {
+        let guess = PROBE_BYTES.min(self.config.chunk_size);
+        let mut probes: Vec<Probe> = opened
+            .into_iter()
+            .map(|(index, file)| Probe {
+                index,
+                file,
+                stx: Box::new(MaybeUninit::zeroed()),
+                buf: Vec::with_capacity(guess),
+                size: None,
+                read: None,
+                attempts: 0,
+            })
+            .collect();
+
+        if let Err(e) = self.run_probes(&mut probes, deadline) {
+            /// The ring itself failed, every file that is not done yet fails with it
+            for probe in &mut probes {
+                probe.size.get_or_insert_with(|| Err(copy_error(&e)));
+                probe.read.get_or_insert_with(|| Err(copy_error(&e)));
+            }
+        }
+
+        for probe in probes {
+            let Probe {
+                index,
+                file,
+                mut buf,
+                size,
+                read,
+                ..
+            } = probe;
+            let path = paths[index];
+            let size = match size.expect("every statx has a result") {
+                Ok(size) => size,
+                Err(e) => {
+                    results[index] = Some(Err(e));
+                    continue;
+                }
+            };
+            match read.expect("every read has a result") {
+                /// SAFETY: the kernel initialized `n` bytes of the spare capacity
+                Ok(n) => unsafe { buf.set_len(n as usize) },
+                Err(e) => {
+                    results[index] = Some(Err(e));
+                    continue;
+                }
+            }
+
+            if size == 0 {
+                results[index] = Some(self.read_fd_on(types::Fd(file.as_raw_fd()), path, buf));
+            } else if let Err(e) = self.check_size(path, size) {
+                results[index] = Some(Err(e));
+            } else if buf.len() as u64 >= size {
+                buf.truncate(size as usize);
+                lock(&self.stats).linked_statx_files += 1;
+                results[index] = Some(Ok(buf));
+            } else if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                // Not worth the allocation, none of it would be read
+                results[index] = Some(Err(timed_out()));
+            } else {
+                let done = buf.len();
+                buf.resize(size as usize, 0);
+                sized.push((index, file, buf, done));
+            }
+        }
+    }
+
+    /// The submit/reap loop behind `probe_many`, Err only if the ring itself fails
+    ///
+    /// Slot 2i is the statx of probe i, 2i + 1 its read. A failed read is retried on its own, behind a
+    /// `RETRY_TIMER` timeout like the chunks of `read_regions`.
+    #[allow(unused_doc_comments)]
+    fn run_probes(&self, probes: &mut [Probe], deadline: Option<Instant>) -> io::Result<()> {
+        let statx = |probe: &mut Probe| {
+            opcode::Statx::new(
+                types::Fd(probe.file.as_raw_fd()),
+                c"".as_ptr(),
+                probe.stx.as_mut_ptr().cast(),
+            )
+            .flags(libc::AT_EMPTY_PATH)
+            .mask(libc::STATX_BASIC_STATS)
+            .build()
+        };
+
+        /// Backoff of the retry timeouts, one per probe
+        let mut timers = vec![types::Timespec::default(); probes.len()];
+        /// Declared after `probes` and `timers`: the kernel uses them until it is dropped
+        let mut session = self.session();
+        session.cap_deadline(deadline);
+        let depth = self.depth().max(2);
+        let mut next = 0;
+        let mut pushed = false;
+        loop {
+            session.check_deadline()?;
+            /// Refill once half of the window is free, so one submit carries many pairs
+            let refill = session.in_flight() <= depth / 2
+                && (next == probes.len() || self.admit(session.in_flight()));
+            while refill && next < probes.len() && session.in_flight() + 2 <= depth {
+                pushed = true;
+                let probe = &mut probes[next];
+                /// Hard linked: the read runs even if the statx fails, and only after it
+                let statx_e = statx(probe).flags(squeue::Flags::IO_HARDLINK);
+                let read_e = read_spare(&probe.file, 0, &mut probe.buf);
+                let slot = 2 * next as u32;
+                session.push_group(vec![(slot, statx_e), (slot + 1, read_e)])?;
+                next += 1;
+            }
+            if session.in_flight() == 0 {
+                return Ok(());
+            }
+            /// `next` enters the kernel anyway if nothing is there yet, only submit what is new
+            if std::mem::take(&mut pushed) {
+                session.submit()?;
+            }
+
+            let cqe = session.next()?;
+            let slot = cqe.user_data() as u32;
+            if slot & RETRY_TIMER != 0 {
+                /// The backoff is over (-ETIME), or the timeout was canceled, either way: go
+                let slot = slot & !RETRY_TIMER;
+                let probe = &mut probes[slot as usize / 2];
+                session.push(slot, read_spare(&probe.file, 0, &mut probe.buf))?;
+                pushed = true;
+                continue;
+            }
+            let probe = &mut probes[slot as usize / 2];
+            let is_statx = slot.is_multiple_of(2);
+            match cqe.into_result() {
+                Err(e) if is_retryable(&e) => {
+                    let entry = if is_statx {
+                        statx(probe)
+                    } else {
+                        read_spare(&probe.file, 0, &mut probe.buf)
+                    };
+                    session.push(slot, entry)?;
+                    pushed = true;
+                }
+                /// SAFETY: zeroed is a valid statx, and the kernel filled it in
+                Ok(_) if is_statx => {
+                    probe.size = Some(Ok(unsafe { probe.stx.assume_init_ref() }.stx_size))
+                }
+                Err(e) if is_statx => probe.size = Some(Err(e)),
+                Err(e) => {
+                    probe.attempts += 1;
+                    let delay = self
+                        .config
+                        .retry
+                        .as_ref()
+                        .and_then(|policy| policy.next_delay(&e, probe.attempts));
+                    match delay {
+                        Some(delay) => {
+                            lock(&self.stats).retries += 1;
+                            if delay.is_zero() {
+                                session.push(slot, read_spare(&probe.file, 0, &mut probe.buf))?;
+                            } else {
+                                let timer = &mut timers[slot as usize / 2];
+                                *timer = types::Timespec::from(delay);
+                                let timeout_e = opcode::Timeout::new(timer).build();
+                                session.push(slot | RETRY_TIMER, timeout_e)?;
+                            }
+                            pushed = true;
+                        }
+                        None => {
+                            if probe.attempts > 1 {
+                                lock(&self.stats).retries_exhausted += 1;
+                            }
+                            probe.read = Some(Err(exhausted(e, probe.attempts)));
+                        }
+                    }
+                }
+                result => {
+                    if let Ok(n) = result {
+                        self.throttled(u64::from(n));
+                    }
+                    probe.read = Some(result)
+                }
+            }
+        }
+    }
+
+    /// Read every regular file below `root`, sorted by path
+    ///
+    /// Ok(entries) -> (path, contents or error) per file, plus an error entry for every directory that
+    /// could not be listed
+    /// Err(e) -> `root` itself could not be listed
+    ///
+    /// Symlinks are not followed. All files go through `read_many_files`, so they share the ring.
+    pub fn read_tree(
+        &self,
+        root: impl AsRef<Path>,
+    ) -> io::Result<Vec<(PathBuf, io::Result<Vec<u8>>)>> {
+        self.read_tree_by(root.as_ref(), None)
+    }
+
+    /// `read_tree` with a budget for the whole tree, see `read_many_files_until`
+    ///
+    /// The walk itself isn't interrupted, the deadline applies to reading the files it found.
+    pub fn read_tree_until(
+        &self,
+        root: impl AsRef<Path>,
+        deadline: Instant,
+    ) -> io::Result<Partial<(PathBuf, io::Result<Vec<u8>>)>> {
+        let entries = self.read_tree_by(root.as_ref(), Some(deadline))?;
+        let paths: Vec<PathBuf> = entries.iter().map(|(path, _)| path.clone()).collect();
+        Ok(partial(entries, &paths, deadline, |(_, result)| result))
+    }
+
+    /// `read_tree`, or `read_tree_until` with a deadline
+    fn read_tree_by(
+        &self,
+        root: &Path,
+        deadline: Option<Instant>,
+    ) -> io::Result<Vec<(PathBuf, io::Result<Vec<u8>>)>> {
+        let walk = walk_files(root)?;
+        let paths: Vec<&Path> = walk.files.iter().map(PathBuf::as_path).collect();
+        let data = self.read_many_by(&paths, deadline);
+
+        let mut entries: Vec<(PathBuf, io::Result<Vec<u8>>)> =
+            walk.files.into_iter().zip(data).collect();
+        entries.extend(walk.errors.into_iter().map(|(path, e)| (path, Err(e))));
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(entries)
+    }
+
+    /// Allocate the buffer of an open file, enforcing `max_bytes`
+    fn size_file(&self, file: File, path: &Path) -> io::Result<Opened> {
+        let size = file.metadata()?.len();
+
+        if size == 0 {
+            return Ok(Opened::Unsized(file));
+        }
+        self.check_size(path, size)?;
+        Ok(Opened::Sized(file, vec![0u8; size as usize]))
+    }
+
+    /// `UringConfig::on_shrink` for a file sized at `expected` bytes whose reads ended at `actual`
+    pub(crate) fn check_shrunk(
+        &self,
+        path: &Path,
+        expected: usize,
+        actual: usize,
+    ) -> io::Result<()> {
+        if actual >= expected {
+            return Ok(());
+        }
+        match self.config.on_shrink {
+            ShrinkPolicy::Truncate => {
+                lock(&self.stats).shrunk_files += 1;
+                Ok(())
+            }
+            ShrinkPolicy::Fail => Err(ReadError::FileChangedDuringRead {
+                path: path.to_path_buf(),
+                expected: expected as u64,
+                actual: actual as u64,
+            }
+            .into()),
+        }
+    }
+
+    /// `ReadError::FileTooLarge` if `size` is over the configured limit
+    pub(crate) fn check_size(&self, path: &Path, size: u64) -> io::Result<()> {
+        match self.config.max_bytes {
+            Some(limit) if size > limit => Err(ReadError::FileTooLarge {
+                path: path.to_path_buf(),
+                size,
+                limit,
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fill `buf` from `fd` starting at `offset`, split into `chunk_size` requests that run in parallel
+    ///
+    /// Ok(n) -> bytes read, less than `buf.len()` only if EOF was hit
+    /// Err(e) -> the first error any chunk reported
+    pub(crate) fn read_into(
+        &self,
+        fd: types::Fd,
+        buf: &mut [u8],
+        offset: u64,
+    ) -> io::Result<usize> {
+        let mut regions = [Region { fd, buf, offset }];
+        self.read_regions(&mut regions)
+            .pop()
+            .expect("one result per region")
+    }
+
+    /// Fill every region, all of them sharing the ring
+    ///
+    /// Returns one result per region:
+    /// Ok(n) -> bytes read, less than the buffer length only if EOF was hit
+    /// Err(e) -> the first error one of its chunks reported, its other chunks are not issued anymore
+    ///
+    /// Short reads are resubmitted for the rest of their chunk, EINTR/EAGAIN are retried.
+    pub(crate) fn read_regions(&self, regions: &mut [Region<'_>]) -> Vec<io::Result<usize>> {
+        self.read_regions_by(regions, None)
+    }
+
+    /// `read_regions` that gives up at `deadline`, what was not filled by then fails with `TimedOut`
+    #[allow(unused_doc_comments)]
+    pub(crate) fn read_regions_by(
+        &self,
+        regions: &mut [Region<'_>],
+        deadline: Option<Instant>,
+    ) -> Vec<io::Result<usize>> {
+        let chunk_size = self.config.chunk_size;
+        let mut chunks = Vec::new();
+        for (r, region) in regions.iter().enumerate() {
+            for start in (0..region.buf.len()).step_by(chunk_size) {
+                chunks.push(Chunk {
+                    region: r,
+                    start,
+                    end: (start + chunk_size).min(region.buf.len()),
+                    done: 0,
+                    attempts: 0,
+                });
+            }
+        }
+
+        let mut state = RegionState {
+            filled: regions.iter().map(|r| r.buf.len()).collect(),
+            errors: regions.iter().map(|_| None).collect(),
+            ended: vec![false; regions.len()],
+            pending: vec![0; regions.len()],
+        };
+        for chunk in &chunks {
+            state.pending[chunk.region] += 1;
+        }
+
+        /// The raw pointers are taken once, up front, the buffers are not touched from Rust until the
+        /// session (declared after them) has reaped every chunk
+        let targets: Vec<(types::Fd, *mut u8, u64)> = regions
+            .iter_mut()
+            .map(|r| (r.fd, r.buf.as_mut_ptr(), r.offset))
+            .collect();
+        /// Backoff of the retry timeouts, the kernel reads them when the timeout is submitted
+        let mut timers = vec![types::Timespec::default(); chunks.len()];
+
+        let mut session = self.session();
+        session.cap_deadline(deadline);
+        let driven =
+            self.drive_chunks(&mut session, &targets, &mut chunks, &mut timers, &mut state);
+        if let Err(e) = driven {
+            /// The ring itself failed, every region that was not finished yet fails with it
+            for r in 0..regions.len() {
+                if state.pending[r] > 0 && state.errors[r].is_none() {
+                    state.errors[r] = Some(copy_error(&e));
+                }
+            }
+        }
+        drop(session);
+        debug_assert_eq!(self.sq_dropped(), 0, "the kernel dropped SQEs we pushed");
+
+        state
+            .filled
+            .into_iter()
+            .zip(state.errors)
+            .map(|(n, e)| match e {
+                Some(e) => Err(e),
+                None => Ok(n),
+            })
+            .collect()
+    }
+
+    /// The submit/reap loop behind `read_regions`, Err only if the ring itself fails
+    #[allow(unused_doc_comments)]
+    fn drive_chunks(
+        &self,
+        session: &mut Session<'_>,
+        targets: &[(types::Fd, *mut u8, u64)],
+        chunks: &mut [Chunk],
+        timers: &mut [types::Timespec],
+        state: &mut RegionState,
+    ) -> io::Result<()> {
+        let push = |session: &mut Session<'_>, chunks: &[Chunk], slot: usize| {
+            let chunk = &chunks[slot];
+            let (fd, base, offset) = targets[chunk.region];
+            let pos = chunk.start + chunk.done;
+            /// SAFETY: `pos < chunk.end <= buf.len()`, the pointer stays inside the region's buffer
+            let ptr = unsafe { base.add(pos) };
+            let read_e = opcode::Read::new(fd, ptr, (chunk.end - pos) as u32)
+                .offset(offset + pos as u64)
+                .build();
+            session.push(slot as u32, read_e)
+        };
+
+        let mut next = 0;
+        loop {
+            session.check_deadline()?;
+            let depth = self.depth();
+            let admitted = next == chunks.len() || self.admit(session.in_flight());
+            while admitted && next < chunks.len() && session.in_flight() < depth {
+                let region = chunks[next].region;
+                if state.errors[region].is_none() && !state.ended[region] {
+                    push(session, chunks, next)?;
+                } else {
+                    state.pending[region] -= 1;
+                }
+                next += 1;
+            }
+            if session.in_flight() == 0 {
+                return Ok(());
+            }
+            session.submit()?;
+
+            let cqe = session.next()?;
+            let slot = cqe.user_data() as u32;
+            if slot & RETRY_TIMER != 0 {
+                /// The backoff is over (-ETIME), or the timeout was canceled, either way: go, unless
+                /// another chunk failed the region in the meantime
+                let slot = (slot & !RETRY_TIMER) as usize;
+                let region = chunks[slot].region;
+                if state.errors[region].is_some() {
+                    state.pending[region] -= 1;
+                } else {
+                    push(session, chunks, slot)?;
+                }
+                continue;
+            }
+            let slot = slot as usize;
+            let region = chunks[slot].region;
+            match cqe.into_result() {
+                Err(e) if is_retryable(&e) => {
+                    push(session, chunks, slot)?;
+                    continue;
+                }
+                Err(e) => {
+                    let chunk = &mut chunks[slot];
+                    chunk.attempts += 1;
+                    let delay = self
+                        .config
+                        .retry
+                        .as_ref()
+                        .and_then(|policy| policy.next_delay(&e, chunk.attempts));
+                    match delay {
+                        Some(delay) if state.errors[region].is_none() => {
+                            lock(&self.stats).retries += 1;
+                            if delay.is_zero() {
+                                push(session, chunks, slot)?;
+                            } else {
+                                timers[slot] = types::Timespec::from(delay);
+                                let timeout_e = opcode::Timeout::new(&timers[slot]).build();
+                                session.push(slot as u32 | RETRY_TIMER, timeout_e)?;
+                            }
+                            continue;
+                        }
+                        _ => {
+                            if chunk.attempts > 1 {
+                                lock(&self.stats).retries_exhausted += 1;
+                            }
+                            state.errors[region].get_or_insert(exhausted(e, chunk.attempts));
+                        }
+                    }
+                }
+                Ok(0) => {
+                    /// EOF before the end of this chunk, the file is shorter than we were told (it was
+                    /// truncated): nothing after it is issued anymore, what is in flight finishes
+                    let chunk = &chunks[slot];
+                    state.filled[region] = state.filled[region].min(chunk.start + chunk.done);
+                    state.ended[region] = true;
+                }
+                Ok(n) => {
+                    self.tune(u64::from(n), session.in_flight() + 1);
+                    self.throttled(u64::from(n));
+                    let chunk = &mut chunks[slot];
+                    chunk.done += n as usize;
+                    let short = chunk.start + chunk.done < chunk.end;
+                    if short && state.errors[region].is_none() && !state.ended[region] {
+                        self.metrics.short_read_retry();
+                        push(session, chunks, slot)?;
+                        continue;
+                    }
+                    /// A later chunk hit EOF first, this one is not resubmitted: the region ends where
+                    /// its data does, not at that chunk
+                    if state.ended[region] {
+                        state.filled[region] = state.filled[region].min(chunk.start + chunk.done);
+                    }
+                }
+            }
+            state.pending[region] -= 1;
+        }
+    }
+
+    /// Read `fd` from the start until a read returns 0, for files whose size is unknown
+    ///
+    /// Stops with `ReadError::FileTooLarge` once more than `max_bytes` came back (`/dev/zero` never ends).
+    fn read_fd_to_end(&self, fd: types::Fd, path: &Path) -> io::Result<Vec<u8>> {
+        self.read_fd_on(fd, path, Vec::new())
+    }
+
+    /// `read_fd_to_end` for a file whose first `data.len()` bytes were read already
+    fn read_fd_on(&self, fd: types::Fd, path: &Path, mut data: Vec<u8>) -> io::Result<Vec<u8>> {
+        let chunk_size = self.config.chunk_size;
+
+        loop {
+            let len = data.len();
+            data.resize(len + chunk_size, 0);
+            let n = self.read_into(fd, &mut data[len..], len as u64)?;
+            data.truncate(len + n);
+            if n == 0 {
+                return Ok(data);
+            }
+            self.check_size(path, data.len() as u64)?;
+        }
+    }
+}
+
+/// Per region bookkeeping of `read_regions`
+/// - filled -> bytes that are valid (shrinks when EOF is hit early)
+/// - errors -> first error of the region
+/// - ended -> a chunk hit EOF early, the rest of the region is not read
+/// - pending -> chunks not finished yet
+struct RegionState {
+    filled: Vec<usize>,
+    errors: Vec<Option<io::Error>>,
+    ended: Vec<bool>,
+    pending: Vec<usize>,
+}
+
+/// What the batch sees for everything it didn't get to before its deadline, made a `ReadError::Deadline`
+/// by `partial`
+fn timed_out() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        "deadline passed before the read started",
+    )
+}
+
+/// Count a deadline batch: `TimedOut` entries become `ReadError::Deadline`
+///
+/// A `UringConfig::timeout` that fired before the deadline can't be told apart once the deadline has
+/// passed too, it's counted as abandoned as well.
+pub(crate) fn partial<T>(
+    mut results: Vec<T>,
+    paths: &[impl AsRef<Path>],
+    deadline: Instant,
+    result: impl Fn(&mut T) -> &mut io::Result<Vec<u8>>,
+) -> Partial<T> {
+    let passed = Instant::now() >= deadline;
+    let (mut finished, mut abandoned, mut cancelled, mut failed) = (0, 0, 0, 0);
+    for (entry, path) in results.iter_mut().zip(paths) {
+        let result = result(entry);
+        match result {
+            Ok(_) => finished += 1,
+            Err(e) if passed && e.kind() == io::ErrorKind::TimedOut => {
+                *result = Err(ReadError::Deadline {
+                    path: path.as_ref().to_path_buf(),
+                }
+                .into());
+                abandoned += 1;
+            }
+            Err(e) if is_cancelled(e) => cancelled += 1,
+            Err(_) => failed += 1,
+        }
+    }
+    Partial {
+        results,
+        finished,
+        abandoned,
+        cancelled,
+        failed,
+    }
+}
+
+/// `io::Error` is not `Clone`, this keeps the kind and the OS error code (or the message)
+pub(crate) fn copy_error(e: &io::Error) -> io::Error {
+    if let Some(&ReadError::Cancelled { reason }) = ReadError::from_io(e) {
+        return ReadError::Cancelled { reason }.into();
+    }
+    match e.raw_os_error() {
+        Some(code) => io::Error::from_raw_os_error(code),
+        None => io::Error::new(e.kind(), e.to_string()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::UringConfig;
+    use crate::error::Cancelled;
+    use crate::throttle::RateLimit;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::thread;
+    use std::time::Duration;
+
+    /// An empty directory of its own for `test`, under the temp directory
+    pub(crate) fn scratch_dir(test: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("uring_fast_read-{}-{test}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    const SIZE: usize = 1 << 20;
+
+    /// `SIZE` bytes that differ from chunk to chunk
+    fn contents() -> Vec<u8> {
+        (0..SIZE).map(|i| (i / 4096 + i) as u8).collect()
+    }
+
+    /// A reader that takes about 250 ms for a `SIZE` file: 4 KiB chunks one at a time at 4 MiB/s,
+    /// plenty of time for another thread to change the file in the middle of it
+    fn slow_reader(on_shrink: ShrinkPolicy) -> UringReader {
+        let config = UringConfig::default()
+            .chunk_size(4096)
+            .queue_depth(1)
+            .rate_limit(Some(RateLimit::new(4 << 20).burst(Some(4096))))
+            .on_shrink(on_shrink);
+        UringReader::new(config).unwrap()
+    }
+
+    /// Read `path` with `reader` while another thread runs `change` on it 50 ms in
+    fn read_while(
+        reader: &UringReader,
+        path: &Path,
+        change: impl FnOnce(&Path) + Send,
+    ) -> io::Result<Vec<u8>> {
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                change(path);
+            });
+            reader.read_file_to_vec(path)
+        })
+    }
+
+    fn truncate(path: &Path) {
+        OpenOptions::new()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_len(64 * 1024)
+            .unwrap();
+    }
+
+    #[test]
+    fn truncated_mid_read_keeps_what_was_there() {
+        let path = scratch_dir("shrink-truncate").join("file");
+        let original = contents();
+        std::fs::write(&path, &original).unwrap();
+        let reader = slow_reader(ShrinkPolicy::Truncate);
+
+        let data = read_while(&reader, &path, truncate).unwrap();
+        assert!(
+            data.len() < SIZE,
+            "read all {} bytes, no truncation seen",
+            data.len()
+        );
+        assert!(data.len() >= 64 * 1024);
+        assert_eq!(data, original[..data.len()]);
+        assert_eq!(reader.stats().shrunk_files, 1);
+    }
+
+    #[test]
+    fn truncated_mid_read_fails_with_fail() {
+        let path = scratch_dir("shrink-fail").join("file");
+        std::fs::write(&path, contents()).unwrap();
+        let reader = slow_reader(ShrinkPolicy::Fail);
+
+        let e = read_while(&reader, &path, truncate).unwrap_err();
+        match ReadError::from_io(&e) {
+            Some(ReadError::FileChangedDuringRead {
+                expected, actual, ..
+            }) => {
+                assert_eq!(*expected, SIZE as u64);
+                assert!(*actual < SIZE as u64);
+            }
+            other => panic!("expected FileChangedDuringRead, got {other:?} ({e})"),
+        }
+        assert_eq!(reader.stats().shrunk_files, 0);
+    }
+
+    #[test]
+    fn timeout_cancels_what_is_left_by_timeout() {
+        let dir = scratch_dir("timeout");
+        let (file, fifo) = (dir.join("file"), dir.join("fifo"));
+        std::fs::write(&file, b"contents").unwrap();
+        let c_fifo = crate::chain::c_path(&fifo).unwrap();
+        // SAFETY: `c_fifo` is a NUL terminated path
+        assert_eq!(unsafe { libc::mkfifo(c_fifo.as_ptr(), 0o600) }, 0);
+        // A writer that never writes: opening the fifo doesn't block, reading it never ends
+        let _writer = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&fifo)
+            .unwrap();
+
+        let config = UringConfig::default().timeout(Some(Duration::from_millis(50)));
+        let reader = UringReader::new(config).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let counted = reader.read_many_files_until(&[&file, &fifo], deadline);
+
+        assert_eq!(counted.results[0].as_ref().unwrap(), b"contents");
+        let e = counted.results[1].as_ref().unwrap_err();
+        assert!(matches!(
+            ReadError::from_io(e),
+            Some(ReadError::Cancelled {
+                reason: Cancelled::ByTimeout
+            })
+        ));
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(
+            (
+                counted.finished,
+                counted.abandoned,
+                counted.cancelled,
+                counted.failed
+            ),
+            (1, 0, 1, 0)
+        );
+    }
+
+    #[test]
+    fn short_read_after_a_later_chunk_hit_eof_ends_the_region() {
+        let path = scratch_dir("short-after-eof").join("file");
+        std::fs::write(&path, vec![0xab; 2000]).unwrap();
+        let file = File::open(&path).unwrap();
+        let config = UringConfig::default().chunk_size(4096).queue_depth(4);
+        let reader = UringReader::new(config).unwrap();
+
+        // Both chunks in flight at once, the second half first: it sees EOF and ends the region
+        // before the first half comes back with its 2000 bytes
+        let mut buf = vec![0u8; 8192];
+        let mut chunks: Vec<Chunk> = [4096, 0]
+            .into_iter()
+            .map(|start| Chunk {
+                region: 0,
+                start,
+                end: start + 4096,
+                done: 0,
+                attempts: 0,
+            })
+            .collect();
+        let mut state = RegionState {
+            filled: vec![buf.len()],
+            errors: vec![None],
+            ended: vec![false],
+            pending: vec![chunks.len()],
+        };
+        let targets = [(types::Fd(file.as_raw_fd()), buf.as_mut_ptr(), 0)];
+        let mut timers = vec![types::Timespec::default(); chunks.len()];
+        let mut session = reader.session();
+        reader
+            .drive_chunks(&mut session, &targets, &mut chunks, &mut timers, &mut state)
+            .unwrap();
+        drop(session);
+
+        assert!(state.ended[0]);
+        assert_eq!(state.pending[0], 0);
+        assert_eq!(state.filled[0], 2000);
+        assert!(buf[..2000].iter().all(|&b| b == 0xab));
+    }
+
+    #[cfg(feature = "failpoints")]
+    #[test]
+    fn probe_reads_follow_the_retry_policy() {
+        use crate::failpoints::{FailPoints, FailRule, InjectedFault};
+        use crate::retry::RetryPolicy;
+
+        let dir = scratch_dir("probe-retry");
+        let (good, flaky) = (dir.join("good"), dir.join("flaky"));
+        std::fs::write(&good, b"good").unwrap();
+        std::fs::write(&flaky, b"flaky").unwrap();
+        let points = FailPoints::new(1)
+            .rule(FailRule::new(InjectedFault::Errno(libc::EIO)).path_contains("flaky"));
+        let config = UringConfig::default()
+            .retry(Some(RetryPolicy {
+                max_attempts: 3,
+                backoff: Duration::from_millis(1),
+                ..RetryPolicy::default()
+            }))
+            .failpoints(Some(points));
+        let reader = UringReader::new(config).unwrap();
+
+        let results = reader.read_many_files(&[&good, &flaky]);
+        assert_eq!(results[0].as_ref().unwrap(), b"good");
+        let e = results[1].as_ref().unwrap_err();
+        match ReadError::from_io(e) {
+            Some(ReadError::RetriesExhausted { attempts, source }) => {
+                assert_eq!(*attempts, 3);
+                assert_eq!(source.raw_os_error(), Some(libc::EIO));
+            }
+            other => panic!("expected RetriesExhausted, got {other:?} ({e})"),
+        }
+        let stats = reader.stats();
+        assert_eq!((stats.retries, stats.retries_exhausted), (2, 1));
+        assert_eq!(stats.injected_faults, 3);
+    }
+
+    #[cfg(feature = "failpoints")]
+    #[test]
+    fn chunk_waiting_for_its_retry_is_dropped_when_the_region_failed() {
+        use crate::failpoints::{FailPoints, FailRule, InjectedFault};
+        use crate::retry::RetryPolicy;
+
+        let path = scratch_dir("retry-failed-region").join("file");
+        std::fs::write(&path, vec![1u8; 8192]).unwrap();
+        let file = File::open(&path).unwrap();
+        // The rolls of seed 3: the first chunk gets EIO (retried after the backoff), the second one
+        // EINVAL, which fails the region while the first one waits
+        let points = FailPoints::new(3)
+            .rule(FailRule::new(InjectedFault::Errno(libc::EIO)).probability(0.5))
+            .rule(FailRule::new(InjectedFault::Errno(libc::EINVAL)));
+        let config = UringConfig::default()
+            .chunk_size(4096)
+            .queue_depth(4)
+            .retry(Some(RetryPolicy {
+                max_attempts: 3,
+                backoff: Duration::from_millis(20),
+                ..RetryPolicy::default()
+            }))
+            .failpoints(Some(points));
+        let reader = UringReader::new(config).unwrap();
+
+        let mut buf = vec![0u8; 8192];
+        let e = reader
+            .read_into(types::Fd(file.as_raw_fd()), &mut buf, 0)
+            .unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::EINVAL));
+        let stats = reader.stats();
+        assert_eq!((stats.retries, stats.retries_exhausted), (1, 0));
+        // The first chunk was not read again once its backoff was over
+        assert_eq!(stats.injected_faults, 2);
+    }
+
+    #[test]
+    fn grown_mid_read_returns_the_size_stat_saw() {
+        for policy in [ShrinkPolicy::Truncate, ShrinkPolicy::Fail] {
+            let path = scratch_dir("grow").join("file");
+            let original = contents();
+            std::fs::write(&path, &original).unwrap();
+            let reader = slow_reader(policy);
+
+            let data = read_while(&reader, &path, |path| {
+                let mut file = OpenOptions::new().append(true).open(path).unwrap();
+                file.write_all(&[0xee; 256 * 1024]).unwrap();
+            })
+            .unwrap();
+            assert_eq!(
+                std::fs::metadata(&path).unwrap().len(),
+                (SIZE + 256 * 1024) as u64
+            );
+            assert_eq!(data.len(), SIZE);
+            assert_eq!(data, original);
+            assert_eq!(reader.stats().shrunk_files, 0);
+        }
+    }
+}
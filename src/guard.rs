@@ -0,0 +1,308 @@
+use io_uring::{IoUring, opcode, squeue};
+
+use std::io;
+use std::mem::ManuallyDrop;
+use std::time::Duration;
+
+use crate::completion::Completion;
+
+/// Failed waits in a row after which a request still in flight is given up on, see `give_up`
+pub(crate) const SETTLE_ATTEMPTS: u32 = 100;
+
+/// user_data of the cancels the guard pushes, their own CQEs are skipped
+const CANCEL_USER_DATA: u64 = u64::MAX - 1;
+
+/// A ring with requests in flight that point into `keep`
+///
+/// Once an SQE is pushed the kernel may write into the memory it points to until its CQE is reaped,
+/// so nothing may free that memory before. The guard owns it (`keep`: the buffer, the file), and
+/// every way out goes through `Drop` while requests are in flight: an error returned with `?`, a
+/// panic. Drop cancels what is still in flight and reaps until the kernel confirmed every request
+/// (its CQE, -ECANCELED or the real result). Only then is `keep` dropped. If the ring can't even be
+/// waited on anymore, `keep` is leaked: a leak is the only outcome that's safe for memory the
+/// kernel may still own.
+///
+/// This is the raw ring version for `read_one_file`. Everything on a `UringReader` goes through a
+/// `Session`, which does the same on drop for buffers the caller declared before it.
+pub(crate) struct InFlightGuard<'r, K> {
+    ring: &'r mut IoUring,
+    /// user_data of every request pushed, one entry per request not reaped yet
+    pending: Vec<u64>,
+    keep: ManuallyDrop<K>,
+    /// Waits still to fail with EBUSY before entering the kernel, like an `io_uring_enter` that
+    /// submitted nothing (tests only)
+    #[cfg(test)]
+    fail_waits: u32,
+}
+
+impl<'r, K> InFlightGuard<'r, K> {
+    pub(crate) fn new(ring: &'r mut IoUring, keep: K) -> Self {
+        InFlightGuard {
+            ring,
+            pending: Vec::new(),
+            keep: ManuallyDrop::new(keep),
+            #[cfg(test)]
+            fail_waits: 0,
+        }
+    }
+
+    /// What the requests point into, to build them (the pointers stay valid, `keep` never moves)
+    pub(crate) fn keep_mut(&mut self) -> &mut K {
+        &mut self.keep
+    }
+
+    /// Push `entry`, from now on it counts as in flight
+    ///
+    /// # Safety
+    /// Everything `entry` points to is owned by `keep` (or outlives the guard).
+    pub(crate) unsafe fn push(&mut self, entry: &squeue::Entry) -> io::Result<()> {
+        // SAFETY: the caller vouches for what the entry points to
+        unsafe { self.ring.submission().push(entry) }
+            .map_err(|_| io::Error::new(io::ErrorKind::WouldBlock, "submission queue is full"))?;
+        self.pending.push(entry.get_user_data());
+        Ok(())
+    }
+
+    /// Submit, and wait for at least one completion of ours
+    pub(crate) fn submit_and_wait(&mut self) -> io::Result<Completion> {
+        loop {
+            if let Some(cqe) = self.reap() {
+                return Ok(cqe);
+            }
+            match self.wait() {
+                Err(e) if e.kind() != io::ErrorKind::Interrupted => return Err(e),
+                _ => {}
+            }
+        }
+    }
+
+    /// Give `keep` back, only once nothing is in flight anymore
+    pub(crate) fn finish(mut self) -> K {
+        assert!(self.pending.is_empty(), "requests still in flight");
+        // SAFETY: taken once, `Drop` sees an empty `pending` and doesn't touch `keep` again
+        let keep = unsafe { ManuallyDrop::take(&mut self.keep) };
+        drop(std::mem::take(&mut self.pending));
+        std::mem::forget(self);
+        keep
+    }
+
+    /// Submit and wait for one completion
+    fn wait(&mut self) -> io::Result<usize> {
+        #[cfg(test)]
+        if self.fail_waits > 0 {
+            self.fail_waits -= 1;
+            return Err(io::Error::from_raw_os_error(libc::EBUSY));
+        }
+        self.ring.submit_and_wait(1)
+    }
+
+    /// One of our CQEs if the completion queue has one, the cancels' own are skipped
+    fn reap(&mut self) -> Option<Completion> {
+        for cqe in self.ring.completion() {
+            let cqe = Completion::from(cqe);
+            if let Some(at) = self.pending.iter().position(|&u| u == cqe.user_data()) {
+                self.pending.swap_remove(at);
+                return Some(cqe);
+            }
+        }
+        None
+    }
+
+    /// Cancel everything in flight and reap it, false if the ring failed `SETTLE_ATTEMPTS` waits in a
+    /// row before every request was confirmed
+    fn settle(&mut self) -> bool {
+        for &user_data in &self.pending.clone() {
+            let cancel_e = opcode::AsyncCancel::new(user_data)
+                .build()
+                .user_data(CANCEL_USER_DATA);
+            // SAFETY: a cancel points to nothing
+            if unsafe { self.ring.submission().push(&cancel_e) }.is_err() {
+                break;
+            }
+        }
+        let mut failures = 0;
+        while !self.pending.is_empty() {
+            if self.reap().is_some() {
+                failures = 0;
+                continue;
+            }
+            match self.wait() {
+                Err(e) if e.kind() != io::ErrorKind::Interrupted => {
+                    failures += 1;
+                    if failures >= SETTLE_ATTEMPTS {
+                        return false;
+                    }
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                _ => {}
+            }
+        }
+        true
+    }
+}
+
+impl<K> Drop for InFlightGuard<'_, K> {
+    fn drop(&mut self) {
+        if self.pending.is_empty() || self.settle() {
+            // SAFETY: nothing in flight points into it anymore, and it's only dropped here
+            unsafe { ManuallyDrop::drop(&mut self.keep) }
+        }
+    }
+}
+
+/// The last resort of a drain that couldn't confirm its requests and doesn't own their memory: the
+/// caller is about to free buffers the kernel may still write into, so stop the process instead
+pub(crate) fn give_up(in_flight: usize) -> ! {
+    eprintln!(
+        "uring_fast_read: {in_flight} io_uring requests could not be reaped, aborting rather than \
+         freeing memory the kernel may still write into"
+    );
+    std::process::abort()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use io_uring::types;
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+    use std::sync::{Arc, OnceLock};
+
+    /// What the leak test leaks on purpose, reachable from here so a leak checker (ASan) doesn't
+    /// report it
+    static LEAKED: OnceLock<(AtomicPtr<u8>, Arc<AtomicBool>)> = OnceLock::new();
+
+    /// Flags its drop, to see when (and whether) the guard let go of `keep`
+    struct Dropped(Arc<AtomicBool>);
+
+    impl Drop for Dropped {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// (read end, write end), the read end non-blocking so a test can look at what is left in it
+    pub(crate) fn pipe() -> (File, File) {
+        let mut fds = [0; 2];
+        // SAFETY: `fds` has room for the two fds
+        assert_eq!(
+            unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK) },
+            0
+        );
+        // SAFETY: both fds were just created and are owned by nobody else
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) }
+    }
+
+    /// A read of `fd` into `buf` through a guard whose first `fail_waits` waits fail
+    fn guarded_read(
+        ring: &mut IoUring,
+        fd: i32,
+        buf: &mut [u8],
+        fail_waits: u32,
+        dropped: &Arc<AtomicBool>,
+    ) -> io::Result<Completion> {
+        let mut guard = InFlightGuard::new(ring, (buf, Dropped(Arc::clone(dropped))));
+        guard.fail_waits = fail_waits;
+        let buf = &mut guard.keep_mut().0;
+        let read_e = opcode::Read::new(types::Fd(fd), buf.as_mut_ptr(), buf.len() as u32)
+            .build()
+            .user_data(7);
+        // SAFETY: the read points into `buf`, which the guard holds
+        unsafe { guard.push(&read_e)? };
+        // In flight for real before the waits start failing
+        guard.ring.submit()?;
+        let cqe = guard.submit_and_wait()?;
+        guard.finish();
+        Ok(cqe)
+    }
+
+    /// Nothing of ours is left in `ring`: a NOP is the next CQE (the cancels' own aside)
+    fn assert_idle(ring: &mut IoUring) {
+        let nop_e = opcode::Nop::new().build().user_data(9);
+        // SAFETY: a NOP points to nothing
+        unsafe { ring.submission().push(&nop_e).unwrap() };
+        ring.submit_and_wait(1).unwrap();
+        let user_data: Vec<u64> = ring
+            .completion()
+            .map(|cqe| cqe.user_data())
+            .filter(|&u| u != CANCEL_USER_DATA)
+            .collect();
+        assert_eq!(user_data, [9]);
+    }
+
+    #[test]
+    fn failed_wait_cancels_and_reaps_before_letting_go() {
+        let mut ring = IoUring::new(8).unwrap();
+        let (mut rx, mut tx) = pipe();
+        let mut buf = [0xaa; 16];
+        let dropped = Arc::new(AtomicBool::new(false));
+
+        let e = guarded_read(&mut ring, rx.as_raw_fd(), &mut buf, 1, &dropped).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::EBUSY));
+        assert!(dropped.load(Ordering::SeqCst));
+        assert_idle(&mut ring);
+
+        // The read was canceled, not just forgotten: what is written now stays in the pipe
+        tx.write_all(b"late").unwrap();
+        let mut left = [0u8; 8];
+        assert_eq!(rx.read(&mut left).unwrap(), 4);
+        assert_eq!(&left[..4], b"late");
+        assert_eq!(buf, [0xaa; 16]);
+    }
+
+    #[test]
+    fn bad_fd_completes_with_ebadf() {
+        let mut ring = IoUring::new(8).unwrap();
+        let mut buf = [0xaa; 16];
+        let dropped = Arc::new(AtomicBool::new(false));
+
+        let cqe = guarded_read(&mut ring, -1, &mut buf, 0, &dropped).unwrap();
+        assert_eq!(cqe.result(), -libc::EBADF);
+        assert!(dropped.load(Ordering::SeqCst));
+        assert_eq!(buf, [0xaa; 16]);
+        assert_idle(&mut ring);
+    }
+
+    #[test]
+    fn full_completion_queue_still_finds_ours() {
+        let mut ring = IoUring::builder().setup_cqsize(8).build(4).unwrap();
+        // 12 NOPs nobody reaps on a CQ of 8, the kernel holds the rest as overflow
+        for round in 0..3 {
+            for i in 0..4 {
+                let nop_e = opcode::Nop::new().build().user_data(100 + round * 4 + i);
+                // SAFETY: a NOP points to nothing
+                unsafe { ring.submission().push(&nop_e).unwrap() };
+            }
+            ring.submit().unwrap();
+        }
+        let file = File::open("Cargo.toml").unwrap();
+        let mut buf = [0u8; 9];
+        let dropped = Arc::new(AtomicBool::new(false));
+
+        let cqe = guarded_read(&mut ring, file.as_raw_fd(), &mut buf, 0, &dropped).unwrap();
+        assert_eq!(cqe.result(), 9);
+        assert_eq!(&buf, b"[package]");
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn ring_that_cannot_be_waited_on_leaks_instead_of_freeing() {
+        let mut ring = IoUring::new(8).unwrap();
+        let (rx, _tx) = pipe();
+        // The kernel may still write into it, so it must outlive everything
+        let buf: &'static mut [u8] = Box::leak(Box::new([0xaa; 16]));
+        let dropped = Arc::new(AtomicBool::new(false));
+        LEAKED
+            .set((AtomicPtr::new(buf.as_mut_ptr()), Arc::clone(&dropped)))
+            .unwrap();
+
+        let e = guarded_read(&mut ring, rx.as_raw_fd(), buf, u32::MAX, &dropped).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::EBUSY));
+        assert!(
+            !dropped.load(Ordering::SeqCst),
+            "keep was freed with a read in flight"
+        );
+    }
+}
@@ -0,0 +1,124 @@
+#[cfg(feature = "metrics")]
+use metrics::{Counter, Histogram, counter, histogram};
+
+use crate::config::UringConfig;
+use crate::timing::RequestTiming;
+
+/// Handles of everything a reader exports through the `metrics` facade (feature `metrics`)
+///
+/// uring_bytes_read -> bytes returned by read requests
+/// uring_requests_submitted -> SQEs pushed into the submission queue
+/// uring_completions -> CQEs reaped
+/// uring_short_read_retries -> chunk reads resubmitted because the kernel returned less than asked
+/// uring_sq_full_events -> pushes that found the submission queue full
+/// uring_cq_overflows -> CQEs the kernel had to hold back because the completion queue was full
+/// uring_fallback_activations -> a feature the kernel lacks was replaced by a slower path (syscall
+///   xattrs, no ATTACH_WQ, no eventfd, no bounded wait)
+/// uring_injected_faults -> faults injected by `UringConfig::failpoints`, never a real failure
+/// uring_request_latency_seconds -> push to reap of every request, only with `record_timings`
+///
+/// Every metric carries a `reader` label, `UringConfig::metrics_label`. The handles are registered
+/// once when the reader is created, so install the recorder before that. Without a recorder each
+/// update is a branch on an empty handle. Without the feature this is a zero sized struct whose
+/// methods compile to nothing.
+pub(crate) struct Metrics {
+    #[cfg(feature = "metrics")]
+    bytes_read: Counter,
+    #[cfg(feature = "metrics")]
+    requests_submitted: Counter,
+    #[cfg(feature = "metrics")]
+    completions: Counter,
+    #[cfg(feature = "metrics")]
+    short_read_retries: Counter,
+    #[cfg(feature = "metrics")]
+    sq_full_events: Counter,
+    #[cfg(feature = "metrics")]
+    cq_overflows: Counter,
+    #[cfg(feature = "metrics")]
+    fallback_activations: Counter,
+    #[cfg(all(feature = "metrics", feature = "failpoints"))]
+    injected_faults: Counter,
+    #[cfg(feature = "metrics")]
+    request_latency: Histogram,
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+impl Metrics {
+    pub(crate) fn new(config: &UringConfig) -> Self {
+        #[cfg(feature = "metrics")]
+        {
+            let label = config.metrics_label.clone();
+            Metrics {
+                bytes_read: counter!("uring_bytes_read", "reader" => label.clone()),
+                requests_submitted: counter!("uring_requests_submitted", "reader" => label.clone()),
+                completions: counter!("uring_completions", "reader" => label.clone()),
+                short_read_retries: counter!("uring_short_read_retries", "reader" => label.clone()),
+                sq_full_events: counter!("uring_sq_full_events", "reader" => label.clone()),
+                cq_overflows: counter!("uring_cq_overflows", "reader" => label.clone()),
+                fallback_activations: counter!("uring_fallback_activations", "reader" => label.clone()),
+                #[cfg(feature = "failpoints")]
+                injected_faults: counter!("uring_injected_faults", "reader" => label.clone()),
+                request_latency: histogram!("uring_request_latency_seconds", "reader" => label),
+            }
+        }
+        #[cfg(not(feature = "metrics"))]
+        Metrics {}
+    }
+
+    /// Only called with the feature, `Session` tracks which requests are reads just for this
+    #[cfg(feature = "metrics")]
+    #[inline]
+    pub(crate) fn bytes_read(&self, bytes: u64) {
+        self.bytes_read.increment(bytes);
+    }
+
+    #[inline]
+    pub(crate) fn submitted(&self) {
+        #[cfg(feature = "metrics")]
+        self.requests_submitted.increment(1);
+    }
+
+    #[inline]
+    pub(crate) fn completed(&self, cqes: u64) {
+        #[cfg(feature = "metrics")]
+        self.completions.increment(cqes);
+    }
+
+    #[inline]
+    pub(crate) fn short_read_retry(&self) {
+        #[cfg(feature = "metrics")]
+        self.short_read_retries.increment(1);
+    }
+
+    #[inline]
+    pub(crate) fn sq_full(&self) {
+        #[cfg(feature = "metrics")]
+        self.sq_full_events.increment(1);
+    }
+
+    #[inline]
+    pub(crate) fn cq_overflows(&self, cqes: u64) {
+        #[cfg(feature = "metrics")]
+        self.cq_overflows.increment(cqes);
+    }
+
+    #[inline]
+    pub(crate) fn fell_back(&self) {
+        #[cfg(feature = "metrics")]
+        self.fallback_activations.increment(1);
+    }
+
+    #[cfg(feature = "failpoints")]
+    #[inline]
+    pub(crate) fn injected_fault(&self) {
+        #[cfg(feature = "metrics")]
+        self.injected_faults.increment(1);
+    }
+
+    #[inline]
+    pub(crate) fn latency(&self, timing: &RequestTiming) {
+        #[cfg(feature = "metrics")]
+        self.request_latency
+            .record((timing.queued + timing.in_kernel).as_secs_f64());
+    }
+}
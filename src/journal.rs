@@ -0,0 +1,275 @@
+use io_uring::{opcode, squeue};
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::completion::Completion;
+use crate::reader::{UringReader, lock};
+
+/// The fields of an SQE the journal and the fault injection look at
+/// - fd -> None for registered files (IOSQE_FIXED_FILE), `fixed` is the table index then
+pub(crate) struct SqeFields {
+    pub(crate) opcode: u8,
+    pub(crate) flags: u8,
+    pub(crate) fd: Option<i32>,
+    pub(crate) fixed: Option<u32>,
+    pub(crate) offset: u64,
+    pub(crate) len: u32,
+}
+
+impl SqeFields {
+    /// Read from the raw `io_uring_sqe` behind `entry`: opcode u8 @0, flags u8 @1, fd i32 @4,
+    /// off u64 @8, len u32 @24
+    pub(crate) fn of(entry: &squeue::Entry) -> Self {
+        let raw = (entry as *const squeue::Entry).cast::<u8>();
+        // SAFETY: `Entry` is `repr(C)` around the kernel's 64 byte `io_uring_sqe`, whose layout is ABI
+        let (flags, fd, offset, len) = unsafe {
+            (
+                *raw.add(1),
+                raw.add(4).cast::<i32>().read_unaligned(),
+                raw.add(8).cast::<u64>().read_unaligned(),
+                raw.add(24).cast::<u32>().read_unaligned(),
+            )
+        };
+        let fixed = flags & squeue::Flags::FIXED_FILE.bits() != 0;
+        SqeFields {
+            opcode: entry.get_opcode() as u8,
+            flags,
+            fd: (!fixed).then_some(fd),
+            fixed: fixed.then_some(fd as u32),
+            offset,
+            len,
+        }
+    }
+}
+
+/// One request as the journal saw it (`UringConfig::journal`)
+/// - user_data -> (session << 32) | slot, session 0 is the reader's own (cancels, wait timers)
+/// - opcode -> the IORING_OP_* number, `opcode_name` spells it out
+/// - flags -> its IOSQE_* flags (IO_LINK: the next request waits for this one, ASYNC, ...)
+/// - fd / fixed -> the file it works on: an fd, or the index in the registered file table
+/// - path -> what `/proc/self/fd` says about `fd`, only looked up by `dump_pending` (the fd is still
+///   open then), None otherwise
+/// - offset / len -> as in the SQE (for a read: file offset and buffer length)
+/// - submitted -> when it was pushed into the submission queue
+/// - completed -> when its CQE was reaped and the result, None while pending
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub user_data: u64,
+    pub opcode: u8,
+    pub flags: u8,
+    pub fd: Option<i32>,
+    pub fixed: Option<u32>,
+    pub path: Option<Arc<Path>>,
+    pub offset: u64,
+    pub len: u32,
+    pub submitted: Instant,
+    pub completed: Option<(Instant, i32)>,
+}
+
+impl JournalEntry {
+    /// The opcode by name ("READ", "STATX", ...), "OP" for the ones this crate never pushes
+    pub fn opcode_name(&self) -> &'static str {
+        match self.opcode {
+            opcode::Nop::CODE => "NOP",
+            opcode::Readv::CODE => "READV",
+            opcode::Writev::CODE => "WRITEV",
+            opcode::Fsync::CODE => "FSYNC",
+            opcode::ReadFixed::CODE => "READ_FIXED",
+            opcode::WriteFixed::CODE => "WRITE_FIXED",
+            opcode::PollAdd::CODE => "POLL_ADD",
+            opcode::Timeout::CODE => "TIMEOUT",
+            opcode::TimeoutRemove::CODE => "TIMEOUT_REMOVE",
+            opcode::AsyncCancel::CODE => "ASYNC_CANCEL",
+            opcode::LinkTimeout::CODE => "LINK_TIMEOUT",
+            opcode::OpenAt::CODE => "OPENAT",
+            opcode::OpenAt2::CODE => "OPENAT2",
+            opcode::Close::CODE => "CLOSE",
+            opcode::FilesUpdate::CODE => "FILES_UPDATE",
+            opcode::Statx::CODE => "STATX",
+            opcode::Read::CODE => "READ",
+            opcode::Write::CODE => "WRITE",
+            opcode::Fadvise::CODE => "FADVISE",
+            opcode::Splice::CODE => "SPLICE",
+            opcode::ProvideBuffers::CODE => "PROVIDE_BUFFERS",
+            opcode::RenameAt::CODE => "RENAMEAT",
+            opcode::UnlinkAt::CODE => "UNLINKAT",
+            opcode::MkDirAt::CODE => "MKDIRAT",
+            opcode::SymlinkAt::CODE => "SYMLINKAT",
+            opcode::MsgRingData::CODE => "MSG_RING",
+            opcode::GetXattr::CODE => "GETXATTR",
+            opcode::FGetXattr::CODE => "FGETXATTR",
+            opcode::SetXattr::CODE => "SETXATTR",
+            opcode::FSetXattr::CODE => "FSETXATTR",
+            opcode::Recv::CODE => "RECV",
+            opcode::ReadMulti::CODE => "READ_MULTISHOT",
+            _ => "OP",
+        }
+    }
+
+    /// How long it has been in flight (pending), or how long it took
+    pub fn age(&self) -> Duration {
+        match self.completed {
+            Some((at, _)) => at - self.submitted,
+            None => self.submitted.elapsed(),
+        }
+    }
+}
+
+impl fmt::Display for JournalEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x} {}", self.user_data, self.opcode_name())?;
+        match (self.fd, self.fixed) {
+            (Some(fd), _) => write!(f, " fd {fd}")?,
+            (None, Some(index)) => write!(f, " fixed {index}")?,
+            (None, None) => {}
+        }
+        if let Some(path) = &self.path {
+            write!(f, " ({})", path.display())?;
+        }
+        write!(f, " offset {} len {}", self.offset, self.len)?;
+        if self.flags != 0 {
+            write!(f, " flags {:#x}", self.flags)?;
+        }
+        match self.completed {
+            Some((_, result)) => write!(f, " -> {result} after {:?}", self.age()),
+            None => write!(f, " pending for {:?}", self.age()),
+        }
+    }
+}
+
+/// The journal of one reader
+/// - pending -> pushed, no CQE yet, in push order per user_data (the cancels all share one)
+/// - recent -> the last `capacity` completions, oldest first
+pub(crate) struct Journal {
+    capacity: usize,
+    state: Mutex<JournalState>,
+}
+
+#[derive(Default)]
+struct JournalState {
+    pending: HashMap<u64, VecDeque<JournalEntry>>,
+    recent: VecDeque<JournalEntry>,
+}
+
+impl Journal {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Journal {
+            capacity: capacity.max(1),
+            state: Mutex::new(JournalState::default()),
+        }
+    }
+
+    /// `entry` went into the submission queue
+    pub(crate) fn pushed(&self, entry: &squeue::Entry) {
+        let now = Instant::now();
+        let sqe = SqeFields::of(entry);
+        let record = JournalEntry {
+            user_data: entry.get_user_data(),
+            opcode: sqe.opcode,
+            flags: sqe.flags,
+            fd: sqe.fd,
+            fixed: sqe.fixed,
+            path: None,
+            offset: sqe.offset,
+            len: sqe.len,
+            submitted: now,
+            completed: None,
+        };
+        lock(&self.state)
+            .pending
+            .entry(record.user_data)
+            .or_default()
+            .push_back(record);
+    }
+
+    /// Start logging the CQEs of one reap, the journal stays locked until the end of it
+    pub(crate) fn reaping(&self) -> Reaping<'_> {
+        Reaping {
+            state: lock(&self.state),
+            capacity: self.capacity,
+            now: Instant::now(),
+        }
+    }
+
+    fn pending(&self) -> Vec<JournalEntry> {
+        let mut pending: Vec<JournalEntry> = lock(&self.state)
+            .pending
+            .values()
+            .flatten()
+            .cloned()
+            .collect();
+        pending.sort_by_key(|record| record.submitted);
+        pending
+    }
+
+    fn recent(&self, n: usize) -> Vec<JournalEntry> {
+        let state = lock(&self.state);
+        let skip = state.recent.len().saturating_sub(n);
+        state.recent.iter().skip(skip).cloned().collect()
+    }
+}
+
+/// The journal while one reap runs, see `Journal::reaping`
+pub(crate) struct Reaping<'j> {
+    state: MutexGuard<'j, JournalState>,
+    capacity: usize,
+    now: Instant,
+}
+
+impl Reaping<'_> {
+    #[allow(unused_doc_comments)]
+    pub(crate) fn reaped(&mut self, cqe: &Completion) {
+        let Some(queue) = self.state.pending.get_mut(&cqe.user_data()) else {
+            return;
+        };
+        /// A multishot request stays armed, every CQE but the last is logged as a copy
+        let record = if cqe.is_more() {
+            queue.front().cloned()
+        } else {
+            queue.pop_front()
+        };
+        if queue.is_empty() {
+            self.state.pending.remove(&cqe.user_data());
+        }
+        let Some(mut record) = record else {
+            return;
+        };
+        record.completed = Some((self.now, cqe.result()));
+        if self.state.recent.len() == self.capacity {
+            self.state.recent.pop_front();
+        }
+        self.state.recent.push_back(record);
+    }
+}
+
+impl UringReader {
+    /// Requests pushed on this reader whose CQE wasn't reaped yet, oldest first, for "what is this
+    /// batch waiting on" (`UringConfig::journal`, empty without it)
+    ///
+    /// Paths are looked up now, one `readlink` per entry with an fd.
+    pub fn dump_pending(&self) -> Vec<JournalEntry> {
+        let Some(journal) = &self.journal else {
+            return Vec::new();
+        };
+        let mut pending = journal.pending();
+        for record in &mut pending {
+            // Outside of the journal's lock, a readlink is a syscall
+            record.path = record
+                .fd
+                .and_then(|fd| std::fs::read_link(format!("/proc/self/fd/{fd}")).ok())
+                .map(PathBuf::into);
+        }
+        pending
+    }
+
+    /// The last `n` requests that completed, oldest first (`UringConfig::journal`, empty without it)
+    pub fn dump_recent(&self, n: usize) -> Vec<JournalEntry> {
+        self.journal
+            .as_ref()
+            .map_or_else(Vec::new, |journal| journal.recent(n))
+    }
+}
@@ -0,0 +1,171 @@
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::UringConfig;
+use crate::error::ReadError;
+use crate::reader::UringReader;
+use crate::stats::{CloseReport, LaneStats};
+
+/// Which ring of a `LanedReader` a request goes to
+/// - Fast -> the small ring, for reads somebody is waiting on (a config file, an index block)
+/// - Bulk -> the big ring, for scans and batches, the default
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Lane {
+    Fast,
+    #[default]
+    Bulk,
+}
+
+/// Configuration for a `LanedReader`, one `UringConfig` per lane
+///
+/// The default fast lane is a ring of 4 entries that spins 50 µs on its completions before it
+/// sleeps, the bulk lane is `UringConfig::default()`. Add `UringConfig::sqpoll` to the fast lane to
+/// skip the submit syscall too.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use uring_fast_read::{Lane, LanedConfig, LanedReader, UringConfig};
+///
+/// let lanes = LanedReader::new(
+///     LanedConfig::default()
+///         .fast(LanedConfig::default_fast().sqpoll(Some(Duration::from_millis(10))))
+///         .bulk(UringConfig::default().queue_depth(256)),
+/// )
+/// .unwrap();
+/// let config = lanes.read_file_to_vec(Lane::Fast, "/etc/app.toml").unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct LanedConfig {
+    pub(crate) fast: UringConfig,
+    pub(crate) bulk: UringConfig,
+}
+
+impl Default for LanedConfig {
+    fn default() -> Self {
+        LanedConfig {
+            fast: Self::default_fast(),
+            bulk: UringConfig::default(),
+        }
+    }
+}
+
+impl LanedConfig {
+    /// Same as `LanedConfig::default()`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// What the fast lane gets by default, to build on
+    pub fn default_fast() -> UringConfig {
+        UringConfig::default()
+            .queue_depth(4)
+            .spin_before_wait(Duration::from_micros(50))
+    }
+
+    /// The config of the fast lane's ring
+    pub fn fast(mut self, config: UringConfig) -> Self {
+        self.fast = config;
+        self
+    }
+
+    /// The config of the bulk lane's ring
+    pub fn bulk(mut self, config: UringConfig) -> Self {
+        self.bulk = config;
+        self
+    }
+}
+
+/// Two rings behind one reader: a small one for latency critical reads, a big one for the rest
+///
+/// A 4 KiB read that needs to be fast waits behind everything queued before it on a ring that also
+/// runs a 10 GB scan. Here each request names its `Lane`, the fast lane's reads never share a queue
+/// (or an io-wq) with the bulk ones. Each lane is a full `UringReader` created like any other, with
+/// the same probing and the same fallbacks for what the kernel lacks, `lane` hands it out for
+/// everything that isn't forwarded here.
+///
+/// `stats` reports each lane on its own: the fast lane's `ReadStats` only ever count fast reads, the
+/// isolation can be checked against them.
+pub struct LanedReader {
+    fast: UringReader,
+    bulk: UringReader,
+}
+
+impl LanedReader {
+    /// Create both rings, see `UringReader::new`
+    pub fn new(config: LanedConfig) -> io::Result<Self> {
+        Ok(LanedReader {
+            fast: UringReader::new(config.fast)?,
+            bulk: UringReader::new(config.bulk)?,
+        })
+    }
+
+    /// The reader behind `lane`, for the calls that aren't forwarded
+    pub fn lane(&self, lane: Lane) -> &UringReader {
+        match lane {
+            Lane::Fast => &self.fast,
+            Lane::Bulk => &self.bulk,
+        }
+    }
+
+    /// `UringReader::read_file_to_vec` on `lane`
+    pub fn read_file_to_vec(&self, lane: Lane, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        self.lane(lane).read_file_to_vec(path)
+    }
+
+    /// `UringReader::read_at` on `lane`
+    pub fn read_at(
+        &self,
+        lane: Lane,
+        path: impl AsRef<Path>,
+        offset: u64,
+        len: usize,
+    ) -> io::Result<(Vec<u8>, usize)> {
+        self.lane(lane).read_at(path, offset, len)
+    }
+
+    /// `UringReader::read_many_files` on `lane`
+    pub fn read_many_files<P: AsRef<Path>>(
+        &self,
+        lane: Lane,
+        paths: &[P],
+    ) -> Vec<io::Result<Vec<u8>>> {
+        self.lane(lane).read_many_files(paths)
+    }
+
+    /// The counters of both lanes, each on its own
+    pub fn stats(&self) -> LaneStats {
+        LaneStats {
+            fast: self.fast.stats(),
+            bulk: self.bulk.stats(),
+        }
+    }
+
+    /// Close both rings, see `UringReader::close`
+    ///
+    /// The report adds up both lanes, a failure names the lane it happened on. Dropping the reader
+    /// closes them too and ignores what went wrong.
+    pub fn close(self) -> io::Result<CloseReport> {
+        let mut report = CloseReport::default();
+        for (name, reader) in [("fast", self.fast), ("bulk", self.bulk)] {
+            match reader.close() {
+                Ok(closed) => report.absorb(closed),
+                Err(e) => match ReadError::from_io(&e) {
+                    Some(ReadError::CloseFailed { report: closed }) => {
+                        let mut closed = closed.clone();
+                        for failure in &mut closed.failures {
+                            *failure = format!("{name} lane: {failure}");
+                        }
+                        report.absorb(closed);
+                    }
+                    _ => report.failures.push(format!("{name} lane: {e}")),
+                },
+            }
+        }
+        if report.failures.is_empty() {
+            Ok(report)
+        } else {
+            Err(ReadError::CloseFailed { report }.into())
+        }
+    }
+}
@@ -1,16 +1,275 @@
-
 /// IoUring -> the ring itself (shared memory with the kernel)
 /// opcode -> what operation to perform (read, write, etc.)
 /// types -> wrappers for Linux kernel types (FDs, fixed files, etc)
+#[cfg(target_os = "linux")]
 use io_uring::{IoUring, opcode, types};
 
 /// File -> normal file in the filesystem
+#[cfg(target_os = "linux")]
 use std::fs::File;
 
 /// AsRawFd -> lets us extract the raw Linux file descriptor, the Kernel does not understand `File`.
 /// It only understands integers (int fd)
+#[cfg(target_os = "linux")]
 use std::os::unix::io::AsRawFd;
 
+/// InFlightGuard -> owns the file and the buffer while the kernel may still use them
+#[cfg(target_os = "linux")]
+use guard::InFlightGuard;
+
+/// bench -> `bench::compare`, io_uring against std::fs on the same files (feature `bench`)
+#[cfg(feature = "bench")]
+pub mod bench;
+
+/// backend -> `ReadBackend`, what the whole-file reads need from a ring, and `BackendReader` on top of it
+mod backend;
+pub use backend::{BackendReader, ReadBackend, Reaped};
+
+/// caps -> `Capabilities`, the IORING_FEAT_* flags of the running kernel
+mod caps;
+pub use caps::Capabilities;
+
+/// checksum -> `checksum_tree` (a path -> digest manifest of a directory), `read_verified` and
+/// `hash_file_parallel` (features `xxh3`/`sha256`)
+#[cfg(any(feature = "xxh3", feature = "sha256"))]
+mod checksum;
+#[cfg(any(feature = "xxh3", feature = "sha256"))]
+pub use checksum::{Digest, HashAlgo, TreeDigest, VerifyError};
+
+/// completion -> owned, decoded CQEs (result + flags), the one place the CQE flags word is interpreted
+mod completion;
+pub use completion::Completion;
+
+/// config -> knobs for the persistent reader
+/// dedup -> finding the paths of a batch that name the same file (`UringConfig::dedup`)
+/// decompress -> `read_decompressed`, gzip/zstd decoded on the fly (features `flate2`/`zstd`)
+/// effective -> `EffectiveConfig`, a config next to what the reader made of it (support bundles)
+/// error -> `ReadError`, the crate specific errors carried inside `io::Error`
+/// failpoints -> `FailPoints`, seeded fault injection into the reads of a reader (feature `failpoints`)
+/// lines -> `LineReader`, line by line on top of `UringFile`
+/// mock -> `MockBackend`, an in-memory `ReadBackend` with scripted faults (feature `test-util`)
+/// records -> `RecordReader`, fixed size records on top of `UringFile`
+/// retry -> `RetryPolicy`, retrying transient errors (`UringConfig::retry`)
+/// stats -> counters collected by the reader
+/// throttle -> `RateLimit`, a token bucket on completed bytes (`UringConfig::rate_limit`)
+/// tune -> `AutoTune`, AIMD queue depth tuning (`UringConfig::auto_tune`)
+/// timing -> per request queue/in-kernel timestamps (`record_timings`)
+/// tree -> `CopyTreeOptions` and the policies of `copy_tree`, and the tree copy both readers share
+/// walk -> recursive directory walker used by the tree APIs
+mod config;
+#[cfg(any(feature = "flate2", feature = "zstd"))]
+mod decompress;
+mod dedup;
+mod effective;
+mod error;
+#[cfg(feature = "failpoints")]
+mod failpoints;
+mod lines;
+#[cfg(feature = "test-util")]
+mod mock;
+mod records;
+mod retry;
+mod stats;
+mod throttle;
+mod timing;
+mod tree;
+mod tune;
+mod walk;
+pub use config::{
+    ChangePolicy, DedupPolicy, MemlockPolicy, PadPolicy, ShrinkPolicy, UringConfig, VerifyPolicy,
+};
+#[cfg(any(feature = "flate2", feature = "zstd"))]
+pub use decompress::Compression;
+pub use effective::{EffectiveConfig, Granted};
+pub use error::{Cancelled, ReadError, Stage};
+#[cfg(feature = "failpoints")]
+pub use failpoints::{FailPoints, FailRule, InjectedFault};
+pub use lines::LineReader;
+#[cfg(feature = "test-util")]
+pub use mock::{Fault, MockBackend, MockRequest};
+pub use records::{RecordReader, TrailingRecord};
+pub use retry::{RetryPolicy, is_transient};
+pub use stats::{
+    AbandonedRequest, CloseReport, CopyReport, DrainReport, FileMeta, FileStamp, LaneStats,
+    Partial, ReadOutcome, ReadStats, RingSnapshot, SyscallSummary, TreeCopyReport, TreeEntry,
+};
+pub use throttle::RateLimit;
+pub use timing::RequestTiming;
+pub use tree::{CopyTreeOptions, ExistingPolicy, SymlinkPolicy};
+pub use tune::AutoTune;
+
+/// The io_uring implementation, Linux only
+/// buffers -> buffers registered with the ring (`UringConfig::fixed_buffers`), RLIMIT_MEMLOCK handling
+/// chain -> linked open/read/write/fsync/close/rename chains with per stage errors
+/// concat -> `read_concat`, the parts of a sharded file back into one buffer
+/// copy -> `copy_file` and `copy_tree`, read and write chunks through the ring
+/// dir -> `DirHandle`, files opened by name relative to one held directory fd
+/// driver -> `AsyncUring` and its `Driver` (or `current_thread`, no driver), async reads on async-io/smol
+///   executors (feature `async-io`)
+/// ffi -> the C ABI, `uring_fast_read` and `uring_reader_*` (feature `ffi`)
+/// file -> `UringFile`, sequential `Read`/`BufRead` with one chunk read ahead
+/// files -> whole-file reads: one file, many files, a directory tree
+/// guard -> `InFlightGuard`, keeps what a raw ring request points into alive until its CQE is reaped
+/// instrument -> counters and histograms through the `metrics` facade (feature `metrics`)
+/// journal -> `dump_pending`/`dump_recent`, an in-memory log of requests (`UringConfig::journal`)
+/// lanes -> `LanedReader`, a small ring for latency critical reads next to the bulk one
+/// minimal -> `MinimalReader`, reads into caller buffers through a fixed array of slots, no
+///   allocation after `new` (feature `minimal`)
+/// notify -> eventfd based wake ups for async callers (feature `async`)
+/// owned -> `read_owned`, reads that own their buffer while in flight (feature `async` for the future)
+/// personality -> `register_personality`, opening files with captured credentials
+/// poller -> `ReadPoller`, submit and reap without ever blocking (frame budget style loops)
+/// pool -> `RingPool`, several rings driven by their own threads (optionally NUMA placed)
+/// prepared -> `PreparedRead`, one read template executed over and over
+/// ring_backend -> `UringBackend`, `ReadBackend` on a `UringReader`
+/// ranges -> `read_at`/`read_ranges`, pieces of a file (`UringConfig::pad`)
+/// ready -> `read_when_ready`, a POLLIN poll linked to the read for pipes/FIFOs/devices
+/// recv -> `recv`/`recv_multishot`, bytes from a socket the caller owns (provided buffers)
+/// reader -> `UringReader`, one ring that is kept around and shared between calls/threads
+/// sandbox -> `SandboxedReader`, reads that can't escape a root directory (openat2 + RESOLVE_BENEATH)
+/// sequential -> `SequentialReader`, one open file read block by block at a tracked offset
+/// scan -> `read_chunks_with`, a file in offset order to a callback that can stop early
+/// stat -> statx through the ring, `stat_many` for a batch of paths
+/// stream -> `ReadManyStream`, files as a `futures_core::Stream` (feature `async`)
+/// verify -> `UringConfig::verify_against_std`, whole-file reads checked against std in the background
+/// xattr -> extended attributes through the ring (GetXattr/SetXattr), syscalls on older kernels
+#[cfg(target_os = "linux")]
+mod buffers;
+#[cfg(target_os = "linux")]
+mod chain;
+#[cfg(target_os = "linux")]
+mod concat;
+#[cfg(target_os = "linux")]
+mod copy;
+#[cfg(target_os = "linux")]
+mod dir;
+#[cfg(all(target_os = "linux", feature = "async-io"))]
+mod driver;
+#[cfg(all(target_os = "linux", feature = "ffi"))]
+pub mod ffi;
+#[cfg(target_os = "linux")]
+mod file;
+#[cfg(target_os = "linux")]
+mod files;
+#[cfg(target_os = "linux")]
+mod guard;
+#[cfg(target_os = "linux")]
+mod instrument;
+#[cfg(target_os = "linux")]
+mod journal;
+#[cfg(target_os = "linux")]
+mod lanes;
+#[cfg(all(target_os = "linux", feature = "minimal"))]
+mod minimal;
+#[cfg(all(target_os = "linux", feature = "async"))]
+mod notify;
+#[cfg(target_os = "linux")]
+mod owned;
+#[cfg(target_os = "linux")]
+mod personality;
+#[cfg(target_os = "linux")]
+mod poller;
+#[cfg(target_os = "linux")]
+mod pool;
+#[cfg(target_os = "linux")]
+mod prepared;
+#[cfg(target_os = "linux")]
+mod ranges;
+#[cfg(target_os = "linux")]
+mod reader;
+#[cfg(target_os = "linux")]
+mod ready;
+#[cfg(target_os = "linux")]
+mod recv;
+#[cfg(target_os = "linux")]
+mod ring_backend;
+#[cfg(target_os = "linux")]
+mod sandbox;
+#[cfg(target_os = "linux")]
+mod scan;
+#[cfg(target_os = "linux")]
+mod sequential;
+#[cfg(target_os = "linux")]
+mod stat;
+#[cfg(all(target_os = "linux", feature = "async"))]
+mod stream;
+#[cfg(target_os = "linux")]
+mod verify;
+#[cfg(target_os = "linux")]
+mod xattr;
+#[cfg(target_os = "linux")]
+pub use chain::{ChainResult, ChainToken};
+#[cfg(target_os = "linux")]
+pub use dir::DirHandle;
+#[cfg(all(target_os = "linux", feature = "async-io"))]
+pub use driver::{AsyncUring, Driven, Driver};
+#[cfg(target_os = "linux")]
+pub use file::UringFile;
+#[cfg(target_os = "linux")]
+pub use journal::JournalEntry;
+#[cfg(target_os = "linux")]
+pub use lanes::{Lane, LanedConfig, LanedReader};
+#[cfg(all(target_os = "linux", feature = "minimal"))]
+pub use minimal::MinimalReader;
+#[cfg(all(target_os = "linux", feature = "async"))]
+pub use owned::ReadOwned;
+#[cfg(target_os = "linux")]
+pub use owned::{Completed, Failed};
+#[cfg(target_os = "linux")]
+pub use personality::PersonalityId;
+#[cfg(target_os = "linux")]
+pub use poller::{ReadPoller, Token};
+#[cfg(target_os = "linux")]
+pub use pool::{PoolConfig, RingPool};
+#[cfg(target_os = "linux")]
+pub use prepared::PreparedRead;
+#[cfg(target_os = "linux")]
+pub use reader::UringReader;
+#[cfg(target_os = "linux")]
+pub use recv::{BufferGroup, MultishotRecv, Received};
+#[cfg(target_os = "linux")]
+pub use ring_backend::UringBackend;
+#[cfg(target_os = "linux")]
+pub use sandbox::SandboxedReader;
+#[cfg(target_os = "linux")]
+pub use sequential::SequentialReader;
+#[cfg(all(target_os = "linux", feature = "async"))]
+pub use stream::ReadManyStream;
+#[cfg(target_os = "linux")]
+pub use xattr::FileWithXattrs;
+
+/// fallback -> the same API on top of `std::fs` everywhere else (the slow path)
+#[cfg(not(target_os = "linux"))]
+mod fallback;
+#[cfg(all(not(target_os = "linux"), feature = "async"))]
+pub use fallback::ReadManyStream;
+#[cfg(not(target_os = "linux"))]
+pub use fallback::{
+    DirHandle, SequentialReader, UringBackend, UringFile, UringReader, read_one_file,
+};
+
+/// Which implementation is behind `UringReader`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Backend {
+    /// io_uring (Linux)
+    IoUring,
+    /// plain blocking `std::fs`, see the fallback module docs
+    Std,
+}
+
+/// The implementation this build uses, e.g. to assert in tests that Linux really gets io_uring
+///
+/// This is decided at compile time. On Linux a kernel without io_uring (or with it disabled) still
+/// reports `Backend::IoUring`, `UringReader::new` is what fails there.
+pub const fn backend() -> Backend {
+    if cfg!(target_os = "linux") {
+        Backend::IoUring
+    } else {
+        Backend::Std
+    }
+}
+
 /// This function takes the file path as input and outputs;
 /// Ok(n) -> number of bytes read
 /// Err(e) -> an OS error
@@ -20,9 +279,9 @@ use std::os::unix::io::AsRawFd;
 /// - synchronous in the thread it's in
 ///
 /// [TODO]: Remove `submit_and_wait()` and poll manually
+#[cfg(target_os = "linux")]
 #[allow(unused_doc_comments)]
 pub fn read_one_file(path: &str) -> std::io::Result<usize> {
-
     /// Step 1: Create the ring "Create shared memory queues that can hold up to 8(in this case) in-flight requests"
     /// Why 8? No reason, this is arbitrary, it just needs to be >= number of requests you'll submit.
     ///
@@ -47,9 +306,16 @@ pub fn read_one_file(path: &str) -> std::io::Result<usize> {
     /// Why 4096? This is the typical filesystem block size, it is not required but convenient
     ///
     /// NOTE: The buffer must not move in memory while the kernel is using it. This is why fixed buffers exist
-    let mut buffer = vec![0u8; 4096];
+    let buffer = vec![0u8; 4096];
+
+    /// Step 4: Hand the file and the buffer to a guard
+    /// Once the request is pushed the kernel owns the buffer until its completion is reaped. Every early return from
+    /// here on (a `?`, a panic) drops the guard, which cancels the request and waits until the kernel confirmed it
+    /// before the buffer and the file are freed.
+    let mut guard = InFlightGuard::new(&mut ring, (file, buffer));
+    let buffer = &mut guard.keep_mut().1;
 
-    /// Step 4: Prepare the read request (SQE: Submission Queue Entry) "Kernel, please read `len` bytes from file `fd` starting at offset `0`, and write them into this memory address"
+    /// Step 5: Prepare the read request (SQE: Submission Queue Entry) "Kernel, please read `len` bytes from file `fd` starting at offset `0`, and write them into this memory address"
     ///
     /// opcode::Read::new(...) constructs a read request, not execution with arguments:
     /// - types::Fd(fd): Read from this file descriptor
@@ -60,26 +326,21 @@ pub fn read_one_file(path: &str) -> std::io::Result<usize> {
     /// .build(): Turns the builder into an actual Submission Queue Entry, basically "Freeze the request into a kernel-understandable format"
     /// .user_data(0xdead_beef): This is the tag, kernel will copy this value back into the completion event inorder to;
     ///     - Identify which request completed, especiall when many requests are submitted
-    let read_e = opcode::Read::new(
-        types::Fd(fd),
-        buffer.as_mut_ptr(),
-        buffer.len() as u32,
-    )
-    .offset(0)
-    .build()
-    .user_data(0xdead_beef);
-
-    /// Step 5: Push request into submission queue
-    /// Why unsafe? Writing into shared memory, Rust cannot guarantee the kernel won't misuse it.
+    let read_e = opcode::Read::new(types::Fd(fd), buffer.as_mut_ptr(), buffer.len() as u32)
+        .offset(0)
+        .build()
+        .user_data(0xdead_beef);
+
+    /// Step 6: Push request into submission queue
+    /// Why unsafe? Writing into shared memory, Rust cannot guarantee the kernel won't misuse it. The guard owns
+    /// everything the request points to, which is what it needs to be sure of.
     ///
     /// This basically places the read request into the Submission Queue, it does not execute it yet. Nothing happens until it is actually submitted.
     unsafe {
-        ring.submission()
-            .push(&read_e)
-            .expect("Submission queue is full");
+        guard.push(&read_e)?;
     }
 
-    /// Step 6: Submit & wait
+    /// Step 7: Submit, wait & read the completion (CQE: Completion Queue Entry)
     /// This does two things at the same time:
     /// - Tells the kernel "I've added requests. Go process them"
     /// - Blocks until atleast one completion exists
@@ -88,12 +349,12 @@ pub fn read_one_file(path: &str) -> std::io::Result<usize> {
     /// - Kernel reads the file
     /// - DMA / page cache / disk happens
     /// - Kernel writes result into Completion Queue (CQ)
-    ring.submit_and_wait(1)?;
-
-    /// Step 7: Read completion (CQE: Completion Queue Entry)
-    /// - Pulls one completion event
-    /// - This is the kernel saying "That request you submitted? It's done"
-    let cqe = ring.completion().next().unwrap();
+    /// - `Completion::from` copies it out of the ring (result + flags) so the slot can be reused
+    ///
+    /// EINTR only means a signal woke us up early, the request was still submitted, it waits again. If the wait
+    /// fails for real the `?` returns, and the guard makes sure the request is gone before the buffer is.
+    let cqe = guard.submit_and_wait()?;
+    let (_file, _buffer) = guard.finish();
 
     /// Step 8: Interpret result
     /// - res >= 0 bytes were read
@@ -105,15 +366,13 @@ pub fn read_one_file(path: &str) -> std::io::Result<usize> {
     ///     return Err(std::os::Error::from_raw_os_error(-res));
     /// }
     /// ```
-    let res = cqe.result();
-
-    if res < 0 {
-        return Err(std::io::Error::from_raw_os_error(-res));
-    }
+    ///
+    /// `into_result()` does exactly that for us
+    let res = cqe.into_result()?;
 
     /// Step 9: Return bytes read
     /// At this point:
-    /// - buffer[..res] contains file data
+    /// - _buffer[..res] contains file data
     /// - Kernel is done touching memory
     Ok(res as usize)
 }
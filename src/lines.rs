@@ -0,0 +1,176 @@
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+
+use crate::error::ReadError;
+use crate::{UringFile, UringReader};
+
+/// Longest line accepted by default, 1 MiB
+const DEFAULT_MAX_LINE_LEN: usize = 1 << 20;
+
+/// Lines of a file, read through the ring
+///
+/// ```no_run
+/// use uring_fast_read::LineReader;
+///
+/// for line in LineReader::open("/var/log/syslog").unwrap() {
+///     println!("{}", line.unwrap());
+/// }
+/// ```
+///
+/// - `\n` and `\r\n` both end a line, the terminator is not part of the line
+/// - the last line does not need a trailing newline
+/// - lines longer than `max_line_len` are reported as `ReadError::LineTooLong` and skipped, the lines
+///   after them are still read
+///
+/// Iterating yields `String`s (invalid UTF-8 is an `InvalidData` error). `next_line` hands out the raw
+/// bytes instead, borrowed from the reader, without allocating per line.
+pub struct LineReader<'r> {
+    file: UringFile<'r>,
+    path: PathBuf,
+    /// the line being assembled, lines that span chunk boundaries are carried here
+    line: Vec<u8>,
+    max_line_len: usize,
+}
+
+impl LineReader<'static> {
+    /// Open `path` on the process wide default reader (`UringConfig::default()`, created on first use)
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        UringReader::shared_default()?.read_lines(path)
+    }
+}
+
+impl UringReader {
+    /// Read `path` line by line, see `LineReader`
+    pub fn read_lines(&self, path: impl AsRef<Path>) -> io::Result<LineReader<'_>> {
+        let path = path.as_ref();
+        Ok(LineReader {
+            file: self.open_file(path)?,
+            path: path.to_path_buf(),
+            line: Vec::new(),
+            max_line_len: DEFAULT_MAX_LINE_LEN,
+        })
+    }
+}
+
+impl<'r> LineReader<'r> {
+    /// Longest line (without its terminator) that is accepted, defaults to 1 MiB
+    ///
+    /// A file without newlines would otherwise be buffered whole.
+    pub fn max_line_len(mut self, max_line_len: usize) -> Self {
+        self.max_line_len = max_line_len;
+        self
+    }
+
+    /// The next line as raw bytes, borrowed until the next call
+    ///
+    /// None -> end of file
+    /// Some(Err(e)) -> a read error, or `ReadError::LineTooLong` (that line is skipped)
+    #[allow(unused_doc_comments)]
+    pub fn next_line(&mut self) -> Option<io::Result<&[u8]>> {
+        self.line.clear();
+        let mut too_long = false;
+        let mut seen_any = false;
+
+        loop {
+            let chunk = match self.file.fill_buf() {
+                Ok(chunk) => chunk,
+                Err(e) => return Some(Err(e)),
+            };
+            if chunk.is_empty() {
+                if !seen_any {
+                    return None;
+                }
+                break;
+            }
+            seen_any = true;
+
+            let (part, used, done) = match chunk.iter().position(|&b| b == b'\n') {
+                Some(i) => (&chunk[..i], i + 1, true),
+                None => (chunk, chunk.len(), false),
+            };
+            /// One byte over the limit is kept until the line is complete, it may be the `\r` of a
+            /// `\r\n`
+            if !too_long {
+                if self.line.len() + part.len() > self.max_line_len.saturating_add(1) {
+                    too_long = true;
+                    self.line.clear();
+                } else {
+                    self.line.extend_from_slice(part);
+                }
+            }
+            self.file.consume(used);
+            if done {
+                break;
+            }
+        }
+
+        if self.line.last() == Some(&b'\r') {
+            self.line.pop();
+        }
+        if too_long || self.line.len() > self.max_line_len {
+            return Some(Err(ReadError::LineTooLong {
+                path: self.path.clone(),
+                limit: self.max_line_len,
+            }
+            .into()));
+        }
+        Some(Ok(&self.line))
+    }
+}
+
+impl Iterator for LineReader<'_> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = match self.next_line()? {
+            Ok(line) => line.to_vec(),
+            Err(e) => return Some(Err(e)),
+        };
+        Some(String::from_utf8(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UringConfig;
+
+    /// The lines of `contents` with a limit of `max` bytes, read `chunk` bytes at a time
+    fn lines(contents: &[u8], max: usize, chunk: usize) -> Vec<Result<Vec<u8>, usize>> {
+        let name = format!("uring_fast_read-{}-lines-{max}-{chunk}", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, contents).unwrap();
+        let reader = UringReader::new(UringConfig::default().chunk_size(chunk)).unwrap();
+        let mut lines = reader.read_lines(&path).unwrap().max_line_len(max);
+        let mut all = Vec::new();
+        while let Some(line) = lines.next_line() {
+            all.push(match line {
+                Ok(line) => Ok(line.to_vec()),
+                Err(e) => match ReadError::from_io(&e) {
+                    Some(ReadError::LineTooLong { limit, .. }) => Err(*limit),
+                    _ => panic!("{e}"),
+                },
+            });
+        }
+        drop(lines);
+        std::fs::remove_file(&path).unwrap();
+        all
+    }
+
+    #[test]
+    fn crlf_lines_at_the_limit() {
+        let contents = b"1234\r\n12345\r\n123\r\n1234\n12345\n1234\r";
+        let expected = vec![
+            Ok(b"1234".to_vec()),
+            Err(4),
+            Ok(b"123".to_vec()),
+            Ok(b"1234".to_vec()),
+            Err(4),
+            Ok(b"1234".to_vec()),
+        ];
+        // Chunks of 3 and 5 bytes split some lines between their `\r` and their `\n`
+        for chunk in [3, 5, 4096] {
+            assert_eq!(lines(contents, 4, chunk), expected, "chunks of {chunk}");
+        }
+    }
+}
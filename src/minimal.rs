@@ -0,0 +1,178 @@
+use io_uring::{IoUring, opcode, types};
+
+use std::io;
+use std::os::fd::{AsFd, AsRawFd};
+use std::time::Duration;
+
+use crate::guard::{SETTLE_ATTEMPTS, give_up};
+
+/// Smallest piece a read is split into, a shorter buffer goes out as one request
+const MIN_PIECE: usize = 64 * 1024;
+
+/// Longest single read, a longer piece is read in several (the SQE length is a u32)
+const MAX_READ: usize = 1 << 30;
+
+/// The piece of the caller's buffer one slot fills: `len` bytes from `start`, `done` of them read
+#[derive(Debug, Clone, Copy, Default)]
+struct Slot {
+    start: usize,
+    len: usize,
+    done: usize,
+}
+
+/// A ring of `N` entries that reads into buffers of the caller and never allocates after `new`
+/// (feature `minimal`)
+///
+/// ```no_run
+/// use uring_fast_read::MinimalReader;
+///
+/// let mut reader = MinimalReader::<4>::new().unwrap();
+/// let file = std::fs::File::open("/proc/cmdline").unwrap();
+/// let mut buf = [0u8; 4096];
+/// let n = reader.read_into(&file, &mut buf).unwrap();
+/// ```
+///
+/// For early boot tooling and other places where allocations are budgeted. Request state is one
+/// `[Slot; N]` indexed by the slot in the user_data, there is no map, no queue and no session behind
+/// it, a call pushes, waits and reaps on its own and returns with nothing in flight. Errors are bare
+/// OS errors or kinds, which don't allocate either. None of the batch, async or metrics machinery of
+/// `UringReader` is involved: with `default-features = false` nothing of it ends up in a binary that
+/// only uses this.
+///
+/// `N` is the most reads in flight at once, 1 to 4096. A buffer is split into up to `N` pieces of at
+/// least 64 KiB that are read in parallel, short reads are continued and EINTR/EAGAIN retried.
+pub struct MinimalReader<const N: usize> {
+    ring: IoUring,
+    slots: [Slot; N],
+}
+
+impl<const N: usize> MinimalReader<N> {
+    /// Create the ring, the only allocation (and mmap) of the reader's life
+    pub fn new() -> io::Result<Self> {
+        const { assert!(N > 0 && N <= 4096, "MinimalReader needs 1 to 4096 slots") };
+        Ok(MinimalReader {
+            ring: IoUring::new(N as u32)?,
+            slots: [Slot::default(); N],
+        })
+    }
+
+    /// Read `file` from its start into `buf`, see `read_at_into`
+    pub fn read_into(&mut self, file: impl AsFd, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_at_into(file, 0, buf)
+    }
+
+    /// Fill `buf` from `file` at `offset`, the bytes read: `buf.len()`, or less where the file ends
+    ///
+    /// Nothing is in flight once it returns, whether it succeeded or not. The first error fails the
+    /// call, the pieces still in flight are reaped first.
+    pub fn read_at_into(
+        &mut self,
+        file: impl AsFd,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let fd = types::Fd(file.as_fd().as_raw_fd());
+        let piece = buf.len().div_ceil(N).max(MIN_PIECE);
+        let pieces = buf.len().div_ceil(piece);
+        for (i, slot) in self.slots[..pieces].iter_mut().enumerate() {
+            let start = i * piece;
+            *slot = Slot {
+                start,
+                len: piece.min(buf.len() - start),
+                done: 0,
+            };
+        }
+
+        let base = buf.as_mut_ptr();
+        let mut error = None;
+        let mut in_flight = 0;
+        for at in 0..pieces {
+            match self.push(fd, offset, base, at) {
+                Ok(()) => in_flight += 1,
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            }
+        }
+
+        let mut failures = 0;
+        while in_flight > 0 {
+            match self.ring.submit_and_wait(1) {
+                Ok(_) => failures = 0,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                // The pieces point into `buf`, returning before their CQEs is a use after free
+                Err(e) => {
+                    failures += 1;
+                    if failures >= SETTLE_ATTEMPTS {
+                        give_up(in_flight);
+                    }
+                    error.get_or_insert(e);
+                    std::thread::sleep(Duration::from_millis(1));
+                    continue;
+                }
+            }
+            loop {
+                let Some(cqe) = self.ring.completion().next() else {
+                    break;
+                };
+                in_flight -= 1;
+                let at = cqe.user_data() as usize;
+                match cqe.result() {
+                    res if res == -libc::EINTR || res == -libc::EAGAIN => {}
+                    res if res < 0 => {
+                        error.get_or_insert(io::Error::from_raw_os_error(-res));
+                        continue;
+                    }
+                    // The file ends inside of this piece
+                    0 => continue,
+                    res => {
+                        let slot = &mut self.slots[at];
+                        slot.done += res as usize;
+                        if slot.done == slot.len {
+                            continue;
+                        }
+                    }
+                }
+                if error.is_some() {
+                    continue;
+                }
+                match self.push(fd, offset, base, at) {
+                    Ok(()) => in_flight += 1,
+                    Err(e) => error = Some(e),
+                }
+            }
+        }
+
+        if let Some(e) = error {
+            return Err(e);
+        }
+        Ok(self.slots[..pieces]
+            .iter()
+            .find(|slot| slot.done < slot.len)
+            .map_or(buf.len(), |slot| slot.start + slot.done))
+    }
+
+    /// Push the read of what slot `at` is still missing, submitted by the next wait
+    fn push(&mut self, fd: types::Fd, offset: u64, base: *mut u8, at: usize) -> io::Result<()> {
+        let slot = self.slots[at];
+        let from = slot.start + slot.done;
+        // SAFETY: `from` is inside of the caller's buffer at `base`, which `read_at_into` borrows
+        // until this read's CQE is reaped
+        let read_e = opcode::Read::new(
+            fd,
+            unsafe { base.add(from) },
+            (slot.len - slot.done).min(MAX_READ) as u32,
+        )
+        .offset(offset + from as u64)
+        .build()
+        .user_data(at as u64);
+        // SAFETY: as above, the read points into nothing else
+        unsafe { self.ring.submission().push(&read_e) }
+            // At most N requests out of at least N entries, only a broken invariant gets here
+            .map_err(|_| io::Error::from_raw_os_error(libc::EBUSY))
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::backend::{ReadBackend, Reaped};
+
+/// What a `MockBackend` does to the requests of one path (`MockBackend::with_fault`)
+/// - Errno -> every read fails with this errno
+/// - ErrnoOnce -> the next read fails with this errno, the ones after it succeed (EINTR, EAGAIN, a
+///   transient EIO)
+/// - StatErrno -> statx fails with this errno
+/// - ShortReads -> reads return at most this many bytes
+/// - Delay -> completions are held back for this many extra `wait` calls, so they come back after
+///   the ones of other paths
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    Errno(i32),
+    ErrnoOnce(i32),
+    StatErrno(i32),
+    ShortReads(usize),
+    Delay(u32),
+}
+
+/// A request the mock received, in submission order (`MockBackend::requests`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockRequest {
+    Read {
+        path: PathBuf,
+        offset: u64,
+        len: usize,
+    },
+    Statx {
+        path: PathBuf,
+    },
+}
+
+/// An in-memory `ReadBackend` for tests (feature `test-util`)
+///
+/// Files are byte vectors keyed by path, anything else is `NotFound`. Completions come back in submission
+/// order unless a `Fault::Delay` holds some of them back, the same script always gives the same
+/// interleaving. Nothing is shared, no threads, no kernel.
+///
+/// ```
+/// use uring_fast_read::{BackendReader, Fault, MockBackend, UringConfig};
+///
+/// let mock = MockBackend::new()
+///     .with_file("/data/a", vec![7u8; 10_000])
+///     .with_fault("/data/a", Fault::ShortReads(1000))
+///     .with_fault("/data/a", Fault::ErrnoOnce(4)); // EINTR
+/// let mut reader = BackendReader::new(mock, UringConfig::default().chunk_size(4096));
+/// assert_eq!(reader.read_file_to_vec("/data/a").unwrap(), vec![7u8; 10_000]);
+/// assert!(reader.read_file_to_vec("/data/missing").is_err());
+/// ```
+#[derive(Debug, Default)]
+pub struct MockBackend {
+    files: HashMap<PathBuf, Vec<u8>>,
+    faults: HashMap<PathBuf, Vec<Fault>>,
+    /// Completions not visible yet, with the number of `wait` calls they still sit out
+    queued: VecDeque<(u64, Reaped, u32)>,
+    ready: Vec<(u64, Reaped)>,
+    requests: Vec<MockRequest>,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve `data` for `path`
+    pub fn with_file(mut self, path: impl Into<PathBuf>, data: impl Into<Vec<u8>>) -> Self {
+        self.files.insert(path.into(), data.into());
+        self
+    }
+
+    /// Script a fault for the requests of `path`, faults of one path add up
+    pub fn with_fault(mut self, path: impl Into<PathBuf>, fault: Fault) -> Self {
+        self.faults.entry(path.into()).or_default().push(fault);
+        self
+    }
+
+    /// Every request received so far, in submission order
+    pub fn requests(&self) -> &[MockRequest] {
+        &self.requests
+    }
+
+    /// Number of reads received so far
+    pub fn reads(&self) -> usize {
+        self.requests
+            .iter()
+            .filter(|r| matches!(r, MockRequest::Read { .. }))
+            .count()
+    }
+
+    /// Requests submitted whose completion wasn't reaped yet
+    pub fn in_flight(&self) -> usize {
+        self.queued.len() + self.ready.len()
+    }
+
+    fn delay(&self, path: &Path) -> u32 {
+        self.faults.get(path).map_or(0, |faults| {
+            faults
+                .iter()
+                .map(|f| match f {
+                    Fault::Delay(waits) => *waits,
+                    _ => 0,
+                })
+                .sum()
+        })
+    }
+
+    /// The scripted errno for the next read of `path`, taking a `ErrnoOnce` out of the script
+    fn read_errno(&mut self, path: &Path) -> Option<i32> {
+        let faults = self.faults.get_mut(path)?;
+        if let Some(i) = faults.iter().position(|f| matches!(f, Fault::ErrnoOnce(_))) {
+            let Fault::ErrnoOnce(errno) = faults.remove(i) else {
+                unreachable!()
+            };
+            return Some(errno);
+        }
+        faults.iter().find_map(|f| match f {
+            Fault::Errno(errno) => Some(*errno),
+            _ => None,
+        })
+    }
+
+    fn stat_errno(&self, path: &Path) -> Option<i32> {
+        self.faults.get(path)?.iter().find_map(|f| match f {
+            Fault::StatErrno(errno) => Some(*errno),
+            _ => None,
+        })
+    }
+
+    fn short_read(&self, path: &Path) -> Option<usize> {
+        self.faults.get(path)?.iter().find_map(|f| match f {
+            Fault::ShortReads(max) => Some(*max),
+            _ => None,
+        })
+    }
+}
+
+impl ReadBackend for MockBackend {
+    fn submit_read(&mut self, tag: u64, path: &Path, offset: u64, len: usize) -> io::Result<()> {
+        self.requests.push(MockRequest::Read {
+            path: path.to_path_buf(),
+            offset,
+            len,
+        });
+        let result = match (self.read_errno(path), self.files.get(path)) {
+            (Some(errno), _) => Err(io::Error::from_raw_os_error(errno)),
+            (None, None) => Err(io::ErrorKind::NotFound.into()),
+            (None, Some(data)) => {
+                let start = (offset as usize).min(data.len());
+                let len = len.min(self.short_read(path).unwrap_or(usize::MAX));
+                let end = start.saturating_add(len).min(data.len());
+                Ok(data[start..end].to_vec())
+            }
+        };
+        let delay = self.delay(path);
+        self.queued.push_back((tag, Reaped::Read(result), delay));
+        Ok(())
+    }
+
+    fn submit_statx(&mut self, tag: u64, path: &Path) -> io::Result<()> {
+        self.requests.push(MockRequest::Statx {
+            path: path.to_path_buf(),
+        });
+        let result = match (self.stat_errno(path), self.files.get(path)) {
+            (Some(errno), _) => Err(io::Error::from_raw_os_error(errno)),
+            (None, None) => Err(io::ErrorKind::NotFound.into()),
+            (None, Some(data)) => Ok(data.len() as u64),
+        };
+        let delay = self.delay(path);
+        self.queued.push_back((tag, Reaped::Statx(result), delay));
+        Ok(())
+    }
+
+    /// Every queued completion whose delay ran out becomes ready, that repeats until one is
+    fn wait(&mut self) -> io::Result<()> {
+        if self.queued.is_empty() && self.ready.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no requests in flight",
+            ));
+        }
+        while self.ready.is_empty() {
+            let mut held = VecDeque::new();
+            for (tag, reaped, delay) in self.queued.drain(..) {
+                match delay {
+                    0 => self.ready.push((tag, reaped)),
+                    _ => held.push_back((tag, reaped, delay - 1)),
+                }
+            }
+            self.queued = held;
+        }
+        Ok(())
+    }
+
+    fn reap(&mut self) -> Vec<(u64, Reaped)> {
+        std::mem::take(&mut self.ready)
+    }
+}
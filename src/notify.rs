@@ -0,0 +1,136 @@
+use std::io;
+#[cfg(feature = "async-io")]
+use std::os::fd::{AsFd, BorrowedFd};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::Waker;
+use std::thread::{self, JoinHandle};
+
+use crate::reader::lock;
+
+/// Wakes async tasks when their completions may have arrived
+///
+/// An eventfd is registered with the ring, the kernel bumps it for every CQE it posts. Something waits
+/// on it and wakes every registered waker, the woken tasks then reap the ring themselves:
+/// - `new` -> a small thread of our own blocks on it, works with any executor
+/// - `external` -> nobody here, the executor's reactor does (`Driver`, feature `async-io`)
+///
+/// Completions that a blocking caller reaped (and parked) instead wake the wakers from `reap`.
+///
+/// Only created when the first async caller shows up, blocking-only users never pay for it.
+pub(crate) struct Notifier {
+    eventfd: OwnedFd,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// - detached -> the external driver is gone, wakers are woken right away (the tasks poll)
+struct Shared {
+    wakers: Mutex<Vec<Waker>>,
+    stop: AtomicBool,
+    detached: AtomicBool,
+}
+
+impl Notifier {
+    /// Create the eventfd and its thread, `register` hands the eventfd to the ring
+    pub(crate) fn new(register: impl FnOnce(i32) -> io::Result<()>) -> io::Result<Self> {
+        let mut notifier = Self::external(register)?;
+        let thread = {
+            let eventfd = notifier.eventfd.try_clone()?;
+            let shared = Arc::clone(&notifier.shared);
+            thread::Builder::new()
+                .name("uring-notify".into())
+                .spawn(move || {
+                    while !shared.stop.load(Ordering::Acquire) {
+                        let mut count = 0u64;
+                        // SAFETY: reads 8 bytes into `count`
+                        let n =
+                            unsafe { libc::read(eventfd.as_raw_fd(), (&raw mut count).cast(), 8) };
+                        if n < 0 && io::Error::last_os_error().kind() != io::ErrorKind::Interrupted
+                        {
+                            break;
+                        }
+                        wake(&shared);
+                    }
+                })?
+        };
+        notifier.thread = Some(thread);
+        Ok(notifier)
+    }
+
+    /// Create the eventfd only, whoever calls `eventfd` waits on it and calls `wake_all`
+    pub(crate) fn external(register: impl FnOnce(i32) -> io::Result<()>) -> io::Result<Self> {
+        // SAFETY: plain syscall, the fd is owned right away
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: a fresh fd nobody else owns
+        let eventfd = unsafe { OwnedFd::from_raw_fd(fd) };
+        register(eventfd.as_raw_fd())?;
+
+        Ok(Notifier {
+            eventfd,
+            shared: Arc::new(Shared {
+                wakers: Mutex::new(Vec::new()),
+                stop: AtomicBool::new(false),
+                detached: AtomicBool::new(false),
+            }),
+            thread: None,
+        })
+    }
+
+    /// The eventfd, for an external driver
+    #[cfg(feature = "async-io")]
+    pub(crate) fn eventfd(&self) -> BorrowedFd<'_> {
+        self.eventfd.as_fd()
+    }
+
+    /// The external driver went away, from now on wakers are woken as soon as they are registered
+    #[cfg(feature = "async-io")]
+    pub(crate) fn detach(&self) {
+        self.shared.detached.store(true, Ordering::Release);
+        wake(&self.shared);
+    }
+
+    /// Wake `waker` the next time a completion shows up
+    #[allow(unused_doc_comments)]
+    pub(crate) fn register(&self, waker: &Waker) {
+        let mut wakers = lock(&self.shared.wakers);
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+        /// Checked after the push: a `detach` in between either sees our waker or we see the flag
+        if self.shared.detached.load(Ordering::Acquire) {
+            drop(wakers);
+            wake(&self.shared);
+        }
+    }
+
+    /// Wake everything that is registered
+    pub(crate) fn wake_all(&self) {
+        wake(&self.shared);
+    }
+}
+
+fn wake(shared: &Shared) {
+    let wakers = std::mem::take(&mut *lock(&shared.wakers));
+    for waker in wakers {
+        waker.wake();
+    }
+}
+
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+        let one = 1u64;
+        // SAFETY: writes 8 bytes from `one`, this unblocks the thread
+        unsafe {
+            libc::write(self.eventfd.as_raw_fd(), (&raw const one).cast(), 8);
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
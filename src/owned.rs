@@ -0,0 +1,203 @@
+use io_uring::{opcode, squeue, types};
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+#[cfg(feature = "async")]
+use crate::error::Cancelled;
+#[cfg(feature = "async")]
+use crate::reader::{Session, UringReader, is_retryable};
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+
+/// A finished read that owned its buffer (`read_owned`, `ReadPoller`), the data is appended to it
+pub struct Completed {
+    pub buf: Vec<u8>,
+    pub bytes: usize,
+}
+
+/// A failed (or canceled, `ReadError::Cancelled`) read that owned its buffer, the buffer comes back unchanged
+pub struct Failed {
+    pub buf: Vec<u8>,
+    pub error: io::Error,
+}
+
+impl fmt::Debug for Failed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Failed")
+            .field("buf_len", &self.buf.len())
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+impl fmt::Display for Failed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl std::error::Error for Failed {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// For `?` in functions that don't care about getting the buffer back
+impl From<Failed> for io::Error {
+    fn from(failed: Failed) -> Self {
+        failed.error
+    }
+}
+
+/// A read into the spare capacity of `buf`, at most `buf.capacity() - buf.len()` bytes
+pub(crate) fn read_spare(file: &File, offset: u64, buf: &mut Vec<u8>) -> squeue::Entry {
+    let spare = buf.spare_capacity_mut();
+    opcode::Read::new(
+        types::Fd(file.as_raw_fd()),
+        spare.as_mut_ptr().cast(),
+        spare.len().min(u32::MAX as usize) as u32,
+    )
+    .offset(offset)
+    .build()
+}
+
+/// The CQE result of a `read_spare` and its buffer
+///
+/// SAFETY: `result` must be the result of a `read_spare` into `buf`
+pub(crate) unsafe fn finish_spare(
+    mut buf: Vec<u8>,
+    result: io::Result<u32>,
+) -> Result<Completed, Failed> {
+    match result {
+        Ok(n) => {
+            let bytes = n as usize;
+            // SAFETY: the kernel initialized `bytes` bytes of the spare capacity
+            unsafe { buf.set_len(buf.len() + bytes) };
+            Ok(Completed { buf, bytes })
+        }
+        Err(error) => Err(Failed { buf, error }),
+    }
+}
+
+/// Future of one read that owns its buffer, returned by `UringReader::read_owned`
+///
+/// The buffer is moved in and only comes back with the result, so there is no point where Rust thinks
+/// it is free while the kernel still holds a pointer into it. That is what makes dropping the future
+/// early sound without blocking: the read is canceled and the buffer is handed to the reader, which
+/// frees it once the kernel posted the CQE.
+///
+/// `cancel` asks for the cancellation but keeps the future, it then resolves to `Failed` with
+/// `Cancelled::ByUser` (or the real result, if the read was already done) and the buffer.
+#[cfg(feature = "async")]
+pub struct ReadOwned<'r> {
+    session: Option<Session<'r>>,
+    file: &'r File,
+    offset: u64,
+    buf: Option<Vec<u8>>,
+    pushed: bool,
+}
+
+#[cfg(feature = "async")]
+impl UringReader {
+    /// Read at `offset` of `file` into the spare capacity of `buf`, see `ReadOwned`
+    ///
+    /// At most `buf.capacity() - buf.len()` bytes are read and appended, `buf.len()` grows by the number
+    /// of bytes read. Nothing happens until the future is polled.
+    pub fn read_owned<'r>(&'r self, file: &'r File, offset: u64, buf: Vec<u8>) -> ReadOwned<'r> {
+        ReadOwned {
+            session: Some(self.session()),
+            file,
+            offset,
+            buf: Some(buf),
+            pushed: false,
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl ReadOwned<'_> {
+    /// Ask the kernel to cancel the read, the future still has to be polled to get the buffer back
+    pub fn cancel(&mut self) {
+        if let Some(session) = self.session.as_mut()
+            && session.in_flight() > 0
+        {
+            session.cancel_all(Cancelled::ByUser);
+        }
+    }
+
+    fn push(&mut self) -> io::Result<()> {
+        let session = self.session.as_mut().expect("polled after completion");
+        let buf = self.buf.as_mut().expect("polled after completion");
+        session.push(0, read_spare(self.file, self.offset, buf))?;
+        session.submit()
+    }
+
+    fn fail(&mut self, error: io::Error) -> Poll<Result<Completed, Failed>> {
+        let buf = self.buf.take().expect("polled after completion");
+        self.session = None;
+        Poll::Ready(Err(Failed { buf, error }))
+    }
+}
+
+#[cfg(feature = "async")]
+impl Future for ReadOwned<'_> {
+    type Output = Result<Completed, Failed>;
+
+    #[allow(unused_doc_comments)]
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            if !this.pushed {
+                if let Err(e) = this.push() {
+                    return this.fail(e);
+                }
+                this.pushed = true;
+            }
+
+            let session = this.session.as_mut().expect("polled after completion");
+            /// Register before looking, a completion that shows up in between still wakes us
+            session.reader().register_waker(cx.waker());
+            let Some(cqe) = session.try_next() else {
+                return Poll::Pending;
+            };
+            match cqe.into_result() {
+                Err(e) if is_retryable(&e) => this.pushed = false,
+                result => {
+                    let buf = this.buf.take().expect("polled after completion");
+                    this.session = None;
+                    /// SAFETY: the CQE of the `read_spare` into `buf`
+                    return Poll::Ready(unsafe { finish_spare(buf, result) });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for ReadOwned<'_> {
+    #[allow(unused_doc_comments)]
+    fn drop(&mut self) {
+        let Some(mut session) = self.session.take() else {
+            return;
+        };
+        if session.in_flight() == 0 {
+            return;
+        }
+        /// Still in the kernel: cancel it and let the reader keep the buffer until the CQE shows up,
+        /// instead of waiting for it here
+        session.cancel_all(Cancelled::ByUser);
+        let what = format!(
+            "read_owned of fd {} at offset {}",
+            self.file.as_raw_fd(),
+            self.offset
+        );
+        session.abandon(Box::new(self.buf.take()), what);
+    }
+}
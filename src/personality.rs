@@ -0,0 +1,88 @@
+use io_uring::{opcode, types};
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::FromRawFd;
+use std::path::Path;
+
+use crate::reader::{UringReader, lock};
+
+/// Credentials captured by `UringReader::register_personality`
+///
+/// Only meaningful on the reader that registered it, another reader rejects it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PersonalityId(u16);
+
+impl PersonalityId {
+    /// The id the kernel handed out (the value that goes into the SQE)
+    pub fn get(self) -> u16 {
+        self.0
+    }
+}
+
+impl UringReader {
+    /// Capture the credentials of the calling thread, requests issued with the id run as them
+    ///
+    /// SECURITY: this is a capability. Whoever can issue requests on this reader with the id can open
+    /// files as the credentials (uid, gid, supplementary groups, capabilities) the thread had right now,
+    /// even after the process dropped them. Register before dropping privileges only what you really
+    /// need, don't hand the reader (or the id) to code you don't trust, and unregister as soon as it is
+    /// not needed anymore. Dropping the reader unregisters everything.
+    pub fn register_personality(&self) -> io::Result<PersonalityId> {
+        let id = self.submitter().register_personality()?;
+        lock(&self.personalities).insert(id);
+        Ok(PersonalityId(id))
+    }
+
+    /// Forget the credentials behind `id`, requests already in flight keep them
+    pub fn unregister_personality(&self, id: PersonalityId) -> io::Result<()> {
+        self.check_personality(id)?;
+        self.submitter().unregister_personality(id.0)?;
+        lock(&self.personalities).remove(&id.0);
+        Ok(())
+    }
+
+    /// Open `path` read-only with the credentials behind `id` (`IORING_OP_OPENAT` with `personality`)
+    ///
+    /// The permission check happens at open, so this is what makes a root-owned file readable. The
+    /// returned `File` is a normal fd of this process, reading it needs no personality anymore.
+    #[allow(unused_doc_comments)]
+    pub fn open_as(&self, path: impl AsRef<Path>, id: PersonalityId) -> io::Result<File> {
+        self.check_personality(id)?;
+        /// The path is read by the kernel, it must outlive the session
+        let c_path = CString::new(path.as_ref().as_os_str().as_bytes())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))?;
+
+        let mut session = self.session();
+        let open_e = opcode::OpenAt::new(types::Fd(libc::AT_FDCWD), c_path.as_ptr())
+            .flags(libc::O_RDONLY | libc::O_CLOEXEC)
+            .build()
+            .personality(id.0);
+        session.push(0, open_e)?;
+        session.submit()?;
+
+        let fd = session.next()?.into_result()?;
+        /// SAFETY: the kernel just handed us this fd, nobody else owns it
+        Ok(unsafe { File::from_raw_fd(fd as i32) })
+    }
+
+    /// `read_file_to_vec` of a file opened with `open_as`
+    pub fn read_file_as(&self, path: impl AsRef<Path>, id: PersonalityId) -> io::Result<Vec<u8>> {
+        let path = path.as_ref();
+        self.read_open_file(self.open_as(path, id)?, path)
+    }
+
+    /// Catch ids that were never registered here (or already unregistered) before anything is submitted
+    fn check_personality(&self, id: PersonalityId) -> io::Result<()> {
+        if lock(&self.personalities).contains(&id.0) {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("personality {} is not registered on this reader", id.0),
+            ))
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use io_uring::squeue;
+
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::completion::Completion;
+use crate::error::Cancelled;
+use crate::guard::{SETTLE_ATTEMPTS, give_up};
+use crate::owned::{Completed, Failed, finish_spare, read_spare};
+use crate::reader::{Session, UringReader};
+
+/// Names one read of a `ReadPoller`, comes back with its result
+///
+/// Tokens are reused once their read was handed out by `try_complete`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Token(u32);
+
+impl Token {
+    pub fn get(self) -> u32 {
+        self.0
+    }
+}
+
+/// Reads driven by hand without ever blocking, for loops that interleave I/O with other work
+///
+/// Returned by `UringReader::poller`. Nothing in here enters the kernel except `submit`:
+/// 1. `try_submit_read` pushes a read (refuses with `WouldBlock` when the submission queue is full)
+/// 2. `submit` hands everything pushed so far to the kernel, one `io_uring_enter`
+/// 3. `try_complete` picks up whatever finished in the meantime
+///
+/// Every read owns its buffer (see `Completed`) and makes one request, short reads come back short.
+/// `UringReader::sq_space_left` and `in_flight` tell how much room there is. Dropping the poller
+/// cancels what is still in flight and waits for it.
+///
+/// `C` is a context of your own carried by every read (a request struct, a reply channel, ...), see
+/// `UringReader::poller_with_ctx`. It is kept next to the buffer and handed back with the result, so
+/// there is no side table keyed by `Token` to maintain. The poller never leaves the thread that drives
+/// it, so `C` doesn't need to be `Send`.
+pub struct ReadPoller<'r, C = ()> {
+    /// Declared first so it is dropped (waits for every request) before the buffers
+    session: Session<'r>,
+    reads: Vec<Option<Pending<C>>>,
+}
+
+/// What one read in flight holds on to until its CQE is reaped
+/// - file -> opened by `submit_read_with_ctx`, None for reads into a borrowed file
+struct Pending<C> {
+    buf: Vec<u8>,
+    file: Option<File>,
+    ctx: C,
+}
+
+impl UringReader {
+    /// Start a `ReadPoller` on this reader
+    pub fn poller(&self) -> ReadPoller<'_> {
+        self.poller_with_ctx()
+    }
+
+    /// Start a `ReadPoller` whose reads each carry a `C`
+    ///
+    /// ```no_run
+    /// use uring_fast_read::{UringConfig, UringReader};
+    ///
+    /// let reader = UringReader::new(UringConfig::default()).unwrap();
+    /// let mut poller = reader.poller_with_ctx::<String>();
+    /// poller
+    ///     .submit_read_with_ctx("/etc/hostname", 0..64, "hostname".to_string())
+    ///     .unwrap();
+    /// poller.submit().unwrap();
+    /// loop {
+    ///     for (name, data) in poller.try_complete_with_ctx() {
+    ///         println!("{name}: {:?}", data.map(|d| d.len()));
+    ///     }
+    ///     if poller.in_flight() == 0 {
+    ///         break;
+    ///     }
+    /// }
+    /// ```
+    pub fn poller_with_ctx<C>(&self) -> ReadPoller<'_, C> {
+        ReadPoller {
+            session: self.session(),
+            reads: Vec::new(),
+        }
+    }
+}
+
+impl<'r> ReadPoller<'r> {
+    /// Push a read at `offset` of `file` into the spare capacity of `buf`, does not submit
+    ///
+    /// Ok(token) -> pushed, it shows up in `try_complete` under this token
+    /// Err(failed) -> not pushed, `buf` comes back (`io::ErrorKind::WouldBlock` if the queue was full)
+    pub fn try_submit_read(
+        &mut self,
+        file: &'r File,
+        offset: u64,
+        buf: Vec<u8>,
+    ) -> Result<Token, Failed> {
+        self.try_submit_read_with_ctx(file, offset, buf, ())
+            .map_err(|(failed, ())| failed)
+    }
+
+    /// Every read that finished so far, never waits
+    pub fn try_complete(&mut self) -> Vec<(Token, Result<Completed, Failed>)> {
+        self.reap()
+            .into_iter()
+            .map(|(token, (), result)| (token, result))
+            .collect()
+    }
+}
+
+impl<'r, C> ReadPoller<'r, C> {
+    /// `try_submit_read` with a context, which comes back with the result (or right away, with `buf`,
+    /// if the read could not be pushed)
+    pub fn try_submit_read_with_ctx(
+        &mut self,
+        file: &'r File,
+        offset: u64,
+        mut buf: Vec<u8>,
+        ctx: C,
+    ) -> Result<Token, (Failed, C)> {
+        let read_e = read_spare(file, offset, &mut buf);
+        self.push(read_e, buf, None, ctx)
+            .map_err(|(pending, error)| {
+                let Pending { buf, ctx, .. } = pending;
+                (Failed { buf, error }, ctx)
+            })
+    }
+
+    /// Open `path` and push a read of `range` of it, does not submit
+    ///
+    /// The poller owns the file and a buffer of `range` bytes until the read is handed out by
+    /// `try_complete_with_ctx`, a read past the end comes back short. If the file can't be opened or the
+    /// queue is full (`io::ErrorKind::WouldBlock`), `ctx` comes back right away with the error.
+    pub fn submit_read_with_ctx(
+        &mut self,
+        path: impl AsRef<Path>,
+        range: Range<u64>,
+        ctx: C,
+    ) -> Result<Token, (C, io::Error)> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => return Err((ctx, e)),
+        };
+        let len = range.end.saturating_sub(range.start).min(u32::MAX as u64) as usize;
+        let mut buf = Vec::with_capacity(len);
+        let read_e = read_spare(&file, range.start, &mut buf);
+        self.push(read_e, buf, Some(file), ctx)
+            .map_err(|(pending, error)| (pending.ctx, error))
+    }
+
+    fn push(
+        &mut self,
+        read_e: squeue::Entry,
+        buf: Vec<u8>,
+        file: Option<File>,
+        ctx: C,
+    ) -> Result<Token, (Pending<C>, io::Error)> {
+        let slot = match self.reads.iter().position(Option::is_none) {
+            Some(slot) => slot,
+            None => {
+                self.reads.push(None);
+                self.reads.len() - 1
+            }
+        };
+        let pending = Pending { buf, file, ctx };
+        match self.session.try_push(slot as u32, read_e) {
+            Ok(()) => {
+                self.reads[slot] = Some(pending);
+                Ok(Token(slot as u32))
+            }
+            Err(error) => Err((pending, error)),
+        }
+    }
+
+    /// Hand everything pushed so far to the kernel (the one syscall of the poller)
+    pub fn submit(&self) -> io::Result<()> {
+        self.session.submit()
+    }
+
+    /// Every read that finished so far with its context, never waits
+    ///
+    /// Failed and canceled reads are in here too, every context comes back exactly once.
+    pub fn try_complete_with_ctx(&mut self) -> Vec<(C, io::Result<Vec<u8>>)> {
+        self.reap()
+            .into_iter()
+            .map(|(_, ctx, result)| (ctx, result.map(|done| done.buf).map_err(|f| f.error)))
+            .collect()
+    }
+
+    /// Cancel every read still in flight and wait for them, their contexts come back like from
+    /// `try_complete_with_ctx` (mostly with `Cancelled::ByUser`)
+    ///
+    /// A failing wait is retried like in `Session`'s drop, every context comes back: the buffers
+    /// can't be handed out before the kernel is done with them.
+    #[allow(unused_doc_comments)]
+    pub fn cancel_all(&mut self) -> Vec<(C, io::Result<Vec<u8>>)> {
+        if self.session.in_flight() > 0 {
+            self.session.cancel_all(Cancelled::ByUser);
+        }
+        let mut done = self.try_complete_with_ctx();
+        let mut failures = 0;
+        while self.session.in_flight() > 0 {
+            let cqe = match self.session.next() {
+                Ok(cqe) => cqe,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                /// Leaving with reads in flight would lose their contexts and free buffers the
+                /// kernel may still write into
+                Err(_) => {
+                    failures += 1;
+                    if failures >= SETTLE_ATTEMPTS {
+                        give_up(self.session.in_flight());
+                    }
+                    std::thread::sleep(Duration::from_millis(1));
+                    continue;
+                }
+            };
+            failures = 0;
+            done.extend(
+                self.finish(cqe).map(|(_, ctx, result)| {
+                    (ctx, result.map(|done| done.buf).map_err(|f| f.error))
+                }),
+            );
+        }
+        done
+    }
+
+    fn reap(&mut self) -> Vec<(Token, C, Result<Completed, Failed>)> {
+        let mut done = Vec::new();
+        while let Some(cqe) = self.session.try_next() {
+            done.extend(self.finish(cqe));
+        }
+        done
+    }
+
+    /// The context, buffer and result of the read `cqe` belongs to (None for a slot that isn't in flight)
+    #[allow(unused_doc_comments)]
+    fn finish(&mut self, cqe: Completion) -> Option<(Token, C, Result<Completed, Failed>)> {
+        let slot = (cqe.user_data() & u64::from(u32::MAX)) as usize;
+        let Pending { buf, file, ctx } = self.reads.get_mut(slot)?.take()?;
+        /// SAFETY: slot `slot` is the `read_spare` into `buf`
+        let result = unsafe { finish_spare(buf, cqe.into_result()) };
+        drop(file);
+        Some((Token(slot as u32), ctx, result))
+    }
+
+    /// Reads of this poller that were pushed but not handed out by `try_complete` yet
+    pub fn in_flight(&self) -> usize {
+        self.session.in_flight()
+    }
+}
+
+impl<C> Drop for ReadPoller<'_, C> {
+    #[allow(unused_doc_comments)]
+    fn drop(&mut self) {
+        /// The session drains on drop, canceling first makes that quick
+        if self.session.in_flight() > 0 {
+            self.session.cancel_all(Cancelled::ByUser);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UringConfig;
+    use crate::error::ReadError;
+    use crate::guard::tests::pipe;
+    use std::sync::atomic::Ordering;
+
+    /// Eight reads that never complete on their own, on a ring with room for four CQEs: most of the
+    /// cancellations overflow, so `cancel_all` has to wait for them
+    #[test]
+    fn cancel_all_keeps_waiting_through_failed_waits() {
+        let reader = UringReader::new(UringConfig::default().queue_depth(2)).unwrap();
+        let (rx, _tx) = pipe();
+        let mut poller = reader.poller_with_ctx();
+        for ctx in 0..8 {
+            let pushed = poller.try_submit_read_with_ctx(&rx, 0, Vec::with_capacity(64), ctx);
+            assert!(pushed.is_ok());
+            poller.submit().unwrap();
+        }
+
+        reader.fail_waits.store(5, Ordering::Relaxed);
+        let mut done = poller.cancel_all();
+        assert_eq!(poller.in_flight(), 0);
+        assert_eq!(reader.fail_waits.load(Ordering::Relaxed), 0);
+
+        done.sort_by_key(|(ctx, _)| *ctx);
+        assert_eq!(
+            done.iter().map(|(ctx, _)| *ctx).collect::<Vec<_>>(),
+            [0, 1, 2, 3, 4, 5, 6, 7]
+        );
+        for (_, result) in done {
+            let e = result.unwrap_err();
+            assert!(
+                matches!(
+                    ReadError::from_io(&e),
+                    Some(ReadError::Cancelled {
+                        reason: Cancelled::ByUser
+                    })
+                ),
+                "{e:?}"
+            );
+        }
+    }
+}
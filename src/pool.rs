@@ -0,0 +1,477 @@
+use std::io;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+/// mpsc -> the job queue between callers and the driver threads
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+use crate::config::UringConfig;
+use crate::error::ReadError;
+use crate::reader::UringReader;
+use crate::stats::CloseReport;
+
+/// Configuration for a `RingPool`
+///
+/// ```no_run
+/// use uring_fast_read::{PoolConfig, RingPool};
+///
+/// // 4 rings, spread over NUMA nodes 0 and 1
+/// let pool = RingPool::new(PoolConfig::default().rings(4).numa_nodes(&[0, 1])).unwrap();
+/// let data = pool.read("/etc/hostname").unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub(crate) rings: usize,
+    pub(crate) reader: UringConfig,
+    pub(crate) numa_nodes: Vec<usize>,
+    pub(crate) cpus: Vec<usize>,
+    pub(crate) shared_workqueue: bool,
+    pub(crate) max_workers: Option<(u32, u32)>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            rings: 4,
+            reader: UringConfig::default(),
+            numa_nodes: Vec::new(),
+            cpus: Vec::new(),
+            shared_workqueue: false,
+            max_workers: None,
+        }
+    }
+}
+
+impl PoolConfig {
+    /// Same as `PoolConfig::default()`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of rings (and driver threads) in the pool, default 4
+    pub fn rings(mut self, rings: usize) -> Self {
+        self.rings = rings.max(1);
+        self
+    }
+
+    /// The configuration every ring of the pool is created with
+    pub fn reader_config(mut self, config: UringConfig) -> Self {
+        self.reader = config;
+        self
+    }
+
+    /// Spread the rings over these NUMA nodes (round robin)
+    ///
+    /// Each driver thread is pinned to the CPUs of its node, prefers memory from that node and has the
+    /// ring's io-wq workers pinned there too, so the buffers it allocates end up local to the CPUs that
+    /// touch them. Nodes that do not exist on this machine are ignored, and an empty list (the default)
+    /// means no NUMA placement at all.
+    pub fn numa_nodes(mut self, nodes: &[usize]) -> Self {
+        self.numa_nodes = nodes.to_vec();
+        self
+    }
+
+    /// Only run the driver threads on these CPUs
+    ///
+    /// Combined with `numa_nodes`, each thread is pinned to the CPUs of its node that are also in this set.
+    pub fn cpus(mut self, cpus: &[usize]) -> Self {
+        self.cpus = cpus.to_vec();
+        self
+    }
+
+    /// Let all rings share the io-wq worker pool of the first one (IORING_SETUP_ATTACH_WQ, default off)
+    ///
+    /// Without it every ring brings its own set of kernel workers, eight rings that all miss the page
+    /// cache can end up with eight times the workers. Kernels that don't know the flag get independent
+    /// rings, no error, `RingPool::shares_workqueue` tells which one you got.
+    ///
+    /// NOTE: Since Linux 5.12 io-wq belongs to the submitting thread rather than to the ring, so on new
+    /// kernels the flag changes little and `max_workers` is the knob that actually caps the workers.
+    pub fn shared_workqueue(mut self, on: bool) -> Self {
+        self.shared_workqueue = on;
+        self
+    }
+
+    /// Cap the io-wq workers of every driver thread, see `UringReader::set_iowq_max_workers`
+    ///
+    /// bounded -> workers for regular files and block devices
+    /// unbounded -> workers for sockets, pipes, ...
+    ///
+    /// `0` leaves that limit alone. Best effort, kernels older than 5.15 can't cap them and the pool is
+    /// still created.
+    pub fn max_workers(mut self, bounded: u32, unbounded: u32) -> Self {
+        self.max_workers = Some((bounded, unbounded));
+        self
+    }
+}
+
+/// One read request sent to a driver thread
+struct Job {
+    path: PathBuf,
+    reply: mpsc::Sender<io::Result<Vec<u8>>>,
+}
+
+/// One ring of the pool, driven by its own thread
+struct Member {
+    node: Option<usize>,
+    jobs: Option<mpsc::Sender<Job>>,
+    thread: Option<JoinHandle<io::Result<CloseReport>>>,
+}
+
+/// Several `UringReader`s, each owned by a driver thread
+///
+/// One ring is driven by one thread, so a single ring tops out at what one core can submit and reap.
+/// The pool spreads requests over several of them. With `PoolConfig::numa_nodes` the rings are
+/// placed on specific nodes, and `read` sends each request to a ring on the caller's own node.
+pub struct RingPool {
+    members: Vec<Member>,
+    next: AtomicUsize,
+    shared: bool,
+}
+
+impl RingPool {
+    /// Create every ring and start its driver thread
+    ///
+    /// Fails if any of the rings can't be created, the threads that were already started are stopped again.
+    #[allow(unused_doc_comments)]
+    pub fn new(config: PoolConfig) -> io::Result<Self> {
+        let nodes = numa::usable_nodes(&config.numa_nodes);
+        let mut pool = RingPool {
+            members: Vec::with_capacity(config.rings),
+            next: AtomicUsize::new(0),
+            shared: config.shared_workqueue,
+        };
+        /// The first ring's fd once it exists, the others attach to it
+        let mut wq_fd: Option<RawFd> = None;
+
+        for i in 0..config.rings {
+            let node = (!nodes.is_empty()).then(|| nodes[i % nodes.len()]);
+            let cpus = match node {
+                Some(node) => {
+                    let node_cpus = numa::node_cpus(node);
+                    if config.cpus.is_empty() {
+                        node_cpus
+                    } else {
+                        node_cpus
+                            .into_iter()
+                            .filter(|cpu| config.cpus.contains(cpu))
+                            .collect()
+                    }
+                }
+                None => config.cpus.clone(),
+            };
+
+            let (jobs, rx) = mpsc::channel::<Job>();
+            let (ready_tx, ready_rx) = mpsc::channel::<io::Result<(RawFd, bool)>>();
+            let reader_config = config.reader.clone();
+            let attach_to = wq_fd.filter(|_| config.shared_workqueue);
+            let max_workers = config.max_workers;
+
+            let thread = thread::Builder::new()
+                .name(format!("uring-pool-{i}"))
+                .spawn(move || {
+                    /// Pin first, so the ring, its buffers and its kernel workers are all set up on the right CPUs
+                    numa::bind_current_thread(&cpus, node);
+
+                    let created = match attach_to {
+                        Some(fd) => UringReader::attached(reader_config, fd),
+                        None => UringReader::new(reader_config).map(|reader| (reader, true)),
+                    };
+                    let (reader, attached) = match created {
+                        Ok(created) => created,
+                        Err(e) => {
+                            let _ = ready_tx.send(Err(e));
+                            return Ok(CloseReport::default());
+                        }
+                    };
+                    reader.pin_workers(&cpus);
+                    if let Some((bounded, unbounded)) = max_workers {
+                        let _ = reader.set_iowq_max_workers(bounded, unbounded);
+                    }
+                    let _ = ready_tx.send(Ok((reader.ring_fd(), attached)));
+
+                    for job in rx {
+                        let _ = job.reply.send(reader.read_file_to_vec(&job.path));
+                    }
+                    reader.close()
+                })?;
+
+            let member = Member {
+                node,
+                jobs: Some(jobs),
+                thread: Some(thread),
+            };
+            pool.members.push(member);
+
+            let (fd, attached) = ready_rx.recv().unwrap_or_else(|_| {
+                Err(io::Error::other(
+                    "ring pool driver thread exited during setup",
+                ))
+            })?;
+            /// The first ring stays alive as long as the pool, so its fd is valid for all the others
+            wq_fd.get_or_insert(fd);
+            pool.shared &= attached;
+        }
+
+        Ok(pool)
+    }
+
+    /// Number of rings in the pool
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Always false, a pool has at least one ring
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Whether all rings share one io-wq worker pool
+    ///
+    /// false -> `shared_workqueue` was off, or the kernel didn't take IORING_SETUP_ATTACH_WQ and the
+    /// rings were created independently. Always true for a pool of one ring with the option on.
+    pub fn shares_workqueue(&self) -> bool {
+        self.shared
+    }
+
+    /// The NUMA node each ring was placed on (`None` without NUMA placement)
+    pub fn nodes(&self) -> Vec<Option<usize>> {
+        self.members.iter().map(|m| m.node).collect()
+    }
+
+    /// Read a whole file on a ring of the caller's NUMA node
+    ///
+    /// Falls back to any ring (round robin) when no ring lives on the caller's node or the node can't be
+    /// determined.
+    pub fn read(&self, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        self.read_on_node(numa::current_node(), path)
+    }
+
+    /// Read a whole file on a ring placed on `node`, or any ring if there is none
+    pub fn read_on_node(&self, node: Option<usize>, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        let member = self.pick(node);
+        let (reply, rx) = mpsc::channel();
+        let job = Job {
+            path: path.as_ref().to_path_buf(),
+            reply,
+        };
+
+        let stopped = || io::Error::other("ring pool driver thread is gone");
+        member
+            .jobs
+            .as_ref()
+            .ok_or_else(stopped)?
+            .send(job)
+            .map_err(|_| stopped())?;
+        rx.recv().map_err(|_| stopped())?
+    }
+
+    /// Round robin over the rings on `node`, or over all rings
+    fn pick(&self, node: Option<usize>) -> &Member {
+        let n = self.next.fetch_add(1, Ordering::Relaxed);
+        let local: Vec<&Member> = self
+            .members
+            .iter()
+            .filter(|m| node.is_some() && m.node == node)
+            .collect();
+
+        if local.is_empty() {
+            &self.members[n % self.members.len()]
+        } else {
+            local[n % local.len()]
+        }
+    }
+}
+
+impl RingPool {
+    /// Stop every driver thread and close its ring, see `UringReader::close`
+    ///
+    /// The report sums up all rings, a failure names the ring it happened on. Dropping the pool does the
+    /// same and ignores what went wrong.
+    pub fn close(mut self) -> io::Result<CloseReport> {
+        let report = self.stop();
+        if report.failures.is_empty() {
+            Ok(report)
+        } else {
+            Err(ReadError::CloseFailed { report }.into())
+        }
+    }
+
+    /// Stops the threads that are still running, a no-op the second time
+    #[allow(unused_doc_comments)]
+    fn stop(&mut self) -> CloseReport {
+        /// Closing the job channels ends the driver loops, each thread then closes its reader and exits
+        for member in &mut self.members {
+            member.jobs.take();
+        }
+        let mut report = CloseReport::default();
+        for (i, member) in self.members.iter_mut().enumerate() {
+            let Some(thread) = member.thread.take() else {
+                continue;
+            };
+            match thread.join() {
+                Ok(Ok(closed)) => report.absorb(closed),
+                Ok(Err(e)) => match ReadError::from_io(&e) {
+                    Some(ReadError::CloseFailed { report: closed }) => {
+                        let mut closed = closed.clone();
+                        for failure in &mut closed.failures {
+                            *failure = format!("ring {i}: {failure}");
+                        }
+                        report.absorb(closed);
+                    }
+                    _ => report.failures.push(format!("ring {i}: {e}")),
+                },
+                Err(_) => report
+                    .failures
+                    .push(format!("ring {i}: driver thread panicked")),
+            }
+        }
+        report
+    }
+}
+
+impl Drop for RingPool {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Everything NUMA, all of it best effort
+///
+/// Machines without `/sys/devices/system/node` (or with a single node), containers that forbid
+/// `sched_setaffinity` / `set_mempolicy`, ... all end up as "no placement", never as an error.
+mod numa {
+    use std::fs;
+
+    /// MPOL_PREFERRED from <linux/mempolicy.h>
+    const MPOL_PREFERRED: libc::c_int = 1;
+
+    /// The nodes of `wanted` that exist on this machine, empty if there is only one node
+    pub(super) fn usable_nodes(wanted: &[usize]) -> Vec<usize> {
+        let online = online_nodes();
+        if online.len() < 2 {
+            return Vec::new();
+        }
+        wanted
+            .iter()
+            .copied()
+            .filter(|n| online.contains(n))
+            .collect()
+    }
+
+    /// `/sys/devices/system/node/online`, e.g. "0-1"
+    fn online_nodes() -> Vec<usize> {
+        fs::read_to_string("/sys/devices/system/node/online")
+            .map(|list| parse_list(&list))
+            .unwrap_or_default()
+    }
+
+    /// `/sys/devices/system/node/nodeN/cpulist`, e.g. "0-15,32-47"
+    pub(super) fn node_cpus(node: usize) -> Vec<usize> {
+        fs::read_to_string(format!("/sys/devices/system/node/node{node}/cpulist"))
+            .map(|list| parse_list(&list))
+            .unwrap_or_default()
+    }
+
+    /// Parse the kernel's list format "0-3,8,10-11"
+    fn parse_list(list: &str) -> Vec<usize> {
+        let mut out = Vec::new();
+        for part in list.trim().split(',').filter(|p| !p.is_empty()) {
+            let mut bounds = part.splitn(2, '-').map(|b| b.trim().parse::<usize>());
+            match (bounds.next(), bounds.next()) {
+                (Some(Ok(lo)), Some(Ok(hi))) => out.extend(lo..=hi),
+                (Some(Ok(one)), None) => out.push(one),
+                _ => {}
+            }
+        }
+        out
+    }
+
+    /// The node the calling thread is running on right now
+    pub(super) fn current_node() -> Option<usize> {
+        let mut cpu: libc::c_uint = 0;
+        let mut node: libc::c_uint = 0;
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_getcpu,
+                &mut cpu as *mut libc::c_uint,
+                &mut node as *mut libc::c_uint,
+                std::ptr::null_mut::<libc::c_void>(),
+            )
+        };
+        (res == 0).then_some(node as usize)
+    }
+
+    /// A `cpu_set_t` with the given CPUs, `None` if the list is empty
+    pub(crate) fn cpu_set(cpus: &[usize]) -> Option<libc::cpu_set_t> {
+        if cpus.is_empty() {
+            return None;
+        }
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        for &cpu in cpus {
+            if cpu < libc::CPU_SETSIZE as usize {
+                unsafe { libc::CPU_SET(cpu, &mut set) };
+            }
+        }
+        Some(set)
+    }
+
+    /// Pin the calling thread to `cpus` and prefer memory from `node`
+    ///
+    /// With the memory policy in place, the pages of every buffer this thread allocates are placed on
+    /// `node` when they are first touched.
+    pub(super) fn bind_current_thread(cpus: &[usize], node: Option<usize>) {
+        if let Some(set) = cpu_set(cpus) {
+            unsafe {
+                libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set);
+            }
+        }
+
+        if let Some(node) = node.filter(|&n| n < 64) {
+            let mask: libc::c_ulong = 1 << node;
+            unsafe {
+                libc::syscall(
+                    libc::SYS_set_mempolicy,
+                    MPOL_PREFERRED,
+                    &mask as *const libc::c_ulong,
+                    64 as libc::c_ulong,
+                );
+            }
+        }
+    }
+}
+
+pub(crate) use numa::cpu_set;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `close` with every driver thread still busy: the queued reads are all answered first
+    #[test]
+    fn close_with_reads_queued() {
+        let pool = RingPool::new(PoolConfig::new().rings(2)).unwrap();
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        let expected = std::fs::read(path).unwrap();
+
+        let (reply, replies) = mpsc::channel();
+        for i in 0..200 {
+            let member = &pool.members[i % pool.len()];
+            let job = Job {
+                path: PathBuf::from(path),
+                reply: reply.clone(),
+            };
+            member.jobs.as_ref().unwrap().send(job).unwrap();
+        }
+        drop(reply);
+
+        let report = pool.close().unwrap();
+        assert!(report.is_clean(), "{report:?}");
+        let answers: Vec<_> = replies.into_iter().collect();
+        assert_eq!(answers.len(), 200);
+        for answer in answers {
+            assert_eq!(answer.unwrap(), expected);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use io_uring::{opcode, squeue, types};
+
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use crate::buffers::AlignedBuf;
+use crate::copy::DIRECT_ALIGN;
+use crate::error::ReadError;
+use crate::reader::{UringReader, is_retryable};
+
+/// A read of the same file region, built once and executed as often as needed
+///
+/// For polling (a sysfs value, a small state file every few milliseconds): the file stays open, the
+/// buffer stays allocated and the SQE is filled in once. `execute` only pushes a copy of that entry,
+/// waits, and hands out the buffer.
+///
+/// The template goes bad when the open fd does: the device behind a sysfs file went away, an NFS
+/// handle went stale, ... `execute` then fails with `ReadError::PreparedReadInvalid` and the template
+/// should be rebuilt with `UringReader::prepare_read`. A file that was replaced (renamed over, deleted
+/// and recreated) still reads fine, just the old inode. `is_current` checks for that.
+pub struct PreparedRead<'r> {
+    reader: &'r UringReader,
+    path: PathBuf,
+    file: File,
+    /// (st_dev, st_ino) of the file when it was opened
+    identity: (u64, u64),
+    /// never moves or changes size, `entry` points into it
+    buf: AlignedBuf,
+    offset: u64,
+    len: u32,
+    /// RWF_* of the read
+    rw_flags: i32,
+    entry: squeue::Entry,
+    force_async: bool,
+}
+
+impl UringReader {
+    /// Open `path` and build a read of `len` bytes at `offset`, see `PreparedRead`
+    ///
+    /// With `UringConfig::direct_io` the file is opened with `O_DIRECT`, the buffer is 4 KiB aligned
+    /// and `len` rounded up to a multiple of 4 KiB, `offset` has to be aligned too.
+    pub fn prepare_read(
+        &self,
+        path: impl AsRef<Path>,
+        offset: u64,
+        len: usize,
+    ) -> io::Result<PreparedRead<'_>> {
+        let path = path.as_ref();
+        let direct = self.config.direct_io;
+        let (len, align) = if direct {
+            if !offset.is_multiple_of(DIRECT_ALIGN as u64) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "O_DIRECT reads need a 4 KiB aligned offset",
+                ));
+            }
+            (len.next_multiple_of(DIRECT_ALIGN), DIRECT_ALIGN)
+        } else {
+            (len, 1)
+        };
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(if direct { libc::O_DIRECT } else { 0 })
+            .open(path)?;
+        let meta = file.metadata()?;
+
+        let mut prepared = PreparedRead {
+            reader: self,
+            path: path.to_path_buf(),
+            identity: (meta.dev(), meta.ino()),
+            buf: AlignedBuf::new(len, align),
+            offset,
+            len: len.min(u32::MAX as usize) as u32,
+            rw_flags: 0,
+            entry: opcode::Nop::new().build(),
+            force_async: self.config.force_async,
+            file,
+        };
+        prepared.build();
+        Ok(prepared)
+    }
+
+    /// Whether an `O_DIRECT` read with RWF_HIPRI works on this ring, see `Capabilities::hipri_direct`
+    ///
+    /// A 4 KiB test read of the running executable, once, the answer is kept.
+    #[allow(unused_doc_comments)]
+    pub(crate) fn probe_hipri(&self) -> bool {
+        *self.hipri.get_or_init(|| {
+            let Ok(file) = OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_DIRECT)
+                .open("/proc/self/exe")
+            else {
+                return false;
+            };
+            /// Declared before the session, like every buffer
+            let buf = AlignedBuf::new(DIRECT_ALIGN, DIRECT_ALIGN);
+            let mut session = self.session();
+            let read_e =
+                opcode::Read::new(types::Fd(file.as_raw_fd()), buf.ptr, DIRECT_ALIGN as u32)
+                    .offset(0)
+                    .rw_flags(libc::RWF_HIPRI)
+                    .build();
+            if session
+                .push(0, read_e)
+                .and_then(|()| session.submit())
+                .is_err()
+            {
+                return false;
+            }
+            session.next().is_ok_and(|cqe| cqe.into_result().is_ok())
+        })
+    }
+}
+
+impl PreparedRead<'_> {
+    /// Run the read again, the result borrows the reused buffer
+    ///
+    /// Ok(data) -> the bytes read this time (shorter than the buffer at EOF)
+    /// Err(e) -> `ReadError::PreparedReadInvalid` if the fd went bad, any other error as is
+    #[allow(unused_doc_comments)]
+    pub fn execute(&mut self) -> io::Result<&[u8]> {
+        let nowait = self.rw_flags & libc::RWF_NOWAIT != 0;
+        let n = loop {
+            /// The session only lives for this one request, it has been reaped when it is dropped
+            let mut session = self.reader.session();
+            session.force_async(self.force_async);
+            session.push(0, self.entry.clone())?;
+            session.submit()?;
+            match session.next()?.into_result() {
+                Ok(n) => break n as usize,
+                /// That's the answer `nowait` asked for, not something to retry
+                Err(e) if nowait && e.raw_os_error() == Some(libc::EAGAIN) => return Err(e),
+                Err(e) if is_retryable(&e) => continue,
+                Err(e) if is_invalidating(&e) => {
+                    return Err(ReadError::PreparedReadInvalid {
+                        path: self.path.clone(),
+                        source: e,
+                    }
+                    .into());
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        /// SAFETY: the read is reaped, the kernel filled `n <= len` bytes of the buffer
+        Ok(unsafe { std::slice::from_raw_parts(self.buf.ptr, n) })
+    }
+
+    /// IOSQE_ASYNC for this template only, starts out as `UringConfig::force_async`
+    pub fn force_async(mut self, on: bool) -> Self {
+        self.force_async = on;
+        self
+    }
+
+    /// RWF_NOWAIT: fail with `WouldBlock` (EAGAIN) instead of waiting for the disk (default off)
+    ///
+    /// For a caller that only wants the read if the data is in the page cache already and would
+    /// rather do something else than block. Buffered reads know it since Linux 5.9 (through io_uring
+    /// 5.6), filesystems that can't tell fail every read with it.
+    pub fn nowait(mut self, on: bool) -> Self {
+        self.set_flag(libc::RWF_NOWAIT, on);
+        self
+    }
+
+    /// RWF_HIPRI: have the completion of this read polled for instead of waiting for an interrupt
+    /// (default off)
+    ///
+    /// Only for the latency critical reads of a mixed workload, the rest of the ring keeps working
+    /// with interrupts (unlike IORING_SETUP_IOPOLL, which polls everything). Needs an `O_DIRECT` file
+    /// (`UringConfig::direct_io`), this fails with `InvalidInput` on any other. The device needs poll
+    /// queues (NVMe `poll_queues=`), and kernels differ on a ring without IOPOLL: some poll, some
+    /// quietly read with interrupts, newer ones reject the read with EINVAL/EOPNOTSUPP.
+    /// `Capabilities::hipri_direct` says which one the running kernel is.
+    pub fn hipri(mut self, on: bool) -> io::Result<Self> {
+        if on {
+            // SAFETY: F_GETFL on an fd we own
+            let flags = unsafe { libc::fcntl(self.file.as_raw_fd(), libc::F_GETFL) };
+            if flags < 0 || flags & libc::O_DIRECT == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "RWF_HIPRI only works on files opened with O_DIRECT (UringConfig::direct_io)",
+                ));
+            }
+        }
+        self.set_flag(libc::RWF_HIPRI, on);
+        Ok(self)
+    }
+
+    fn set_flag(&mut self, flag: i32, on: bool) {
+        if on {
+            self.rw_flags |= flag;
+        } else {
+            self.rw_flags &= !flag;
+        }
+        self.build();
+    }
+
+    /// (Re)build the entry from the template
+    fn build(&mut self) {
+        self.entry = opcode::Read::new(types::Fd(self.file.as_raw_fd()), self.buf.ptr, self.len)
+            .offset(self.offset)
+            .rw_flags(self.rw_flags)
+            .build();
+    }
+
+    /// Whether `path` still names the file that was opened (one `stat`, so not for every read)
+    ///
+    /// false -> the file was replaced or removed, rebuild the template to see the new one
+    pub fn is_current(&self) -> bool {
+        fs::metadata(&self.path).is_ok_and(|meta| (meta.dev(), meta.ino()) == self.identity)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The open file behind the template
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+}
+
+/// Errors that mean the fd itself is no good anymore, retrying the same template can't help
+fn is_invalidating(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::ESTALE | libc::EBADF | libc::ENODEV | libc::ENXIO)
+    )
+}
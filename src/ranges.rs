@@ -0,0 +1,195 @@
+use io_uring::types;
+
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use crate::config::PadPolicy;
+use crate::files::{Region, copy_error};
+use crate::reader::{UringReader, lock};
+
+impl UringReader {
+    /// Read `len` bytes of `path` at `offset`
+    ///
+    /// Ok((data, n)) -> `n` bytes were in the file, less than `len` if it ended before. `data` is `n`
+    /// bytes long, or always `len` with `PadPolicy::ZeroFill` (zeroes from `n` on)
+    ///
+    /// Big lengths are split into `chunk_size` reads that run in parallel, `max_bytes` applies to `len`.
+    pub fn read_at(
+        &self,
+        path: impl AsRef<Path>,
+        offset: u64,
+        len: usize,
+    ) -> io::Result<(Vec<u8>, usize)> {
+        self.read_ranges(path, std::slice::from_ref(&(offset..offset + len as u64)))
+            .pop()
+            .expect("one result per range")
+    }
+
+    /// `read_at` for several ranges of one file, all of them sharing the ring, one result per range
+    ///
+    /// The file is opened once. A range that fails only fails its own entry, ranges may overlap.
+    pub fn read_ranges(
+        &self,
+        path: impl AsRef<Path>,
+        ranges: &[Range<u64>],
+    ) -> Vec<io::Result<(Vec<u8>, usize)>> {
+        let path = path.as_ref();
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => return ranges.iter().map(|_| Err(copy_error(&e))).collect(),
+        };
+        self.read_ranges_of(&file, path, ranges)
+    }
+
+    /// `read_ranges` on an open file, `path` is only used in errors
+    #[allow(unused_doc_comments)]
+    pub(crate) fn read_ranges_of(
+        &self,
+        file: &File,
+        path: &Path,
+        ranges: &[Range<u64>],
+    ) -> Vec<io::Result<(Vec<u8>, usize)>> {
+        let reads = self.coalesce(file, path, ranges);
+        /// The buffers come zeroed from the allocator, the regions need initialized memory anyway, so
+        /// `ZeroFill` costs nothing on top: a range that was fully there is never touched twice
+        let mut buffers: Vec<io::Result<Vec<u8>>> = reads
+            .iter()
+            .map(|read| {
+                let len = read.range.end.saturating_sub(read.range.start);
+                self.check_size(path, len)?;
+                Ok(vec![0u8; len as usize])
+            })
+            .collect();
+
+        let fd = types::Fd(file.as_raw_fd());
+        let mut regions: Vec<Region<'_>> = Vec::new();
+        let mut owners = Vec::new();
+        for (i, (buffer, read)) in buffers.iter_mut().zip(&reads).enumerate() {
+            if let Ok(buffer) = buffer {
+                regions.push(Region {
+                    fd,
+                    buf: buffer.as_mut_slice(),
+                    offset: read.range.start,
+                });
+                owners.push(i);
+            }
+        }
+        let lengths = self.read_regions(&mut regions);
+        drop(regions);
+
+        let mut read_lengths: Vec<Option<io::Result<usize>>> = reads.iter().map(|_| None).collect();
+        for (i, n) in owners.into_iter().zip(lengths) {
+            read_lengths[i] = Some(n);
+        }
+        let mut results: Vec<Option<_>> = ranges.iter().map(|_| None).collect();
+        for ((read, buffer), n) in reads.into_iter().zip(buffers).zip(read_lengths) {
+            let read_result =
+                buffer.and_then(|buffer| Ok((buffer, n.expect("every buffer was read")?)));
+            let (mut buffer, n) = match read_result {
+                Ok(read) => read,
+                Err(e) => {
+                    for &member in &read.members {
+                        results[member] = Some(Err(copy_error(&e)));
+                    }
+                    continue;
+                }
+            };
+            if let [member] = read.members[..] {
+                /// A read of its own, the buffer is the result
+                if self.config.pad == PadPolicy::Exact {
+                    buffer.truncate(n);
+                }
+                results[member] = Some(Ok((buffer, n)));
+                continue;
+            }
+            for &member in &read.members {
+                let range = &ranges[member];
+                let start = (range.start - read.range.start) as usize;
+                let len = (range.end - range.start) as usize;
+                let got = n.saturating_sub(start).min(len);
+                let mut data = buffer[start..start + got].to_vec();
+                if self.config.pad == PadPolicy::ZeroFill {
+                    data.resize(len, 0);
+                }
+                results[member] = Some(Ok((data, got)));
+            }
+        }
+        results
+            .into_iter()
+            .map(|result| result.expect("every range has a result"))
+            .collect()
+    }
+
+    /// The reads that answer `ranges`, merged by `UringConfig::coalesce_gap`
+    ///
+    /// Ranges are merged in offset order as long as the gap to the next one is small enough and the
+    /// merged read stays within `max_bytes`. Empty ranges are never merged.
+    #[allow(unused_doc_comments)]
+    fn coalesce(&self, file: &File, path: &Path, ranges: &[Range<u64>]) -> Vec<Read> {
+        let single = |i: usize| Read {
+            range: ranges[i].clone(),
+            members: vec![i],
+        };
+        let Some(max_gap) = self.config.coalesce_gap.filter(|_| ranges.len() > 1) else {
+            return (0..ranges.len()).map(single).collect();
+        };
+        let mut order: Vec<usize> = (0..ranges.len()).collect();
+        order.sort_by_key(|&i| ranges[i].start);
+
+        let mut reads: Vec<Read> = Vec::new();
+        let mut gaps: Vec<u64> = Vec::new();
+        for i in order {
+            let range = &ranges[i];
+            if range.is_empty() {
+                reads.push(single(i));
+                gaps.push(0);
+                continue;
+            }
+            if let Some(last) = reads.last_mut()
+                && !last.range.is_empty()
+                && range.start <= last.range.end.saturating_add(max_gap)
+                && self
+                    .check_size(path, range.end.max(last.range.end) - last.range.start)
+                    .is_ok()
+            {
+                *gaps.last_mut().expect("one gap count per read") +=
+                    range.start.saturating_sub(last.range.end);
+                last.range.end = last.range.end.max(range.end);
+                last.members.push(i);
+                continue;
+            }
+            reads.push(single(i));
+            gaps.push(0);
+        }
+
+        /// SAFETY: F_GETFL on an fd we own
+        let direct = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFL) } & libc::O_DIRECT != 0;
+        let aligned = |range: &Range<u64>| {
+            range.start.is_multiple_of(DIRECT_ALIGN) && range.end.is_multiple_of(DIRECT_ALIGN)
+        };
+        let mut planned = Vec::with_capacity(ranges.len());
+        let mut stats = lock(&self.stats);
+        for (read, gap) in reads.into_iter().zip(gaps) {
+            if read.members.len() > 1 && direct && !aligned(&read.range) {
+                planned.extend(read.members.into_iter().map(single));
+                continue;
+            }
+            stats.coalesced_ranges += read.members.len() as u64 - 1;
+            stats.gap_bytes += gap;
+            planned.push(read);
+        }
+        planned
+    }
+}
+
+/// One read of `read_ranges_of`, the ranges it answers are `members` (indices into the ranges)
+struct Read {
+    range: Range<u64>,
+    members: Vec<usize>,
+}
+
+/// Offsets and lengths of `O_DIRECT` reads are multiples of this
+const DIRECT_ALIGN: u64 = 4096;
@@ -0,0 +1,1823 @@
+/// IoUring -> the ring itself (shared memory with the kernel)
+/// opcode -> what operation to perform (read, write, etc.)
+/// squeue -> the submission side, `squeue::Entry` is one SQE
+/// types -> wrappers for Linux kernel types (FDs, fixed files, etc)
+use io_uring::{IoUring, Probe, Submitter, opcode, squeue, types};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::buffers::{AlignedBuf, FixedBuffers, memlock_error};
+use crate::caps::Capabilities;
+use crate::chain::FixedFiles;
+use crate::completion::Completion;
+use crate::config::{MemlockPolicy, UringConfig};
+use crate::error::{Cancelled, ReadError};
+#[cfg(feature = "failpoints")]
+use crate::failpoints::{self, InjectedFault, Injector};
+use crate::guard;
+use crate::instrument::Metrics;
+use crate::journal::Journal;
+use crate::recv::GroupIds;
+use crate::stats::{AbandonedRequest, CloseReport, DrainReport, ReadStats, RingSnapshot};
+use crate::throttle::{Admit, Throttle};
+use crate::timing::Timings;
+use crate::tune::{Tuner, push_history};
+use crate::verify::Verifier;
+
+#[cfg(feature = "async")]
+use crate::notify::Notifier;
+#[cfg(feature = "async")]
+use std::task::Waker;
+
+/// A long lived reader that owns one ring and can be shared between threads (`&self` everywhere)
+///
+/// `read_one_file` creates a ring, uses it for one request and throws it away. That is fine for learning
+/// but `io_uring_setup` + mmap is not free, so anything that reads more than a handful of files should
+/// create one `UringReader` and keep it around.
+///
+/// How requests are told apart:
+/// - Every call gets its own `Session` with a unique 32 bit id
+/// - user_data = (session id << 32) | slot, the slot is chosen by the caller (e.g. the index of the file)
+/// - Whoever reaps the completion queue parks each CQE under its session id, so a thread never
+///   "loses" a completion that belongs to somebody else
+pub struct UringReader {
+    ring: IoUring,
+    pub(crate) config: UringConfig,
+    /// The IORING_FEAT_* flags, read once at setup
+    pub(crate) caps: Capabilities,
+    /// Serializes access to the submission queue (`submission_shared`)
+    sq: Mutex<()>,
+    /// Completions that were reaped but not yet picked up by their session
+    cq: Mutex<CqState>,
+    /// Signalled every time new completions have been parked
+    cq_ready: Condvar,
+    next_session: AtomicU32,
+    /// CQEs reaped with F_MORE, the extra ones of multishot requests (`ReadStats::completed` counts
+    /// them too), updated under the `stats` lock
+    more_cqes: AtomicU64,
+    /// Set by `drain`, no new requests after that
+    drained: AtomicBool,
+    /// Set once the teardown of `close` / drop ran, it never runs twice
+    closed: bool,
+    pub(crate) stats: Mutex<ReadStats>,
+    /// What goes out through the `metrics` facade, nothing at all without the feature
+    pub(crate) metrics: Metrics,
+    /// Only there with `record_timings`, so there is nothing to pay when it is off
+    timings: Option<Mutex<Timings>>,
+    /// Only there with `journal`
+    pub(crate) journal: Option<Journal>,
+    /// Only there with `auto_tune`
+    tuner: Option<Mutex<Tuner>>,
+    /// Only there with `rate_limit`
+    throttle: Option<Mutex<Throttle>>,
+    /// Only there with `failpoints`
+    #[cfg(feature = "failpoints")]
+    injector: Option<Injector>,
+    /// Only there with `verify_against_std`
+    pub(crate) verifier: Option<Verifier>,
+    /// What `IORING_REGISTER_PROBE` said, asked on first use (None inside if the kernel has no probe)
+    probe: OnceLock<Option<Probe>>,
+    /// `probe_hipri`, asked on first use
+    pub(crate) hipri: OnceLock<bool>,
+    /// Personality ids registered through this reader
+    pub(crate) personalities: Mutex<HashSet<u16>>,
+    /// `UringConfig::fixed_buffers`, None if not configured or RLIMIT_MEMLOCK said no
+    pub(crate) buffers: Option<FixedBuffers>,
+    /// Chunk buffers of finished copies, for the next one to take (`copy_file`)
+    pub(crate) copy_bufs: Mutex<Vec<AlignedBuf>>,
+    /// Provided buffer group ids, one per `recv_multishot`
+    pub(crate) buffer_groups: Mutex<GroupIds>,
+    /// Sparse file table for linked chains, registered by the first one (None inside if the kernel refused)
+    fixed_files: OnceLock<Option<FixedFiles>>,
+    /// Wakes async callers, created by the first one (None inside if the eventfd could not be set up)
+    #[cfg(feature = "async")]
+    notifier: OnceLock<Option<Notifier>>,
+    /// Waits still to fail with EBUSY before entering the kernel (tests only)
+    #[cfg(test)]
+    pub(crate) fail_waits: AtomicU32,
+}
+
+/// State behind the `cq` lock
+///
+/// Only one thread at a time is allowed to wait inside the kernel (`waiting == true`), everyone else
+/// sleeps on the condvar. The waiter is also the only one touching the completion queue while it is
+/// waiting, this is what makes `completion_shared()` sound.
+struct CqState {
+    parked: HashMap<u32, VecDeque<Completion>>,
+    waiting: bool,
+    /// Sessions that went away with requests still in flight, see `Session::abandon`
+    orphans: HashMap<u32, Orphan>,
+    /// Counts what `reap` sees while `drain` runs
+    tally: Option<DrainReport>,
+    /// The kernel's CQ overflow counter at the last reap
+    overflows: u32,
+    /// Timespecs of wait timers (kernels without EXT_ARG) the kernel may still read, by slot
+    timers: HashMap<u32, Box<types::Timespec>>,
+    next_timer: u32,
+}
+
+/// What an abandoned session left behind: its buffers, freed once its last CQE was reaped
+/// - requests -> user_data of what was in flight, `what` says whose they were
+struct Orphan {
+    in_flight: usize,
+    requests: Vec<u64>,
+    what: String,
+    keep: Box<dyn Send>,
+}
+
+/// Rough size of the memory a ring of `entries` pins: SQEs, twice as many CQEs, the SQ index array
+fn ring_bytes(entries: u32) -> u64 {
+    u64::from(entries.next_power_of_two()) * (64 + 2 * 16 + 4)
+}
+
+/// How long `drain` waits for the CQEs of what it canceled at the deadline
+const CANCEL_GRACE: Duration = Duration::from_millis(100);
+
+/// How long closing (or dropping) a reader drains before leaking what is left
+const DROP_DRAIN: Duration = Duration::from_secs(1);
+
+/// Lock a mutex, ignoring poisoning
+///
+/// A panic in one caller must not take the whole reader down with it, the data behind our locks stays
+/// consistent between statements.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl UringReader {
+    /// Create the ring described by `config`
+    ///
+    /// This call:
+    /// - Makes a syscall (io_uring_setup)
+    /// - Maps the submission and completion queues into our memory
+    ///
+    /// On kernels before 5.12 the ring's queues count against RLIMIT_MEMLOCK. With
+    /// `MemlockPolicy::Shrink` a ring that doesn't fit is retried with half the queue depth, otherwise
+    /// that fails with `ReadError::MemlockLimit`. `UringConfig::fixed_buffers` are registered here too.
+    pub fn new(config: UringConfig) -> io::Result<Self> {
+        Self::build(config, None).map(|(reader, _)| reader)
+    }
+
+    /// Like `new`, but share the io-wq worker pool of the ring behind `wq_fd` (IORING_SETUP_ATTACH_WQ)
+    ///
+    /// true -> the ring was attached
+    /// false -> the kernel refused the flag (older than 5.6, or `wq_fd` is no ring), this is a plain ring
+    pub(crate) fn attached(config: UringConfig, wq_fd: RawFd) -> io::Result<(Self, bool)> {
+        Self::build(config, Some(wq_fd))
+    }
+
+    fn build(mut config: UringConfig, wq_fd: Option<RawFd>) -> io::Result<(Self, bool)> {
+        let mut attach = wq_fd;
+        let mut sqpoll = config.sqpoll;
+        let mut shrunk = false;
+        let ring = loop {
+            let mut builder = IoUring::builder();
+            if let Some(fd) = attach {
+                builder.setup_attach_wq(fd);
+            }
+            if let Some(idle) = sqpoll {
+                builder.setup_sqpoll(idle.as_millis().min(u128::from(u32::MAX)) as u32);
+            }
+            match builder.build(config.queue_depth) {
+                Ok(ring) => break ring,
+                Err(e) if attach.is_some() && e.raw_os_error() == Some(libc::EINVAL) => {
+                    attach = None
+                }
+                Err(e)
+                    if sqpoll.is_some()
+                        && matches!(e.raw_os_error(), Some(libc::EPERM | libc::EINVAL)) =>
+                {
+                    sqpoll = None
+                }
+                Err(e) if e.raw_os_error() == Some(libc::ENOMEM) => {
+                    if config.memlock_policy == MemlockPolicy::Shrink && config.queue_depth > 1 {
+                        config.queue_depth /= 2;
+                        shrunk = true;
+                        continue;
+                    }
+                    return Err(memlock_error(
+                        "creating the ring",
+                        ring_bytes(config.queue_depth),
+                        e,
+                    ));
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        let mut reader = Self::with_ring(ring, config);
+        let attached = attach.is_some();
+        reader.caps.attached_wq = attached;
+        if wq_fd.is_some() && !attached {
+            reader.metrics.fell_back();
+        }
+        reader.caps.sqpoll = sqpoll.is_some();
+        if reader.config.sqpoll.is_some() && sqpoll.is_none() {
+            reader.metrics.fell_back();
+        }
+        reader.caps.memlock_degraded = shrunk;
+        reader.limit_workers();
+
+        if let Some((count, size)) = reader.config.fixed_buffers {
+            let submitter = reader.ring.submitter();
+            let registered =
+                FixedBuffers::register(count, size, reader.config.memlock_policy, |iovecs| {
+                    // SAFETY: the buffers live in the `FixedBuffers` stored on the reader, which is dropped
+                    // after the ring (and unregistered before that by `close`)
+                    unsafe { submitter.register_buffers(iovecs) }
+                })?;
+            match registered {
+                Some(buffers) => {
+                    reader.caps.fixed_buffers = buffers.count();
+                    reader.caps.fixed_buffer_size = buffers.size();
+                    reader.caps.memlock_degraded |= buffers.count() < count;
+                    reader.buffers = Some(buffers);
+                }
+                None => reader.caps.memlock_degraded = true,
+            }
+        }
+        Ok((reader, attached))
+    }
+
+    fn with_ring(ring: IoUring, config: UringConfig) -> Self {
+        let ring_limit = ring.params().sq_entries().min(ring.params().cq_entries());
+        let tuner = config
+            .auto_tune
+            .as_ref()
+            .map(|tune| Mutex::new(Tuner::new(tune, config.chunk_size, ring_limit)));
+        let throttle = config
+            .rate_limit
+            .as_ref()
+            .map(|limit| Mutex::new(Throttle::new(limit, config.chunk_size, ring_limit)));
+        let mut stats = ReadStats::default();
+        if let Some(tuner) = &tuner {
+            stats.tuned_depth = Some(lock(tuner).depth());
+        }
+
+        UringReader {
+            caps: Capabilities::from_params(ring.params()),
+            ring,
+            sq: Mutex::new(()),
+            cq: Mutex::new(CqState {
+                parked: HashMap::new(),
+                waiting: false,
+                orphans: HashMap::new(),
+                tally: None,
+                overflows: 0,
+                timers: HashMap::new(),
+                next_timer: 0,
+            }),
+            cq_ready: Condvar::new(),
+            next_session: AtomicU32::new(1),
+            more_cqes: AtomicU64::new(0),
+            drained: AtomicBool::new(false),
+            closed: false,
+            stats: Mutex::new(stats),
+            metrics: Metrics::new(&config),
+            timings: config.record_timings.then(Mutex::default),
+            journal: config.journal.map(Journal::new),
+            tuner,
+            throttle,
+            #[cfg(feature = "failpoints")]
+            injector: config.failpoints.as_ref().map(Injector::new),
+            verifier: Verifier::start(config.verify_against_std),
+            probe: OnceLock::new(),
+            hipri: OnceLock::new(),
+            personalities: Mutex::new(HashSet::new()),
+            buffers: None,
+            copy_bufs: Mutex::new(Vec::new()),
+            buffer_groups: Mutex::new(GroupIds::default()),
+            fixed_files: OnceLock::new(),
+            #[cfg(feature = "async")]
+            notifier: OnceLock::new(),
+            #[cfg(test)]
+            fail_waits: AtomicU32::new(0),
+            config,
+        }
+    }
+
+    /// The ring's own fd, what other rings attach their io-wq to
+    pub(crate) fn ring_fd(&self) -> RawFd {
+        self.ring.as_raw_fd()
+    }
+
+    /// The configuration this reader was created with
+    pub fn config(&self) -> &UringConfig {
+        &self.config
+    }
+
+    /// What the kernel's ring supports, see `Capabilities`
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            hipri_direct: self.probe_hipri(),
+            ..self.caps
+        }
+    }
+
+    /// A snapshot of the counters collected so far
+    pub fn stats(&self) -> ReadStats {
+        let mut stats = lock(&self.stats).clone();
+        if let Some(verifier) = &self.verifier {
+            verifier.add_to(&mut stats);
+        }
+        stats
+    }
+
+    /// How many chunk reads to keep in flight: the auto-tuner's pick, or `queue_depth`
+    pub(crate) fn depth(&self) -> usize {
+        match &self.tuner {
+            Some(tuner) => lock(tuner).depth() as usize,
+            None => self.config.queue_depth as usize,
+        }
+    }
+
+    /// Feed one finished chunk read to the auto-tuner, no-op without `auto_tune`
+    pub(crate) fn tune(&self, bytes: u64, in_flight: usize) {
+        let Some(tuner) = &self.tuner else {
+            return;
+        };
+        let Some(decision) = lock(tuner).completed(bytes, in_flight) else {
+            return;
+        };
+        let mut stats = lock(&self.stats);
+        stats.tuned_depth = Some(decision.depth);
+        if decision.changed {
+            push_history(&mut stats.depth_history, decision.depth);
+        }
+    }
+
+    /// Whether a call may issue new reads under `UringConfig::rate_limit`, always true without one
+    ///
+    /// `in_flight` -> the caller's requests not reaped yet. In debt with some of them left it's false,
+    /// the caller reaps instead. In debt with none left this sleeps the debt off and says true.
+    pub(crate) fn admit(&self, in_flight: usize) -> bool {
+        let Some(throttle) = &self.throttle else {
+            return true;
+        };
+        let admit = lock(throttle).admit(in_flight);
+        match admit {
+            Admit::Go => true,
+            Admit::Reap => false,
+            Admit::Sleep(delay) => {
+                std::thread::sleep(delay);
+                let mut stats = lock(&self.stats);
+                stats.throttle_sleeps += 1;
+                stats.throttle_slept += delay;
+                true
+            }
+        }
+    }
+
+    /// Charge one completed read to the rate limit, no-op without `rate_limit`
+    pub(crate) fn throttled(&self, bytes: u64) {
+        let Some(throttle) = &self.throttle else {
+            return;
+        };
+        let rate = lock(throttle).completed(bytes);
+        lock(&self.stats).throttled_rate = Some(rate);
+    }
+
+    /// For the `io_uring_register` calls that live next to the feature using them
+    pub(crate) fn submitter(&self) -> Submitter<'_> {
+        self.ring.submitter()
+    }
+
+    /// Whether the registered file table of `fixed_files` is there, None if nobody asked for it yet
+    pub(crate) fn file_table(&self) -> Option<bool> {
+        self.fixed_files.get().map(Option::is_some)
+    }
+
+    /// The registered file table used by linked chains, `queue_depth` slots
+    pub(crate) fn fixed_files(&self) -> io::Result<&FixedFiles> {
+        if !self.caps.linked_file {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "linked requests resolve fixed files at submit on this kernel (needs Linux 5.17)",
+            ));
+        }
+        let slots = self.config.queue_depth;
+        self.fixed_files
+            .get_or_init(|| {
+                let registered = self.ring.submitter().register_files_sparse(slots);
+                registered.ok().map(|()| FixedFiles::new(slots))
+            })
+            .as_ref()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "this kernel can't register a sparse file table (needs Linux 5.19)",
+                )
+            })
+    }
+
+    /// Whether the running kernel supports `opcode` (`IORING_REGISTER_PROBE`)
+    ///
+    /// Kernels without the probe (older than 5.6) report every opcode as unsupported. The probe is
+    /// asked once, the answer doesn't change while the ring lives.
+    pub(crate) fn is_supported(&self, opcode: u8) -> bool {
+        let probe = self.probe.get_or_init(|| {
+            let mut probe = Probe::new();
+            self.ring
+                .submitter()
+                .register_probe(&mut probe)
+                .ok()
+                .map(|()| probe)
+        });
+        probe
+            .as_ref()
+            .is_some_and(|probe| probe.is_supported(opcode))
+    }
+
+    /// Cap the number of io-wq kernel workers (IORING_REGISTER_IOWQ_MAX_WORKERS, Linux 5.15)
+    ///
+    /// bounded -> workers for I/O that always finishes (regular files, block devices)
+    /// unbounded -> workers for I/O that may wait forever (sockets, pipes, `read_when_ready`)
+    ///
+    /// The limits are per NUMA node and belong to the io-wq of the calling thread, so call it from the
+    /// thread that submits. `0` leaves a limit alone. Returns the previous `(bounded, unbounded)` limits,
+    /// `set_iowq_max_workers(0, 0)` just asks for them.
+    pub fn set_iowq_max_workers(&self, bounded: u32, unbounded: u32) -> io::Result<(u32, u32)> {
+        let mut max = [bounded, unbounded];
+        self.ring.submitter().register_iowq_max_workers(&mut max)?;
+        Ok((max[0], max[1]))
+    }
+
+    /// Apply `UringConfig::max_bounded_workers` / `max_unbounded_workers`, recorded in the capabilities
+    /// rather than failing
+    #[allow(unused_doc_comments)]
+    fn limit_workers(&mut self) {
+        let (bounded, unbounded) = (
+            self.config.max_bounded_workers,
+            self.config.max_unbounded_workers,
+        );
+        if bounded.is_none() && unbounded.is_none() {
+            return;
+        }
+        /// The second call only reads back what is in effect now
+        let granted = self
+            .set_iowq_max_workers(bounded.unwrap_or(0), unbounded.unwrap_or(0))
+            .and_then(|_| self.set_iowq_max_workers(0, 0));
+        match granted {
+            Ok(granted) => self.caps.max_workers = Some(granted),
+            Err(_) => self.caps.max_workers_unsupported = true,
+        }
+    }
+
+    /// Pin the ring's io-wq kernel workers to `cpus`, best effort (ignored on kernels without the register op)
+    pub(crate) fn pin_workers(&self, cpus: &[usize]) {
+        if let Some(set) = crate::pool::cpu_set(cpus) {
+            let _ = self.ring.submitter().register_iowq_aff(&set);
+        }
+    }
+
+    /// Same as the free `read_one_file`, but on this reader's ring
+    ///
+    /// Ok(n) -> number of bytes read (at most 4096, one filesystem block)
+    /// Err(e) -> an OS error
+    #[allow(unused_doc_comments)]
+    pub fn read_one_file(&self, path: impl AsRef<Path>) -> io::Result<usize> {
+        /// The file and the buffer are created before the session, so they are dropped after it.
+        /// Dropping a session waits for its requests, so the kernel is done with both by then.
+        let file = File::open(path)?;
+        let mut buffer = vec![0u8; 4096];
+
+        let mut session = self.session();
+        let read_e = opcode::Read::new(
+            types::Fd(file.as_raw_fd()),
+            buffer.as_mut_ptr(),
+            buffer.len() as u32,
+        )
+        .offset(0)
+        .build();
+
+        session.push(0, read_e)?;
+        session.submit()?;
+
+        let res = session.next()?.into_result()?;
+        Ok(res as usize)
+    }
+
+    /// The process wide reader behind convenience constructors like `LineReader::open`
+    ///
+    /// Created with `UringConfig::default()` on first use. If two threads race, one of the two rings
+    /// is simply dropped again.
+    pub(crate) fn shared_default() -> io::Result<&'static UringReader> {
+        static DEFAULT: OnceLock<UringReader> = OnceLock::new();
+        if let Some(reader) = DEFAULT.get() {
+            return Ok(reader);
+        }
+        let reader = UringReader::new(UringConfig::default())?;
+        Ok(DEFAULT.get_or_init(|| reader))
+    }
+
+    /// Start a new session, see the type level docs for how user_data is laid out
+    #[allow(unused_doc_comments)]
+    pub(crate) fn session(&self) -> Session<'_> {
+        /// 0 is never handed out (it is skipped when the counter wraps around), so a zeroed
+        /// user_data never matches a session
+        let id = loop {
+            let id = self.next_session.fetch_add(1, Ordering::Relaxed);
+            if id != 0 {
+                break id;
+            }
+        };
+        lock(&self.cq).parked.insert(id, VecDeque::new());
+
+        Session {
+            reader: self,
+            id,
+            in_flight: 0,
+            outstanding: HashMap::new(),
+            cancels: HashMap::new(),
+            deadline: self.config.timeout.map(|timeout| Instant::now() + timeout),
+            force_async: self.config.force_async,
+            #[cfg(feature = "metrics")]
+            reads: HashSet::new(),
+            #[cfg(feature = "failpoints")]
+            injected: HashMap::new(),
+        }
+    }
+
+    /// One request in a session of its own, waited for
+    pub(crate) fn run_one(&self, entry: squeue::Entry) -> io::Result<usize> {
+        let mut session = self.session();
+        session.push(0, entry)?;
+        session.submit()?;
+        session.next()?.into_result().map(|n| n as usize)
+    }
+
+    /// Push one SQE, submitting first if the submission queue is full
+    ///
+    /// `forced_async` only tells the timings that the entry carries IOSQE_ASYNC, it doesn't set it
+    fn push(&self, entry: &squeue::Entry, forced_async: bool) -> io::Result<()> {
+        let _sq = lock(&self.sq);
+
+        for _ in 0..3 {
+            if self.push_locked(entry, forced_async) {
+                return Ok(());
+            }
+            self.metrics.sq_full();
+            self.submit()?;
+        }
+        Err(sq_full())
+    }
+
+    /// Push `entries` back to back, nobody else's SQE ends up in between (that would break IO_LINK)
+    ///
+    /// Submits what is queued until there is room for all of them, a group longer than the whole
+    /// submission queue can never fit and fails with `InvalidInput`.
+    #[allow(unused_doc_comments)]
+    fn push_group(&self, entries: &[(squeue::Entry, bool)]) -> io::Result<()> {
+        let _sq = lock(&self.sq);
+        /// SAFETY: we hold the `sq` lock, see `push_locked`
+        let space = || {
+            let sq = unsafe { self.ring.submission_shared() };
+            (sq.capacity(), sq.capacity() - sq.len())
+        };
+        if entries.len() > space().0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the chain is longer than the submission queue",
+            ));
+        }
+
+        for _ in 0..3 {
+            if space().1 >= entries.len() {
+                for (entry, forced_async) in entries {
+                    let pushed = self.push_locked(entry, *forced_async);
+                    debug_assert!(pushed, "checked the space under the same lock");
+                }
+                return Ok(());
+            }
+            self.metrics.sq_full();
+            self.submit()?;
+        }
+        Err(sq_full())
+    }
+
+    /// Push one SQE if there is room, never enters the kernel
+    fn try_push(&self, entry: &squeue::Entry, forced_async: bool) -> io::Result<()> {
+        let _sq = lock(&self.sq);
+        if self.push_locked(entry, forced_async) {
+            Ok(())
+        } else {
+            self.metrics.sq_full();
+            Err(sq_full())
+        }
+    }
+
+    /// One push attempt, the caller holds the `sq` lock
+    #[allow(unused_doc_comments)]
+    fn push_locked(&self, entry: &squeue::Entry, forced_async: bool) -> bool {
+        /// SAFETY: the caller holds the `sq` lock, so this is the only `SubmissionQueue` alive.
+        /// The queue is synced (tail published to the kernel) when it is dropped.
+        let pushed = unsafe { self.ring.submission_shared().push(entry).is_ok() };
+        if pushed {
+            let mut stats = lock(&self.stats);
+            stats.submitted += 1;
+            stats.classic_syscalls += classic_syscalls(entry);
+            drop(stats);
+            self.metrics.submitted();
+            if let Some(timings) = &self.timings {
+                lock(timings).pushed(entry.get_user_data(), forced_async);
+            }
+            if let Some(journal) = &self.journal {
+                journal.pushed(entry);
+            }
+        }
+        pushed
+    }
+
+    /// Free entries in the submission queue right now, pushing more than this would have to submit
+    ///
+    /// No syscall, just a look at the ring. Other threads using the reader can fill it any time.
+    #[allow(unused_doc_comments)]
+    pub fn sq_space_left(&self) -> usize {
+        let _sq = lock(&self.sq);
+        /// SAFETY: we hold the `sq` lock, see `push_locked`
+        let sq = unsafe { self.ring.submission_shared() };
+        sq.capacity() - sq.len()
+    }
+
+    /// Requests pushed on this reader (by anyone) whose completion was not reaped yet, no syscall
+    pub fn in_flight(&self) -> u64 {
+        self.outstanding()
+    }
+
+    /// Queue sizes, occupancy and the kernel's drop/overflow counters, for "is the ring sized right?"
+    ///
+    /// Cheap enough to poll every second: two short lock holds and a few loads from the shared ring
+    /// memory, no syscall. Other threads keep using the reader meanwhile, the fields are not one atomic
+    /// picture of the ring.
+    #[allow(unused_doc_comments)]
+    pub fn ring_snapshot(&self) -> RingSnapshot {
+        let (sq_len, sq_dropped, sqpoll_needs_wakeup) = {
+            let _sq = lock(&self.sq);
+            /// SAFETY: we hold the `sq` lock, see `push_locked`
+            let sq = unsafe { self.ring.submission_shared() };
+            (sq.len(), sq.dropped(), sq.need_wakeup())
+        };
+        let (parked, cq_overflows) = {
+            let state = lock(&self.cq);
+            (
+                state.parked.values().map(VecDeque::len).sum(),
+                state.overflows,
+            )
+        };
+        RingSnapshot {
+            sq_entries: self.caps.sq_entries,
+            cq_entries: self.caps.cq_entries,
+            sq_len,
+            in_flight: self.outstanding(),
+            parked,
+            sq_dropped,
+            cq_overflows,
+            sqpoll_needs_wakeup,
+        }
+    }
+
+    /// The kernel's count of SQEs it dropped as invalid, the batch reads assert it stays 0
+    #[allow(unused_doc_comments)]
+    pub(crate) fn sq_dropped(&self) -> u32 {
+        let _sq = lock(&self.sq);
+        /// SAFETY: we hold the `sq` lock, see `push_locked`
+        unsafe { self.ring.submission_shared() }.dropped()
+    }
+
+    /// Tell the kernel about everything pushed so far, without waiting
+    ///
+    /// Without IORING_FEAT_NODROP the kernel drops CQEs that don't fit, so whatever already completed
+    /// is reaped right away (unless another thread waits in the kernel, it reaps anyway).
+    fn submit(&self) -> io::Result<usize> {
+        let submitted = loop {
+            // An SQPOLL ring doesn't enter to submit, its kernel thread picks the SQEs up (the wake
+            // up after the thread went idle does enter, it isn't counted)
+            self.entering(!self.caps.sqpoll);
+            match self.ring.submit() {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                other => break other,
+            }
+        };
+        if !self.caps.nodrop
+            && let Ok(mut state) = self.cq.try_lock()
+            && !state.waiting
+            && self.reap(&mut state) > 0
+        {
+            self.cq_ready.notify_all();
+        }
+        submitted
+    }
+
+    /// Bookkeeping right before every `io_uring_enter`, it submits whatever was pushed so far
+    /// (`enters` false -> a submit that doesn't need the syscall)
+    fn entering(&self, enters: bool) {
+        if enters {
+            lock(&self.stats).enters += 1;
+        }
+        if let Some(timings) = &self.timings {
+            lock(timings).submitted();
+        }
+    }
+
+    /// Block until the given session has a completion, or until `deadline`
+    ///
+    /// If another thread is already waiting inside the kernel we sleep on the condvar instead, that
+    /// thread parks every CQE it sees (ours included) and wakes us up.
+    ///
+    /// An empty completion queue after a wait is normal (EINTR, another thread reaped first, a bounded
+    /// wait that expired), it just means: look again.
+    fn next_completion(&self, session: u32, deadline: Option<Instant>) -> io::Result<Completion> {
+        let mut state = lock(&self.cq);
+        loop {
+            if let Some(cqe) = state.parked.get_mut(&session).and_then(|q| q.pop_front()) {
+                return Ok(cqe);
+            }
+
+            let remaining = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) if !remaining.is_zero() => Some(remaining),
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "timed out waiting for io_uring completions",
+                        ));
+                    }
+                },
+                None => None,
+            };
+
+            if state.waiting {
+                state = match remaining {
+                    Some(remaining) => {
+                        self.cq_ready
+                            .wait_timeout(state, remaining)
+                            .unwrap_or_else(|poisoned| poisoned.into_inner())
+                            .0
+                    }
+                    None => self
+                        .cq_ready
+                        .wait(state)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner()),
+                };
+                continue;
+            }
+
+            if self.reap(&mut state) > 0 {
+                self.cq_ready.notify_all();
+                continue;
+            }
+
+            state.waiting = true;
+            drop(state);
+
+            let waited = self.wait_for_cqe(remaining);
+
+            state = lock(&self.cq);
+            state.waiting = false;
+            self.reap(&mut state);
+            self.cq_ready.notify_all();
+            waited?;
+        }
+    }
+
+    /// Move every CQE that is currently in the completion queue to its session
+    ///
+    /// CQEs of sessions that are gone are dropped on the floor, nobody is waiting for them anymore.
+    #[allow(unused_doc_comments)]
+    fn reap(&self, state: &mut CqState) -> usize {
+        let mut reaped = 0;
+
+        /// SAFETY: the caller holds the `cq` lock and nobody is waiting in the kernel
+        /// (`waiting == false` or we are the waiter), so this is the only `CompletionQueue` alive.
+        let cq = unsafe { self.ring.completion_shared() };
+        let overflows = cq.overflow();
+        self.metrics
+            .cq_overflows(u64::from(overflows.wrapping_sub(state.overflows)));
+        state.overflows = overflows;
+        let mut timings = self.timings.as_ref().map(lock);
+        let mut journal = self.journal.as_ref().map(Journal::reaping);
+        let mut stats = lock(&self.stats);
+        let mut more = 0;
+        for cqe in cq {
+            let mut cqe = Completion::from(cqe);
+            more += u64::from(cqe.is_more());
+            if let Some(journal) = journal.as_mut() {
+                journal.reaped(&cqe);
+            }
+            if let Some(timings) = timings.as_mut() {
+                cqe.timing = timings.reaped(cqe.user_data(), cqe.is_more());
+                if let Some(timing) = cqe.timing {
+                    stats.record_timing(timing);
+                    self.metrics.latency(&timing);
+                }
+            }
+            let session = (cqe.user_data() >> 32) as u32;
+            if session == 0 {
+                /// Session 0 is the reader's own: cancels, and the wait timers whose CQE ends them
+                state.timers.remove(&(cqe.user_data() as u32));
+                reaped += 1;
+                continue;
+            }
+            if let Some(tally) = state.tally.as_mut()
+                && !cqe.is_more()
+            {
+                match cqe.result() {
+                    0.. => tally.completed += 1,
+                    res if res == -libc::ECANCELED => tally.canceled += 1,
+                    _ => tally.failed += 1,
+                }
+            }
+            if let Some(queue) = state.parked.get_mut(&session) {
+                queue.push_back(cqe);
+            } else if !cqe.is_more()
+                && let Some(orphan) = state.orphans.get_mut(&session)
+            {
+                orphan.in_flight -= 1;
+                if orphan.in_flight == 0 {
+                    state.orphans.remove(&session);
+                }
+            }
+            reaped += 1;
+        }
+
+        stats.completed += reaped as u64;
+        self.more_cqes.fetch_add(more, Ordering::Relaxed);
+        self.metrics.completed(reaped as u64);
+        #[cfg(feature = "async")]
+        if reaped > 0
+            && let Some(Some(notifier)) = self.notifier.get()
+        {
+            notifier.wake_all();
+        }
+        reaped
+    }
+
+    /// A completion of `session` if one is there already, never waits
+    ///
+    /// Reaps the completion queue itself unless another thread is waiting in the kernel (that thread
+    /// reaps for us).
+    fn try_completion(&self, session: u32) -> Option<Completion> {
+        let mut state = lock(&self.cq);
+        if let Some(cqe) = state.parked.get_mut(&session).and_then(|q| q.pop_front()) {
+            return Some(cqe);
+        }
+        if !state.waiting && self.reap(&mut state) > 0 {
+            self.cq_ready.notify_all();
+        }
+        state.parked.get_mut(&session).and_then(|q| q.pop_front())
+    }
+
+    /// Wake `waker` once new completions may be there
+    ///
+    /// Falls back to waking it right away (the task polls again) if the eventfd can't be set up.
+    #[cfg(feature = "async")]
+    pub(crate) fn register_waker(&self, waker: &Waker) {
+        let notifier = self
+            .notifier
+            .get_or_init(|| Notifier::new(|fd| self.ring.submitter().register_eventfd(fd)).ok());
+        match notifier {
+            Some(notifier) => notifier.register(waker),
+            None => {
+                self.metrics.fell_back();
+                waker.wake_by_ref();
+            }
+        }
+    }
+
+    /// Set up the eventfd for an external driver (`Driver`), Err if the kernel refused it
+    ///
+    /// Must be the first async use of the reader, a notifier thread that already runs is kept.
+    #[cfg(feature = "async-io")]
+    pub(crate) fn external_notifier(&self) -> io::Result<&Notifier> {
+        let mut error = None;
+        let notifier = self.notifier.get_or_init(|| {
+            Notifier::external(|fd| self.ring.submitter().register_eventfd(fd))
+                .map_err(|e| error = Some(e))
+                .ok()
+        });
+        match (notifier, error) {
+            (Some(notifier), _) => Ok(notifier),
+            (None, Some(e)) => Err(e),
+            (None, None) => Err(io::Error::other("the eventfd could not be registered")),
+        }
+    }
+
+    /// The notifier, if an async caller set it up
+    #[cfg(feature = "async-io")]
+    pub(crate) fn notifier(&self) -> Option<&Notifier> {
+        self.notifier.get().and_then(Option::as_ref)
+    }
+
+    /// Reap whatever is in the completion queue (unless a blocking caller waits in the kernel, it
+    /// reaps anyway), this wakes the async tasks it belongs to
+    #[cfg(feature = "async-io")]
+    pub(crate) fn reap_ready(&self) {
+        let mut state = lock(&self.cq);
+        if !state.waiting && self.reap(&mut state) > 0 {
+            self.cq_ready.notify_all();
+        }
+    }
+
+    /// Shut the reader down: no new requests, wait for everything in flight, cancel what is left
+    ///
+    /// From now on every call that would push a request fails. Then it waits until every request of
+    /// every caller has posted its CQE, for at most `timeout` (`None` -> as long as it takes). Whatever
+    /// is still in flight at the deadline is canceled (`IORING_ASYNC_CANCEL_ANY`, Linux 5.19) and gets
+    /// another 100 ms to report back.
+    ///
+    /// Dropping the reader does this too, with a 1 s timeout.
+    pub fn drain(&self, timeout: Option<Duration>) -> DrainReport {
+        self.drained.store(true, Ordering::Release);
+        lock(&self.cq).tally = Some(DrainReport::default());
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        if !self.wait_idle(deadline) {
+            let cancel_e = opcode::AsyncCancel2::new(types::CancelBuilder::any())
+                .build()
+                .user_data(IGNORED_USER_DATA);
+            if self.push(&cancel_e, false).is_ok() && self.submit().is_ok() {
+                self.wait_idle(Some(Instant::now() + CANCEL_GRACE));
+            }
+        }
+
+        let mut state = lock(&self.cq);
+        let mut report = state.tally.take().unwrap_or_default();
+        for orphan in state.orphans.values() {
+            report
+                .abandoned
+                .extend(orphan.requests.iter().map(|&user_data| AbandonedRequest {
+                    user_data,
+                    what: orphan.what.clone(),
+                }));
+        }
+        let orphaned: usize = state.orphans.values().map(|orphan| orphan.in_flight).sum();
+        report.still_running = self.outstanding().saturating_sub(orphaned as u64);
+        report
+    }
+
+    /// Shut the reader down for good and release everything registered with the ring
+    ///
+    /// In this order:
+    /// 1. no new requests (same as `drain`)
+    /// 2. wait for what is in flight, cancel what is left after 1 s, see `drain`
+    /// 3. unregister the buffers, the file table, personalities and the eventfd, only if nothing is in
+    ///    flight anymore
+    /// 4. close the ring
+    ///
+    /// Every step runs even if an earlier one failed. The error is a `ReadError::CloseFailed` carrying
+    /// the full `CloseReport`. Dropping the reader runs the same steps and ignores what went wrong.
+    pub fn close(mut self) -> io::Result<CloseReport> {
+        let report = self.shutdown();
+        drop(self);
+        if report.failures.is_empty() {
+            Ok(report)
+        } else {
+            Err(ReadError::CloseFailed { report }.into())
+        }
+    }
+
+    /// Steps 1 to 3 of `close`, a no-op the second time
+    #[allow(unused_doc_comments)]
+    fn shutdown(&mut self) -> CloseReport {
+        let mut report = CloseReport::default();
+        if std::mem::replace(&mut self.closed, true) {
+            return report;
+        }
+        report.drain = self.drain(Some(DROP_DRAIN));
+
+        let in_flight = self.outstanding();
+        if in_flight > 0 {
+            report.failures.push(format!(
+                "{in_flight} requests still in flight, registered buffers/files/personalities/eventfd left to the kernel"
+            ));
+        } else {
+            let submitter = self.ring.submitter();
+            if self.buffers.is_some() {
+                match submitter.unregister_buffers() {
+                    Ok(()) => report.buffers_unregistered += 1,
+                    Err(e) => report.failures.push(format!("unregister buffers: {e}")),
+                }
+            }
+            if let Some(Some(_)) = self.fixed_files.get() {
+                match submitter.unregister_files() {
+                    Ok(()) => report.files_unregistered += 1,
+                    Err(e) => report.failures.push(format!("unregister files: {e}")),
+                }
+            }
+            let personalities = std::mem::take(
+                self.personalities
+                    .get_mut()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            );
+            for id in personalities {
+                match submitter.unregister_personality(id) {
+                    Ok(()) => report.personalities_unregistered += 1,
+                    Err(e) => report
+                        .failures
+                        .push(format!("unregister personality {id}: {e}")),
+                }
+            }
+            #[cfg(feature = "async")]
+            if let Some(Some(_)) = self.notifier.get() {
+                match submitter.unregister_eventfd() {
+                    Ok(()) => report.eventfds_unregistered += 1,
+                    Err(e) => report.failures.push(format!("unregister eventfd: {e}")),
+                }
+                self.notifier.take();
+            }
+        }
+
+        /// Whatever abandoned requests still use stays allocated, the kernel may write into it until the ring is gone
+        let state = self
+            .cq
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        for (_, orphan) in state.orphans.drain() {
+            std::mem::forget(orphan.keep);
+        }
+        report
+    }
+
+    /// Whether `drain` was called
+    pub(crate) fn is_drained(&self) -> bool {
+        self.drained.load(Ordering::Acquire)
+    }
+
+    /// SQEs pushed whose last CQE has not been reaped yet (every request posts exactly one without
+    /// F_MORE)
+    fn outstanding(&self) -> u64 {
+        let stats = lock(&self.stats);
+        stats.submitted - (stats.completed - self.more_cqes.load(Ordering::Relaxed))
+    }
+
+    /// Reap until nothing is outstanding, false if `deadline` passed (or waiting failed) first
+    fn wait_idle(&self, deadline: Option<Instant>) -> bool {
+        let mut state = lock(&self.cq);
+        loop {
+            if self.outstanding() == 0 {
+                return true;
+            }
+            let remaining = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) if !remaining.is_zero() => Some(remaining),
+                    _ => return false,
+                },
+                None => None,
+            };
+
+            if state.waiting {
+                state = match remaining {
+                    Some(remaining) => {
+                        self.cq_ready
+                            .wait_timeout(state, remaining)
+                            .unwrap_or_else(|poisoned| poisoned.into_inner())
+                            .0
+                    }
+                    None => self
+                        .cq_ready
+                        .wait(state)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner()),
+                };
+                continue;
+            }
+            if self.reap(&mut state) > 0 {
+                self.cq_ready.notify_all();
+                continue;
+            }
+
+            state.waiting = true;
+            drop(state);
+            let waited = self.wait_for_cqe(remaining);
+            state = lock(&self.cq);
+            state.waiting = false;
+            self.reap(&mut state);
+            self.cq_ready.notify_all();
+            if waited.is_err() {
+                return false;
+            }
+        }
+    }
+
+    /// Wait (as the one waiting thread) until at least one CQE is visible, or `timeout` passed
+    ///
+    /// Returning Ok does not promise a CQE, the caller always looks at the queue again.
+    #[allow(unused_doc_comments)]
+    fn wait_for_cqe(&self, timeout: Option<Duration>) -> io::Result<()> {
+        #[cfg(test)]
+        if self
+            .fail_waits
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            .is_ok()
+        {
+            return Err(io::Error::from_raw_os_error(libc::EBUSY));
+        }
+        let spin = match timeout {
+            Some(timeout) => self.config.spin_before_wait.min(timeout),
+            None => self.config.spin_before_wait,
+        };
+
+        if !spin.is_zero() {
+            /// Submit first, there is no point spinning on requests the kernel has not seen yet
+            self.submit()?;
+
+            /// The `CompletionQueue` caches head/tail when it is created, `sync()` reloads the tail the
+            /// kernel published. Without it we would spin on a stale snapshot forever.
+            ///
+            /// SAFETY: we are the waiting thread, nobody else touches the completion queue, and we
+            /// only look at its length here.
+            let mut cq = unsafe { self.ring.completion_shared() };
+            let start = Instant::now();
+            loop {
+                cq.sync();
+                if !cq.is_empty() {
+                    lock(&self.stats).spin_hits += 1;
+                    return Ok(());
+                }
+                if start.elapsed() >= spin {
+                    break;
+                }
+                std::hint::spin_loop();
+            }
+            lock(&self.stats).spin_misses += 1;
+        }
+
+        /// Submit anything still pending and sleep until at least one completion exists.
+        /// A bounded wait needs IORING_FEAT_EXT_ARG (5.11+), older kernels get a timeout SQE whose
+        /// CQE wakes us up.
+        let waited = match timeout.map(types::Timespec::from) {
+            Some(ts) if self.caps.ext_arg => {
+                self.entering(true);
+                self.ring
+                    .submitter()
+                    .submit_with_args(1, &types::SubmitArgs::new().timespec(&ts))
+            }
+            Some(ts) => {
+                self.metrics.fell_back();
+                let slot = self.push_wait_timer(ts)?;
+                self.entering(true);
+                let waited = self.ring.submit_and_wait(1);
+                /// With SUBMIT_STABLE the kernel copied the timespec at submit, otherwise it stays
+                /// until `reap` sees the timer's CQE
+                if self.caps.submit_stable {
+                    lock(&self.cq).timers.remove(&slot);
+                }
+                waited
+            }
+            None => {
+                self.entering(true);
+                self.ring.submit_and_wait(1)
+            }
+        };
+        match waited {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => Ok(()),
+            Err(e) if e.raw_os_error() == Some(libc::ETIME) => Ok(()),
+            Err(e) => Err(e),
+            Ok(_) => Ok(()),
+        }
+    }
+}
+
+impl UringReader {
+    /// Push an IORING_OP_TIMEOUT that completes after `ts`, for waits without EXT_ARG
+    ///
+    /// It runs in session 0 (slot = the timer), so its CQE goes nowhere except freeing the timespec.
+    fn push_wait_timer(&self, ts: types::Timespec) -> io::Result<u32> {
+        let timer = Box::new(ts);
+        let ptr: *const types::Timespec = &*timer;
+        let slot = {
+            let mut state = lock(&self.cq);
+            state.next_timer = state.next_timer.wrapping_add(1).max(1);
+            let slot = state.next_timer;
+            state.timers.insert(slot, timer);
+            slot
+        };
+        let timeout_e = opcode::Timeout::new(ptr).build().user_data(u64::from(slot));
+        if let Err(e) = self.push(&timeout_e, false) {
+            lock(&self.cq).timers.remove(&slot);
+            return Err(e);
+        }
+        Ok(slot)
+    }
+}
+
+/// What `entry` would cost as syscalls without the ring, the rules are on `SyscallSummary`
+fn classic_syscalls(entry: &squeue::Entry) -> u64 {
+    match entry.get_opcode() as u8 {
+        opcode::Nop::CODE
+        | opcode::AsyncCancel::CODE
+        | opcode::Timeout::CODE
+        | opcode::LinkTimeout::CODE
+        | opcode::TimeoutRemove::CODE
+        | opcode::FilesUpdate::CODE
+        | opcode::MsgRingData::CODE
+        | opcode::ProvideBuffers::CODE
+        | opcode::RemoveBuffers::CODE => 0,
+        _ => 1,
+    }
+}
+
+/// One logical operation on a `UringReader` (a single read, a batch, ...)
+///
+/// The session keeps track of how many of its requests are still owned by the kernel. Dropping it
+/// waits for all of them, so any buffer declared before the session outlives every request that
+/// points into it. This is the in-flight guard of everything on a `UringReader`: an error returned
+/// with `?` or a panic in the middle of a batch still goes through that drain.
+///
+/// With `UringConfig::timeout` the session has a deadline: once it passes, `next` cancels everything
+/// still in flight and reports `Cancelled::ByTimeout` (an `io::ErrorKind::TimedOut`).
+pub(crate) struct Session<'r> {
+    reader: &'r UringReader,
+    id: u32,
+    in_flight: usize,
+    /// user_data -> number of requests in flight with it
+    outstanding: HashMap<u64, usize>,
+    /// user_data -> why `cancel_all` canceled it, what its -ECANCELED completion says
+    cancels: HashMap<u64, Cancelled>,
+    deadline: Option<Instant>,
+    /// Reads and writes get IOSQE_ASYNC, starts out as `UringConfig::force_async`
+    force_async: bool,
+    /// user_data of the reads in flight, their results count as `uring_bytes_read`
+    #[cfg(feature = "metrics")]
+    reads: HashSet<u64>,
+    /// user_data -> the fault its completion gets (`UringConfig::failpoints`)
+    #[cfg(feature = "failpoints")]
+    injected: HashMap<u64, InjectedFault>,
+}
+
+/// Opcodes `force_async` applies to, everything else (timeouts, cancels, ...) is never worth a worker
+fn moves_data(opcode: u32) -> bool {
+    let opcode = opcode as u8;
+    opcode == opcode::Read::CODE
+        || opcode == opcode::Write::CODE
+        || opcode == opcode::ReadFixed::CODE
+        || opcode == opcode::WriteFixed::CODE
+}
+
+/// Opcodes whose result is a number of bytes read
+#[cfg(any(feature = "metrics", feature = "failpoints"))]
+pub(crate) fn reads_data(opcode: u32) -> bool {
+    let opcode = opcode as u8;
+    opcode == opcode::Read::CODE
+        || opcode == opcode::ReadFixed::CODE
+        || opcode == opcode::Readv::CODE
+}
+
+/// user_data of requests whose completion nobody wants (e.g. the `AsyncCancel` itself), session 0 is
+/// never handed out so their CQEs are dropped by `reap`
+pub(crate) const IGNORED_USER_DATA: u64 = 0;
+
+fn sq_full() -> io::Error {
+    io::Error::new(io::ErrorKind::WouldBlock, "submission queue is full")
+}
+
+/// Errors that only mean "try again"
+pub(crate) fn is_retryable(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+    )
+}
+
+impl Session<'_> {
+    /// Tag the entry with (session, slot) and push it, it is not submitted yet
+    pub(crate) fn push(&mut self, slot: u32, entry: squeue::Entry) -> io::Result<()> {
+        let (entry, forced_async) = self.prepare(slot, entry)?;
+        self.reader.push(&entry, forced_async)?;
+        self.pushed(&entry);
+        Ok(())
+    }
+
+    /// `push` for several entries that must stay next to each other in the submission queue (a linked
+    /// chain), see `UringReader::push_group`
+    pub(crate) fn push_group(&mut self, entries: Vec<(u32, squeue::Entry)>) -> io::Result<()> {
+        let prepared = entries
+            .into_iter()
+            .map(|(slot, entry)| self.prepare(slot, entry))
+            .collect::<io::Result<Vec<_>>>()?;
+        self.reader.push_group(&prepared)?;
+        for (entry, _) in &prepared {
+            self.pushed(entry);
+        }
+        Ok(())
+    }
+
+    /// `push`, but fails with `WouldBlock` instead of submitting when the submission queue is full
+    pub(crate) fn try_push(&mut self, slot: u32, entry: squeue::Entry) -> io::Result<()> {
+        let (entry, forced_async) = self.prepare(slot, entry)?;
+        self.reader.try_push(&entry, forced_async)?;
+        self.pushed(&entry);
+        Ok(())
+    }
+
+    /// The entry as it goes into the ring: user_data of `slot`, IOSQE_ASYNC if wanted, a NOP if an
+    /// injected fault replaces it
+    fn prepare(&mut self, slot: u32, entry: squeue::Entry) -> io::Result<(squeue::Entry, bool)> {
+        if self.reader.is_drained() {
+            return Err(io::Error::other(
+                "the reader was drained, it takes no new requests",
+            ));
+        }
+        let user_data = (u64::from(self.id) << 32) | u64::from(slot);
+        let forced_async = self.force_async && moves_data(entry.get_opcode());
+        let entry = if forced_async {
+            entry.flags(squeue::Flags::ASYNC)
+        } else {
+            entry
+        };
+        let entry = entry.user_data(user_data);
+        #[cfg(feature = "failpoints")]
+        let entry = match &self.reader.injector {
+            Some(injector) => {
+                let (entry, fault) = injector.plan(entry);
+                match fault {
+                    Some(fault) => self.injected.insert(user_data, fault),
+                    None => self.injected.remove(&user_data),
+                };
+                entry
+            }
+            None => entry,
+        };
+        Ok((entry, forced_async))
+    }
+
+    fn pushed(&mut self, entry: &squeue::Entry) {
+        self.in_flight += 1;
+        *self.outstanding.entry(entry.get_user_data()).or_default() += 1;
+        #[cfg(feature = "metrics")]
+        if reads_data(entry.get_opcode()) {
+            self.reads.insert(entry.get_user_data());
+        }
+    }
+
+    /// Override `UringConfig::force_async` for the requests pushed from now on
+    pub(crate) fn force_async(&mut self, on: bool) {
+        self.force_async = on;
+    }
+
+    /// Submit everything pushed so far
+    pub(crate) fn submit(&self) -> io::Result<()> {
+        self.reader.submit().map(|_| ())
+    }
+
+    /// Number of requests pushed by this session whose last CQE has not been picked up yet
+    pub(crate) fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Wait for the next completion of this session, in completion order
+    pub(crate) fn next(&mut self) -> io::Result<Completion> {
+        if self.in_flight == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no requests in flight",
+            ));
+        }
+
+        match self.reader.next_completion(self.id, self.deadline) {
+            Ok(cqe) => {
+                let cqe = self.explain(cqe);
+                self.picked_up(&cqe);
+                Ok(self.inject(cqe))
+            }
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                self.cancel_all(Cancelled::ByTimeout);
+                Err(ReadError::Cancelled {
+                    reason: Cancelled::ByTimeout,
+                }
+                .into())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The next completion of this session if there is one already, never waits
+    pub(crate) fn try_next(&mut self) -> Option<Completion> {
+        if self.in_flight == 0 {
+            return None;
+        }
+        let cqe = self.explain(self.reader.try_completion(self.id)?);
+        self.picked_up(&cqe);
+        Some(self.inject(cqe))
+    }
+
+    /// The completion as the caller sees it, with the fault planned for it applied
+    #[cfg(feature = "failpoints")]
+    fn inject(&mut self, cqe: Completion) -> Completion {
+        let Some(fault) = self.injected.remove(&cqe.user_data()) else {
+            return cqe;
+        };
+        match failpoints::apply(fault, cqe) {
+            Some(injected) => {
+                lock(&self.reader.stats).injected_faults += 1;
+                self.reader.metrics.injected_fault();
+                injected
+            }
+            None => cqe,
+        }
+    }
+
+    #[cfg(not(feature = "failpoints"))]
+    #[inline]
+    fn inject(&mut self, cqe: Completion) -> Completion {
+        cqe
+    }
+
+    /// Bookkeeping for a completion handed to the caller
+    /// `cqe` with the reason of its cancel, if it is the -ECANCELED of a request we canceled
+    fn explain(&self, cqe: Completion) -> Completion {
+        match self.cancels.get(&cqe.user_data()) {
+            Some(&reason) if cqe.result() == -libc::ECANCELED => cqe.with_cancelled(reason),
+            _ => cqe,
+        }
+    }
+
+    fn picked_up(&mut self, cqe: &Completion) {
+        if cqe.is_more() {
+            return;
+        }
+        self.in_flight -= 1;
+        #[cfg(feature = "metrics")]
+        if cqe.result() > 0 && self.reads.contains(&cqe.user_data()) {
+            self.reader.metrics.bytes_read(cqe.result() as u64);
+        }
+        if let Some(count) = self.outstanding.get_mut(&cqe.user_data()) {
+            *count -= 1;
+            if *count == 0 {
+                self.outstanding.remove(&cqe.user_data());
+                self.cancels.remove(&cqe.user_data());
+                #[cfg(feature = "metrics")]
+                self.reads.remove(&cqe.user_data());
+            }
+        }
+    }
+
+    /// The reader this session runs on
+    pub(crate) fn reader(&self) -> &UringReader {
+        self.reader
+    }
+
+    /// Go away without waiting for the requests still in flight, `keep` is everything they point into
+    ///
+    /// The reader holds on to `keep` and drops it once the last CQE of this session was reaped (if the
+    /// reader is dropped first, `keep` is leaked). Cancel first, or it may be a while. `what` shows up
+    /// in `DrainReport::abandoned`.
+    #[cfg(feature = "async")]
+    pub(crate) fn abandon(mut self, keep: Box<dyn Send>, what: String) {
+        let mut state = lock(&self.reader.cq);
+        let parked = state.parked.remove(&self.id).unwrap_or_default();
+        let done = parked.iter().filter(|cqe| !cqe.is_more()).count();
+        let in_flight = self.in_flight - done;
+        if in_flight > 0 {
+            state.orphans.insert(
+                self.id,
+                Orphan {
+                    in_flight,
+                    requests: self.outstanding.keys().copied().collect(),
+                    what,
+                    keep,
+                },
+            );
+        }
+        self.in_flight = 0;
+    }
+
+    /// Replace the deadline (`UringConfig::timeout` by default) for calls that take their own
+    pub(crate) fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Pull the deadline in to `deadline` if that is earlier, for calls with a budget of their own
+    pub(crate) fn cap_deadline(&mut self, deadline: Option<Instant>) {
+        if let Some(deadline) = deadline {
+            self.deadline = Some(self.deadline.map_or(deadline, |own| own.min(deadline)));
+        }
+    }
+
+    /// Fail with `Cancelled::ByTimeout` (after canceling what is in flight) once the deadline has
+    /// passed, for loops that would otherwise keep issuing while completions keep coming in without a
+    /// wait
+    pub(crate) fn check_deadline(&mut self) -> io::Result<()> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                self.cancel_all(Cancelled::ByTimeout);
+                Err(ReadError::Cancelled {
+                    reason: Cancelled::ByTimeout,
+                }
+                .into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Start the deadline over, for sessions that live across many unrelated waits
+    pub(crate) fn restart_deadline(&mut self) {
+        self.deadline = self
+            .reader
+            .config
+            .timeout
+            .map(|timeout| Instant::now() + timeout);
+    }
+
+    /// Ask the kernel to cancel everything this session still has in flight
+    ///
+    /// The canceled requests still post a CQE (usually -ECANCELED, or their real result if they were
+    /// already past the point of no return), so they are still reaped as usual. The deadline is
+    /// cleared, waiting for those CQEs must not time out again. An -ECANCELED among them says `why`
+    /// (`Completion::cancelled`), unless an earlier cancel already gave a reason.
+    pub(crate) fn cancel_all(&mut self, why: Cancelled) {
+        self.deadline = None;
+        for &user_data in self.outstanding.keys() {
+            self.cancels.entry(user_data).or_insert(why);
+            let cancel_e = opcode::AsyncCancel::new(user_data)
+                .build()
+                .user_data(IGNORED_USER_DATA);
+            if self.reader.push(&cancel_e, false).is_err() {
+                break;
+            }
+        }
+        let _ = self.reader.submit();
+    }
+}
+
+/// Best effort `drain`: the kernel may still write into the buffers of abandoned sessions, those are
+/// leaked if their CQEs don't show up in time
+impl Drop for UringReader {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl Drop for Session<'_> {
+    #[allow(unused_doc_comments)]
+    fn drop(&mut self) {
+        let mut failures = 0;
+        while self.in_flight > 0 {
+            match self.next() {
+                Ok(_) => failures = 0,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                /// The buffers are the caller's and freed right after this, leaving with requests in
+                /// flight is a use after free. Cancel and keep waiting, and stop the process if the ring
+                /// can't be waited on at all anymore.
+                Err(_) => {
+                    if failures == 0 {
+                        self.cancel_all(Cancelled::ByUser);
+                    }
+                    failures += 1;
+                    if failures >= guard::SETTLE_ATTEMPTS {
+                        guard::give_up(self.in_flight);
+                    }
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+        }
+        lock(&self.reader.cq).parked.remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::guard::tests::pipe;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn failed_wait_drains_the_session_before_returning() {
+        let reader = UringReader::new(UringConfig::default()).unwrap();
+        let (mut rx, mut tx) = pipe();
+        let mut buf = [0xaa; 16];
+
+        let read = (|| -> io::Result<Completion> {
+            let mut session = reader.session();
+            let read_e = opcode::Read::new(
+                types::Fd(rx.as_raw_fd()),
+                buf.as_mut_ptr(),
+                buf.len() as u32,
+            )
+            .build();
+            session.push(0, read_e)?;
+            session.submit()?;
+            // The read is in the kernel, the next waits fail: `?` returns and the session drops
+            reader.fail_waits.store(3, Ordering::Relaxed);
+            session.next()
+        })();
+        assert_eq!(read.unwrap_err().raw_os_error(), Some(libc::EBUSY));
+
+        // Canceled and reaped by the drop: nothing reads the pipe anymore
+        tx.write_all(b"late").unwrap();
+        let mut left = [0u8; 8];
+        assert_eq!(rx.read(&mut left).unwrap(), 4);
+        assert_eq!(buf, [0xaa; 16]);
+    }
+
+    #[test]
+    fn bad_fd_fails_the_read_only() {
+        let reader = UringReader::new(UringConfig::default()).unwrap();
+        let mut buf = [0xaa; 16];
+        let mut session = reader.session();
+        let read_e = opcode::Read::new(types::Fd(-1), buf.as_mut_ptr(), buf.len() as u32).build();
+        session.push(0, read_e).unwrap();
+        session.submit().unwrap();
+        let cqe = session.next().unwrap();
+        assert_eq!(
+            cqe.into_result().unwrap_err().raw_os_error(),
+            Some(libc::EBADF)
+        );
+        assert_eq!(session.in_flight(), 0);
+        drop(session);
+        assert_eq!(buf, [0xaa; 16]);
+    }
+
+    #[test]
+    fn cancel_all_says_by_user() {
+        let reader = UringReader::new(UringConfig::default()).unwrap();
+        let (full, mut tx) = pipe();
+        let (empty, _tx) = pipe();
+        tx.write_all(b"data").unwrap();
+        let mut bufs = [[0u8; 16]; 2];
+
+        let mut session = reader.session();
+        for (slot, (fd, buf)) in [&full, &empty].into_iter().zip(&mut bufs).enumerate() {
+            let read_e = opcode::Read::new(
+                types::Fd(fd.as_raw_fd()),
+                buf.as_mut_ptr(),
+                buf.len() as u32,
+            )
+            .build();
+            session.push(slot as u32, read_e).unwrap();
+        }
+        session.submit().unwrap();
+        session.cancel_all(Cancelled::ByUser);
+
+        let mut results = vec![None, None];
+        while session.in_flight() > 0 {
+            let cqe = session.next().unwrap();
+            results[(cqe.user_data() & u64::from(u32::MAX)) as usize] =
+                Some(cqe.into_result().map(|n| vec![0; n as usize]));
+        }
+        drop(session);
+        let results: Vec<io::Result<Vec<u8>>> = results.into_iter().map(Option::unwrap).collect();
+        let e = results[1].as_ref().unwrap_err();
+        assert!(matches!(
+            ReadError::from_io(e),
+            Some(ReadError::Cancelled {
+                reason: Cancelled::ByUser
+            })
+        ));
+
+        let paths = [Path::new("full"), Path::new("empty")];
+        let counted = crate::files::partial(
+            results,
+            &paths,
+            Instant::now() + Duration::from_secs(60),
+            |r| r,
+        );
+        assert_eq!(
+            (counted.finished, counted.cancelled, counted.failed),
+            (1, 1, 0)
+        );
+        assert_eq!(&bufs[0][..4], b"data");
+    }
+
+    /// Reads on four threads while the reader is drained under them, then closed
+    #[test]
+    fn drain_under_load_then_close() {
+        let config = UringConfig::default()
+            .queue_depth(16)
+            .fixed_buffers(4, 4096);
+        let reader = UringReader::new(config).unwrap();
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        let expected = std::fs::read(path).unwrap();
+        let reads = AtomicU64::new(0);
+
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let (reader, expected, reads) = (&reader, &expected, &reads);
+                s.spawn(move || {
+                    loop {
+                        // read_linked uses the registered file table, close unregisters it
+                        let read = match t % 2 {
+                            0 => reader.read_file_to_vec(path),
+                            _ => reader.read_linked(path, 1 << 20),
+                        };
+                        match read {
+                            Ok(data) => assert_eq!(&data, expected),
+                            Err(_) if reader.is_drained() => break,
+                            Err(e) => panic!("read failed before the drain: {e}"),
+                        }
+                        reads.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+            while reads.load(Ordering::Relaxed) < 100 {
+                std::thread::yield_now();
+            }
+            let drained = reader.drain(Some(Duration::from_secs(5)));
+            assert!(drained.abandoned.is_empty(), "{drained:?}");
+        });
+        assert_eq!(reader.in_flight(), 0);
+
+        let registered_buffers = reader.caps.fixed_buffers > 0;
+        let report = reader.close().unwrap();
+        assert!(report.is_clean(), "{report:?}");
+        assert_eq!(report.buffers_unregistered, usize::from(registered_buffers));
+        assert_eq!(report.files_unregistered, 1);
+    }
+
+    /// A dropped read is still in the kernel when `close` starts, it is canceled and then reaped
+    #[cfg(feature = "async")]
+    #[test]
+    fn close_cancels_an_abandoned_read() {
+        let reader = UringReader::new(UringConfig::default()).unwrap();
+        let (rx, _tx) = pipe();
+        {
+            let mut read = std::pin::pin!(reader.read_owned(&rx, 0, Vec::with_capacity(16)));
+            let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+            assert!(read.as_mut().poll(&mut cx).is_pending());
+        }
+        // The read and the cancel its drop pushed, neither reaped yet
+        assert_eq!(reader.in_flight(), 2);
+
+        let report = reader.close().unwrap();
+        assert_eq!(report.drain.canceled, 1);
+        assert!(report.is_clean(), "{report:?}");
+    }
+
+    /// A reader whose `Capabilities` claim a kernel without some of the features
+    fn reader_with(config: UringConfig, caps: impl FnOnce(&mut Capabilities)) -> UringReader {
+        let mut reader = UringReader::new(config).unwrap();
+        caps(&mut reader.caps);
+        reader
+    }
+
+    #[test]
+    fn bounded_waits_with_and_without_ext_arg() {
+        for (ext_arg, submit_stable) in [(true, true), (false, true), (false, false)] {
+            let config = UringConfig::default().timeout(Some(Duration::from_millis(20)));
+            let reader = reader_with(config, |caps| {
+                caps.ext_arg = ext_arg;
+                caps.submit_stable = submit_stable;
+            });
+            let (rx, _tx) = pipe();
+
+            let e = reader.read_when_ready(&rx, 16, None).unwrap_err();
+            assert_eq!(
+                e.kind(),
+                io::ErrorKind::TimedOut,
+                "{ext_arg} {submit_stable}: {e}"
+            );
+            // The wait timers are freed at submit (SUBMIT_STABLE) or when their CQE shows up, a
+            // last one may still run: wait it out and reap it
+            std::thread::sleep(Duration::from_millis(40));
+            let mut state = lock(&reader.cq);
+            reader.reap(&mut state);
+            assert!(state.timers.is_empty(), "{ext_arg} {submit_stable}");
+            drop(state);
+            assert_eq!(reader.in_flight(), 0);
+        }
+    }
+
+    #[test]
+    fn submit_reaps_right_away_without_nodrop() {
+        for nodrop in [true, false] {
+            let reader = reader_with(UringConfig::default(), |caps| caps.nodrop = nodrop);
+            let mut session = reader.session();
+            session.push(0, opcode::Nop::new().build()).unwrap();
+            session.submit().unwrap();
+
+            // A NOP completes inside the submit, only a reader without NODROP takes it out of the
+            // ring right then
+            let parked = lock(&reader.cq).parked[&session.id].len();
+            assert_eq!(parked, usize::from(!nodrop));
+            assert_eq!(session.next().unwrap().result(), 0);
+        }
+    }
+
+    #[test]
+    fn chains_need_linked_file() {
+        let reader = reader_with(UringConfig::default(), |caps| caps.linked_file = false);
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        let e = reader.read_linked(path, 64).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn ready_reads_without_rw_cur_pos() {
+        for rw_cur_pos in [true, false] {
+            let reader = reader_with(UringConfig::default(), |caps| caps.rw_cur_pos = rw_cur_pos);
+            let (rx, mut tx) = pipe();
+            tx.write_all(b"first").unwrap();
+            assert_eq!(reader.read_when_ready(&rx, 16, None).unwrap(), b"first");
+            tx.write_all(b"second").unwrap();
+            assert_eq!(reader.read_when_ready(&rx, 16, None).unwrap(), b"second");
+        }
+    }
+}
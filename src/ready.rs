@@ -0,0 +1,89 @@
+use io_uring::{opcode, squeue, types};
+
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+use crate::reader::{UringReader, is_retryable};
+
+/// Offset -1 -> read at (and advance) the file position, what `read(2)` does
+const CURRENT_POSITION: u64 = u64::MAX;
+
+impl UringReader {
+    /// Wait until `fd` is readable, then read up to `len` bytes from it (pipes, FIFOs, ttys, devices)
+    ///
+    /// A plain read on an fd without data either parks an io-wq worker until data shows up or fails
+    /// with EAGAIN (O_NONBLOCK). Here a `POLLIN` poll is armed first and the read is linked behind it
+    /// (IOSQE_IO_LINK), both go out in one submit and the read only runs once the poll fired. Reads at
+    /// the file position, like `read(2)`.
+    ///
+    /// Ok(data) -> whatever was available, empty at EOF (POLLHUP without data: the writer went away)
+    /// Err(e) -> EBADF for POLLNVAL, the read's error (EIO if it had none) for POLLERR,
+    /// `TimedOut` if nothing arrived within `timeout` (`None` -> `UringConfig::timeout`)
+    #[allow(unused_doc_comments)]
+    pub fn read_when_ready(
+        &self,
+        fd: &impl AsRawFd,
+        len: usize,
+        timeout: Option<Duration>,
+    ) -> io::Result<Vec<u8>> {
+        let fd = types::Fd(fd.as_raw_fd());
+        let len = len.min(u32::MAX as usize);
+        /// Declared before the session, see `Session`
+        let mut buf = vec![0u8; len];
+        let mut session = self.session();
+        if let Some(timeout) = timeout {
+            session.set_deadline(Some(Instant::now() + timeout));
+        }
+
+        /// Before RW_CUR_POS (5.6) -1 is no valid offset, 0 is what non-seekable files ignore
+        let offset = if self.caps.rw_cur_pos {
+            CURRENT_POSITION
+        } else {
+            0
+        };
+
+        loop {
+            let poll_e = opcode::PollAdd::new(fd, libc::POLLIN as u32)
+                .build()
+                .flags(squeue::Flags::IO_LINK);
+            let read_e = opcode::Read::new(fd, buf.as_mut_ptr(), len as u32)
+                .offset(offset)
+                .build();
+            session.push(0, poll_e)?;
+            session.push(1, read_e)?;
+            session.submit()?;
+
+            /// slot 0 -> poll, slot 1 -> read, in whichever order they show up
+            let (mut mask, mut read) = (None, None);
+            while session.in_flight() > 0 {
+                let cqe = session.next()?;
+                if cqe.user_data() & u64::from(u32::MAX) == 0 {
+                    mask = Some(cqe.into_result());
+                } else {
+                    read = Some(cqe.into_result());
+                }
+            }
+            let mask = mask.expect("the poll completed")?;
+            let read = read.expect("the read completed");
+
+            let mask = mask as i16;
+            if mask & libc::POLLNVAL != 0 {
+                return Err(io::Error::from_raw_os_error(libc::EBADF));
+            }
+            match read {
+                Ok(n) => {
+                    if n == 0 && mask & libc::POLLERR != 0 {
+                        return Err(io::Error::from_raw_os_error(libc::EIO));
+                    }
+                    buf.truncate(n as usize);
+                    return Ok(buf);
+                }
+                /// Readiness can go away again before the read (another reader took the data), or
+                /// the poll was spurious. Arm it again.
+                Err(e) if is_retryable(&e) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
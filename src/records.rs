@@ -0,0 +1,340 @@
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+
+use crate::error::ReadError;
+use crate::{UringFile, UringReader};
+
+/// What a `RecordReader` does with a last record the file ends inside of
+/// (`RecordReader::trailing`)
+/// - Error -> one `ReadError::PartialRecord`, then the end. The default.
+/// - Skip -> the bytes are dropped, the records end with the last whole one
+/// - Yield -> handed out as it is, shorter than `record_size`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrailingRecord {
+    #[default]
+    Error,
+    Skip,
+    Yield,
+}
+
+/// Fixed size records of a file, read through the ring
+///
+/// ```no_run
+/// use uring_fast_read::RecordReader;
+///
+/// let mut records = RecordReader::open("/data/ticks.bin", 32).unwrap();
+/// records.seek_to_record(1_000).unwrap();
+/// while let Some(record) = records.next_record() {
+///     let record = record.unwrap();
+///     println!("{:?}", &record[..8]);
+/// }
+/// ```
+///
+/// The file is read in `UringConfig::chunk_size` chunks with one read ahead (`UringFile`), records are
+/// sliced out of them. A record that lies in one chunk comes straight out of the chunk, only the ones
+/// that straddle two chunks (or are longer than a chunk) are assembled in a buffer of their own.
+///
+/// Iterating yields owned `Vec<u8>`s. `next_record` hands out the bytes borrowed from the reader
+/// instead, without allocating per record.
+pub struct RecordReader<'r> {
+    file: UringFile<'r>,
+    path: PathBuf,
+    record_size: usize,
+    /// a record that straddles chunk boundaries, assembled here
+    record: Vec<u8>,
+    /// bytes of the chunk handed out by the last `next_record`, consumed on the next call
+    borrowed: usize,
+    /// index of the record `next_record` returns
+    next: u64,
+    trailing: TrailingRecord,
+    done: bool,
+}
+
+impl RecordReader<'static> {
+    /// Open `path` on the process wide default reader (`UringConfig::default()`, created on first use)
+    pub fn open(path: impl AsRef<Path>, record_size: usize) -> io::Result<Self> {
+        UringReader::shared_default()?.read_records(path, record_size)
+    }
+}
+
+impl UringReader {
+    /// Read `path` as records of `record_size` bytes, see `RecordReader`
+    pub fn read_records(
+        &self,
+        path: impl AsRef<Path>,
+        record_size: usize,
+    ) -> io::Result<RecordReader<'_>> {
+        if record_size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "record_size must not be 0",
+            ));
+        }
+        let path = path.as_ref();
+        Ok(RecordReader {
+            file: self.open_file(path)?,
+            path: path.to_path_buf(),
+            record_size,
+            record: Vec::new(),
+            borrowed: 0,
+            next: 0,
+            trailing: TrailingRecord::Error,
+            done: false,
+        })
+    }
+}
+
+impl<'r> RecordReader<'r> {
+    /// What a record the file ends inside of turns into, defaults to `TrailingRecord::Error`
+    pub fn trailing(mut self, trailing: TrailingRecord) -> Self {
+        self.trailing = trailing;
+        self
+    }
+
+    /// Bytes per record
+    pub fn record_size(&self) -> usize {
+        self.record_size
+    }
+
+    /// Index of the record the next `next_record` returns
+    pub fn position(&self) -> u64 {
+        self.next
+    }
+
+    /// Continue at record `n`, the records before it are not read
+    ///
+    /// Past the end is fine, reading then returns None. A jump inside the current chunk costs nothing,
+    /// anywhere else the next chunk is read at the new offset.
+    pub fn seek_to_record(&mut self, n: u64) -> io::Result<()> {
+        let offset = n.checked_mul(self.record_size as u64).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "record offset overflows u64")
+        })?;
+        self.borrowed = 0;
+        self.file.seek_to(offset)?;
+        self.next = n;
+        self.done = false;
+        Ok(())
+    }
+
+    /// The next record, borrowed until the next call
+    ///
+    /// None -> end of file
+    /// Some(Err(e)) -> a read error, or `ReadError::PartialRecord` for a cut off last record
+    ///
+    /// A read error ends the records like the end of file does, `seek_to_record` starts over.
+    pub fn next_record(&mut self) -> Option<io::Result<&[u8]>> {
+        self.file.consume(std::mem::take(&mut self.borrowed));
+        if self.done {
+            return None;
+        }
+        let record_size = self.record_size;
+
+        let available = match self.file.fill_buf() {
+            Ok(chunk) => chunk.len(),
+            Err(e) => return Some(Err(self.failed(e))),
+        };
+        if available >= record_size {
+            self.borrowed = record_size;
+            self.next += 1;
+            return Some(self.file.fill_buf().map(|chunk| &chunk[..record_size]));
+        }
+
+        self.record.clear();
+        while self.record.len() < record_size {
+            let chunk = match self.file.fill_buf() {
+                Ok(chunk) => chunk,
+                Err(e) => return Some(Err(self.failed(e))),
+            };
+            if chunk.is_empty() {
+                break;
+            }
+            let used = chunk.len().min(record_size - self.record.len());
+            self.record.extend_from_slice(&chunk[..used]);
+            self.file.consume(used);
+        }
+
+        if self.record.is_empty() {
+            self.done = true;
+            return None;
+        }
+        if self.record.len() < record_size {
+            self.done = true;
+            match self.trailing {
+                TrailingRecord::Error => {
+                    return Some(Err(ReadError::PartialRecord {
+                        path: self.path.clone(),
+                        record: self.next,
+                        len: self.record.len(),
+                        record_size,
+                    }
+                    .into()));
+                }
+                TrailingRecord::Skip => return None,
+                TrailingRecord::Yield => {}
+            }
+        }
+        self.next += 1;
+        Some(Ok(&self.record))
+    }
+
+    /// `e` of a failed read, after which nothing is read anymore: the bytes of the record that were
+    /// consumed already are gone, the records after it would be off
+    fn failed(&mut self, e: io::Error) -> io::Error {
+        self.done = true;
+        e
+    }
+}
+
+impl Iterator for RecordReader<'_> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_record()?.map(<[u8]>::to_vec))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UringConfig;
+
+    /// Bytes that differ from position to position
+    fn contents(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// `contents` in a file of its own, with a reader of `chunk` byte chunks
+    fn setup(test: &str, len: usize, chunk: usize) -> (PathBuf, Vec<u8>, UringReader) {
+        let name = format!("uring_fast_read-{}-records-{test}", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        let data = contents(len);
+        std::fs::write(&path, &data).unwrap();
+        let reader = UringReader::new(UringConfig::default().chunk_size(chunk)).unwrap();
+        (path, data, reader)
+    }
+
+    fn all(records: RecordReader<'_>) -> Vec<Vec<u8>> {
+        records.map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn records_across_chunk_boundaries() {
+        // 24 byte records in 64 byte chunks: every third record straddles two chunks
+        let (path, data, reader) = setup("straddle", 24 * 40, 64);
+        let records = all(reader.read_records(&path, 24).unwrap());
+        let expected: Vec<Vec<u8>> = data.chunks(24).map(<[u8]>::to_vec).collect();
+        assert_eq!(records, expected);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn records_longer_than_a_chunk() {
+        let (path, data, reader) = setup("long", 100 * 10, 64);
+        let records = all(reader.read_records(&path, 100).unwrap());
+        let expected: Vec<Vec<u8>> = data.chunks(100).map(<[u8]>::to_vec).collect();
+        assert_eq!(records, expected);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn seek_inside_and_outside_the_chunk_and_past_the_end() {
+        let (path, data, reader) = setup("seek", 24 * 1000, 64);
+        let mut records = reader.read_records(&path, 24).unwrap();
+        let record = |n: usize| data[n * 24..(n + 1) * 24].to_vec();
+
+        assert_eq!(records.next_record().unwrap().unwrap(), record(0));
+        // Record 2 starts at 48, still in the first chunk
+        records.seek_to_record(2).unwrap();
+        assert_eq!(records.position(), 2);
+        assert_eq!(records.next_record().unwrap().unwrap(), record(2));
+        // Back to the start of the same chunk
+        records.seek_to_record(0).unwrap();
+        assert_eq!(records.next_record().unwrap().unwrap(), record(0));
+        // 500 * 24 = 12000, not on a block boundary
+        records.seek_to_record(500).unwrap();
+        assert_eq!(records.next_record().unwrap().unwrap(), record(500));
+        assert_eq!(records.next_record().unwrap().unwrap(), record(501));
+        // Backwards out of the chunk
+        records.seek_to_record(3).unwrap();
+        assert_eq!(records.next_record().unwrap().unwrap(), record(3));
+
+        records.seek_to_record(1000).unwrap();
+        assert!(records.next_record().is_none());
+        records.seek_to_record(5000).unwrap();
+        assert!(records.next_record().is_none());
+        // And back from past the end
+        records.seek_to_record(999).unwrap();
+        assert_eq!(records.next_record().unwrap().unwrap(), record(999));
+        assert!(records.next_record().is_none());
+
+        assert!(records.seek_to_record(u64::MAX).is_err());
+        drop(records);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn each_trailing_mode() {
+        // 10 whole records and 7 bytes of an 11th, cut off across a chunk boundary
+        let (path, data, reader) = setup("trailing", 24 * 10 + 7, 64);
+        let whole: Vec<Vec<u8>> = data.chunks_exact(24).map(<[u8]>::to_vec).collect();
+
+        let mut records = reader.read_records(&path, 24).unwrap();
+        for expected in &whole {
+            assert_eq!(records.next_record().unwrap().unwrap(), expected.as_slice());
+        }
+        let e = records.next_record().unwrap().unwrap_err();
+        match ReadError::from_io(&e) {
+            Some(ReadError::PartialRecord {
+                record,
+                len,
+                record_size,
+                ..
+            }) => assert_eq!((*record, *len, *record_size), (10, 7, 24)),
+            other => panic!("expected PartialRecord, got {other:?} ({e})"),
+        }
+        assert!(records.next_record().is_none());
+        drop(records);
+
+        let records = reader.read_records(&path, 24).unwrap();
+        assert_eq!(all(records.trailing(TrailingRecord::Skip)), whole);
+
+        let records = reader.read_records(&path, 24).unwrap();
+        let mut expected = whole.clone();
+        expected.push(data[240..].to_vec());
+        assert_eq!(all(records.trailing(TrailingRecord::Yield)), expected);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn failed_read_inside_a_record_ends_the_records() {
+        use std::io::Write;
+        use std::time::Duration;
+
+        // A pipe with 40 bytes in it and a writer that never writes again: record 1 gets 16 of its
+        // bytes, then the read of the rest times out
+        let (rx, mut tx) = crate::guard::tests::pipe();
+        tx.write_all(&contents(40)).unwrap();
+        let config = UringConfig::default()
+            .chunk_size(64)
+            .timeout(Some(Duration::from_millis(50)));
+        let reader = UringReader::new(config).unwrap();
+        let mut records = RecordReader {
+            file: reader.uring_file(rx).unwrap(),
+            path: PathBuf::from("pipe"),
+            record_size: 24,
+            record: Vec::new(),
+            borrowed: 0,
+            next: 0,
+            trailing: TrailingRecord::Error,
+            done: false,
+        };
+
+        assert_eq!(records.next_record().unwrap().unwrap(), &contents(24)[..]);
+        let e = records.next_record().unwrap().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        assert!(records.next_record().is_none());
+        assert_eq!(records.position(), 1);
+    }
+}
@@ -0,0 +1,363 @@
+use io_uring::{opcode, types};
+
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use crate::error::Cancelled;
+use crate::reader::{Session, UringReader, lock};
+
+/// slot 0 -> the recv, slot 1 -> buffers handed (back) to the kernel, slot 2 -> their removal
+const RECV: u32 = 0;
+const PROVIDE: u32 = 1;
+const REMOVE: u32 = 2;
+
+/// What one `recv` got
+/// - data -> the bytes, empty if the peer closed its side
+/// - closed -> the peer shut down (an orderly 0 byte recv), nothing more will come
+/// - truncated -> a datagram (UDP, SOCK_SEQPACKET) was longer than `len`, the rest of it is gone
+///   (MSG_TRUNC). Never set for stream sockets, what didn't fit is still in the socket there.
+/// - pending -> the socket had more data right away (IORING_CQE_F_SOCK_NONEMPTY, Linux 5.19)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Received {
+    pub data: Vec<u8>,
+    pub closed: bool,
+    pub truncated: bool,
+    pub pending: bool,
+}
+
+/// The provided buffers of a `recv_multishot`: `count` buffers of `size` bytes, the kernel picks one
+/// per completion (default 16 of 64 KiB)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferGroup {
+    pub(crate) count: u16,
+    pub(crate) size: usize,
+}
+
+impl Default for BufferGroup {
+    fn default() -> Self {
+        BufferGroup {
+            count: 16,
+            size: 64 * 1024,
+        }
+    }
+}
+
+impl BufferGroup {
+    /// `count` buffers (at least 1) of `size` bytes (1 to `i32::MAX`)
+    pub fn new(count: u16, size: usize) -> Self {
+        BufferGroup {
+            count: count.max(1),
+            size: size.clamp(1, i32::MAX as usize),
+        }
+    }
+}
+
+/// The provided buffer group ids (bgid) of one reader
+///
+/// An id only comes back once the kernel confirmed its buffers removed, a group that still had
+/// buffers in it would hand the old (freed) ones out to the next user of the id.
+#[derive(Default)]
+pub(crate) struct GroupIds {
+    next: u32,
+    free: Vec<u16>,
+}
+
+impl GroupIds {
+    fn take(&mut self) -> Option<u16> {
+        self.free.pop().or_else(|| {
+            let id = u16::try_from(self.next).ok()?;
+            self.next += 1;
+            Some(id)
+        })
+    }
+}
+
+/// Whether `fd` is a socket with message boundaries, where MSG_TRUNC reports the full length of a
+/// datagram (on a stream socket it would discard the data instead)
+fn has_boundaries(fd: RawFd) -> io::Result<bool> {
+    let mut kind: libc::c_int = 0;
+    let mut len = size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: `kind` and `len` are valid for the duration of the call, `len` says how big `kind` is
+    let res = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            (&raw mut kind).cast(),
+            &mut len,
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(kind != libc::SOCK_STREAM)
+}
+
+impl UringReader {
+    /// Receive up to `len` bytes from the connected socket `fd` (TCP, unix, UDP), one `IORING_OP_RECV`
+    ///
+    /// Only reads, the socket is the caller's: no listen, no connect, nothing is closed. The kernel
+    /// waits for data with an internal poll, no io-wq worker is parked on it. Current kernels do that
+    /// for `O_NONBLOCK` sockets too, the ones that keep the NOWAIT semantics complete with EAGAIN.
+    ///
+    /// Ok(received) -> see `Received`, `closed` once the peer shut down
+    /// Err(e) -> `WouldBlock` for that EAGAIN (not retried, the caller asked not to wait), ENOTSOCK if
+    /// `fd` is no socket, `Cancelled::ByTimeout` past `UringConfig::timeout`
+    ///
+    /// Datagram sockets are asked with MSG_TRUNC, which costs one `getsockopt` per call to tell them
+    /// apart from stream sockets.
+    #[allow(unused_doc_comments)]
+    pub fn recv(&self, fd: &impl AsRawFd, len: usize) -> io::Result<Received> {
+        let raw = fd.as_raw_fd();
+        let flags = if has_boundaries(raw)? {
+            libc::MSG_TRUNC
+        } else {
+            0
+        };
+        let len = len.min(u32::MAX as usize);
+        /// Declared before the session, see `Session`
+        let mut buf = vec![0u8; len];
+        let mut session = self.session();
+        loop {
+            let recv_e = opcode::Recv::new(types::Fd(raw), buf.as_mut_ptr(), len as u32)
+                .flags(flags)
+                .build();
+            session.push(RECV, recv_e)?;
+            session.submit()?;
+            let cqe = session.next()?;
+            let n = match cqe.into_result() {
+                Ok(n) => n as usize,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if n > 0 {
+                let mut stats = lock(&self.stats);
+                stats.socket_recvs += 1;
+                stats.socket_bytes += n.min(len) as u64;
+            }
+            buf.truncate(n.min(len));
+            return Ok(Received {
+                data: buf,
+                closed: n == 0 && len > 0,
+                truncated: n > len,
+                pending: cqe.is_sock_nonempty(),
+            });
+        }
+    }
+
+    /// Keep receiving from the socket `fd` into the provided buffers of `group`, see `MultishotRecv`
+    ///
+    /// One `IORING_OP_RECV` with IORING_RECV_MULTISHOT (Linux 6.0) stays armed and posts a completion
+    /// per arrival. Older kernels get a plain recv from the same buffers, armed again after each one.
+    pub fn recv_multishot(
+        &self,
+        fd: &impl AsRawFd,
+        group: BufferGroup,
+    ) -> io::Result<MultishotRecv<'_>> {
+        let bgid = lock(&self.buffer_groups).take().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                "all 65536 buffer groups are in use",
+            )
+        })?;
+        let mut recv = MultishotRecv {
+            session: self.session(),
+            bufs: vec![0u8; usize::from(group.count) * group.size],
+            fd: types::Fd(fd.as_raw_fd()),
+            group,
+            bgid,
+            multishot: true,
+            armed: false,
+            lent: None,
+            done: false,
+        };
+        let provide_e = opcode::ProvideBuffers::new(
+            recv.bufs.as_mut_ptr(),
+            group.size as i32,
+            group.count,
+            bgid,
+            0,
+        )
+        .build();
+        recv.session.push(PROVIDE, provide_e)?;
+        recv.session.submit()?;
+        recv.session.next()?.into_result()?;
+        Ok(recv)
+    }
+}
+
+/// Data from one socket as it arrives, in provided buffers the kernel fills (`recv_multishot`)
+///
+/// ```no_run
+/// use std::net::TcpStream;
+/// use uring_fast_read::{BufferGroup, UringConfig, UringReader};
+///
+/// let reader = UringReader::new(UringConfig::default()).unwrap();
+/// let stream = TcpStream::connect("127.0.0.1:8080").unwrap();
+/// let mut recv = reader.recv_multishot(&stream, BufferGroup::new(32, 16 * 1024)).unwrap();
+/// while let Some(chunk) = recv.next_recv() {
+///     println!("{} bytes", chunk.unwrap().len());
+/// }
+/// ```
+///
+/// `next_recv` hands out the buffer the kernel picked, borrowed until the next call, which gives it
+/// back to the kernel. Iterating copies each one into a `Vec<u8>` instead. When the kernel runs out of
+/// buffers (ENOBUFS, the caller fell behind by `count` arrivals) the recv is armed again, nothing
+/// is lost: the data waits in the socket.
+///
+/// A datagram longer than a buffer is cut to it, use `recv` where the length of a datagram matters.
+/// Dropping it cancels the recv and removes the buffers from the kernel.
+pub struct MultishotRecv<'r> {
+    session: Session<'r>,
+    /// `count` buffers of `size` bytes, buffer id `bid` at `bid * size`. Only touched through raw
+    /// pointers (`as_mut_ptr` makes no reference to all of it), the session drains before it's freed.
+    bufs: Vec<u8>,
+    fd: types::Fd,
+    group: BufferGroup,
+    bgid: u16,
+    /// False once the kernel refused IORING_RECV_MULTISHOT, each recv is armed on its own then
+    multishot: bool,
+    armed: bool,
+    /// Buffer handed out by the last `next_recv`, given back to the kernel on the next call
+    lent: Option<u16>,
+    done: bool,
+}
+
+impl MultishotRecv<'_> {
+    /// The next arrival, borrowed until the next call
+    ///
+    /// None -> the peer closed its side (or an earlier call failed)
+    /// Some(Err(e)) -> the recv failed. `WouldBlock` (EAGAIN of an `O_NONBLOCK` socket, see `recv`)
+    /// can be tried again, every other error is the last item.
+    pub fn next_recv(&mut self) -> Option<io::Result<&[u8]>> {
+        if let Some(bid) = self.lent.take()
+            && let Err(e) = self.give_back(bid)
+        {
+            return self.fail(e);
+        }
+        loop {
+            if self.done {
+                return None;
+            }
+            if !self.armed
+                && let Err(e) = self.arm()
+            {
+                return self.fail(e);
+            }
+            self.session.restart_deadline();
+            let cqe = match self.session.next() {
+                Ok(cqe) => cqe,
+                Err(e) => return self.fail(e),
+            };
+            if cqe.user_data() & u64::from(u32::MAX) == u64::from(PROVIDE) {
+                if let Err(e) = cqe.into_result() {
+                    return self.fail(e);
+                }
+                continue;
+            }
+            if !cqe.is_more() {
+                self.armed = false;
+            }
+            match cqe.result() {
+                res if res > 0 => {
+                    let bid = cqe.buffer_id().expect("a recv with data picked a buffer");
+                    let len = (res as usize).min(self.group.size);
+                    let mut stats = lock(&self.session.reader().stats);
+                    stats.socket_recvs += 1;
+                    stats.socket_bytes += len as u64;
+                    drop(stats);
+                    self.lent = Some(bid);
+                    // SAFETY: the kernel filled buffer `bid` and is done with it until `give_back`,
+                    // which only happens on the next call, after this borrow ended
+                    return Some(Ok(unsafe {
+                        std::slice::from_raw_parts(
+                            self.bufs.as_ptr().add(usize::from(bid) * self.group.size),
+                            len,
+                        )
+                    }));
+                }
+                0 => {
+                    self.done = true;
+                    return None;
+                }
+                // Every buffer is filled and handed out or given back by now, arm again
+                res if res == -libc::ENOBUFS => {}
+                res if res == -libc::EINVAL && self.multishot => self.multishot = false,
+                res if res == -libc::EINTR => {}
+                _ => {
+                    let e = cqe.into_result().expect_err("a negative result");
+                    if e.kind() == io::ErrorKind::WouldBlock {
+                        return Some(Err(e));
+                    }
+                    return self.fail(e);
+                }
+            }
+        }
+    }
+
+    fn fail<T>(&mut self, e: io::Error) -> Option<io::Result<T>> {
+        self.done = true;
+        Some(Err(e))
+    }
+
+    fn arm(&mut self) -> io::Result<()> {
+        let recv_e = if self.multishot {
+            opcode::RecvMulti::new(self.fd, self.bgid).build()
+        } else {
+            opcode::Recv::new(self.fd, std::ptr::null_mut(), self.group.size as u32)
+                .buf_group(self.bgid)
+                .build()
+                .flags(io_uring::squeue::Flags::BUFFER_SELECT)
+        };
+        self.session.push(RECV, recv_e)?;
+        self.session.submit()?;
+        self.armed = true;
+        Ok(())
+    }
+
+    /// Hand buffer `bid` back to the kernel, its completion is picked up by `next_recv`
+    fn give_back(&mut self, bid: u16) -> io::Result<()> {
+        // SAFETY: `bid` is one of the `count` buffers of `bufs`
+        let at = unsafe {
+            self.bufs
+                .as_mut_ptr()
+                .add(usize::from(bid) * self.group.size)
+        };
+        let provide_e =
+            opcode::ProvideBuffers::new(at, self.group.size as i32, 1, self.bgid, bid).build();
+        self.session.push(PROVIDE, provide_e)?;
+        self.session.submit()
+    }
+}
+
+impl Iterator for MultishotRecv<'_> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_recv()?.map(<[u8]>::to_vec))
+    }
+}
+
+impl Drop for MultishotRecv<'_> {
+    fn drop(&mut self) {
+        if self.session.in_flight() > 0 {
+            self.session.cancel_all(Cancelled::ByUser);
+        }
+        while self.session.in_flight() > 0 {
+            // The session's own drop keeps at it, `bgid` stays taken
+            if self.session.next().is_err() {
+                return;
+            }
+        }
+        // Whatever the kernel still holds of the group goes, ENOENT if that was nothing
+        let remove_e = opcode::RemoveBuffers::new(self.group.count, self.bgid).build();
+        let removed = self.session.push(REMOVE, remove_e).is_ok()
+            && self.session.submit().is_ok()
+            && self.session.next().is_ok();
+        if removed {
+            lock(&self.session.reader().buffer_groups)
+                .free
+                .push(self.bgid);
+        }
+    }
+}
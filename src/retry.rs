@@ -0,0 +1,90 @@
+#[cfg(target_os = "linux")]
+use std::io;
+use std::time::Duration;
+
+/// What to do when a read fails with something that may go away on its own (`UringConfig::retry`)
+///
+/// - max_attempts -> attempts per request in total, the first one included (1 never retries)
+/// - backoff -> wait before the first retry, doubled for every retry after it
+/// - retryable -> which errnos are worth another attempt (default `is_transient`: EIO, ESTALE)
+///
+/// EINTR/EAGAIN are always retried right away and don't count. The wait is an io_uring timeout on the
+/// same ring: the other requests of the call keep flowing while one of them sits out its backoff.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use uring_fast_read::{RetryPolicy, UringConfig};
+///
+/// let config = UringConfig::default().retry(Some(RetryPolicy {
+///     max_attempts: 5,
+///     backoff: Duration::from_millis(100),
+///     retryable: |errno| errno == libc::EIO,
+/// }));
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    #[cfg_attr(feature = "serde", serde(with = "crate::stats::nanos"))]
+    pub backoff: Duration,
+    /// Not serialized (it's code), a deserialized policy gets `is_transient`
+    #[cfg_attr(feature = "serde", serde(skip, default = "transient"))]
+    pub retryable: fn(i32) -> bool,
+}
+
+#[cfg(feature = "serde")]
+fn transient() -> fn(i32) -> bool {
+    is_transient
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff: Duration::from_millis(10),
+            retryable: is_transient,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Same as `RetryPolicy::default()`: 3 attempts, 10 ms backoff, EIO and ESTALE
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How long to wait before trying again after `e`, None if it is not retried (anymore)
+    ///
+    /// `attempts` -> attempts made so far
+    #[cfg(target_os = "linux")]
+    pub(crate) fn next_delay(&self, e: &io::Error, attempts: u32) -> Option<Duration> {
+        let errno = e.raw_os_error()?;
+        if attempts >= self.max_attempts || !(self.retryable)(errno) {
+            return None;
+        }
+        let doublings = attempts.saturating_sub(1).min(16);
+        Some(self.backoff.saturating_mul(1 << doublings))
+    }
+}
+
+/// The errnos flaky (network) filesystems report now and then: EIO and ESTALE
+pub fn is_transient(errno: i32) -> bool {
+    #[cfg(target_os = "linux")]
+    return errno == libc::EIO || errno == libc::ESTALE;
+    #[cfg(not(target_os = "linux"))]
+    return errno == 5;
+}
+
+/// `e` as the final error of a request that was tried `attempts` times
+#[cfg(target_os = "linux")]
+pub(crate) fn exhausted(e: io::Error, attempts: u32) -> io::Error {
+    if attempts <= 1 {
+        return e;
+    }
+    crate::ReadError::RetriesExhausted {
+        attempts,
+        source: e,
+    }
+    .into()
+}
@@ -0,0 +1,133 @@
+use io_uring::{opcode, types};
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use crate::backend::{ReadBackend, Reaped};
+use crate::completion::Completion;
+use crate::owned::{finish_spare, read_spare};
+use crate::reader::{Session, UringReader};
+
+/// `ReadBackend` on a `UringReader`, returned by `UringReader::backend`
+///
+/// Every path is opened once (blocking `open`) and kept open until the backend is dropped, reads and
+/// statx (`AT_EMPTY_PATH` on that fd) go through the ring. Queued requests are submitted by `wait`.
+pub struct UringBackend<'r> {
+    /// Declared first so it is dropped (waits for every request) before the files and buffers
+    session: Session<'r>,
+    files: HashMap<PathBuf, File>,
+    ops: Vec<Option<Op>>,
+    done: VecDeque<Completion>,
+}
+
+/// What one request in flight holds on to until its CQE is reaped
+enum Op {
+    Read {
+        tag: u64,
+        buf: Vec<u8>,
+    },
+    Statx {
+        tag: u64,
+        stx: Box<MaybeUninit<libc::statx>>,
+    },
+}
+
+impl UringReader {
+    /// A `ReadBackend` on this reader, for `BackendReader`
+    pub fn backend(&self) -> UringBackend<'_> {
+        UringBackend {
+            session: self.session(),
+            files: HashMap::new(),
+            ops: Vec::new(),
+            done: VecDeque::new(),
+        }
+    }
+}
+
+impl UringBackend<'_> {
+    fn file(&mut self, path: &Path) -> io::Result<&File> {
+        if !self.files.contains_key(path) {
+            self.files.insert(path.to_path_buf(), File::open(path)?);
+        }
+        Ok(&self.files[path])
+    }
+
+    /// Push `entry` for `op` in a free slot
+    fn push(&mut self, entry: io_uring::squeue::Entry, op: Op) -> io::Result<()> {
+        let slot = match self.ops.iter().position(Option::is_none) {
+            Some(slot) => slot,
+            None => {
+                self.ops.push(None);
+                self.ops.len() - 1
+            }
+        };
+        self.session.push(slot as u32, entry)?;
+        self.ops[slot] = Some(op);
+        Ok(())
+    }
+}
+
+impl ReadBackend for UringBackend<'_> {
+    fn submit_read(&mut self, tag: u64, path: &Path, offset: u64, len: usize) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(len);
+        let read_e = read_spare(self.file(path)?, offset, &mut buf);
+        self.push(read_e, Op::Read { tag, buf })
+    }
+
+    #[allow(unused_doc_comments)]
+    fn submit_statx(&mut self, tag: u64, path: &Path) -> io::Result<()> {
+        let fd = types::Fd(self.file(path)?.as_raw_fd());
+        /// Boxed, the kernel writes into it after `Op` moved into `ops`
+        let mut stx = Box::new(MaybeUninit::<libc::statx>::zeroed());
+        let statx_e = opcode::Statx::new(fd, c"".as_ptr(), stx.as_mut_ptr().cast())
+            .flags(libc::AT_EMPTY_PATH)
+            .mask(libc::STATX_BASIC_STATS)
+            .build();
+        self.push(statx_e, Op::Statx { tag, stx })
+    }
+
+    fn wait(&mut self) -> io::Result<()> {
+        self.session.submit()?;
+        if self.done.is_empty() {
+            let cqe = self.session.next()?;
+            self.done.push_back(cqe);
+        }
+        Ok(())
+    }
+
+    #[allow(unused_doc_comments)]
+    fn reap(&mut self) -> Vec<(u64, Reaped)> {
+        while let Some(cqe) = self.session.try_next() {
+            self.done.push_back(cqe);
+        }
+        let mut reaped = Vec::with_capacity(self.done.len());
+        for cqe in self.done.drain(..) {
+            let slot = (cqe.user_data() & u64::from(u32::MAX)) as usize;
+            let Some(op) = self.ops.get_mut(slot).and_then(Option::take) else {
+                continue;
+            };
+            reaped.push(match op {
+                Op::Read { tag, buf } => {
+                    /// SAFETY: this slot is the `read_spare` into `buf`
+                    let result = unsafe { finish_spare(buf, cqe.into_result()) };
+                    (
+                        tag,
+                        Reaped::Read(result.map(|c| c.buf).map_err(|f| f.error)),
+                    )
+                }
+                Op::Statx { tag, stx } => {
+                    /// SAFETY: zeroed is a valid statx, and the kernel filled it in on success
+                    let size = cqe
+                        .into_result()
+                        .map(|_| unsafe { stx.assume_init() }.stx_size);
+                    (tag, Reaped::Statx(size))
+                }
+            });
+        }
+        reaped
+    }
+}
@@ -0,0 +1,110 @@
+/// OpenAt2 -> `openat2(2)` as an SQE, the only open that takes RESOLVE_* flags
+use io_uring::{opcode, types};
+
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+
+use crate::config::UringConfig;
+use crate::error::ReadError;
+use crate::reader::UringReader;
+
+/// A reader that can only read files below one root directory
+///
+/// Paths given to `read` are resolved by the kernel relative to a directory fd of the root, with
+/// `RESOLVE_BENEATH | RESOLVE_NO_MAGICLINKS`:
+/// - `..` that climbs above the root, absolute paths and symlinks pointing outside are refused
+/// - `/proc/self/fd/N` style magic links are refused
+/// - the root is pinned by its fd, renaming it while reading does not change what is reachable
+///
+/// There is no fallback: on kernels without `openat2` (or without it as an io_uring opcode) the
+/// constructor fails, silently resolving paths the unsafe way would defeat the point.
+pub struct SandboxedReader {
+    reader: UringReader,
+    root: PathBuf,
+    dir: File,
+}
+
+impl SandboxedReader {
+    /// Sandbox rooted at `root`, with a default `UringConfig`
+    pub fn new(root: &Path) -> io::Result<Self> {
+        Self::with_config(root, UringConfig::default())
+    }
+
+    /// Sandbox rooted at `root`, reading through a ring created from `config`
+    pub fn with_config(root: &Path, config: UringConfig) -> io::Result<Self> {
+        let reader = UringReader::new(config)?;
+        if !reader.is_supported(opcode::OpenAt2::CODE) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "this kernel has no io_uring openat2, refusing to resolve sandboxed paths without RESOLVE_BENEATH",
+            ));
+        }
+
+        let dir = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY | libc::O_CLOEXEC)
+            .open(root)?;
+
+        Ok(SandboxedReader {
+            reader,
+            root: root.to_path_buf(),
+            dir,
+        })
+    }
+
+    /// The root directory (as it was named when the sandbox was created)
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The reader used for the actual reads
+    pub fn reader(&self) -> &UringReader {
+        &self.reader
+    }
+
+    /// Read a whole file, `rel_path` is relative to the root
+    ///
+    /// Ok(data) -> the file contents
+    /// Err(e) -> `ReadError::PathEscapesSandbox` if the path leaves the root, otherwise what
+    /// `UringReader::read_file_to_vec` would report
+    pub fn read(&self, rel_path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        let rel_path = rel_path.as_ref();
+        let file = self.open(rel_path)?;
+        self.reader.read_open_file(file, &self.root.join(rel_path))
+    }
+
+    /// Open `rel_path` below the root with `IORING_OP_OPENAT2`
+    #[allow(unused_doc_comments)]
+    fn open(&self, rel_path: &Path) -> io::Result<File> {
+        /// The path and the open_how are read by the kernel, they must outlive the session
+        let c_path = CString::new(rel_path.as_os_str().as_bytes())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))?;
+        let how = types::OpenHow::new()
+            .flags((libc::O_RDONLY | libc::O_CLOEXEC) as u64)
+            .resolve(libc::RESOLVE_BENEATH | libc::RESOLVE_NO_MAGICLINKS);
+
+        let mut session = self.reader.session();
+        let open_e =
+            opcode::OpenAt2::new(types::Fd(self.dir.as_raw_fd()), c_path.as_ptr(), &how).build();
+        session.push(0, open_e)?;
+        session.submit()?;
+
+        match session.next()?.into_result() {
+            /// SAFETY: the kernel just handed us this fd, nobody else owns it
+            Ok(fd) => Ok(unsafe { File::from_raw_fd(fd as i32) }),
+            Err(e) if matches!(e.raw_os_error(), Some(libc::EXDEV) | Some(libc::ELOOP)) => {
+                Err(ReadError::PathEscapesSandbox {
+                    root: self.root.clone(),
+                    path: rel_path.to_path_buf(),
+                }
+                .into())
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
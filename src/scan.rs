@@ -0,0 +1,184 @@
+use io_uring::{opcode, types};
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::ops::ControlFlow;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use crate::config::PadPolicy;
+use crate::error::Cancelled;
+use crate::reader::{Session, UringReader, is_retryable};
+use crate::stats::ReadOutcome;
+
+/// One buffer of `read_chunks_with` and the chunk it is reading
+/// - start -> file offset of the chunk
+/// - len -> bytes asked for (shrinks to `done` at EOF)
+/// - done -> bytes already read (short reads are resubmitted from here)
+#[derive(Clone, Copy, Default)]
+struct Slot {
+    start: u64,
+    len: usize,
+    done: usize,
+    busy: bool,
+}
+
+impl UringReader {
+    /// Read `path` in chunks of `chunk_size`, handing them to `f` in offset order until it says stop
+    ///
+    /// Ok(outcome) -> the file was read to the end, or `f` returned `Break` (`outcome.stopped`)
+    /// Err(e) -> open or one of the reads failed, `f` has seen everything before the failing chunk
+    ///
+    /// Up to `queue_depth` chunks are in flight, each with its own buffer. They complete in any order but
+    /// `f(offset, chunk)` always gets them front to back, every chunk once, so it can parse sequentially.
+    /// After a `Break` no more reads are issued and the ones in flight are canceled. Files without a size
+    /// (`/proc`, ...) are read one chunk at a time until EOF. `max_bytes` doesn't apply, nothing is kept.
+    ///
+    /// With `PadPolicy::ZeroFill` the last chunk is zero padded to `chunk_size`, `outcome.consumed`
+    /// still only counts the bytes of the file.
+    #[allow(unused_doc_comments)]
+    pub fn read_chunks_with(
+        &self,
+        path: impl AsRef<Path>,
+        chunk_size: usize,
+        mut f: impl FnMut(u64, &[u8]) -> ControlFlow<()>,
+    ) -> io::Result<ReadOutcome> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let chunk_size = chunk_size.clamp(1, u32::MAX as usize);
+        let depth = self.depth().max(1);
+
+        /// The raw pointers are taken once, up front, the buffers are only touched through them until
+        /// the session (declared after them) has reaped every chunk
+        let mut bufs = vec![vec![0u8; chunk_size]; depth];
+        let ptrs: Vec<*mut u8> = bufs.iter_mut().map(|buf| buf.as_mut_ptr()).collect();
+        let mut session = self.session();
+        let scanned = self.scan(
+            &mut session,
+            types::Fd(file.as_raw_fd()),
+            size,
+            chunk_size,
+            &ptrs,
+            &mut f,
+        );
+        if !matches!(scanned, Ok(ReadOutcome { stopped: false, .. })) {
+            session.cancel_all(Cancelled::ByUser);
+        }
+        drop(session);
+        debug_assert_eq!(self.sq_dropped(), 0, "the kernel dropped SQEs we pushed");
+        scanned
+    }
+
+    /// The submit/reap/deliver loop behind `read_chunks_with`
+    ///
+    /// `ptrs` are the `chunk_size` buffers, one per slot.
+    #[allow(unused_doc_comments)]
+    fn scan(
+        &self,
+        session: &mut Session<'_>,
+        fd: types::Fd,
+        size: u64,
+        chunk_size: usize,
+        ptrs: &[*mut u8],
+        f: &mut impl FnMut(u64, &[u8]) -> ControlFlow<()>,
+    ) -> io::Result<ReadOutcome> {
+        let push = |session: &mut Session<'_>, slot: usize, s: &Slot| {
+            /// SAFETY: `done < len <= chunk_size`, the pointer stays inside the slot's buffer
+            let ptr = unsafe { ptrs[slot].add(s.done) };
+            let read_e = opcode::Read::new(fd, ptr, (s.len - s.done) as u32)
+                .offset(s.start + s.done as u64)
+                .build();
+            session.push(slot as u32, read_e)
+        };
+
+        let pad = self.config.pad == PadPolicy::ZeroFill;
+        let known = size > 0;
+        let mut end = if known { size } else { u64::MAX };
+        let mut slots = vec![Slot::default(); ptrs.len()];
+        /// Chunks that are done but not delivered yet, by file offset
+        let mut ready: BTreeMap<u64, usize> = BTreeMap::new();
+        let mut next_issue = 0u64;
+        let mut consumed = 0u64;
+
+        loop {
+            while next_issue < end && (known || session.in_flight() == 0) {
+                let Some(slot) = slots.iter().position(|s| !s.busy) else {
+                    break;
+                };
+                let len = (chunk_size as u64).min(end - next_issue) as usize;
+                slots[slot] = Slot {
+                    start: next_issue,
+                    len,
+                    done: 0,
+                    busy: true,
+                };
+                push(session, slot, &slots[slot])?;
+                next_issue += len as u64;
+            }
+            if session.in_flight() == 0 {
+                return Ok(ReadOutcome {
+                    consumed,
+                    stopped: false,
+                });
+            }
+            session.submit()?;
+
+            let cqe = session.next()?;
+            let slot = cqe.user_data() as u32 as usize;
+            match cqe.into_result() {
+                Err(e) if is_retryable(&e) => {
+                    push(session, slot, &slots[slot])?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+                Ok(0) => {
+                    /// EOF, nothing after this chunk is delivered (or issued)
+                    let s = &mut slots[slot];
+                    s.len = s.done;
+                    end = end.min(s.start + s.done as u64);
+                    ready.retain(|&start, &mut other| {
+                        slots[other].busy = start < end;
+                        start < end
+                    });
+                }
+                Ok(n) => {
+                    let s = &mut slots[slot];
+                    s.done += n as usize;
+                    if s.done < s.len {
+                        self.metrics.short_read_retry();
+                        push(session, slot, &slots[slot])?;
+                        continue;
+                    }
+                }
+            }
+            if slots[slot].start < end && slots[slot].len > 0 {
+                ready.insert(slots[slot].start, slot);
+            } else {
+                slots[slot].busy = false;
+            }
+
+            while let Some(slot) = ready.remove(&consumed) {
+                let s = slots[slot];
+                slots[slot].busy = false;
+                consumed += s.len as u64;
+                /// SAFETY: the slot is not in flight, the kernel filled its first `len` bytes. The rest
+                /// of the buffer may hold an older chunk, a short one is cleared before it's padded
+                let chunk = unsafe {
+                    if pad && s.len < chunk_size {
+                        ptrs[slot].add(s.len).write_bytes(0, chunk_size - s.len);
+                        std::slice::from_raw_parts(ptrs[slot], chunk_size)
+                    } else {
+                        std::slice::from_raw_parts(ptrs[slot], s.len)
+                    }
+                };
+                if f(s.start, chunk).is_break() {
+                    return Ok(ReadOutcome {
+                        consumed,
+                        stopped: true,
+                    });
+                }
+            }
+        }
+    }
+}
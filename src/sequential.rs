@@ -0,0 +1,173 @@
+use io_uring::{opcode, types};
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use crate::chain::Slot;
+use crate::reader::{Session, UringReader, is_retryable};
+
+/// user_data slots of a `SequentialReader`, the advice CQEs are only reaped so they don't pile up
+const READ: u32 = 0;
+const ADVICE: u32 = 1;
+
+/// One open file read front to back through the ring, the offset is kept for you
+///
+/// Unlike `read_at`, the file is opened once: every `read_next` is a single read at the current
+/// offset. Unlike `UringFile` (a `Read` adapter) every call returns its own buffer, as long as what
+/// was actually read. All of it takes `&mut self`, share it between threads by moving it, or use one
+/// per thread.
+///
+/// - On open the file is put into the reader's registered file table when there is a free slot
+///   (Linux 5.19), the reads then skip the fd lookup, and the kernel is told it's read sequentially
+///   (`POSIX_FADV_SEQUENTIAL`, which doubles its readahead window).
+/// - With `read_ahead(true)` every `read_next(len)` also asks for the next `len` bytes
+///   (`POSIX_FADV_WILLNEED`), the page cache is filled while the caller works on the current block.
+pub struct SequentialReader<'r> {
+    /// Declared first so it is dropped first, dropping it reaps the advice still in flight
+    session: Session<'r>,
+    reader: &'r UringReader,
+    file: File,
+    slot: Option<Slot<'r>>,
+    offset: u64,
+    read_ahead: bool,
+}
+
+impl<'r> SequentialReader<'r> {
+    /// Open `path` on `reader`, reading starts at offset 0
+    pub fn open(reader: &'r UringReader, path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let slot = match reader.fixed_files() {
+            Ok(files) => files.try_acquire().filter(|slot| {
+                reader
+                    .submitter()
+                    .register_files_update(slot.index, &[file.as_raw_fd()])
+                    .is_ok()
+            }),
+            Err(_) => None,
+        };
+        let mut sequential = SequentialReader {
+            session: reader.session(),
+            reader,
+            file,
+            slot,
+            offset: 0,
+            read_ahead: false,
+        };
+        sequential.advise(0, 0, libc::POSIX_FADV_SEQUENTIAL)?;
+        Ok(sequential)
+    }
+
+    /// Ask for the next block with every `read_next` (default off), see the type docs
+    pub fn read_ahead(mut self, on: bool) -> Self {
+        self.read_ahead = on;
+        self
+    }
+
+    /// Read up to `len` bytes at the current offset and move past them
+    ///
+    /// The buffer is shorter than `len` only at the end of the file, empty once it's there. Short reads
+    /// in the middle are continued.
+    pub fn read_next(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        let len = len.min(u32::MAX as usize);
+        let mut buf = vec![0u8; len];
+        let mut filled = 0;
+        while filled < len {
+            let n = self.read(&mut buf[filled..])?;
+            if n == 0 {
+                break;
+            }
+            filled += n;
+        }
+        buf.truncate(filled);
+        self.offset += filled as u64;
+        if self.read_ahead && filled == len && len > 0 {
+            self.advise(self.offset, len as u64, libc::POSIX_FADV_WILLNEED)?;
+        }
+        Ok(buf)
+    }
+
+    /// Move the offset `n` bytes forward without reading them, past the end is fine (then reading
+    /// returns nothing)
+    pub fn skip(&mut self, n: u64) {
+        self.offset = self.offset.saturating_add(n);
+    }
+
+    /// Back to offset 0
+    pub fn rewind(&mut self) {
+        self.offset = 0;
+    }
+
+    /// Where the next `read_next` starts
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Whether the file got a slot in the registered file table
+    pub fn is_registered(&self) -> bool {
+        self.slot.is_some()
+    }
+
+    /// One read into `buf` at the current offset, `offset` itself isn't moved
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let offset = self.offset;
+        let read_e = match &self.slot {
+            Some(slot) => {
+                opcode::Read::new(types::Fixed(slot.index), buf.as_mut_ptr(), buf.len() as u32)
+            }
+            None => opcode::Read::new(
+                types::Fd(self.file.as_raw_fd()),
+                buf.as_mut_ptr(),
+                buf.len() as u32,
+            ),
+        }
+        .offset(offset)
+        .build();
+        self.session.push(READ, read_e.clone())?;
+        self.session.submit()?;
+        self.session.restart_deadline();
+        loop {
+            let cqe = self.session.next()?;
+            if cqe.user_data() as u32 != READ {
+                continue;
+            }
+            match cqe.into_result() {
+                Ok(n) => return Ok(n as usize),
+                Err(e) if is_retryable(&e) => {
+                    self.session.push(READ, read_e.clone())?;
+                    self.session.submit()?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Push a fadvise without waiting for it, it's only a hint: kernels without the opcode skip it
+    fn advise(&mut self, offset: u64, len: u64, advice: i32) -> io::Result<()> {
+        if !self.reader.is_supported(opcode::Fadvise::CODE) {
+            return Ok(());
+        }
+        let advise_e = match &self.slot {
+            Some(slot) => opcode::Fadvise::new(types::Fixed(slot.index), len as _, advice),
+            None => opcode::Fadvise::new(types::Fd(self.file.as_raw_fd()), len as _, advice),
+        }
+        .offset(offset)
+        .build();
+        self.session.push(ADVICE, advise_e)?;
+        self.session.submit()
+    }
+}
+
+impl Drop for SequentialReader<'_> {
+    #[allow(unused_doc_comments)]
+    fn drop(&mut self) {
+        /// The table holds its own reference to the file, clear the slot before it's handed out again
+        if let Some(slot) = &self.slot {
+            let _ = self
+                .reader
+                .submitter()
+                .register_files_update(slot.index, &[-1]);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+/// Statx -> `statx(2)` as an SQE
+use io_uring::{opcode, types};
+
+use std::ffi::{CStr, CString};
+use std::io;
+use std::mem::MaybeUninit;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::chain::c_path;
+use crate::files::copy_error;
+use crate::reader::{UringReader, is_retryable};
+use crate::stats::{FileMeta, FileStamp};
+
+/// The statx buffers of one `stat_many`, allocated once for the whole call
+/// - stx -> one buffer per slot, the kernel writes into it until the slot's CQE is reaped
+/// - paths -> the C path of each slot, which the kernel reads
+/// - owner -> the index of the path a slot is busy with, None while it is free
+///
+/// Nothing in here moves or is resized while requests are in flight, a slot only goes to the next
+/// path once its CQE came back.
+struct StatxArena {
+    stx: Box<[MaybeUninit<libc::statx>]>,
+    paths: Box<[Option<CString>]>,
+    owner: Box<[Option<usize>]>,
+    free: Vec<usize>,
+}
+
+impl StatxArena {
+    fn new(slots: usize) -> Self {
+        StatxArena {
+            stx: (0..slots).map(|_| MaybeUninit::zeroed()).collect(),
+            paths: (0..slots).map(|_| None).collect(),
+            owner: vec![None; slots].into_boxed_slice(),
+            free: (0..slots).rev().collect(),
+        }
+    }
+
+    /// The statx of slot `slot`, tagged with it
+    fn statx(&mut self, slot: usize) -> io_uring::squeue::Entry {
+        let path = self.paths[slot].as_deref().unwrap_or(c"");
+        opcode::Statx::new(
+            types::Fd(libc::AT_FDCWD),
+            path.as_ptr(),
+            self.stx[slot].as_mut_ptr().cast(),
+        )
+        .mask(libc::STATX_BASIC_STATS)
+        .build()
+    }
+}
+
+impl UringReader {
+    /// `statx` of an open fd through the ring (`AT_EMPTY_PATH`, so no path lookup at all)
+    ///
+    /// Only the basic fields (`STATX_BASIC_STATS`) are requested.
+    pub(crate) fn statx_fd(&self, fd: types::Fd) -> io::Result<libc::statx> {
+        self.statx(fd, c"", libc::AT_EMPTY_PATH)
+    }
+
+    /// `statx` of `path` through the ring, symlinks are followed
+    pub(crate) fn statx_path(&self, path: &Path) -> io::Result<libc::statx> {
+        self.statx(types::Fd(libc::AT_FDCWD), &c_path(path)?, 0)
+    }
+
+    /// Metadata of every path in `paths`, in the same order, up to `queue_depth` statx in flight
+    ///
+    /// A path that fails (ENOENT, EACCES, ...) gets its own error, the rest of the batch is not
+    /// affected. Symlinks are followed. The statx buffers come from one arena of `queue_depth` slots,
+    /// the user_data of every request is its slot and a slot only moves on to the next path once its
+    /// CQE was reaped, so a result can't end up at another path. Kernels without IORING_OP_STATX
+    /// (before 5.6) get one `std::fs::metadata` per path.
+    pub fn stat_many<P: AsRef<Path>>(&self, paths: &[P]) -> Vec<(PathBuf, io::Result<FileMeta>)> {
+        let paths: Vec<&Path> = paths.iter().map(AsRef::as_ref).collect();
+        let metas = if self.is_supported(opcode::Statx::CODE) {
+            self.statx_many(&paths)
+        } else {
+            paths
+                .iter()
+                .map(|path| std::fs::metadata(path).and_then(|m| FileMeta::from_metadata(&m)))
+                .collect()
+        };
+        paths
+            .into_iter()
+            .map(Path::to_path_buf)
+            .zip(metas)
+            .collect()
+    }
+
+    #[allow(unused_doc_comments)]
+    fn statx_many(&self, paths: &[&Path]) -> Vec<io::Result<FileMeta>> {
+        let mut results: Vec<Option<io::Result<FileMeta>>> = paths.iter().map(|_| None).collect();
+        /// Declared before the session in `run_statx`, see `Session`
+        let mut arena = StatxArena::new(self.depth().clamp(1, paths.len().max(1)));
+        if let Err(e) = self.run_statx(paths, &mut arena, &mut results) {
+            /// The ring itself failed, every path without a result fails with it
+            for result in &mut results {
+                result.get_or_insert_with(|| Err(copy_error(&e)));
+            }
+        }
+        results
+            .into_iter()
+            .map(|result| result.expect("every path has a result"))
+            .collect()
+    }
+
+    /// The submit/reap loop behind `statx_many`, Err only if the ring itself fails
+    #[allow(unused_doc_comments)]
+    fn run_statx(
+        &self,
+        paths: &[&Path],
+        arena: &mut StatxArena,
+        results: &mut [Option<io::Result<FileMeta>>],
+    ) -> io::Result<()> {
+        let depth = arena.stx.len();
+        let mut session = self.session();
+        let mut next = 0;
+        let mut pushed = false;
+        loop {
+            session.check_deadline()?;
+            /// Refill once half of the arena is free, so one submit carries many statx
+            let refill = session.in_flight() <= depth / 2;
+            while refill
+                && next < paths.len()
+                && let Some(slot) = arena.free.pop()
+            {
+                let index = next;
+                next += 1;
+                match c_path(paths[index]) {
+                    Ok(path) => arena.paths[slot] = Some(path),
+                    Err(e) => {
+                        results[index] = Some(Err(e));
+                        arena.free.push(slot);
+                        continue;
+                    }
+                }
+                arena.owner[slot] = Some(index);
+                let statx_e = arena.statx(slot);
+                session.push(slot as u32, statx_e)?;
+                pushed = true;
+            }
+            if session.in_flight() == 0 {
+                return Ok(());
+            }
+            if std::mem::take(&mut pushed) {
+                session.submit()?;
+            }
+
+            let cqe = session.next()?;
+            let slot = (cqe.user_data() & u64::from(u32::MAX)) as usize;
+            let index = arena.owner[slot].expect("a CQE for a busy slot");
+            results[index] = match cqe.into_result() {
+                Err(e) if is_retryable(&e) => {
+                    let statx_e = arena.statx(slot);
+                    session.push(slot as u32, statx_e)?;
+                    pushed = true;
+                    continue;
+                }
+                /// SAFETY: zeroed is a valid statx, and the kernel filled it in
+                Ok(_) => Some(Ok(meta(unsafe { arena.stx[slot].assume_init_ref() }))),
+                Err(e) => Some(Err(e)),
+            };
+            arena.owner[slot] = None;
+            arena.free.push(slot);
+        }
+    }
+
+    #[allow(unused_doc_comments)]
+    fn statx(&self, dir: types::Fd, path: &CStr, flags: i32) -> io::Result<libc::statx> {
+        /// The kernel writes into `stx` and reads `path`, both must outlive the session
+        let mut stx = MaybeUninit::<libc::statx>::zeroed();
+
+        let mut session = self.session();
+        let statx_e = opcode::Statx::new(dir, path.as_ptr(), stx.as_mut_ptr().cast())
+            .flags(flags)
+            .mask(libc::STATX_BASIC_STATS)
+            .build();
+        session.push(0, statx_e)?;
+        session.submit()?;
+        session.next()?.into_result()?;
+        drop(session);
+
+        /// SAFETY: zeroed is a valid statx, and the kernel filled it in
+        Ok(unsafe { stx.assume_init() })
+    }
+}
+
+/// What `stat_many` hands out of a statx
+fn meta(stx: &libc::statx) -> FileMeta {
+    FileMeta {
+        size: stx.stx_size,
+        mtime: stamp(stx).mtime,
+        ino: stx.stx_ino,
+        mode: u32::from(stx.stx_mode),
+        blocks: stx.stx_blocks,
+    }
+}
+
+/// The parts of a statx that tell whether a file changed
+pub(crate) fn stamp(stx: &libc::statx) -> FileStamp {
+    let seconds = Duration::from_secs(stx.stx_mtime.tv_sec.unsigned_abs());
+    let mtime = if stx.stx_mtime.tv_sec < 0 {
+        SystemTime::UNIX_EPOCH - seconds
+    } else {
+        SystemTime::UNIX_EPOCH + seconds
+    };
+    FileStamp {
+        size: stx.stx_size,
+        mtime: mtime + Duration::from_nanos(u64::from(stx.stx_mtime.tv_nsec)),
+        ino: stx.stx_ino,
+        dev: libc::makedev(stx.stx_dev_major, stx.stx_dev_minor),
+    }
+}
@@ -0,0 +1,709 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+#[cfg(target_os = "linux")]
+use crate::timing::RequestTiming;
+
+/// Counters collected by a `UringReader`
+///
+/// All counters are cumulative since the reader was created. `UringReader::stats()` returns a copy,
+/// so it can be kept around and compared with a later snapshot.
+///
+/// With the feature `serde` this (like `DrainReport`, `Capabilities`, `RequestTiming`, `BenchReport`,
+/// `CopyReport` and `TreeCopyReport`) serializes with its field names as they are here. They are a stable format: fields
+/// are only ever added, never renamed or removed, and a missing field deserializes to its default,
+/// so reports of older versions still load. Durations are integer nanoseconds.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ReadStats {
+    /// Number of SQEs pushed into the submission queue
+    pub submitted: u64,
+    /// Number of CQEs reaped from the completion queue
+    pub completed: u64,
+    /// Number of `io_uring_enter` calls made (submit and/or wait)
+    pub enters: u64,
+    /// What the submitted SQEs would have cost as classic syscalls (`read(2)`, `openat(2)`,
+    /// `close(2)`, ...), counted by the rules of `SyscallSummary`
+    pub classic_syscalls: u64,
+    /// Waits where a completion showed up while spinning (`spin_before_wait`), no sleep needed
+    pub spin_hits: u64,
+    /// Waits where the spin ran out and the reader fell back to a blocking wait
+    pub spin_misses: u64,
+    /// Requests with a `RequestTiming` (only counted with `UringConfig::record_timings`)
+    pub timed_requests: u64,
+    /// Sum of the time timed requests sat in the SQ before being submitted
+    #[cfg_attr(feature = "serde", serde(with = "crate::stats::nanos"))]
+    pub queued_time: Duration,
+    /// Sum of the time timed requests spent between submit and reap
+    #[cfg_attr(feature = "serde", serde(with = "crate::stats::nanos"))]
+    pub in_kernel_time: Duration,
+    /// Longest single queue time seen
+    #[cfg_attr(feature = "serde", serde(with = "crate::stats::nanos"))]
+    pub max_queued: Duration,
+    /// Longest single in-kernel time seen
+    #[cfg_attr(feature = "serde", serde(with = "crate::stats::nanos"))]
+    pub max_in_kernel: Duration,
+    /// Requests that were tried again because of `UringConfig::retry`
+    pub retries: u64,
+    /// Requests that still failed after their last retry
+    pub retries_exhausted: u64,
+    /// Linked chains whose data was fine but whose final close failed (`read_linked` still succeeds)
+    pub close_failures: u64,
+    /// Files of `read_many_files` that were done with their linked statx + first read, one submit
+    pub linked_statx_files: u64,
+    /// Files that got shorter while they were read and were returned up to the new EOF
+    /// (`ShrinkPolicy::Truncate`)
+    pub shrunk_files: u64,
+    /// Paths of a batch that weren't read because the same file was already in it (`UringConfig::dedup`)
+    pub deduplicated_reads: u64,
+    /// `read_ranges` ranges answered from a read of another range (`UringConfig::coalesce_gap`), one
+    /// read saved each
+    pub coalesced_ranges: u64,
+    /// Bytes between coalesced ranges that were read only to make one read out of them
+    pub gap_bytes: u64,
+    /// Files read again because they changed while they were read (`ChangePolicy::Retry`)
+    pub modified_rereads: u64,
+    /// Faults injected by `UringConfig::failpoints` (feature `failpoints`), the errors and short reads
+    /// that weren't real
+    pub injected_faults: u64,
+    /// Chunk buffers `copy_file` allocated, stops growing once the copies recycle `queue_depth` of them
+    pub copy_buffers_allocated: u64,
+    /// Whole-file reads a `std::fs::read` agreed with (`UringConfig::verify_against_std`)
+    pub verified_reads: u64,
+    /// Whole-file reads where std read other bytes from the unchanged file, each one worth a bug report
+    pub verify_mismatches: u64,
+    /// Checks that compared nothing because the file changed between the two reads
+    pub verify_raced: u64,
+    /// Checks that didn't run: the queue was full (`VerifyPolicy::Count`) or the std read failed
+    pub verify_skipped: u64,
+    /// Socket receives that brought data (`recv`, `recv_multishot`), and how many bytes in total
+    pub socket_recvs: u64,
+    pub socket_bytes: u64,
+    /// Times a call slept to stay under `UringConfig::rate_limit`, and for how long in total
+    pub throttle_sleeps: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::stats::nanos"))]
+    pub throttle_slept: Duration,
+    /// Bytes/s the rate limited reads achieved since the first of them, None without `rate_limit`
+    pub throttled_rate: Option<u64>,
+    /// The depth the auto-tuner is at right now, None without `UringConfig::auto_tune`
+    pub tuned_depth: Option<u32>,
+    /// Every depth the auto-tuner moved to, oldest first (only the last 256 changes are kept)
+    pub depth_history: Vec<u32>,
+}
+
+/// What `UringReader::drain` saw
+/// - completed / canceled / failed -> CQEs reaped while draining: success, -ECANCELED, any other error
+/// - abandoned -> requests of dropped async reads (`read_owned`) that were still in flight at the end,
+///   their buffers are leaked rather than freed under the kernel
+/// - still_running -> requests of other threads' calls still in flight at the end (they own their
+///   buffers and keep waiting for them)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct DrainReport {
+    pub completed: u64,
+    pub canceled: u64,
+    pub failed: u64,
+    pub abandoned: Vec<AbandonedRequest>,
+    pub still_running: u64,
+}
+
+impl DrainReport {
+    /// Nothing was left in flight
+    pub fn is_clean(&self) -> bool {
+        self.abandoned.is_empty() && self.still_running == 0
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn absorb(&mut self, other: DrainReport) {
+        self.completed += other.completed;
+        self.canceled += other.canceled;
+        self.failed += other.failed;
+        self.abandoned.extend(other.abandoned);
+        self.still_running += other.still_running;
+    }
+}
+
+/// What `UringReader::close` (or `RingPool::close`, summed over the rings) did
+/// - drain -> the drain that ran first, see `DrainReport`
+/// - buffers_unregistered -> registered buffer sets released (`UringConfig::fixed_buffers`)
+/// - files_unregistered -> registered file tables released (the one of the linked chains)
+/// - personalities_unregistered -> personalities that were still registered
+/// - eventfds_unregistered -> the eventfd of the async wake ups
+/// - failures -> teardown steps that went wrong, in order ("unregister files: ...")
+///
+/// Registered resources are only released once nothing is in flight anymore. If something still is
+/// (a request that ignored the cancel), they are left to the kernel, which frees them with the ring.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CloseReport {
+    pub drain: DrainReport,
+    pub buffers_unregistered: usize,
+    pub files_unregistered: usize,
+    pub personalities_unregistered: usize,
+    pub eventfds_unregistered: usize,
+    pub failures: Vec<String>,
+}
+
+impl CloseReport {
+    /// Nothing was left in flight and every step worked
+    pub fn is_clean(&self) -> bool {
+        self.drain.is_clean() && self.failures.is_empty()
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn absorb(&mut self, other: CloseReport) {
+        self.drain.absorb(other.drain);
+        self.buffers_unregistered += other.buffers_unregistered;
+        self.files_unregistered += other.files_unregistered;
+        self.personalities_unregistered += other.personalities_unregistered;
+        self.eventfds_unregistered += other.eventfds_unregistered;
+        self.failures.extend(other.failures);
+    }
+}
+
+/// What `UringReader::ring_snapshot` saw, no syscall behind any of it
+/// - sq_entries / cq_entries -> the queue sizes the kernel gave us
+/// - sq_len -> SQEs pushed but not handed to the kernel yet
+/// - in_flight -> requests whose completion was not reaped yet
+/// - parked -> CQEs reaped but not picked up by their call yet
+/// - sq_dropped -> SQEs the kernel threw away as invalid since the ring was created, anything but 0
+///   means the reader corrupted its own submission queue
+/// - cq_overflows -> CQEs that didn't fit the completion queue since the ring was created, as of the
+///   last reap (IORING_FEAT_NODROP kernels keep them and hand them out later)
+/// - sqpoll_needs_wakeup -> the SQPOLL kernel thread went to sleep, always false for rings without it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RingSnapshot {
+    pub sq_entries: u32,
+    pub cq_entries: u32,
+    pub sq_len: usize,
+    pub in_flight: u64,
+    pub parked: usize,
+    pub sq_dropped: u32,
+    pub cq_overflows: u32,
+    pub sqpoll_needs_wakeup: bool,
+}
+
+/// What `UringReader::read_chunks_with` did
+/// - consumed -> bytes handed to the callback, the chunk it stopped on included
+/// - stopped -> the callback returned `Break`, false if the whole file was read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadOutcome {
+    pub consumed: u64,
+    pub stopped: bool,
+}
+
+/// What `copy_file_report` did
+/// - logical_size -> size of `dst`, the size of `src` (what `copy_file` returns)
+/// - copied -> bytes actually read and written, less than `logical_size` when holes were skipped
+/// - holes -> bytes of `src` that were holes and were left unwritten in `dst`
+/// - dense_reason -> why a `UringConfig::preserve_sparse` copy wrote everything after all (a
+///   filesystem without holes on either side), None if it didn't have to. With `serde`, a reason
+///   this version doesn't know loads as a placeholder that says so.
+/// - chunks -> chunks read and written
+/// - buffers -> buffers the copy moved them through, at most `queue_depth` however many chunks
+/// - allocated -> of those, the ones it had to allocate. The rest were registered buffers or recycled
+///   from the copies before on the same reader.
+///
+/// The std fallback leaves the last three at 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CopyReport {
+    pub logical_size: u64,
+    pub copied: u64,
+    pub holes: u64,
+    pub dense_reason: Option<&'static str>,
+    pub chunks: u64,
+    pub buffers: usize,
+    pub allocated: usize,
+}
+
+/// The `CopyReport::dense_reason`s, of the io_uring copy and of the std fallback
+#[cfg_attr(not(feature = "serde"), allow(dead_code))]
+pub(crate) mod dense {
+    pub(crate) const SOURCE_WITHOUT_HOLES: &str = "the source filesystem doesn't report holes";
+    pub(crate) const DESTINATION_WITHOUT_HOLES: &str =
+        "the destination filesystem can't keep holes";
+    pub(crate) const FALLBACK: &str = "the std fallback always copies dense";
+    /// What a deserialized reason this version doesn't know becomes
+    pub(crate) const UNKNOWN: &str = "a reason of another version of the crate";
+}
+
+/// What `copy_tree` did with one entry of the source tree
+/// - Copied -> a regular file, copied as `copy_file_report` says
+/// - Linked -> a symlink, recreated with the same target (`SymlinkPolicy::Recreate`)
+/// - Skipped -> left alone: the destination was there already (`ExistingPolicy::Skip`), or a symlink
+///   under `SymlinkPolicy::Skip`
+/// - Failed -> why it didn't make it, the rest of the tree was copied anyway (unless `fail_fast`)
+///
+/// With `serde` an error is its OS error code and its message.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TreeEntry {
+    Copied(CopyReport),
+    Linked,
+    Skipped,
+    Failed(#[cfg_attr(feature = "serde", serde(with = "crate::stats::io_error"))] io::Error),
+}
+
+/// What `copy_tree` did
+/// - entries -> (source path, outcome) for every regular file and symlink, plus the directories that
+///   failed and the ones that couldn't be listed, sorted by path
+/// - total -> the reports of the copied files added up. `dense_reason` is the first one any copy
+///   gave, `buffers` the most one copy used.
+/// - dirs -> directories created, the ones that were there already are used as they are
+/// - files / links / skipped / failed -> the entries per outcome
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct TreeCopyReport {
+    pub entries: Vec<(PathBuf, TreeEntry)>,
+    pub total: CopyReport,
+    pub dirs: usize,
+    pub files: usize,
+    pub links: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+/// The counters of a `LanedReader`, one `ReadStats` per lane
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct LaneStats {
+    pub fast: ReadStats,
+    pub bulk: ReadStats,
+}
+
+/// What a stat said about a file, to tell whether it changed (`UringReader::read_file_stamped`)
+/// - size -> bytes
+/// - mtime -> last modification, as precise as the filesystem keeps it
+/// - ino / dev -> which file it is, a replaced file (written elsewhere and renamed over) has a new
+///   inode. Both are 0 on targets without them (Windows).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileStamp {
+    pub size: u64,
+    pub mtime: SystemTime,
+    pub ino: u64,
+    pub dev: u64,
+}
+
+impl FileStamp {
+    /// From std metadata, without inode numbers where there are none
+    pub(crate) fn from_metadata(metadata: &std::fs::Metadata) -> std::io::Result<Self> {
+        #[cfg(unix)]
+        let (ino, dev) = {
+            use std::os::unix::fs::MetadataExt;
+            (metadata.ino(), metadata.dev())
+        };
+        #[cfg(not(unix))]
+        let (ino, dev) = (0, 0);
+        Ok(FileStamp {
+            size: metadata.len(),
+            mtime: metadata.modified()?,
+            ino,
+            dev,
+        })
+    }
+}
+
+/// What `UringReader::stat_many` found out about a path (symlinks followed)
+/// - size -> bytes
+/// - mtime -> last modification, as precise as the filesystem keeps it
+/// - ino -> inode number, 0 on targets without one (Windows)
+/// - mode -> file type and permission bits (`st_mode`: S_IFREG | 0o644, ...), 0 on Windows
+/// - blocks -> 512 byte blocks allocated on disk, less than `size` for sparse files (0 on Windows)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileMeta {
+    pub size: u64,
+    pub mtime: SystemTime,
+    pub ino: u64,
+    pub mode: u32,
+    pub blocks: u64,
+}
+
+impl FileMeta {
+    /// From std metadata, for the fallback and kernels without IORING_OP_STATX
+    pub(crate) fn from_metadata(metadata: &std::fs::Metadata) -> std::io::Result<Self> {
+        #[cfg(unix)]
+        let (ino, mode, blocks) = {
+            use std::os::unix::fs::MetadataExt;
+            (metadata.ino(), metadata.mode(), metadata.blocks())
+        };
+        #[cfg(not(unix))]
+        let (ino, mode, blocks) = (0, 0, 0);
+        Ok(FileMeta {
+            size: metadata.len(),
+            mtime: metadata.modified()?,
+            ino,
+            mode,
+            blocks,
+        })
+    }
+}
+
+/// What a batch with a deadline got done (`read_many_files_until`, `read_tree_until`)
+/// - results -> one entry per file, like the call without a deadline
+/// - finished -> entries with data
+/// - abandoned -> entries that failed with `ReadError::Deadline`
+/// - cancelled -> entries that failed with `ReadError::Cancelled` (a `UringConfig::timeout`, a
+///   broken chain, ...), nothing was wrong with the file
+/// - failed -> entries that failed on their own (missing, too large, EIO, ...)
+///
+/// The four add up to `results.len()`.
+#[derive(Debug)]
+pub struct Partial<T> {
+    pub results: Vec<T>,
+    pub finished: usize,
+    pub abandoned: usize,
+    pub cancelled: usize,
+    pub failed: usize,
+}
+
+/// Syscall load of some work through the ring, next to what it would have been without it
+/// (`ReadStats::syscalls`, `ReadStats::syscalls_since`)
+/// - submitted -> SQEs handed to the kernel
+/// - enters -> `io_uring_enter` calls that did it (submits and waits)
+/// - classic_syscalls -> the estimate for the same work done one syscall at a time
+/// - avoided -> `classic_syscalls - enters`, 0 if batching didn't pay off
+///
+/// The estimate counts every SQE, by its opcode, and nothing else:
+/// - an SQE that does I/O or a file operation is the one syscall that does the same thing: a read is
+///   a `pread(2)`, an open `openat(2)`, a close `close(2)`, the linked statx + read of
+///   `read_many_files` is `statx(2)` + `pread(2)`, and so on; a retried request counts again
+/// - SQEs that only exist because of the ring count 0: nops, cancels, timeouts (the ones linked
+///   behind a read included), timeout updates/removes, registered file updates, ring messages and
+///   provided buffers
+/// - work done outside the ring (the `File::open` before a read, `fstat` on kernels without an
+///   io_uring statx) costs the same both ways, it is in neither count
+///
+/// These rules are part of the API and don't change between releases, numbers from different runs
+/// (and versions) can be compared.
+///
+/// `Display` gives the one line version: `submitted 4096 ops in 37 enters; ~12288 classic syscalls
+/// avoided`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyscallSummary {
+    pub submitted: u64,
+    pub enters: u64,
+    pub classic_syscalls: u64,
+    pub avoided: u64,
+}
+
+impl fmt::Display for SyscallSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "submitted {} ops in {} enters; ~{} classic syscalls avoided",
+            self.submitted, self.enters, self.avoided
+        )
+    }
+}
+
+/// A request nobody waits for anymore, for logging
+/// - user_data -> as pushed, (session id << 32) | slot
+/// - what -> which call it belonged to
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AbandonedRequest {
+    pub user_data: u64,
+    pub what: String,
+}
+
+impl ReadStats {
+    /// Syscall load of everything since the reader was created
+    pub fn syscalls(&self) -> SyscallSummary {
+        self.syscalls_since(&ReadStats::default())
+    }
+
+    /// Syscall load of what happened between `earlier` and this snapshot, for one call take one
+    /// right before it
+    ///
+    /// Calls other threads made on the same reader in between are in it as well.
+    pub fn syscalls_since(&self, earlier: &ReadStats) -> SyscallSummary {
+        let classic_syscalls = self.classic_syscalls - earlier.classic_syscalls;
+        let enters = self.enters - earlier.enters;
+        SyscallSummary {
+            submitted: self.submitted - earlier.submitted,
+            enters,
+            classic_syscalls,
+            avoided: classic_syscalls.saturating_sub(enters),
+        }
+    }
+
+    /// Average queue time per timed request
+    pub fn avg_queued(&self) -> Duration {
+        average(self.queued_time, self.timed_requests)
+    }
+
+    /// Average in-kernel time per timed request
+    pub fn avg_in_kernel(&self) -> Duration {
+        average(self.in_kernel_time, self.timed_requests)
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn record_timing(&mut self, timing: RequestTiming) {
+        self.timed_requests += 1;
+        self.queued_time += timing.queued;
+        self.in_kernel_time += timing.in_kernel;
+        self.max_queued = self.max_queued.max(timing.queued);
+        self.max_in_kernel = self.max_in_kernel.max(timing.in_kernel);
+    }
+}
+
+fn average(total: Duration, count: u64) -> Duration {
+    if count == 0 {
+        return Duration::ZERO;
+    }
+    total / count.min(u32::MAX as u64) as u32
+}
+
+/// `Duration` as integer nanoseconds for serde (`#[serde(with = "crate::stats::nanos")]`), a plain
+/// number any other language can read, unlike serde's `{ secs, nanos }`
+#[cfg(feature = "serde")]
+pub(crate) mod nanos {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    /// Saturates at `u64::MAX` nanoseconds, some 584 years
+    pub(crate) fn serialize<S: Serializer>(
+        duration: &Duration,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX))
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_nanos)
+    }
+
+    /// The same for `Option<Duration>`, None is `null`
+    pub(crate) mod option {
+        use std::time::Duration;
+
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub(crate) fn serialize<S: Serializer>(
+            duration: &Option<Duration>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match duration {
+                Some(duration) => serializer
+                    .serialize_some(&u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Duration>, D::Error> {
+            Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_nanos))
+        }
+    }
+}
+
+/// `CopyReport` deserializes through this: derived, it would only load from `'static` input because
+/// of `dense_reason`. A reason the crate gives comes back as itself, one it doesn't know (from a newer
+/// version) as `dense::UNKNOWN`.
+#[cfg(feature = "serde")]
+mod copy_report {
+    use serde::{Deserialize, Deserializer};
+
+    use super::{CopyReport, dense};
+
+    #[derive(Default, Deserialize)]
+    #[serde(default)]
+    struct Repr {
+        logical_size: u64,
+        copied: u64,
+        holes: u64,
+        dense_reason: Option<String>,
+        chunks: u64,
+        buffers: usize,
+        allocated: usize,
+    }
+
+    impl<'de> Deserialize<'de> for CopyReport {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let repr = Repr::deserialize(deserializer)?;
+            let known = [
+                dense::SOURCE_WITHOUT_HOLES,
+                dense::DESTINATION_WITHOUT_HOLES,
+                dense::FALLBACK,
+            ];
+            let dense_reason = repr.dense_reason.map(|reason| {
+                known
+                    .into_iter()
+                    .find(|known| *known == reason)
+                    .unwrap_or(dense::UNKNOWN)
+            });
+            Ok(CopyReport {
+                logical_size: repr.logical_size,
+                copied: repr.copied,
+                holes: repr.holes,
+                dense_reason,
+                chunks: repr.chunks,
+                buffers: repr.buffers,
+                allocated: repr.allocated,
+            })
+        }
+    }
+}
+
+/// `io::Error` for serde, as `{ "os_error": 2, "message": "No such file or directory (os error 2)" }`
+///
+/// An OS error comes back as that OS error, any other as `io::ErrorKind::Other` with the message.
+#[cfg(feature = "serde")]
+pub(crate) mod io_error {
+    use std::io;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Repr {
+        os_error: Option<i32>,
+        message: String,
+    }
+
+    pub(crate) fn serialize<S: Serializer>(
+        e: &io::Error,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        Repr {
+            os_error: e.raw_os_error(),
+            message: e.to_string(),
+        }
+        .serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<io::Error, D::Error> {
+        let Repr { os_error, message } = Repr::deserialize(deserializer)?;
+        Ok(match os_error {
+            Some(code) => io::Error::from_raw_os_error(code),
+            None => io::Error::other(message),
+        })
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    /// The same number on every target the crate builds for (ERROR_FILE_NOT_FOUND on Windows)
+    const ENOENT: i32 = 2;
+
+    #[test]
+    fn read_stats_durations_are_nanoseconds() {
+        let stats = ReadStats {
+            submitted: 7,
+            queued_time: Duration::from_nanos(1_500),
+            in_kernel_time: Duration::from_secs(3) + Duration::from_nanos(5),
+            max_queued: Duration::from_micros(2),
+            max_in_kernel: Duration::from_millis(4),
+            throttle_slept: Duration::from_nanos(1),
+            throttled_rate: Some(1 << 20),
+            depth_history: vec![8, 16],
+            ..ReadStats::default()
+        };
+        let value = serde_json::to_value(&stats).unwrap();
+        assert_eq!(value["queued_time"], json!(1_500));
+        assert_eq!(value["in_kernel_time"], json!(3_000_000_005u64));
+        assert_eq!(value["max_queued"], json!(2_000));
+        assert_eq!(value["max_in_kernel"], json!(4_000_000));
+        assert_eq!(value["throttle_slept"], json!(1));
+        assert_eq!(serde_json::from_value::<ReadStats>(value).unwrap(), stats);
+
+        // Fields a report of an older version doesn't have come back as their default
+        let old: ReadStats = serde_json::from_value(json!({ "submitted": 7 })).unwrap();
+        assert_eq!(
+            old,
+            ReadStats {
+                submitted: 7,
+                ..ReadStats::default()
+            }
+        );
+    }
+
+    #[test]
+    fn copy_reports_round_trip() {
+        let report = CopyReport {
+            logical_size: 1 << 20,
+            copied: 4096,
+            holes: (1 << 20) - 4096,
+            dense_reason: Some(dense::DESTINATION_WITHOUT_HOLES),
+            chunks: 1,
+            buffers: 1,
+            allocated: 1,
+        };
+        let json = serde_json::to_string(&report).unwrap();
+        let back: CopyReport = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, report);
+        let newer: CopyReport =
+            serde_json::from_value(json!({ "dense_reason": "a reason of a newer version" }))
+                .unwrap();
+        assert_eq!(newer.dense_reason, Some(dense::UNKNOWN));
+
+        let tree = TreeCopyReport {
+            entries: vec![
+                (PathBuf::from("a/file"), TreeEntry::Copied(report)),
+                (PathBuf::from("a/link"), TreeEntry::Linked),
+                (PathBuf::from("a/old"), TreeEntry::Skipped),
+                (
+                    PathBuf::from("a/gone"),
+                    TreeEntry::Failed(io::Error::from_raw_os_error(ENOENT)),
+                ),
+                (
+                    PathBuf::from("a/odd"),
+                    TreeEntry::Failed(io::Error::other("odd")),
+                ),
+            ],
+            total: report,
+            dirs: 1,
+            files: 1,
+            links: 1,
+            skipped: 1,
+            failed: 2,
+        };
+        let value = serde_json::to_value(&tree).unwrap();
+        assert_eq!(value["entries"][1], json!(["a/link", "Linked"]));
+        assert_eq!(value["entries"][3][1]["Failed"]["os_error"], json!(ENOENT));
+        assert_eq!(value["entries"][4][1]["Failed"]["os_error"], Value::Null);
+
+        let back: TreeCopyReport = serde_json::from_value(value).unwrap();
+        assert_eq!(
+            (
+                back.total,
+                back.dirs,
+                back.files,
+                back.links,
+                back.skipped,
+                back.failed
+            ),
+            (report, 1, 1, 1, 1, 2)
+        );
+        let paths: Vec<_> = back
+            .entries
+            .iter()
+            .map(|(path, _)| path.to_str().unwrap())
+            .collect();
+        assert_eq!(paths, ["a/file", "a/link", "a/old", "a/gone", "a/odd"]);
+        assert!(matches!(back.entries[0].1, TreeEntry::Copied(copied) if copied == report));
+        assert!(matches!(back.entries[1].1, TreeEntry::Linked));
+        assert!(matches!(back.entries[2].1, TreeEntry::Skipped));
+        match (&back.entries[3].1, &back.entries[4].1) {
+            (TreeEntry::Failed(gone), TreeEntry::Failed(odd)) => {
+                assert_eq!(gone.raw_os_error(), Some(ENOENT));
+                assert_eq!(odd.to_string(), "odd");
+            }
+            other => panic!("expected two failures, got {other:?}"),
+        }
+    }
+}
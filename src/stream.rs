@@ -0,0 +1,418 @@
+use futures_core::Stream;
+use io_uring::{opcode, types};
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::error::Cancelled;
+use crate::files::copy_error;
+use crate::reader::{Session, UringReader, is_retryable};
+
+/// A file of a `ReadManyStream` that has not been handed out yet
+/// - sized -> `data` has the size `fstat` reported and its chunks are read in parallel. Files without
+///   a size are read one chunk at a time and `data` grows between reads (never while a read is in flight).
+/// - next -> offset of the next chunk to issue
+/// - filled -> bytes that are valid, shrinks if EOF shows up early
+/// - pending -> requests in flight, the file is handed out only once this is 0
+/// - index -> position of its path in the input
+struct StreamFile {
+    index: usize,
+    path: PathBuf,
+    file: File,
+    data: Vec<u8>,
+    sized: bool,
+    next: usize,
+    filled: usize,
+    pending: usize,
+    done: bool,
+    error: Option<io::Error>,
+}
+
+/// One read request, the slot of its user_data is its index in `ReadManyStream::reqs`
+struct Req {
+    file: usize,
+    offset: usize,
+    len: usize,
+    done: usize,
+}
+
+/// `futures_core::Stream` of whole files, each one is yielded as soon as its last read completes
+///
+/// Returned by `UringReader::read_many_stream`. Items come in completion order, not in the order of
+/// the paths, every item carries its path. `UringReader::read_many_stream_ordered` yields them in the
+/// order of the paths instead.
+///
+/// At most `UringConfig::max_in_flight` files are open or waiting to be picked up at once and at most
+/// `queue_depth` reads are in flight. In ordered mode the files that finished ahead of their turn wait
+/// in that same window, so the reorder buffer is never bigger than the in-flight limit and can't
+/// deadlock it: files are opened in path order, the one whose turn it is always has its place.
+///
+/// The window counts files, not bytes: the stream holds up to `max_in_flight` x `max_bytes` of file
+/// data, the reorder buffer included. Lower one of the two for streams of big files.
+///
+/// Dropping the stream early cancels what is still in flight and waits for the kernel to let go of
+/// the buffers (that wait blocks the thread, it is short).
+///
+/// Completions are noticed through an eventfd registered with the ring, so this works on any
+/// executor. `UringConfig::timeout` does not apply, wrap the stream in your runtime's timeout instead.
+pub struct ReadManyStream<'r> {
+    /// Declared first so it is dropped (waits for every request) before the buffers in `files`
+    session: Session<'r>,
+    reader: &'r UringReader,
+    paths: std::vec::IntoIter<PathBuf>,
+    files: Vec<Option<StreamFile>>,
+    reqs: Vec<Option<Req>>,
+    /// Finished files, keyed by path index (ordered) or by the order they finished in
+    ready: BTreeMap<usize, (PathBuf, io::Result<Vec<u8>>)>,
+    /// files in `files`
+    open: usize,
+    ordered: bool,
+    /// paths taken from `paths` so far, the index of the next one
+    taken: usize,
+    /// files that finished so far (the key in completion order)
+    finished: usize,
+    /// key of the next item to yield
+    next_out: usize,
+}
+
+impl UringReader {
+    /// Read many whole files, yielding each one as soon as it is complete, see `ReadManyStream`
+    ///
+    /// Same rules as `read_many_files` (`max_bytes`, per-file errors), but nothing waits for the whole
+    /// batch and a consumer can start on the first file while the others are still being read.
+    pub fn read_many_stream<P: AsRef<Path>>(
+        &self,
+        paths: impl IntoIterator<Item = P>,
+    ) -> ReadManyStream<'_> {
+        self.stream(paths, false)
+    }
+
+    /// Same as `read_many_stream`, but the files are yielded in the order of `paths`
+    ///
+    /// A file that finishes early is held back until every file before it was yielded. One slow file
+    /// stalls the stream once the window (`max_in_flight`) is full of files waiting behind it.
+    pub fn read_many_stream_ordered<P: AsRef<Path>>(
+        &self,
+        paths: impl IntoIterator<Item = P>,
+    ) -> ReadManyStream<'_> {
+        self.stream(paths, true)
+    }
+
+    fn stream<P: AsRef<Path>>(
+        &self,
+        paths: impl IntoIterator<Item = P>,
+        ordered: bool,
+    ) -> ReadManyStream<'_> {
+        let paths: Vec<PathBuf> = paths
+            .into_iter()
+            .map(|p| p.as_ref().to_path_buf())
+            .collect();
+        ReadManyStream {
+            session: self.session(),
+            reader: self,
+            paths: paths.into_iter(),
+            files: Vec::new(),
+            reqs: Vec::new(),
+            ready: BTreeMap::new(),
+            open: 0,
+            ordered,
+            taken: 0,
+            finished: 0,
+            next_out: 0,
+        }
+    }
+}
+
+impl ReadManyStream<'_> {
+    /// Open files until the window is full, opening errors go straight to `ready`
+    fn open_more(&mut self) {
+        while self.open + self.ready.len() < self.reader.config.max_in_flight {
+            let Some(path) = self.paths.next() else { break };
+            let index = self.taken;
+            self.taken += 1;
+            match File::open(&path) {
+                Ok(file) => {
+                    let stream_file = StreamFile {
+                        index,
+                        path,
+                        file,
+                        data: Vec::new(),
+                        sized: false,
+                        next: 0,
+                        filled: 0,
+                        pending: 0,
+                        done: false,
+                        error: None,
+                    };
+                    let slot = self.files.iter().position(Option::is_none);
+                    let slot = slot.unwrap_or_else(|| {
+                        self.files.push(None);
+                        self.files.len() - 1
+                    });
+                    self.files[slot] = Some(stream_file);
+                    self.open += 1;
+                    self.size(slot);
+                }
+                Err(e) => self.finish(index, path, Err(e)),
+            }
+        }
+    }
+
+    /// Size the buffer of a freshly opened file
+    fn size(&mut self, slot: usize) {
+        let reader = self.reader;
+        let file = self.files[slot].as_mut().expect("just opened");
+        let sized = file
+            .file
+            .metadata()
+            .map(|meta| meta.len())
+            .and_then(|size| {
+                if size > 0 {
+                    reader.check_size(&file.path, size)?;
+                }
+                Ok(size)
+            });
+        match sized {
+            Ok(0) => {}
+            Ok(size) => {
+                file.sized = true;
+                file.data = vec![0; size as usize];
+                file.filled = size as usize;
+            }
+            Err(e) => file.error = Some(e),
+        }
+    }
+
+    /// Issue reads for every open file, until `queue_depth` (or the auto-tuned depth) reads are in flight
+    #[allow(unused_doc_comments)]
+    fn issue(&mut self) {
+        let chunk_size = self.reader.config.chunk_size;
+        let depth = self.reader.depth();
+
+        for slot in 0..self.files.len() {
+            loop {
+                if self.session.in_flight() >= depth {
+                    return;
+                }
+                let Some(file) = self.files[slot].as_mut() else {
+                    break;
+                };
+                if file.error.is_some() || file.done {
+                    break;
+                }
+
+                let (offset, len) = if file.sized {
+                    if file.next >= file.filled {
+                        break;
+                    }
+                    (file.next, chunk_size.min(file.filled - file.next))
+                } else {
+                    /// One read at a time, the buffer is only grown while nothing is in flight
+                    if file.pending > 0 {
+                        break;
+                    }
+                    if let Err(e) = self.reader.check_size(&file.path, file.next as u64) {
+                        file.error = Some(e);
+                        break;
+                    }
+                    file.data.resize(file.next + chunk_size, 0);
+                    (file.next, chunk_size)
+                };
+                file.next = offset + len;
+
+                let req = Req {
+                    file: slot,
+                    offset,
+                    len,
+                    done: 0,
+                };
+                let id = match self.reqs.iter().position(Option::is_none) {
+                    Some(id) => id,
+                    None => {
+                        self.reqs.push(None);
+                        self.reqs.len() - 1
+                    }
+                };
+                self.reqs[id] = Some(req);
+                if let Err(e) = self.push(id) {
+                    self.reqs[id] = None;
+                    let file = self.files[slot].as_mut().expect("checked above");
+                    file.next = offset;
+                    if is_retryable(&e) {
+                        /// The shared submission queue is full, try again on the next poll
+                        return;
+                    }
+                    file.error = Some(e);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Push the (rest of the) read `id`
+    fn push(&mut self, id: usize) -> io::Result<()> {
+        let req = self.reqs[id].as_ref().expect("pushing a live request");
+        let file = self.files[req.file]
+            .as_mut()
+            .expect("request of a live file");
+        let start = req.offset + req.done;
+        let read_e = opcode::Read::new(
+            types::Fd(file.file.as_raw_fd()),
+            file.data[start..].as_mut_ptr(),
+            (req.len - req.done) as u32,
+        )
+        .offset(start as u64)
+        .build();
+        self.session.push(id as u32, read_e)?;
+        file.pending += 1;
+        Ok(())
+    }
+
+    /// Account one completion, resubmitting the rest of a short read
+    #[allow(unused_doc_comments)]
+    fn complete(&mut self, id: usize, result: io::Result<u32>) {
+        let req = self.reqs[id]
+            .as_mut()
+            .expect("completion of a live request");
+        let slot = req.file;
+        let file = self.files[slot].as_mut().expect("request of a live file");
+        file.pending -= 1;
+
+        let resubmit = match result {
+            Err(e) if is_retryable(&e) && file.error.is_none() => true,
+            Err(e) => {
+                file.error.get_or_insert(e);
+                false
+            }
+            Ok(0) => {
+                /// EOF before the end of the chunk: the file shrank, or it has no size and this is
+                /// its end
+                let end = req.offset + req.done;
+                file.filled = if file.sized {
+                    file.filled.min(end)
+                } else {
+                    end
+                };
+                file.next = file.next.min(end);
+                file.done = true;
+                false
+            }
+            Ok(n) => {
+                self.reader.tune(u64::from(n), self.session.in_flight() + 1);
+                req.done += n as usize;
+                if !file.sized {
+                    file.filled = req.offset + req.done;
+                }
+                req.done < req.len
+            }
+        };
+        if resubmit {
+            match self.push(id) {
+                Ok(()) => return,
+                Err(e) => {
+                    let file = self.files[slot].as_mut().expect("request of a live file");
+                    file.error.get_or_insert(e);
+                }
+            }
+        }
+        self.reqs[id] = None;
+
+        let file = self.files[slot].as_mut().expect("request of a live file");
+        let finished = file.pending == 0
+            && (file.error.is_some() || file.done || (file.sized && file.next >= file.filled));
+        if finished {
+            self.hand_out(slot);
+        }
+    }
+
+    /// Move a finished file to `ready`
+    fn hand_out(&mut self, slot: usize) {
+        let mut file = self.files[slot].take().expect("handing out a live file");
+        self.open -= 1;
+        let result = match file.error {
+            Some(e) => Err(e),
+            None => {
+                file.data.truncate(file.filled);
+                Ok(file.data)
+            }
+        };
+        self.finish(file.index, file.path, result);
+    }
+
+    fn finish(&mut self, index: usize, path: PathBuf, result: io::Result<Vec<u8>>) {
+        let key = if self.ordered { index } else { self.finished };
+        self.finished += 1;
+        self.ready.insert(key, (path, result));
+    }
+
+    /// The next item, if its turn has come
+    fn pop_ready(&mut self) -> Option<(PathBuf, io::Result<Vec<u8>>)> {
+        let item = self.ready.remove(&self.next_out)?;
+        self.next_out += 1;
+        Some(item)
+    }
+
+    /// Hand out files that failed before any read was issued
+    fn collect_idle(&mut self) {
+        for slot in 0..self.files.len() {
+            let idle = self.files[slot]
+                .as_ref()
+                .is_some_and(|f| f.pending == 0 && f.error.is_some());
+            if idle {
+                self.hand_out(slot);
+            }
+        }
+    }
+}
+
+impl Stream for ReadManyStream<'_> {
+    type Item = (PathBuf, io::Result<Vec<u8>>);
+
+    #[allow(unused_doc_comments)]
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            this.open_more();
+            this.issue();
+            if let Err(e) = this.session.submit() {
+                /// Nothing that was pushed reached the kernel, fail everything that is waiting for it
+                for file in this.files.iter_mut().flatten() {
+                    file.error.get_or_insert_with(|| copy_error(&e));
+                }
+            }
+            this.collect_idle();
+            if let Some(item) = this.pop_ready() {
+                return Poll::Ready(Some(item));
+            }
+            if this.open == 0 && this.paths.len() == 0 {
+                return Poll::Ready(None);
+            }
+
+            /// Register before looking, a completion that shows up in between still wakes us
+            this.reader.register_waker(cx.waker());
+            let Some(cqe) = this.session.try_next() else {
+                return Poll::Pending;
+            };
+            let id = (cqe.user_data() & u64::from(u32::MAX)) as usize;
+            this.complete(id, cqe.into_result());
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.paths.len() + self.open + self.ready.len();
+        (left, Some(left))
+    }
+}
+
+impl Drop for ReadManyStream<'_> {
+    #[allow(unused_doc_comments)]
+    fn drop(&mut self) {
+        /// The session drains on drop, canceling first makes that quick
+        self.session.cancel_all(Cancelled::ByUser);
+    }
+}
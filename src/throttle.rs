@@ -0,0 +1,163 @@
+#[cfg(target_os = "linux")]
+use std::time::{Duration, Instant};
+
+/// A bandwidth budget for background work (`UringConfig::rate_limit`)
+///
+/// - max_bytes_per_sec -> long run read rate, measured over what completed
+/// - max_requests_per_sec -> long run rate of completed requests, `None` (default) is no limit
+/// - burst -> bytes that may complete back to back before the rate kicks in, default `queue_depth` *
+///   `chunk_size` so a full queue is never held back
+///
+/// It's a token bucket that is charged when reads complete. New reads are only issued while the
+/// bucket isn't in debt: with reads in flight the reader just keeps reaping them, once nothing is in
+/// flight it sleeps until the debt is paid. So the sleep is always on the thread driving the call,
+/// between two submits, and never makes a request that is already in the kernel look slower.
+///
+/// ```no_run
+/// use uring_fast_read::{RateLimit, UringConfig};
+///
+/// let config = UringConfig::default().rate_limit(Some(RateLimit::new(50 << 20)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RateLimit {
+    pub(crate) max_bytes_per_sec: u64,
+    pub(crate) max_requests_per_sec: Option<u64>,
+    pub(crate) burst: Option<u64>,
+}
+
+impl RateLimit {
+    /// At most `max_bytes_per_sec` bytes a second
+    pub fn new(max_bytes_per_sec: u64) -> Self {
+        RateLimit {
+            max_bytes_per_sec: max_bytes_per_sec.max(1),
+            max_requests_per_sec: None,
+            burst: None,
+        }
+    }
+
+    /// Also at most this many reads a second, for disks that are bound by IOPS rather than bytes
+    pub fn max_requests_per_sec(mut self, requests: Option<u64>) -> Self {
+        self.max_requests_per_sec = requests.map(|requests| requests.max(1));
+        self
+    }
+
+    /// Bytes allowed back to back (default `None`, one full queue)
+    pub fn burst(mut self, bytes: Option<u64>) -> Self {
+        self.burst = bytes;
+        self
+    }
+}
+
+/// One bucket, `tokens` goes below zero when a completion costs more than was left
+#[cfg(target_os = "linux")]
+struct Bucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+}
+
+#[cfg(target_os = "linux")]
+impl Bucket {
+    fn new(rate: u64, burst: u64) -> Self {
+        Bucket {
+            rate: rate as f64,
+            burst: burst as f64,
+            tokens: burst as f64,
+        }
+    }
+
+    fn refill(&mut self, elapsed: Duration) {
+        self.tokens = (self.tokens + self.rate * elapsed.as_secs_f64()).min(self.burst);
+    }
+
+    /// How long until the bucket is out of debt
+    fn debt(&self) -> Duration {
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// The running limiter, one per reader
+/// - started -> first time somebody asked to submit, the start of the achieved rate
+/// - bytes -> everything that completed since then
+#[cfg(target_os = "linux")]
+pub(crate) struct Throttle {
+    bytes_bucket: Bucket,
+    requests_bucket: Option<Bucket>,
+    refilled: Instant,
+    started: Option<Instant>,
+    bytes: u64,
+}
+
+/// What `Throttle::admit` decided
+/// - Go -> issue new reads
+/// - Reap -> in debt, wait for what is in flight first
+/// - Sleep -> in debt with nothing in flight, sleep this long and go
+#[cfg(target_os = "linux")]
+pub(crate) enum Admit {
+    Go,
+    Reap,
+    Sleep(Duration),
+}
+
+#[cfg(target_os = "linux")]
+impl Throttle {
+    pub(crate) fn new(limit: &RateLimit, chunk_size: usize, queue_depth: u32) -> Self {
+        let full_queue = chunk_size as u64 * u64::from(queue_depth);
+        Throttle {
+            bytes_bucket: Bucket::new(limit.max_bytes_per_sec, limit.burst.unwrap_or(full_queue)),
+            requests_bucket: limit
+                .max_requests_per_sec
+                .map(|rate| Bucket::new(rate, u64::from(queue_depth))),
+            refilled: Instant::now(),
+            started: None,
+            bytes: 0,
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now - self.refilled;
+        self.refilled = now;
+        self.bytes_bucket.refill(elapsed);
+        if let Some(bucket) = &mut self.requests_bucket {
+            bucket.refill(elapsed);
+        }
+    }
+
+    /// May the caller issue new reads, `in_flight` -> its requests that still have to complete
+    pub(crate) fn admit(&mut self, in_flight: usize) -> Admit {
+        self.started.get_or_insert_with(Instant::now);
+        self.refill();
+        let debt = self.bytes_bucket.debt().max(
+            self.requests_bucket
+                .as_ref()
+                .map_or(Duration::ZERO, Bucket::debt),
+        );
+        if debt.is_zero() {
+            Admit::Go
+        } else if in_flight > 0 {
+            Admit::Reap
+        } else {
+            Admit::Sleep(debt)
+        }
+    }
+
+    /// A read completed with `bytes`, returns the achieved rate so far in bytes/s
+    pub(crate) fn completed(&mut self, bytes: u64) -> u64 {
+        self.refill();
+        self.bytes_bucket.tokens -= bytes as f64;
+        if let Some(bucket) = &mut self.requests_bucket {
+            bucket.tokens -= 1.0;
+        }
+        self.bytes += bytes;
+        let elapsed = self
+            .started
+            .map_or(Duration::ZERO, |started| started.elapsed());
+        (self.bytes as f64 / elapsed.as_secs_f64().max(1e-9)) as u64
+    }
+}
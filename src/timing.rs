@@ -0,0 +1,80 @@
+#[cfg(target_os = "linux")]
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+#[cfg(target_os = "linux")]
+use std::time::Instant;
+
+/// Where the time of one request went, only recorded with `UringConfig::record_timings(true)`
+/// - queued -> from the push into the SQ until the `io_uring_enter` that handed it to the kernel
+/// - in_kernel -> from that submit until its CQE was reaped
+/// - forced_async -> it was pushed with IOSQE_ASYNC (`UringConfig::force_async`), so `in_kernel`
+///   includes the hop to an io-wq worker
+///
+/// A big `queued` means requests pile up in the SQ (queue depth too high, submitting too late), a big
+/// `in_kernel` means the device (or the page cache miss path) is slow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RequestTiming {
+    #[cfg_attr(feature = "serde", serde(with = "crate::stats::nanos"))]
+    pub queued: Duration,
+    #[cfg_attr(feature = "serde", serde(with = "crate::stats::nanos"))]
+    pub in_kernel: Duration,
+    pub forced_async: bool,
+}
+
+/// Timestamps of the requests that are still in flight, keyed by user_data
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+pub(crate) struct Timings {
+    /// Pushed, not submitted yet
+    pushed: HashMap<u64, Instant>,
+    /// (pushed, submitted)
+    submitted: HashMap<u64, (Instant, Instant)>,
+    /// Pushed with IOSQE_ASYNC
+    forced_async: HashSet<u64>,
+}
+
+#[cfg(target_os = "linux")]
+impl Timings {
+    pub(crate) fn pushed(&mut self, user_data: u64, forced_async: bool) {
+        self.pushed.insert(user_data, Instant::now());
+        if forced_async {
+            self.forced_async.insert(user_data);
+        }
+    }
+
+    /// Everything pushed so far was just handed to the kernel
+    pub(crate) fn submitted(&mut self) {
+        let now = Instant::now();
+        for (user_data, pushed) in self.pushed.drain() {
+            self.submitted.insert(user_data, (pushed, now));
+        }
+    }
+
+    /// The CQE for `user_data` was reaped, `more` -> it is a multishot request that stays armed
+    pub(crate) fn reaped(&mut self, user_data: u64, more: bool) -> Option<RequestTiming> {
+        let now = Instant::now();
+        let (pushed, submitted) = match self.submitted.get(&user_data) {
+            Some(&times) => times,
+            /// Picked up without an enter of ours (SQPOLL), count it as submitted right away
+            #[allow(unused_doc_comments)]
+            None => {
+                let pushed = self.pushed.remove(&user_data)?;
+                self.submitted.insert(user_data, (pushed, pushed));
+                (pushed, pushed)
+            }
+        };
+        let forced_async = if more {
+            self.forced_async.contains(&user_data)
+        } else {
+            self.submitted.remove(&user_data);
+            self.forced_async.remove(&user_data)
+        };
+
+        Some(RequestTiming {
+            queued: submitted - pushed,
+            in_kernel: now - submitted,
+            forced_async,
+        })
+    }
+}
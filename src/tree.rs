@@ -0,0 +1,281 @@
+use std::fs::{self, Metadata};
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use crate::error::ReadError;
+use crate::stats::{CopyReport, TreeCopyReport, TreeEntry};
+use crate::walk::walk_files;
+
+/// What `copy_tree` does about a file or symlink that is already in the destination
+/// - Skip -> leave it as it is, the entry counts as skipped
+/// - Overwrite -> replace it. A file is truncated and written again, a symlink in the way is removed
+///   first (the copy never writes through it).
+/// - Error -> the entry fails with `io::ErrorKind::AlreadyExists`. The default.
+///
+/// Directories that are there already are always used as they are, the tree is merged into them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExistingPolicy {
+    Skip,
+    Overwrite,
+    #[default]
+    Error,
+}
+
+/// What `copy_tree` does with the symlinks of the source tree, none of them is ever followed
+/// - Skip -> leave them out, each one counts as skipped. The default.
+/// - Recreate -> make a link with the same target in the destination. The target is copied as it is:
+///   a relative one points into the copy, an absolute one to wherever it pointed before.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SymlinkPolicy {
+    #[default]
+    Skip,
+    Recreate,
+}
+
+/// How `copy_tree` copies, the setters consume and return `self` like the ones of `UringConfig`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CopyTreeOptions {
+    pub(crate) existing: ExistingPolicy,
+    pub(crate) symlinks: SymlinkPolicy,
+    pub(crate) preserve_sparse: Option<bool>,
+    pub(crate) fsync: bool,
+    pub(crate) fail_fast: bool,
+}
+
+impl CopyTreeOptions {
+    /// Files and symlinks already in the destination (default `ExistingPolicy::Error`)
+    pub fn existing(mut self, policy: ExistingPolicy) -> Self {
+        self.existing = policy;
+        self
+    }
+
+    /// Symlinks of the source tree (default `SymlinkPolicy::Skip`)
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    /// Keep the holes of sparse files, see `UringConfig::preserve_sparse` (default: whatever the
+    /// reader's config says)
+    pub fn preserve_sparse(mut self, on: bool) -> Self {
+        self.preserve_sparse = Some(on);
+        self
+    }
+
+    /// fsync every copied file before it counts as copied (default off)
+    ///
+    /// A file whose fsync fails is a failed entry, its data may not have reached the disk.
+    pub fn fsync(mut self, on: bool) -> Self {
+        self.fsync = on;
+        self
+    }
+
+    /// Stop at the first entry that fails, with `ReadError::TreeCopyAborted` (default off: the failure
+    /// is recorded and the rest of the tree is copied)
+    pub fn fail_fast(mut self, on: bool) -> Self {
+        self.fail_fast = on;
+        self
+    }
+}
+
+/// What `copy_tree` needs from a reader, the io_uring one does it through the ring
+pub(crate) trait TreeOps {
+    /// Create the directory `path` with `mode` (before the umask), its parent is there already
+    fn make_dir(&self, path: &Path, mode: u32) -> io::Result<()>;
+
+    /// Copy the regular file `src` to `dst`, `sparse` None for the reader's own setting
+    fn copy(
+        &self,
+        src: &Path,
+        dst: &Path,
+        sparse: Option<bool>,
+        fsync: bool,
+    ) -> io::Result<CopyReport>;
+
+    /// Create the symlink `link` pointing to `target`
+    fn make_link(&self, target: &Path, link: &Path) -> io::Result<()>;
+
+    /// Remove the file or symlink `path`
+    fn remove(&self, path: &Path) -> io::Result<()>;
+}
+
+/// Mode a directory is created with: that of the source, but writable for us until every entry is
+/// in (the exact permissions are set at the end)
+fn dir_mode(meta: &Metadata) -> u32 {
+    #[cfg(unix)]
+    return std::os::unix::fs::PermissionsExt::mode(&meta.permissions()) & 0o777 | 0o700;
+    #[cfg(not(unix))]
+    return {
+        let _ = meta;
+        0o777
+    };
+}
+
+impl TreeCopyReport {
+    /// Record what happened to `path`, Err if that stops the copy (`fail_fast`)
+    fn record(&mut self, path: PathBuf, entry: TreeEntry, fail_fast: bool) -> io::Result<()> {
+        match &entry {
+            TreeEntry::Copied(report) => {
+                let total = &mut self.total;
+                total.logical_size += report.logical_size;
+                total.copied += report.copied;
+                total.holes += report.holes;
+                total.dense_reason = total.dense_reason.or(report.dense_reason);
+                total.chunks += report.chunks;
+                total.buffers = total.buffers.max(report.buffers);
+                total.allocated += report.allocated;
+                self.files += 1;
+            }
+            TreeEntry::Linked => self.links += 1,
+            TreeEntry::Skipped => self.skipped += 1,
+            TreeEntry::Failed(_) => self.failed += 1,
+        }
+        match entry {
+            TreeEntry::Failed(source) if fail_fast => {
+                self.entries.sort_by(|a, b| a.0.cmp(&b.0));
+                Err(ReadError::TreeCopyAborted {
+                    path,
+                    source,
+                    report: mem::take(self),
+                }
+                .into())
+            }
+            entry => {
+                self.entries.push((path, entry));
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Whether something is at `dst` already, and what `options.existing` makes of it
+///
+/// Ok(None) -> go ahead (nothing there, or overwrite it)
+/// Ok(Some(entry)) -> done with this entry, skipped or failed
+/// Err(e) -> `dst` couldn't be looked at
+fn check_existing(
+    ops: &impl TreeOps,
+    dst: &Path,
+    options: &CopyTreeOptions,
+) -> io::Result<Option<TreeEntry>> {
+    let meta = match fs::symlink_metadata(dst) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    match options.existing {
+        ExistingPolicy::Skip => Ok(Some(TreeEntry::Skipped)),
+        ExistingPolicy::Error => Ok(Some(TreeEntry::Failed(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists (ExistingPolicy::Error)", dst.display()),
+        )))),
+        // The copy truncates a file in place, a symlink must go or the copy would land at its target
+        ExistingPolicy::Overwrite if meta.is_file() => Ok(None),
+        ExistingPolicy::Overwrite => ops.remove(dst).map(|()| None),
+    }
+}
+
+/// A regular file of the tree
+fn copy_entry(ops: &impl TreeOps, src: &Path, dst: &Path, options: &CopyTreeOptions) -> TreeEntry {
+    match check_existing(ops, dst, options) {
+        Ok(None) => {}
+        Ok(Some(entry)) => return entry,
+        Err(e) => return TreeEntry::Failed(e),
+    }
+    match ops.copy(src, dst, options.preserve_sparse, options.fsync) {
+        Ok(report) => TreeEntry::Copied(report),
+        Err(e) => TreeEntry::Failed(e),
+    }
+}
+
+/// A symlink of the tree
+fn link_entry(ops: &impl TreeOps, src: &Path, dst: &Path, options: &CopyTreeOptions) -> TreeEntry {
+    if options.symlinks == SymlinkPolicy::Skip {
+        return TreeEntry::Skipped;
+    }
+    let linked = fs::read_link(src).and_then(|target| {
+        if let Some(entry) = check_existing(ops, dst, options)? {
+            return Ok(entry);
+        }
+        ops.make_link(&target, dst).map(|()| TreeEntry::Linked)
+    });
+    linked.unwrap_or_else(TreeEntry::Failed)
+}
+
+/// `copy_tree` of both readers, everything but the file system calls
+///
+/// The source tree is walked completely before the first directory is created, so a destination
+/// inside of the source doesn't copy itself.
+#[allow(unused_doc_comments)]
+pub(crate) fn copy_tree(
+    ops: &impl TreeOps,
+    src_root: &Path,
+    dst_root: &Path,
+    options: &CopyTreeOptions,
+) -> io::Result<TreeCopyReport> {
+    let walk = walk_files(src_root)?;
+    let root_meta = fs::metadata(src_root)?;
+    let dst_of = |path: &Path| {
+        let relative = path.strip_prefix(src_root);
+        dst_root.join(relative.expect("the walk only returns paths below its root"))
+    };
+    let mut report = TreeCopyReport::default();
+
+    /// Directories this copy created (source, copy) and the permissions they get once they are filled
+    let mut created = Vec::new();
+    let mut make_dir = |src: &Path, dst: &Path, meta: &Metadata| -> io::Result<bool> {
+        match ops.make_dir(dst, dir_mode(meta)) {
+            Ok(()) => {
+                created.push((src.to_path_buf(), dst.to_path_buf(), meta.permissions()));
+                Ok(true)
+            }
+            Err(e)
+                if e.kind() == io::ErrorKind::AlreadyExists
+                    && fs::metadata(dst).is_ok_and(|meta| meta.is_dir()) =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    };
+    // Without its root nothing of the tree can be copied
+    report.dirs += usize::from(make_dir(src_root, dst_root, &root_meta)?);
+
+    for (path, e) in walk.errors {
+        report.record(path, TreeEntry::Failed(e), options.fail_fast)?;
+    }
+    for dir in walk.dirs {
+        let made = fs::symlink_metadata(&dir).and_then(|meta| make_dir(&dir, &dst_of(&dir), &meta));
+        match made {
+            Ok(made) => report.dirs += usize::from(made),
+            // What was below it fails on its own, with ENOENT
+            Err(e) => report.record(dir, TreeEntry::Failed(e), options.fail_fast)?,
+        }
+    }
+
+    let mut entries: Vec<(PathBuf, bool)> =
+        walk.files.into_iter().map(|path| (path, false)).collect();
+    entries.extend(walk.links.into_iter().map(|path| (path, true)));
+    entries.sort();
+    for (path, is_link) in entries {
+        let dst = dst_of(&path);
+        let entry = if is_link {
+            link_entry(ops, &path, &dst, options)
+        } else {
+            copy_entry(ops, &path, &dst, options)
+        };
+        report.record(path, entry, options.fail_fast)?;
+    }
+
+    /// Deepest first, a read-only parent would refuse the ones below it
+    for (src, dst, permissions) in created.into_iter().rev() {
+        if let Err(e) = fs::set_permissions(&dst, permissions) {
+            report.record(src, TreeEntry::Failed(e), options.fail_fast)?;
+        }
+    }
+    report.entries.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(report)
+}
@@ -0,0 +1,175 @@
+#[cfg(target_os = "linux")]
+use std::time::{Duration, Instant};
+
+/// Settings of the queue depth auto-tuner (`UringConfig::auto_tune`)
+///
+/// The tuner starts at `start` reads in flight and looks at every `window` completions:
+/// - throughput went up by more than 5% -> one more read in flight next time (additive increase)
+/// - throughput dropped by more than 10%, or stayed flat while latency grew by more than 25% ->
+///   a quarter fewer (multiplicative decrease)
+/// - anything else -> keep the depth
+///
+/// The depth always stays within `min..=max`, the ring's SQ and CQ sizes, and `memory_budget`
+/// (bytes of chunk buffers in flight at once). It is shared by everything on the reader, so what it
+/// learned carries over from one call to the next. `ReadStats::tuned_depth` / `depth_history` show
+/// where it went, pin that with `UringConfig::queue_depth` once you know.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct AutoTune {
+    pub(crate) start: u32,
+    pub(crate) min: u32,
+    pub(crate) max: u32,
+    pub(crate) window: u32,
+    pub(crate) memory_budget: Option<usize>,
+}
+
+impl Default for AutoTune {
+    fn default() -> Self {
+        AutoTune {
+            start: 8,
+            min: 1,
+            max: u32::MAX,
+            window: 64,
+            memory_budget: None,
+        }
+    }
+}
+
+impl AutoTune {
+    /// Same as `AutoTune::default()`: start at 8, no bounds besides the ring, windows of 64
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Depth to start with (default 8)
+    pub fn start(mut self, depth: u32) -> Self {
+        self.start = depth.max(1);
+        self
+    }
+
+    /// Lower and upper bound of the depth (default 1 and the ring size)
+    pub fn bounds(mut self, min: u32, max: u32) -> Self {
+        self.min = min.max(1);
+        self.max = max.max(self.min);
+        self
+    }
+
+    /// Completions per measurement window (default 64), bigger is steadier but slower to react
+    pub fn window(mut self, completions: u32) -> Self {
+        self.window = completions.max(1);
+        self
+    }
+
+    /// Most bytes of chunk buffers in flight (depth * `chunk_size`), `None` is no limit (default)
+    pub fn memory_budget(mut self, bytes: Option<usize>) -> Self {
+        self.memory_budget = bytes;
+        self
+    }
+}
+
+/// Most depth changes kept in `ReadStats::depth_history`
+#[cfg(target_os = "linux")]
+const HISTORY: usize = 256;
+
+/// The running tuner, one per reader
+#[cfg(target_os = "linux")]
+pub(crate) struct Tuner {
+    depth: u32,
+    min: u32,
+    max: u32,
+    window: u32,
+    /// current window
+    started: Instant,
+    completions: u32,
+    bytes: u64,
+    in_flight_sum: u64,
+    /// (throughput in bytes/s, latency) of the previous window
+    last: Option<(f64, Duration)>,
+}
+
+/// What a finished window decided, for the stats
+#[cfg(target_os = "linux")]
+pub(crate) struct Decision {
+    pub(crate) depth: u32,
+    pub(crate) changed: bool,
+}
+
+#[cfg(target_os = "linux")]
+impl Tuner {
+    /// `limit` -> the hard cap from the ring (SQ and CQ entries)
+    pub(crate) fn new(tune: &AutoTune, chunk_size: usize, limit: u32) -> Self {
+        let budget = tune.memory_budget.map_or(u32::MAX, |bytes| {
+            (bytes / chunk_size.max(1)).clamp(1, u32::MAX as usize) as u32
+        });
+        let max = tune.max.min(limit).min(budget).max(1);
+        let min = tune.min.min(max);
+        Tuner {
+            depth: tune.start.clamp(min, max),
+            min,
+            max,
+            window: tune.window,
+            started: Instant::now(),
+            completions: 0,
+            bytes: 0,
+            in_flight_sum: 0,
+            last: None,
+        }
+    }
+
+    pub(crate) fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    /// One read completed with `bytes`, `in_flight` requests were outstanding when it did
+    ///
+    /// Returns the decision once a window is full.
+    #[allow(unused_doc_comments)]
+    pub(crate) fn completed(&mut self, bytes: u64, in_flight: usize) -> Option<Decision> {
+        self.completions += 1;
+        self.bytes += bytes;
+        self.in_flight_sum += in_flight as u64;
+        if self.completions < self.window {
+            return None;
+        }
+
+        /// Little's law: latency = average in flight / completion rate
+        let elapsed = self.started.elapsed().max(Duration::from_nanos(1));
+        let throughput = self.bytes as f64 / elapsed.as_secs_f64();
+        let avg_in_flight = self.in_flight_sum as f64 / self.completions as f64;
+        let latency = elapsed.mul_f64(avg_in_flight / self.completions as f64);
+
+        let old = self.depth;
+        self.depth = match self.last {
+            None => self.depth + 1,
+            Some((last_tp, last_latency)) => {
+                if throughput > last_tp * 1.05 {
+                    self.depth + 1
+                } else if throughput < last_tp * 0.9 || latency > last_latency.mul_f64(1.25) {
+                    self.depth - self.depth / 4
+                } else {
+                    self.depth
+                }
+            }
+        }
+        .clamp(self.min, self.max);
+
+        self.last = Some((throughput, latency));
+        self.started = Instant::now();
+        self.completions = 0;
+        self.bytes = 0;
+        self.in_flight_sum = 0;
+        Some(Decision {
+            depth: self.depth,
+            changed: self.depth != old,
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn push_history(history: &mut Vec<u32>, depth: u32) {
+    if history.len() == HISTORY {
+        history.remove(0);
+    }
+    history.push(depth);
+}
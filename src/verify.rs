@@ -0,0 +1,286 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::config::VerifyPolicy;
+use crate::error::ReadError;
+use crate::reader::{UringReader, lock};
+use crate::stats::{FileStamp, ReadStats};
+
+/// Threads doing the std reads, each with one `STD_CHUNK` buffer
+const WORKERS: usize = 2;
+
+/// Checks waiting for a worker, `VerifyPolicy::Count` skips the ones that don't fit
+const QUEUED: usize = 1024;
+
+/// What a worker reads at a time and hashes, the whole file is never held
+const STD_CHUNK: usize = 256 * 1024;
+
+/// A hash that can be fed piece by piece: XXH3 with the feature, std's SipHash without
+struct ContentHash {
+    #[cfg(feature = "xxh3")]
+    state: Box<xxhash_rust::xxh3::Xxh3>,
+    #[cfg(not(feature = "xxh3"))]
+    state: std::hash::DefaultHasher,
+}
+
+impl ContentHash {
+    fn new() -> Self {
+        ContentHash {
+            state: Default::default(),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        #[cfg(feature = "xxh3")]
+        self.state.update(data);
+        #[cfg(not(feature = "xxh3"))]
+        std::hash::Hasher::write(&mut self.state, data);
+    }
+
+    fn finish(self) -> u128 {
+        #[cfg(feature = "xxh3")]
+        return self.state.digest128();
+        #[cfg(not(feature = "xxh3"))]
+        return u128::from(std::hash::Hasher::finish(&self.state));
+    }
+
+    fn of(data: &[u8]) -> u128 {
+        let mut hash = ContentHash::new();
+        hash.update(data);
+        hash.finish()
+    }
+}
+
+/// One whole-file read to check: what the ring returned and the stamp from before it was read
+struct Check {
+    path: PathBuf,
+    before: FileStamp,
+    len: u64,
+    hash: u128,
+    /// Where `VerifyPolicy::Fail` waits for the verdict
+    reply: Option<mpsc::Sender<Verdict>>,
+}
+
+/// How a check came out
+/// - Raced -> the file changed between the reads (or went away), nothing to compare
+/// - Skipped -> the std read failed with the file unchanged, nothing to compare either
+enum Verdict {
+    Match,
+    Mismatch { std_len: u64 },
+    Raced,
+    Skipped,
+}
+
+/// Counted by the workers, added to `ReadStats` by `UringReader::stats`
+#[derive(Default)]
+struct Counters {
+    verified: AtomicU64,
+    mismatches: AtomicU64,
+    raced: AtomicU64,
+    skipped: AtomicU64,
+}
+
+/// The running `UringConfig::verify_against_std` of one reader
+///
+/// The workers go away with the reader: dropping the sender ends their loop once the queue is empty.
+pub(crate) struct Verifier {
+    policy: VerifyPolicy,
+    jobs: SyncSender<Check>,
+    counters: Arc<Counters>,
+}
+
+impl Verifier {
+    /// None for `VerifyPolicy::Off`, or if not even one worker could be started
+    pub(crate) fn start(policy: VerifyPolicy) -> Option<Self> {
+        if policy == VerifyPolicy::Off {
+            return None;
+        }
+        let (jobs, queue) = mpsc::sync_channel(QUEUED);
+        let queue = Arc::new(Mutex::new(queue));
+        let counters = Arc::new(Counters::default());
+        let mut started = 0;
+        for n in 0..WORKERS {
+            let (queue, counters) = (Arc::clone(&queue), Arc::clone(&counters));
+            let worker = thread::Builder::new()
+                .name(format!("uring-verify-{n}"))
+                .spawn(move || work(&queue, &counters));
+            started += usize::from(worker.is_ok());
+        }
+        (started > 0).then(|| Verifier {
+            policy,
+            jobs,
+            counters,
+        })
+    }
+
+    pub(crate) fn add_to(&self, stats: &mut ReadStats) {
+        let counters = &self.counters;
+        stats.verified_reads += counters.verified.load(Ordering::Relaxed);
+        stats.verify_mismatches += counters.mismatches.load(Ordering::Relaxed);
+        stats.verify_raced += counters.raced.load(Ordering::Relaxed);
+        stats.verify_skipped += counters.skipped.load(Ordering::Relaxed);
+    }
+
+    /// Queue the check of `data`, the ring's read of `path`
+    ///
+    /// Count -> returns right away, None. A full queue skips the check.
+    /// Fail -> waits for room in the queue, the verdict comes out of the receiver
+    fn queue(&self, path: &Path, before: FileStamp, data: &[u8]) -> Option<Receiver<Verdict>> {
+        let (reply, verdict) = match self.policy {
+            VerifyPolicy::Fail => {
+                let (reply, verdict) = mpsc::channel();
+                (Some(reply), Some(verdict))
+            }
+            VerifyPolicy::Off | VerifyPolicy::Count => (None, None),
+        };
+        let check = Check {
+            path: path.to_path_buf(),
+            before,
+            len: data.len() as u64,
+            hash: ContentHash::of(data),
+            reply,
+        };
+        let queued = match self.policy {
+            VerifyPolicy::Fail => self.jobs.send(check).is_ok(),
+            VerifyPolicy::Off | VerifyPolicy::Count => match self.jobs.try_send(check) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => false,
+            },
+        };
+        if !queued {
+            self.counters.skipped.fetch_add(1, Ordering::Relaxed);
+        }
+        verdict.filter(|_| queued)
+    }
+}
+
+/// `FileStamp` of `path` right now, None if it can't be stated
+fn stamp_now(path: &Path) -> Option<FileStamp> {
+    FileStamp::from_metadata(&fs::metadata(path).ok()?).ok()
+}
+
+/// A worker: take checks off the queue until the reader is gone
+fn work(queue: &Mutex<Receiver<Check>>, counters: &Counters) {
+    let mut buf = vec![0u8; STD_CHUNK];
+    loop {
+        let Ok(check) = lock(queue).recv() else {
+            return;
+        };
+        let verdict = compare(&check, &mut buf);
+        let counter = match verdict {
+            Verdict::Match => &counters.verified,
+            Verdict::Mismatch { .. } => &counters.mismatches,
+            Verdict::Raced => &counters.raced,
+            Verdict::Skipped => &counters.skipped,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        if let Some(reply) = check.reply {
+            let _ = reply.send(verdict);
+        }
+    }
+}
+
+/// Read `check.path` with std in `buf` sized pieces and hash it like the ring's data was
+///
+/// The file must have the stamp of before the ring's read both before and after this read, else it
+/// changed somewhere in between and a difference says nothing about the ring.
+fn compare(check: &Check, buf: &mut [u8]) -> Verdict {
+    if stamp_now(&check.path) != Some(check.before) {
+        return Verdict::Raced;
+    }
+    let read = (|| {
+        let mut file = File::open(&check.path)?;
+        let mut hash = ContentHash::new();
+        let mut len = 0u64;
+        loop {
+            let n = match file.read(buf) {
+                Ok(0) => return Ok((len, hash.finish())),
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            hash.update(&buf[..n]);
+            len += n as u64;
+        }
+    })();
+    if stamp_now(&check.path) != Some(check.before) {
+        return Verdict::Raced;
+    }
+    match read {
+        Ok((len, hash)) if len == check.len && hash == check.hash => Verdict::Match,
+        Ok((std_len, _)) => Verdict::Mismatch { std_len },
+        Err(_) => Verdict::Skipped,
+    }
+}
+
+/// The stamp of a file from before its whole-file read, None if it isn't checked
+pub(crate) struct Verifying(Option<FileStamp>);
+
+impl UringReader {
+    /// Call before a whole-file read of `path`, hand the result to `verified`
+    pub(crate) fn verifying(&self, path: &Path) -> Verifying {
+        Verifying(self.verifier.as_ref().and_then(|_| stamp_now(path)))
+    }
+
+    /// `result` of the whole-file read of `path`, checked against `std::fs::read` when it succeeded
+    ///
+    /// Count -> `result` as it is, the check runs in the background
+    /// Fail -> waits for the check, `ReadError::VerificationFailed` if the contents differ
+    pub(crate) fn verified<T: AsRef<[u8]>>(
+        &self,
+        path: &Path,
+        verifying: Verifying,
+        result: io::Result<T>,
+    ) -> io::Result<T> {
+        self.verified_many(&[path], vec![verifying], vec![result])
+            .pop()
+            .expect("one result in, one out")
+    }
+
+    /// `verified` for a batch, all checks are queued before the first one is waited on
+    pub(crate) fn verified_many<T: AsRef<[u8]>>(
+        &self,
+        paths: &[&Path],
+        verifying: Vec<Verifying>,
+        results: Vec<io::Result<T>>,
+    ) -> Vec<io::Result<T>> {
+        let Some(verifier) = &self.verifier else {
+            return results;
+        };
+        let verdicts: Vec<Option<Receiver<Verdict>>> = paths
+            .iter()
+            .zip(verifying)
+            .zip(&results)
+            .map(|((path, verifying), result)| match (verifying.0, result) {
+                (Some(before), Ok(data)) => verifier.queue(path, before, data.as_ref()),
+                _ => None,
+            })
+            .collect();
+        results
+            .into_iter()
+            .zip(verdicts)
+            .zip(paths)
+            .map(|((result, verdict), path)| {
+                let Some(verdict) = verdict else {
+                    return result;
+                };
+                match (verdict.recv(), result) {
+                    (Ok(Verdict::Mismatch { std_len }), Ok(data)) => {
+                        Err(ReadError::VerificationFailed {
+                            path: path.to_path_buf(),
+                            len: data.as_ref().len() as u64,
+                            std_len,
+                        }
+                        .into())
+                    }
+                    (_, result) => result,
+                }
+            })
+            .collect()
+    }
+}
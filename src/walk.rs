@@ -0,0 +1,68 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Result of walking a directory tree
+/// - files -> every regular file found, sorted by path
+/// - dirs -> every directory below the root (not the root itself), sorted, parents before children
+/// - links -> every symlink found, sorted by path, not followed
+/// - errors -> directories (or entries) that could not be looked at, with the reason
+pub(crate) struct Walk {
+    pub(crate) files: Vec<PathBuf>,
+    pub(crate) dirs: Vec<PathBuf>,
+    pub(crate) links: Vec<PathBuf>,
+    pub(crate) errors: Vec<(PathBuf, io::Error)>,
+}
+
+/// Collect every regular file under `root`
+///
+/// Symlinks are not followed (a link to a directory could loop forever, a link to a file is skipped),
+/// so everything in `files` is a real file inside the tree, the links themselves are in `links`.
+/// Only failing to list `root` itself is an error, anything below it ends up in `Walk::errors`.
+pub(crate) fn walk_files(root: &Path) -> io::Result<Walk> {
+    let mut walk = Walk {
+        files: Vec::new(),
+        dirs: Vec::new(),
+        links: Vec::new(),
+        errors: Vec::new(),
+    };
+    let mut dirs = vec![root.to_path_buf()];
+    let mut first = true;
+
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if first => return Err(e),
+            Err(e) => {
+                walk.errors.push((dir, e));
+                continue;
+            }
+        };
+        first = false;
+
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    walk.errors.push((dir.clone(), e));
+                    continue;
+                }
+            };
+            match entry.file_type() {
+                Ok(t) if t.is_dir() => {
+                    walk.dirs.push(entry.path());
+                    dirs.push(entry.path());
+                }
+                Ok(t) if t.is_file() => walk.files.push(entry.path()),
+                Ok(t) if t.is_symlink() => walk.links.push(entry.path()),
+                Ok(_) => {}
+                Err(e) => walk.errors.push((entry.path(), e)),
+            }
+        }
+    }
+
+    walk.files.sort();
+    walk.dirs.sort();
+    walk.links.sort();
+    Ok(walk)
+}
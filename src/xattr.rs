@@ -0,0 +1,212 @@
+use io_uring::{opcode, types};
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::ptr;
+
+use crate::reader::UringReader;
+
+/// What `read_with_xattrs` returns
+/// - data -> the file contents
+/// - xattrs -> one result per requested name, same order
+#[derive(Debug)]
+pub struct FileWithXattrs {
+    pub data: Vec<u8>,
+    pub xattrs: Vec<io::Result<Vec<u8>>>,
+}
+
+/// First buffer size for a value, most labels and user.* values fit
+const VALUE_GUESS: usize = 256;
+
+/// A value of unknown size: try `guess` bytes, on ERANGE ask for the size (len 0) and try again
+///
+/// The value can grow between asking and reading, that is just another ERANGE and another round.
+fn sized_value(
+    mut guess: usize,
+    mut get: impl FnMut(*mut libc::c_void, usize) -> io::Result<usize>,
+) -> io::Result<Vec<u8>> {
+    loop {
+        let mut value = vec![0u8; guess];
+        match get(value.as_mut_ptr().cast(), guess) {
+            Ok(n) => {
+                value.truncate(n);
+                return Ok(value);
+            }
+            Err(e) if e.raw_os_error() == Some(libc::ERANGE) => {
+                guess = get(ptr::null_mut(), 0)?;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub(crate) fn c_string(bytes: &[u8]) -> io::Result<CString> {
+    CString::new(bytes)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "name contains a NUL byte"))
+}
+
+/// -1 -> errno, anything else is the result
+fn cvt(res: isize) -> io::Result<usize> {
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res as usize)
+    }
+}
+
+impl UringReader {
+    /// The value of the extended attribute `name` of `path` (follows symlinks, like `getxattr(2)`)
+    ///
+    /// Uses `IORING_OP_GETXATTR` (Linux 5.19), the plain syscall on kernels without it. A value bigger
+    /// than the first guess costs two more requests (ask for the size, read again).
+    ///
+    /// Err(e) -> ENODATA if there is no such attribute, ENOTSUP if the filesystem has no xattrs
+    pub fn get_xattr(&self, path: impl AsRef<Path>, name: &str) -> io::Result<Vec<u8>> {
+        let c_path = c_string(path.as_ref().as_os_str().as_bytes())?;
+        let c_name = c_string(name.as_bytes())?;
+
+        if !self.is_supported(opcode::GetXattr::CODE) {
+            self.metrics.fell_back();
+            return sized_value(VALUE_GUESS, |value, len| {
+                // SAFETY: both strings are NUL terminated, `value` has room for `len` bytes
+                cvt(unsafe { libc::getxattr(c_path.as_ptr(), c_name.as_ptr(), value, len) })
+            });
+        }
+        sized_value(VALUE_GUESS, |value, len| {
+            self.run_one(
+                opcode::GetXattr::new(c_name.as_ptr(), value, c_path.as_ptr(), len as u32).build(),
+            )
+        })
+    }
+
+    /// Set the extended attribute `name` of `path` to `value`, creating or replacing it
+    ///
+    /// Uses `IORING_OP_SETXATTR` (Linux 5.19), the plain syscall on kernels without it.
+    pub fn set_xattr(&self, path: impl AsRef<Path>, name: &str, value: &[u8]) -> io::Result<()> {
+        let c_path = c_string(path.as_ref().as_os_str().as_bytes())?;
+        let c_name = c_string(name.as_bytes())?;
+
+        if !self.is_supported(opcode::SetXattr::CODE) {
+            self.metrics.fell_back();
+            // SAFETY: both strings are NUL terminated, `value` is valid for its length
+            let res = unsafe {
+                libc::setxattr(
+                    c_path.as_ptr(),
+                    c_name.as_ptr(),
+                    value.as_ptr().cast(),
+                    value.len(),
+                    0,
+                )
+            };
+            return cvt(res as isize).map(|_| ());
+        }
+        let len = u32::try_from(value.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "xattr value too large"))?;
+        let set_e =
+            opcode::SetXattr::new(c_name.as_ptr(), value.as_ptr().cast(), c_path.as_ptr(), len)
+                .build();
+        self.run_one(set_e).map(|_| ())
+    }
+
+    /// The whole file plus the xattrs `names`, the first attempt at every value goes out with the
+    /// first batch of content reads (one `io_uring_enter`)
+    ///
+    /// Ok(file) -> the contents, and one result per name in the order of `names`
+    /// Err(e) -> opening or reading the contents failed, same as `read_file_to_vec`
+    #[allow(unused_doc_comments)]
+    pub fn read_with_xattrs(
+        &self,
+        path: impl AsRef<Path>,
+        names: &[&str],
+    ) -> io::Result<FileWithXattrs> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let c_names = names
+            .iter()
+            .map(|name| c_string(name.as_bytes()))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        if !self.is_supported(opcode::FGetXattr::CODE) {
+            self.metrics.fell_back();
+            let xattrs = c_names.iter().map(|name| fget_xattr(&file, name)).collect();
+            let data = self.read_open_file(file.try_clone()?, path)?;
+            return Ok(FileWithXattrs { data, xattrs });
+        }
+
+        /// The value buffers and the names are declared before the session
+        let mut values: Vec<Vec<u8>> = c_names.iter().map(|_| vec![0u8; VALUE_GUESS]).collect();
+        let mut results: Vec<Option<io::Result<Vec<u8>>>> = c_names.iter().map(|_| None).collect();
+        let fd = types::Fd(file.as_raw_fd());
+        let mut session = self.session();
+        for (slot, (name, value)) in c_names.iter().zip(&mut values).enumerate() {
+            let get_e = opcode::FGetXattr::new(
+                fd,
+                name.as_ptr(),
+                value.as_mut_ptr().cast(),
+                VALUE_GUESS as u32,
+            )
+            .build();
+            if let Err(e) = session.push(slot as u32, get_e) {
+                results[slot] = Some(Err(e));
+            }
+        }
+
+        /// Not submitted on purpose, the first submit of the content read takes them along
+        let data = self.read_fd(&file, path)?;
+        session.submit()?;
+
+        while session.in_flight() > 0 {
+            let cqe = session.next()?;
+            let slot = (cqe.user_data() & u64::from(u32::MAX)) as usize;
+            results[slot] = Some(match cqe.into_result() {
+                Ok(n) => {
+                    let mut value = std::mem::take(&mut values[slot]);
+                    value.truncate(n as usize);
+                    Ok(value)
+                }
+                Err(e) if e.raw_os_error() == Some(libc::ERANGE) => {
+                    let name = &c_names[slot];
+                    sized_value(VALUE_GUESS, |value, len| {
+                        self.run_one(
+                            opcode::FGetXattr::new(fd, name.as_ptr(), value, len as u32).build(),
+                        )
+                    })
+                }
+                Err(e) => Err(e),
+            });
+        }
+        drop(session);
+
+        let xattrs = results
+            .into_iter()
+            .map(|result| result.expect("every xattr request completed"))
+            .collect();
+        Ok(FileWithXattrs { data, xattrs })
+    }
+
+    /// `read_open_file` without giving up the file, the xattr requests still use its fd
+    fn read_fd(&self, file: &File, path: &Path) -> io::Result<Vec<u8>> {
+        let size = file.metadata()?.len();
+        if size == 0 {
+            return self.read_open_file(file.try_clone()?, path);
+        }
+        self.check_size(path, size)?;
+        let mut data = vec![0u8; size as usize];
+        let n = self.read_into(types::Fd(file.as_raw_fd()), &mut data, 0)?;
+        self.check_shrunk(path, data.len(), n)?;
+        data.truncate(n);
+        Ok(data)
+    }
+}
+
+/// `fgetxattr(2)` with the sizing loop, for kernels without the opcode
+fn fget_xattr(file: &File, name: &CString) -> io::Result<Vec<u8>> {
+    sized_value(VALUE_GUESS, |value, len| {
+        // SAFETY: the name is NUL terminated, `value` has room for `len` bytes
+        cvt(unsafe { libc::fgetxattr(file.as_raw_fd(), name.as_ptr(), value, len) })
+    })
+}
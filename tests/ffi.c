@@ -0,0 +1,53 @@
+/* Smoke test of the C ABI, compiled against include/uring_fast_read.h and run by tests/ffi.rs
+ *
+ * ffi <path> <contents of path> <path that doesn't exist>
+ */
+
+#include <errno.h>
+#include <stdio.h>
+#include <string.h>
+
+#include "uring_fast_read.h"
+
+#define CHECK(cond)                                                          \
+    do {                                                                     \
+        if (!(cond)) {                                                       \
+            fprintf(stderr, "%s:%d: failed: %s\n", __FILE__, __LINE__, #cond); \
+            return 1;                                                        \
+        }                                                                    \
+    } while (0)
+
+int main(int argc, char **argv) {
+    CHECK(argc == 4);
+    const char *path = argv[1];
+    const char *expected = argv[2];
+    const char *missing = argv[3];
+    uint8_t *buf = (uint8_t *)1;
+    size_t len = 1;
+
+    /* One-shot reads, a ring of their own */
+    CHECK(uring_fast_read(path, &buf, &len) == 0);
+    CHECK(len == strlen(expected) && memcmp(buf, expected, len) == 0);
+    uring_buf_free(buf, len);
+
+    CHECK(uring_fast_read(missing, &buf, &len) == ENOENT);
+    CHECK(buf == NULL && len == 0);
+    uring_buf_free(NULL, 0);
+
+    /* A persistent reader, read twice */
+    struct UringReader *reader = NULL;
+    CHECK(uring_reader_new(0, &reader) == 0);
+    CHECK(reader != NULL);
+    for (int i = 0; i < 2; i++) {
+        CHECK(uring_reader_read(reader, path, &buf, &len) == 0);
+        CHECK(len == strlen(expected) && memcmp(buf, expected, len) == 0);
+        uring_buf_free(buf, len);
+    }
+    buf = (uint8_t *)1;
+    CHECK(uring_reader_read(reader, missing, &buf, &len) == ENOENT);
+    CHECK(buf == NULL && len == 0);
+    uring_reader_free(reader);
+    uring_reader_free(NULL);
+
+    return 0;
+}
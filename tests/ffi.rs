@@ -0,0 +1,79 @@
+//! Compiles `tests/ffi.c` against `include/uring_fast_read.h` and the cdylib, and runs it (feature
+//! `ffi`, Linux only)
+
+#![cfg(target_os = "linux")]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Build the cdylib with only `ffi` into a target directory of its own, the directory it is in
+///
+/// The one of the surrounding `cargo test` won't do: a cdylib has no hash in its name, so builds with
+/// other features (without the C symbols) overwrite it and cargo still thinks it's fresh.
+fn build_cdylib(root: &Path, target_dir: &Path) -> PathBuf {
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let status = Command::new(cargo)
+        .current_dir(root)
+        .args([
+            "build",
+            "--lib",
+            "--no-default-features",
+            "--features",
+            "ffi",
+        ])
+        .arg("--target-dir")
+        .arg(target_dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "building the cdylib failed");
+    let dir = target_dir.join("debug");
+    assert!(dir.join("liburing_fast_read.so").exists());
+    dir
+}
+
+#[test]
+fn c_program_reads_through_the_header() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let lib_dir = build_cdylib(root, &out.join("ffi-cdylib"));
+    let exe = out.join("ffi");
+
+    let target = format!("{}-unknown-linux-gnu", std::env::consts::ARCH);
+    let compiler = cc::Build::new()
+        .cargo_metadata(false)
+        .opt_level(0)
+        .host(&target)
+        .target(&target)
+        .get_compiler();
+    let status = compiler
+        .to_command()
+        .args(["-std=c11", "-Wall", "-Wextra", "-Werror"])
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg(root.join("tests/ffi.c"))
+        .arg("-o")
+        .arg(&exe)
+        .arg("-L")
+        .arg(&lib_dir)
+        .arg("-luring_fast_read")
+        .status()
+        .unwrap();
+    assert!(status.success(), "compiling tests/ffi.c failed");
+
+    let path = out.join("ffi-input.txt");
+    let contents = "read from C through io_uring\n";
+    std::fs::write(&path, contents).unwrap();
+    // cargo test puts its own deps/ (with a cdylib of whatever features came last) on the path
+    let output = Command::new(&exe)
+        .env("LD_LIBRARY_PATH", &lib_dir)
+        .arg(&path)
+        .arg(contents)
+        .arg(out.join("ffi-no-such-file"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "tests/ffi.c: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,65 @@
+//! `MinimalReader` under a counting global allocator: after construction, reads allocate nothing
+#![cfg(target_os = "linux")]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use uring_fast_read::MinimalReader;
+
+/// System allocator that counts the allocations of the threads that have `COUNTING` set
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Only the test's own thread counts, not the harness around it
+    static COUNTING: Cell<bool> = const { Cell::new(false) };
+}
+
+fn count() {
+    if COUNTING.try_with(Cell::get).unwrap_or(false) {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+#[test]
+fn reads_allocate_nothing() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+    let mut reader = MinimalReader::<8>::new().unwrap();
+    let file = std::fs::File::open(path).unwrap();
+    let expected = std::fs::read(path).unwrap();
+    let mut buf = vec![0u8; expected.len() + 4096];
+
+    COUNTING.set(true);
+    for i in 0..1000 {
+        let offset = i % 7;
+        let n = reader.read_at_into(&file, offset as u64, &mut buf).unwrap();
+        assert_eq!(&buf[..n], &expected[offset..]);
+
+        let n = reader.read_into(&file, &mut buf).unwrap();
+        assert_eq!(&buf[..n], &expected[..]);
+    }
+    COUNTING.set(false);
+
+    assert_eq!(ALLOCATIONS.load(Ordering::Relaxed), 0);
+}
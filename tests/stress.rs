@@ -0,0 +1,49 @@
+//! Many threads hammering one shared `UringReader` with small reads
+//!
+//! Every thread waits for its own completions while others reap theirs, which is exactly the situation
+//! where a completion can be gone (reaped by someone else) by the time a thread wakes up.
+//!
+//! The long run: cargo test --release --test stress -- --ignored
+#![cfg(target_os = "linux")]
+
+use uring_fast_read::{UringConfig, UringReader};
+
+fn stress(threads: usize, reads: usize) {
+    let reader = UringReader::new(UringConfig::default().queue_depth(16)).unwrap();
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+    let expected = std::fs::read(path).unwrap();
+
+    std::thread::scope(|s| {
+        for t in 0..threads {
+            let reader = &reader;
+            let expected = &expected;
+            s.spawn(move || {
+                for i in 0..reads {
+                    if (t + i) % 2 == 0 {
+                        assert_eq!(&reader.read_file_to_vec(path).unwrap(), expected);
+                    } else {
+                        // procfs reads are never inline completions, they go through io-wq
+                        let status = reader.read_file_to_vec("/proc/self/status").unwrap();
+                        assert!(status.starts_with(b"Name:"));
+                    }
+                }
+            });
+        }
+    });
+
+    // Every session reaped its own, nothing is left in the kernel or parked for somebody else
+    assert_eq!(reader.in_flight(), 0);
+    let ring = reader.ring_snapshot();
+    assert_eq!((ring.in_flight, ring.parked), (0, 0));
+}
+
+#[test]
+fn shared_reader_under_load() {
+    stress(8, 200);
+}
+
+#[test]
+#[ignore = "long, run with --release --ignored"]
+fn shared_reader_under_heavy_load() {
+    stress(32, 2000);
+}